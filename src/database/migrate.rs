@@ -0,0 +1,313 @@
+//! 数据库版本迁移
+//!
+//! 每个版本对应 `migrations` 目录下的 `NNNN_name.up.sql` 和 `NNNN_name.down.sql`，
+//! 已执行的版本记录在 `schema_migrations` 表中
+use std::fmt::Display;
+
+use mysql::{prelude::Queryable, PooledConn};
+
+use crate::{
+    libs::{TimeFormat, TIME},
+    log,
+};
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    up: &'static str,
+    down: &'static str,
+}
+
+macro_rules! migrations {
+    ($(($version:expr, $name:literal)), + $(,)?) => {
+        &[$(
+            Migration {
+                version: $version,
+                name: $name,
+                up: include_str!(concat!("migrations/", $name, ".up.sql")),
+                down: include_str!(concat!("migrations/", $name, ".down.sql")),
+            },
+        )+]
+    };
+}
+
+/// 所有版本，必须按版本号从小到大排列
+pub static MIGRATIONS: &[Migration] = migrations![
+    (1, "0001_init"),
+    (2, "0002_backfill_columns"),
+    (3, "0003_customer_sea"),
+    (4, "0004_job_history"),
+    (5, "0005_refresh_token"),
+    (6, "0006_session"),
+    (7, "0007_password_hash"),
+    (8, "0008_login_guard"),
+    (9, "0009_leaver"),
+    (10, "0010_role_department"),
+    (11, "0011_role_perm"),
+    (12, "0012_department"),
+    (13, "0013_customer_login"),
+    (14, "0014_decimal_money"),
+    (15, "0015_stock_reservation"),
+    (16, "0016_stock_movement"),
+    (17, "0017_stock_transfer"),
+    (18, "0018_purchase"),
+    (19, "0019_order_status_history"),
+    (20, "0020_pub_sea_scope"),
+    (21, "0021_job_claim"),
+];
+
+#[derive(Debug)]
+pub enum MigrateError {
+    Mysql(mysql::Error),
+    /// 数据库的版本高于程序支持的最新版本
+    DatabaseAhead { database: u32, binary: u32 },
+    UnknownVersion(u32),
+    /// 回滚会破坏数据，拒绝回滚
+    Refused { name: &'static str, reason: &'static str },
+}
+impl Display for MigrateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MigrateError::Mysql(e) => Display::fmt(e, f),
+            MigrateError::DatabaseAhead { database, binary } => f.write_fmt(format_args!(
+                "数据库版本({database})高于程序支持的最新版本({binary})，请更新程序"
+            )),
+            MigrateError::UnknownVersion(v) => {
+                f.write_fmt(format_args!("不存在版本{v}的迁移脚本"))
+            }
+            MigrateError::Refused { name, reason } => {
+                f.write_fmt(format_args!("拒绝回滚{name}，{reason}"))
+            }
+        }
+    }
+}
+impl std::error::Error for MigrateError {}
+impl From<mysql::Error> for MigrateError {
+    fn from(value: mysql::Error) -> Self {
+        Self::Mysql(value)
+    }
+}
+pub type Result<T> = std::result::Result<T, MigrateError>;
+
+/// 重复添加字段或索引，用于兼容手动修补过的旧数据库
+const DUPLICATE_COLUMN_ERROR_CODE: u16 = 1060;
+const DUPLICATE_INDEX_ERROR_CODE: u16 = 1061;
+
+/// 回滚前的检查，查询结果大于0时拒绝回滚该版本
+static DOWN_GUARDS: &[(u32, &str, &str)] = &[(
+    7,
+    "SELECT COUNT(*) FROM user WHERE password_hash IS NOT NULL",
+    "存在Argon2id哈希的密码，无法还原为md5，请先让这些用户重置密码",
+)];
+
+fn check_down_guard(conn: &mut PooledConn, m: &'static Migration) -> Result<()> {
+    for (_, query, reason) in DOWN_GUARDS.iter().filter(|(v, ..)| *v == m.version) {
+        let count: Option<u64> = conn.query_first(*query)?;
+        if count.unwrap_or(0) > 0 {
+            return Err(MigrateError::Refused {
+                name: m.name,
+                reason,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+fn ensure_table(conn: &mut PooledConn) -> mysql::Result<()> {
+    conn.query_drop(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INT NOT NULL,
+            name VARCHAR(100) NOT NULL,
+            applied_at VARCHAR(25) NOT NULL,
+            PRIMARY KEY (version)
+        )",
+    )
+}
+
+/// 已执行的版本及执行时间
+fn applied(conn: &mut PooledConn) -> mysql::Result<Vec<(u32, String)>> {
+    ensure_table(conn)?;
+    conn.query("SELECT version, applied_at FROM schema_migrations ORDER BY version")
+}
+
+fn current_version(conn: &mut PooledConn) -> mysql::Result<u32> {
+    Ok(applied(conn)?.last().map_or(0, |(v, _)| *v))
+}
+
+/// 执行脚本中的每一条语句
+fn execute(conn: &mut PooledConn, sql: &str) -> mysql::Result<()> {
+    for stmt in split_statements(sql) {
+        match conn.query_drop(stmt) {
+            Err(mysql::Error::MySqlError(e))
+                if e.code == DUPLICATE_COLUMN_ERROR_CODE
+                    || e.code == DUPLICATE_INDEX_ERROR_CODE =>
+            {
+                log!("忽略重复的字段或索引：{}", e.message);
+            }
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+/// 按`;`拆分脚本，忽略字符串和注释中的`;`
+pub fn split_statements(sql: &str) -> Vec<&str> {
+    let bytes = sql.as_bytes();
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    let mut has_code = false;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' | b'"' | b'`' => {
+                let quote = bytes[i];
+                has_code = true;
+                i += 1;
+                while i < bytes.len() {
+                    if bytes[i] == b'\\' && quote != b'`' {
+                        i += 1;
+                    } else if bytes[i] == quote {
+                        if bytes.get(i + 1) == Some(&quote) {
+                            i += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    i += 1;
+                }
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i < bytes.len() && !(bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/')) {
+                    i += 1;
+                }
+                i += 1;
+            }
+            b';' => {
+                if has_code {
+                    statements.push(sql[start..i].trim());
+                }
+                start = i + 1;
+                has_code = false;
+            }
+            c if !c.is_ascii_whitespace() => has_code = true,
+            _ => (),
+        }
+        i += 1;
+    }
+    if has_code {
+        statements.push(sql[start..].trim());
+    }
+    statements
+}
+
+/// 执行所有未执行的版本，`target`为空时执行到最新版本
+pub fn up(conn: &mut PooledConn, target: Option<u32>) -> Result<Vec<&'static Migration>> {
+    let target = target.unwrap_or_else(latest_version);
+    if target != 0 && !MIGRATIONS.iter().any(|m| m.version == target) {
+        return Err(MigrateError::UnknownVersion(target));
+    }
+    let current = current_version(conn)?;
+    let mut done = Vec::new();
+    for m in MIGRATIONS
+        .iter()
+        .filter(|m| m.version > current && m.version <= target)
+    {
+        log!("执行数据库迁移 {}", m.name);
+        execute(conn, m.up)?;
+        conn.exec_drop(
+            "INSERT INTO schema_migrations (version, name, applied_at) VALUES (?, ?, ?)",
+            (
+                m.version,
+                m.name,
+                TIME::now()
+                    .unwrap_or_default()
+                    .format(TimeFormat::YYYYMMDD_HHMMSS),
+            ),
+        )?;
+        done.push(m);
+    }
+    Ok(done)
+}
+
+/// 从最新的版本开始回滚`steps`个版本
+pub fn down(conn: &mut PooledConn, steps: usize) -> Result<Vec<&'static Migration>> {
+    let applied = applied(conn)?;
+    let list = applied
+        .iter()
+        .rev()
+        .take(steps)
+        .map(|(version, _)| {
+            MIGRATIONS
+                .iter()
+                .find(|m| m.version == *version)
+                .ok_or(MigrateError::UnknownVersion(*version))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    // 先检查所有要回滚的版本，避免回滚到一半时停止
+    for m in &list {
+        check_down_guard(conn, m)?;
+    }
+    let mut done = Vec::new();
+    for m in list {
+        let version = m.version;
+        log!("回滚数据库迁移 {}", m.name);
+        execute(conn, m.down)?;
+        conn.exec_drop("DELETE FROM schema_migrations WHERE version = ?", (version,))?;
+        done.push(m);
+    }
+    Ok(done)
+}
+
+/// 所有版本及其执行时间，未执行的版本时间为空
+pub fn status(conn: &mut PooledConn) -> Result<Vec<(&'static Migration, Option<String>)>> {
+    let applied = applied(conn)?;
+    Ok(MIGRATIONS
+        .iter()
+        .map(|m| {
+            let time = applied
+                .iter()
+                .find(|(v, _)| *v == m.version)
+                .map(|(_, t)| t.clone());
+            (m, time)
+        })
+        .collect())
+}
+
+/// 启动时检查数据库版本，数据库版本高于程序时拒绝启动，否则执行未执行的版本
+pub fn startup(conn: &mut PooledConn) -> Result<()> {
+    let current = current_version(conn)?;
+    let binary = latest_version();
+    if current > binary {
+        return Err(MigrateError::DatabaseAhead {
+            database: current,
+            binary,
+        });
+    }
+    up(conn, None)?;
+    Ok(())
+}
+
+#[test]
+fn test_split_statements() {
+    let sql = "-- 注释; 不拆分\nCREATE TABLE a (id INT);\n\
+        INSERT INTO a VALUES ('x;y', 'it''s', 'a\\';b');\n/* ; */\n  ;\nSELECT 1";
+    assert_eq!(
+        split_statements(sql),
+        vec![
+            "-- 注释; 不拆分\nCREATE TABLE a (id INT)",
+            "INSERT INTO a VALUES ('x;y', 'it''s', 'a\\';b')",
+            "SELECT 1"
+        ]
+    );
+    assert!(split_statements("-- 只有注释\n").is_empty());
+}
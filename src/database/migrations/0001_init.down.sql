@@ -0,0 +1,27 @@
+-- 删除初始化时创建的所有表，会清空全部数据
+DROP TABLE IF EXISTS supper;
+DROP TABLE IF EXISTS storehouse;
+DROP TABLE IF EXISTS order_instalment;
+DROP TABLE IF EXISTS invoice;
+DROP TABLE IF EXISTS order_product;
+DROP TABLE IF EXISTS order_data;
+DROP TABLE IF EXISTS order_num;
+DROP TABLE IF EXISTS report_cc;
+DROP TABLE IF EXISTS report;
+DROP TABLE IF EXISTS product_num;
+DROP TABLE IF EXISTS product_store;
+DROP TABLE IF EXISTS product;
+DROP TABLE IF EXISTS token;
+DROP TABLE IF EXISTS appoint_comment;
+DROP TABLE IF EXISTS appointment;
+DROP TABLE IF EXISTS customer_colleague;
+DROP TABLE IF EXISTS extra_customer_data;
+DROP TABLE IF EXISTS customer_share;
+DROP TABLE IF EXISTS customer;
+DROP TABLE IF EXISTS leaver;
+DROP TABLE IF EXISTS user;
+DROP TABLE IF EXISTS roles;
+DROP TABLE IF EXISTS custom_field_option;
+DROP TABLE IF EXISTS custom_field_data;
+DROP TABLE IF EXISTS custom_fields;
+DROP TABLE IF EXISTS drop_down_box;
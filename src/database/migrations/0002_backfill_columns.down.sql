@@ -0,0 +1 @@
+-- 这些字段属于当前的表结构，回滚时保留，不做任何修改
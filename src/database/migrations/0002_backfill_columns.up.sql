@@ -0,0 +1,11 @@
+-- 早期部署的数据库缺少以下字段，新安装的数据库在0001中已经包含，
+-- 重复添加字段的错误会被忽略
+ALTER TABLE customer ADD COLUMN status VARCHAR(30);
+ALTER TABLE customer ADD COLUMN source TEXT;
+ALTER TABLE customer ADD COLUMN role VARCHAR(30);
+ALTER TABLE customer ADD COLUMN ty VARCHAR(30);
+ALTER TABLE customer ADD COLUMN tag VARCHAR(30);
+
+ALTER TABLE order_data ADD COLUMN shipped INT NOT NULL DEFAULT 0;
+ALTER TABLE order_data ADD COLUMN shipped_date VARCHAR(25) NULL;
+ALTER TABLE order_data ADD COLUMN shipped_storehouse VARCHAR(30) NULL;
@@ -0,0 +1,3 @@
+ALTER TABLE extra_customer_data DROP COLUMN pop_from_sea_date;
+ALTER TABLE extra_customer_data DROP COLUMN push_to_sea_date;
+DROP TABLE IF EXISTS customer_sea;
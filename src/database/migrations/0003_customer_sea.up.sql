@@ -0,0 +1,17 @@
+-- 公海客户表，客户在公海期间不属于任何业务员
+CREATE TABLE IF NOT EXISTS customer_sea (
+    id VARCHAR(150) NOT NULL,
+    -- 放入公海前的业务员
+    salesman VARCHAR(150) NULL,
+    -- 放入公海前业务员所在的部门，公海按部门划分
+    department VARCHAR(30) NOT NULL,
+    -- 放入原因，自动放入时为系统生成的原因
+    reason TEXT NOT NULL,
+    push_time VARCHAR(25) NOT NULL,
+    PRIMARY KEY (id),
+    FOREIGN KEY (id) REFERENCES customer(id)
+);
+
+-- 最近一次放入公海和从公海领取的时间
+ALTER TABLE extra_customer_data ADD COLUMN push_to_sea_date VARCHAR(25) NULL;
+ALTER TABLE extra_customer_data ADD COLUMN pop_from_sea_date VARCHAR(25) NULL;
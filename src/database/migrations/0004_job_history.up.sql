@@ -0,0 +1,13 @@
+-- 定时任务执行记录
+CREATE TABLE IF NOT EXISTS job_history (
+    id VARCHAR(150) NOT NULL,
+    name VARCHAR(50) NOT NULL,
+    start_time VARCHAR(25) NOT NULL,
+    end_time VARCHAR(25) NOT NULL,
+    -- 1 成功，0 失败
+    success INT NOT NULL,
+    -- 执行摘要或失败原因
+    summary TEXT NOT NULL,
+    PRIMARY KEY (id),
+    INDEX (name, start_time)
+);
@@ -0,0 +1,7 @@
+DELETE FROM token WHERE ty = 2;
+ALTER TABLE token DROP INDEX token_user;
+ALTER TABLE token DROP INDEX token_family;
+ALTER TABLE token DROP COLUMN used;
+ALTER TABLE token DROP COLUMN expire;
+ALTER TABLE token DROP COLUMN family;
+ALTER TABLE token DROP COLUMN user;
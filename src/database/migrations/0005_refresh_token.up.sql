@@ -0,0 +1,10 @@
+-- refresh token保存在token表中，ty = 2，id为token的sha256，不保存token原文
+-- 同一次登录中轮换出的token属于同一个family
+ALTER TABLE token ADD COLUMN user VARCHAR(150) NULL;
+ALTER TABLE token ADD COLUMN family VARCHAR(64) NULL;
+-- 过期时间戳(纳秒)
+ALTER TABLE token ADD COLUMN expire BIGINT NULL;
+-- 已经换取过新token的refresh token，再次使用时撤销整个family
+ALTER TABLE token ADD COLUMN used INT NOT NULL DEFAULT 0;
+ALTER TABLE token ADD INDEX token_family (family);
+ALTER TABLE token ADD INDEX token_user (user);
@@ -0,0 +1,16 @@
+-- 登录会话，一次登录对应一个会话，会话id与该次登录的refresh token family相同
+-- 删除会话后该会话的access token和refresh token立即失效
+CREATE TABLE IF NOT EXISTS session (
+    id VARCHAR(64) NOT NULL,
+    -- 0 员工，1 客户
+    ty INT NOT NULL,
+    user VARCHAR(150) NOT NULL,
+    -- 客户端提供的设备名称，没有提供时为空
+    device VARCHAR(100) NOT NULL,
+    ip VARCHAR(64) NOT NULL,
+    user_agent TEXT NOT NULL,
+    create_time VARCHAR(25) NOT NULL,
+    last_seen VARCHAR(25) NOT NULL,
+    PRIMARY KEY (id),
+    INDEX (ty, user)
+);
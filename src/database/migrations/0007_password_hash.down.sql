@@ -0,0 +1,4 @@
+ALTER TABLE user DROP COLUMN must_change_password;
+-- Argon2id哈希无法还原为md5，存在password_hash时拒绝回滚，见migrate.rs的DOWN_GUARDS
+ALTER TABLE user MODIFY password BINARY(16) NOT NULL;
+ALTER TABLE user DROP COLUMN password_hash;
@@ -0,0 +1,8 @@
+-- Argon2id哈希，PHC格式，包含算法参数和盐
+ALTER TABLE user ADD COLUMN password_hash VARCHAR(255) NULL;
+-- 旧版本的md5密码，登录成功后转换为password_hash并清空
+ALTER TABLE user MODIFY password BINARY(16) NULL;
+-- 1 表示仍在使用默认密码，登录后只能修改密码
+ALTER TABLE user ADD COLUMN must_change_password INT NOT NULL DEFAULT 0;
+-- 12345678 的md5值
+UPDATE user SET must_change_password = 1 WHERE password = UNHEX('25d55ad283aa400af464c76d713c07ad');
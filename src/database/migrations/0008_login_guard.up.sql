@@ -0,0 +1,24 @@
+-- 登录失败计数，key为`account:手机号`或`ip:地址`
+CREATE TABLE IF NOT EXISTS login_failure (
+    k VARCHAR(150) NOT NULL,
+    failures INT NOT NULL,
+    -- 最后一次失败的时间，unix时间戳，单位秒
+    last_failure BIGINT NOT NULL,
+    PRIMARY KEY (k)
+);
+-- 登录记录，成功和失败的登录都会记录
+CREATE TABLE IF NOT EXISTS login_audit (
+    id VARCHAR(150) NOT NULL,
+    smartphone VARCHAR(15) NOT NULL,
+    -- 手机号不存在时为NULL
+    user VARCHAR(150) NULL,
+    ip VARCHAR(64) NOT NULL,
+    user_agent TEXT NOT NULL,
+    success INT NOT NULL,
+    -- 失败原因，或者管理员解锁等操作的说明
+    reason VARCHAR(100) NOT NULL,
+    time VARCHAR(25) NOT NULL,
+    PRIMARY KEY (id),
+    INDEX (smartphone),
+    INDEX (time)
+);
@@ -0,0 +1,4 @@
+ALTER TABLE leaver DROP COLUMN reason;
+ALTER TABLE leaver DROP COLUMN successor;
+ALTER TABLE leaver DROP COLUMN operator;
+ALTER TABLE leaver DROP COLUMN leave_time;
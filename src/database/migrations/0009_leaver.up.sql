@@ -0,0 +1,5 @@
+-- 离职信息，离职员工的客户、预约、意向订单和待审批报告已经交接给successor
+ALTER TABLE leaver ADD COLUMN leave_time VARCHAR(25) NULL;
+ALTER TABLE leaver ADD COLUMN operator VARCHAR(150) NULL;
+ALTER TABLE leaver ADD COLUMN successor VARCHAR(150) NULL;
+ALTER TABLE leaver ADD COLUMN reason TEXT NULL;
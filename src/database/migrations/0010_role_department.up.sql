@@ -0,0 +1,2 @@
+-- 创建该角色的部门，内置角色为NULL
+ALTER TABLE roles ADD COLUMN department VARCHAR(30) NULL;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS role_perm_log;
+DROP TABLE IF EXISTS role_perm;
@@ -0,0 +1,23 @@
+-- 角色权限，代替原来的data/perm文件，启动时为空则从该文件导入
+CREATE TABLE IF NOT EXISTS role_perm (
+    role VARCHAR(50) NOT NULL,
+    -- 权限组，例如customer
+    perm_group VARCHAR(50) NOT NULL,
+    action VARCHAR(50) NOT NULL,
+    -- 数据范围，JSON数组，例如["all"]
+    scopes TEXT NOT NULL,
+    PRIMARY KEY (role, perm_group, action)
+);
+-- 角色权限的修改记录，其他服务器根据最大的id判断是否需要重新加载权限
+CREATE TABLE IF NOT EXISTS role_perm_log (
+    id BIGINT NOT NULL AUTO_INCREMENT,
+    role VARCHAR(50) NOT NULL,
+    -- 修改人，导入和初始化时为system
+    operator VARCHAR(150) NOT NULL,
+    -- 修改前后的权限，JSON格式，角色新建时before为NULL，删除时after为NULL
+    before_perms TEXT NULL,
+    after_perms TEXT NULL,
+    time VARCHAR(25) NOT NULL,
+    PRIMARY KEY (id),
+    INDEX (role)
+);
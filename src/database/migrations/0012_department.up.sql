@@ -0,0 +1,29 @@
+-- 部门，user.department等字段保存部门名称，改名时同步修改
+CREATE TABLE IF NOT EXISTS department (
+    name VARCHAR(30) NOT NULL,
+    -- 上级部门，NULL为顶级部门
+    parent VARCHAR(30) NULL,
+    -- 部门负责人
+    head VARCHAR(150) NULL,
+    create_time VARCHAR(25) NOT NULL,
+    PRIMARY KEY (name),
+    INDEX (parent)
+);
+-- 原来的部门只保存在下拉框和员工数据中
+INSERT
+    IGNORE INTO department (name, parent, head, create_time)
+SELECT
+    value, NULL, NULL, create_time
+FROM
+    drop_down_box
+WHERE
+    name = 'department';
+
+INSERT
+    IGNORE INTO department (name, parent, head, create_time)
+SELECT
+    DISTINCT department, NULL, NULL, '0000-00-00 00:00:00'
+FROM
+    user
+WHERE
+    department != '';
@@ -0,0 +1,16 @@
+-- 客户登录账号，id为客户id，使用客户的手机号登录
+-- 员工发出邀请后客户使用邀请码设置密码，设置密码前不能登录
+CREATE TABLE IF NOT EXISTS customer_login (
+    id VARCHAR(150) NOT NULL,
+    -- 设置密码前为NULL
+    password_hash VARCHAR(255) NULL,
+    -- 邀请码的哈希，设置密码后为NULL
+    invite_code VARCHAR(255) NULL,
+    -- 邀请码过期时间，unix时间戳，单位秒
+    invite_expire BIGINT NULL,
+    -- 发出邀请的员工
+    inviter VARCHAR(150) NOT NULL,
+    create_time VARCHAR(25) NOT NULL,
+    last_login VARCHAR(25) NULL,
+    PRIMARY KEY (id)
+);
@@ -0,0 +1,11 @@
+ALTER TABLE order_instalment
+    MODIFY interest FLOAT NOT NULL,
+    MODIFY original_amount FLOAT NOT NULL;
+
+ALTER TABLE order_product
+    MODIFY price FLOAT NOT NULL,
+    MODIFY discount FLOAT NOT NULL;
+
+ALTER TABLE product
+    MODIFY price FLOAT NOT NULL,
+    MODIFY purchase_price FLOAT NOT NULL;
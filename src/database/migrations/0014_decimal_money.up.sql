@@ -0,0 +1,13 @@
+-- 金额改为定点数，精确到分，折扣精确到万分之一
+-- 已有的浮点数据由数据库四舍五入
+ALTER TABLE product
+    MODIFY price DECIMAL(15, 2) NOT NULL,
+    MODIFY purchase_price DECIMAL(15, 2) NOT NULL;
+
+ALTER TABLE order_product
+    MODIFY price DECIMAL(15, 2) NOT NULL,
+    MODIFY discount DECIMAL(5, 4) NOT NULL;
+
+ALTER TABLE order_instalment
+    MODIFY interest DECIMAL(15, 2) NOT NULL,
+    MODIFY original_amount DECIMAL(15, 2) NOT NULL;
@@ -0,0 +1,11 @@
+-- 意向订单预留的库存，发货或删除订单时释放
+-- 可用库存 = product_store.amount - 其他订单的预留数量
+CREATE TABLE IF NOT EXISTS product_reservation (
+    order_id VARCHAR(150) NOT NULL,
+    product VARCHAR(150) NOT NULL,
+    storehouse VARCHAR(30) NOT NULL,
+    amount INT NOT NULL,
+    create_time VARCHAR(25) NOT NULL,
+    PRIMARY KEY (order_id, product),
+    INDEX idx_product_storehouse (product, storehouse)
+);
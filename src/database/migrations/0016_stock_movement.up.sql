@@ -0,0 +1,27 @@
+-- 库存流水，只追加不修改，product_store.amount等于同一产品和库房所有delta之和
+-- kind: opening 期初, adjust 手动调整, ship 订单发货, return 取消发货退回,
+-- transfer_out 调拨出库, transfer_in 调拨入库, receipt 采购入库
+CREATE TABLE IF NOT EXISTS stock_movement (
+    id BIGINT NOT NULL AUTO_INCREMENT,
+    product VARCHAR(150) NOT NULL,
+    storehouse VARCHAR(30) NOT NULL,
+    kind VARCHAR(20) NOT NULL,
+    delta INT NOT NULL,
+    -- 变动后的库存
+    balance INT NOT NULL,
+    operator VARCHAR(150) NOT NULL,
+    -- 关联的单据，例如订单id，手动调整时为NULL
+    reference VARCHAR(150) NULL,
+    create_time VARCHAR(25) NOT NULL,
+    PRIMARY KEY (id),
+    INDEX idx_product_storehouse (product, storehouse, id),
+    INDEX idx_storehouse (storehouse, id),
+    INDEX idx_reference (reference)
+);
+
+-- 已有的库存作为期初
+INSERT INTO stock_movement (product, storehouse, kind, delta, balance, operator, reference, create_time)
+SELECT product, storehouse, 'opening', amount, amount, 'system', NULL,
+    DATE_FORMAT(NOW(), '%Y-%m-%d %H:%i:%s')
+FROM product_store
+WHERE amount <> 0;
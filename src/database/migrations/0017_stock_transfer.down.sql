@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS stock_transfer_product;
+DROP TABLE IF EXISTS stock_transfer;
@@ -0,0 +1,24 @@
+-- 库房之间的调拨单
+-- status: 0 草稿, 1 在途(已从调出库房扣减), 2 已接收(已计入调入库房)
+CREATE TABLE IF NOT EXISTS stock_transfer (
+    id VARCHAR(150) NOT NULL,
+    source VARCHAR(30) NOT NULL,
+    destination VARCHAR(30) NOT NULL,
+    status INT NOT NULL,
+    creator VARCHAR(150) NOT NULL,
+    create_time VARCHAR(25) NOT NULL,
+    sender VARCHAR(150) NULL,
+    send_time VARCHAR(25) NULL,
+    receiver VARCHAR(150) NULL,
+    receive_time VARCHAR(25) NULL,
+    comment TEXT NULL,
+    PRIMARY KEY (id),
+    INDEX idx_status (status, create_time)
+);
+
+CREATE TABLE IF NOT EXISTS stock_transfer_product (
+    transfer_id VARCHAR(150) NOT NULL,
+    product VARCHAR(150) NOT NULL,
+    amount INT NOT NULL,
+    PRIMARY KEY (transfer_id, product)
+);
@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS purchase_payment;
+DROP TABLE IF EXISTS purchase_receipt_product;
+DROP TABLE IF EXISTS purchase_receipt;
+DROP TABLE IF EXISTS purchase_order_product;
+DROP TABLE IF EXISTS purchase_order;
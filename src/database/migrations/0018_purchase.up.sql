@@ -0,0 +1,57 @@
+-- 采购单，向供应商(supper)采购产品
+-- status: 0 草稿, 1 已下单, 2 部分到货, 3 全部到货
+CREATE TABLE IF NOT EXISTS purchase_order (
+    id VARCHAR(150) NOT NULL,
+    supper VARCHAR(150) NOT NULL,
+    -- 默认的入库库房
+    storehouse VARCHAR(30) NOT NULL,
+    status INT NOT NULL,
+    creator VARCHAR(150) NOT NULL,
+    create_time VARCHAR(25) NOT NULL,
+    order_time VARCHAR(25) NULL,
+    comment TEXT NULL,
+    PRIMARY KEY (id),
+    INDEX idx_supper (supper, create_time)
+);
+
+-- 采购单的产品，单价为创建时产品的采购价
+CREATE TABLE IF NOT EXISTS purchase_order_product (
+    order_id VARCHAR(150) NOT NULL,
+    product VARCHAR(150) NOT NULL,
+    price DECIMAL(15, 2) NOT NULL,
+    amount INT NOT NULL,
+    -- 已到货数量
+    received INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (order_id, product)
+);
+
+-- 到货单，每次到货计入一个库房
+CREATE TABLE IF NOT EXISTS purchase_receipt (
+    id VARCHAR(150) NOT NULL,
+    order_id VARCHAR(150) NOT NULL,
+    storehouse VARCHAR(30) NOT NULL,
+    operator VARCHAR(150) NOT NULL,
+    create_time VARCHAR(25) NOT NULL,
+    comment TEXT NULL,
+    PRIMARY KEY (id),
+    INDEX idx_order (order_id)
+);
+
+CREATE TABLE IF NOT EXISTS purchase_receipt_product (
+    receipt_id VARCHAR(150) NOT NULL,
+    product VARCHAR(150) NOT NULL,
+    amount INT NOT NULL,
+    PRIMARY KEY (receipt_id, product)
+);
+
+-- 向供应商的付款
+CREATE TABLE IF NOT EXISTS purchase_payment (
+    id VARCHAR(150) NOT NULL,
+    order_id VARCHAR(150) NOT NULL,
+    amount DECIMAL(15, 2) NOT NULL,
+    operator VARCHAR(150) NOT NULL,
+    create_time VARCHAR(25) NOT NULL,
+    comment TEXT NULL,
+    PRIMARY KEY (id),
+    INDEX idx_order (order_id)
+);
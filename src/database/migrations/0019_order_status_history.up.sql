@@ -0,0 +1,19 @@
+-- 订单状态变化记录，只追加不修改
+-- status: 0 意向, 1 成交, 2 完成, 3 已取消, 4 已退款, 5 已退货
+CREATE TABLE IF NOT EXISTS order_status_history (
+    id BIGINT NOT NULL AUTO_INCREMENT,
+    order_id VARCHAR(150) NOT NULL,
+    -- 创建订单时为NULL
+    from_status INT NULL,
+    to_status INT NOT NULL,
+    operator VARCHAR(150) NOT NULL,
+    create_time VARCHAR(25) NOT NULL,
+    reason TEXT NULL,
+    PRIMARY KEY (id),
+    INDEX idx_order (order_id, id)
+);
+
+-- 已有订单的当前状态作为第一条记录
+INSERT INTO order_status_history (order_id, from_status, to_status, operator, create_time, reason)
+SELECT id, NULL, status, salesman, create_time, '迁移前的状态'
+FROM order_data;
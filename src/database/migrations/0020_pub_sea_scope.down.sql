@@ -0,0 +1 @@
+-- 回滚时保留department范围，无法区分哪些角色是迁移时加上的，删除会丢失原有的权限
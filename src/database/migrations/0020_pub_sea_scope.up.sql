@@ -0,0 +1,5 @@
+-- 查询公海改为必须有department或all范围，原来勾选了查询公海的角色保留查看本部门公海的权限
+UPDATE role_perm
+SET scopes = JSON_ARRAY_INSERT(scopes, '$[0]', 'department')
+WHERE perm_group = 'customer' AND action = 'query_pub_sea'
+    AND NOT JSON_CONTAINS(scopes, '"department"');
@@ -0,0 +1,14 @@
+-- 每日任务的执行权，多台服务器同时检查时只有插入成功的服务器执行任务
+CREATE TABLE IF NOT EXISTS job_claim (
+    name VARCHAR(50) NOT NULL,
+    -- 执行日期，YYYY-MM-DD
+    run_date VARCHAR(10) NOT NULL,
+    claim_time VARCHAR(25) NOT NULL,
+    PRIMARY KEY (name, run_date)
+);
+
+-- 已经执行过的日期不再执行
+INSERT IGNORE INTO job_claim (name, run_date, claim_time)
+SELECT name, LEFT(start_time, 10), MIN(start_time)
+FROM job_history
+GROUP BY name, LEFT(start_time, 10);
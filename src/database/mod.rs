@@ -1,13 +1,12 @@
 // #[forbid(unused)]
 // mod table;
-use std::{
-    fmt::Display,
-    sync::Arc,
-    time::{Duration, SystemTime},
-};
+pub mod migrate;
+pub mod query;
+use std::fmt::Display;
 
-use mysql::{prelude::Queryable, Pool, PooledConn, Result};
-use tokio::sync::{Mutex, MutexGuard};
+use mysql::{
+    prelude::Queryable, Opts, OptsBuilder, Pool, PoolConstraints, PoolOpts, PooledConn, Result,
+};
 
 pub struct Database;
 impl Database {
@@ -161,55 +160,40 @@ macro_rules! commit_or_rollback {
         }
     }};
 }
-pub type DB<'err> = MutexGuard<'err, PooledConn>;
 lazy_static::lazy_static! {
-    pub static ref LAST_LEFT_TIME: Arc<Mutex<Duration>> = {
-        Arc::new(Mutex::new(Duration::from_secs(1)))
+    /// 全局数据库连接池，每个请求从中取出各自的连接
+    pub static ref POOL: Pool = {
+        Pool::new(pool_opts()).expect("数据库连接失败")
     };
 }
-/// 闲置6分钟，数据库连接失效，重新连接
-pub async fn get_db() -> Result<Arc<Mutex<PooledConn>>, Response> {
-    let mut last_time = LAST_LEFT_TIME.lock().await;
-    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
-    let ten = last_time
-        .checked_add(Duration::from_secs(600))
-        .unwrap_or_default();
-    *last_time = now;
-    if ten < now {
-        let pool = __get_conn()?;
-        log!("更新数据库连接");
-        let mut db = DBC.lock().await;
-        *db = pool;
-    }
-    Ok(Arc::clone(&DBC))
+
+fn pool_opts() -> OptsBuilder {
+    let config = CONFIG.pool();
+    let constraints = PoolConstraints::new(config.min_size(), config.max_size())
+        .expect("config/config.json中连接池的min_size不能大于max_size");
+    let opts = Opts::from_url(&CONFIG.mysql_addr()).expect("数据库地址格式错误");
+    OptsBuilder::from_opts(opts)
+        .pool_opts(PoolOpts::default().with_constraints(constraints))
+        // 闲置超时的连接由数据库断开，连接池取出连接时会检测并重新连接
+        .init(vec![format!(
+            "SET SESSION wait_timeout = {}",
+            config.idle_timeout()
+        )])
+}
+
+/// 从连接池中获取一个连接
+pub async fn get_db() -> Result<PooledConn, Response> {
+    let pool = POOL.clone();
+    // 连接池已满时会阻塞等待，不能占用异步运行时的线程
+    let conn = tokio::task::spawn_blocking(move || pool.get_conn())
+        .await
+        .map_err(Response::internal_server_error)??;
+    Ok(conn)
 }
 
 /// 连接数据库
 pub fn __get_conn() -> Result<PooledConn> {
-    Pool::new(CONFIG.mysql.uri().as_str())?.get_conn()
+    POOL.get_conn()
 }
-lazy_static::lazy_static! {
-    /// 全局数据库连接池
-    pub static ref DBC: Arc<Mutex<PooledConn>> = {
-        Arc::new(
-            Mutex::new(
-                Pool::new( CONFIG.mysql.uri().as_str())
-                .expect("数据库连接失败")
-                .get_conn()
-                .expect("数据库连接失败")
-            )
-        )
-    };
-}
-
-use crate::{log, Response, CONFIG};
 
-pub fn create_table() -> Result<()> {
-    let mut conn = __get_conn()?;
-
-    let sql = include_str!("./table.sql");
-    for s in sql.split(';').filter(|s| !s.trim().is_empty()) {
-        conn.query_drop(s)?
-    }
-    Ok(())
-}
+use crate::{Response, CONFIG};
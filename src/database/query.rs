@@ -0,0 +1,165 @@
+//! 参数化查询
+//!
+//! 所有来自用户的值都通过`?`占位符传给数据库，不拼接进SQL中。
+//! 固定的SQL直接使用`exec_*`，需要按条件拼接的SQL使用[`Query`]和[`Cond`]
+use mysql::{
+    prelude::{FromRow, Queryable},
+    PooledConn, Value,
+};
+
+/// 一段带占位符的条件及其参数，例如`ex.salesman = ?`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cond {
+    sql: String,
+    params: Vec<Value>,
+}
+
+impl Cond {
+    /// `sql`中的`?`与`params`一一对应
+    pub fn new(sql: impl Into<String>, params: Vec<Value>) -> Self {
+        Self {
+            sql: sql.into(),
+            params,
+        }
+    }
+    /// 恒为真的条件
+    pub fn any() -> Self {
+        Self::new("1 = 1", Vec::new())
+    }
+    pub fn eq(column: &str, value: impl Into<Value>) -> Self {
+        Self::new(format!("{column} = ?"), vec![value.into()])
+    }
+    pub fn ge(column: &str, value: impl Into<Value>) -> Self {
+        Self::new(format!("{column} >= ?"), vec![value.into()])
+    }
+    pub fn le(column: &str, value: impl Into<Value>) -> Self {
+        Self::new(format!("{column} <= ?"), vec![value.into()])
+    }
+    /// `values`为空时恒为假
+    pub fn in_list<T: Into<Value>>(column: &str, values: impl IntoIterator<Item = T>) -> Self {
+        let params: Vec<Value> = values.into_iter().map(Into::into).collect();
+        if params.is_empty() {
+            return Self::new("1 = 0", params);
+        }
+        let holders = vec!["?"; params.len()].join(", ");
+        Self::new(format!("{column} IN ({holders})"), params)
+    }
+    pub fn not_null(column: &str) -> Self {
+        Self::new(format!("{column} IS NOT NULL"), Vec::new())
+    }
+    pub fn and(self, other: Cond) -> Self {
+        let mut params = self.params;
+        params.extend(other.params);
+        Self::new(format!("({}) AND ({})", self.sql, other.sql), params)
+    }
+    pub fn or(self, other: Cond) -> Self {
+        let mut params = self.params;
+        params.extend(other.params);
+        Self::new(format!("({}) OR ({})", self.sql, other.sql), params)
+    }
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+    pub fn params(&self) -> &[Value] {
+        &self.params
+    }
+}
+
+/// 按顺序拼接SQL和参数
+#[derive(Debug, Default, Clone)]
+pub struct Query {
+    sql: String,
+    params: Vec<Value>,
+}
+
+impl Query {
+    pub fn new(sql: impl AsRef<str>) -> Self {
+        Self {
+            sql: sql.as_ref().to_owned(),
+            params: Vec::new(),
+        }
+    }
+    /// 拼接固定的SQL，不能包含用户输入
+    pub fn push(mut self, sql: impl AsRef<str>) -> Self {
+        self.sql.push_str(sql.as_ref());
+        self
+    }
+    /// 拼接一个`?`占位符及其参数
+    pub fn bind(mut self, value: impl Into<Value>) -> Self {
+        self.sql.push('?');
+        self.params.push(value.into());
+        self
+    }
+    /// 拼接`(条件)`
+    pub fn cond(mut self, cond: &Cond) -> Self {
+        self.sql.push('(');
+        self.sql.push_str(&cond.sql);
+        self.sql.push(')');
+        self.params.extend(cond.params.iter().cloned());
+        self
+    }
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+    pub fn params(&self) -> &[Value] {
+        &self.params
+    }
+    pub fn fetch<T: FromRow>(self, conn: &mut PooledConn) -> mysql::Result<Vec<T>> {
+        conn.exec(self.sql, self.params)
+    }
+    pub fn first<T: FromRow>(self, conn: &mut PooledConn) -> mysql::Result<Option<T>> {
+        conn.exec_first(self.sql, self.params)
+    }
+    pub fn execute(self, conn: &mut PooledConn) -> mysql::Result<()> {
+        conn.exec_drop(self.sql, self.params)
+    }
+}
+
+/// 包含引号和反斜杠的输入，用于测试各个接口的参数都经过绑定
+#[cfg(test)]
+pub const PAYLOADS: [&str; 4] = ["O'Brien", "a\\", "x' OR '1'='1", "\\'; DROP TABLE user; -- "];
+
+/// 检查`payload`没有拼接进SQL，并且作为参数绑定
+#[cfg(test)]
+pub fn assert_bound(query: &Query, payload: &str) {
+    assert!(!query.sql().contains(payload), "{}", query.sql());
+    assert!(
+        query.params().contains(&Value::from(payload)),
+        "{payload} 没有绑定"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payloads_are_bound() {
+        for payload in PAYLOADS {
+            let query = Query::new("SELECT * FROM customer c WHERE ")
+                .cond(&Cond::eq("c.name", payload).and(Cond::not_null("c.ty")))
+                .push(" AND c.id = ")
+                .bind(payload);
+            assert_eq!(
+                query.sql(),
+                "SELECT * FROM customer c WHERE ((c.name = ?) AND (c.ty IS NOT NULL)) AND c.id = ?"
+            );
+            assert_eq!(query.params(), &[Value::from(payload), Value::from(payload)]);
+        }
+    }
+
+    #[test]
+    fn test_cond() {
+        let cond = Cond::ge("a", 1).or(Cond::le("b", "x'")).and(Cond::any());
+        assert_eq!(cond.sql(), "((a >= ?) OR (b <= ?)) AND (1 = 1)");
+        assert_eq!(cond.params(), &[Value::from(1), Value::from("x'")]);
+    }
+
+    #[test]
+    fn test_in_list() {
+        let cond = Cond::in_list("u.department", ["a", "b'"]);
+        assert_eq!(cond.sql(), "u.department IN (?, ?)");
+        assert_eq!(cond.params(), &[Value::from("a"), Value::from("b'")]);
+        assert_eq!(Cond::in_list("u.department", Vec::<String>::new()).sql(), "1 = 0");
+    }
+}
@@ -28,7 +28,6 @@ macro_rules! get_cache {
     };
 }
 
-
 #[macro_export]
 macro_rules! log {
     ($($args:tt)+) => {
@@ -110,10 +109,197 @@ lazy_static::lazy_static! {
         Config::read()
     };
 }
+/// 数据库连接池设置
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+#[serde(default)]
+pub struct PoolConfig {
+    /// 连接池保持的最少连接数
+    min_size: usize,
+    /// 连接池允许的最大连接数
+    max_size: usize,
+    /// 连接闲置超过该秒数后被断开
+    idle_timeout: u64,
+}
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            min_size: 2,
+            max_size: 20,
+            idle_timeout: 600,
+        }
+    }
+}
+impl PoolConfig {
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+    pub fn idle_timeout(&self) -> u64 {
+        self.idle_timeout
+    }
+}
+/// 每日任务的执行时间
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    hour: usize,
+    minute: usize,
+}
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self { hour: 3, minute: 0 }
+    }
+}
+impl ScheduleConfig {
+    pub fn hour(&self) -> usize {
+        self.hour
+    }
+    pub fn minute(&self) -> usize {
+        self.minute
+    }
+}
+/// token签名密钥，签发时`kid`写入token头部
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct JwtKey {
+    pub kid: String,
+    pub secret: String,
+    /// HS256、HS384或HS512
+    #[serde(default = "default_jwt_algorithm")]
+    pub algorithm: String,
+}
+fn default_jwt_algorithm() -> String {
+    "HS512".to_owned()
+}
+/// token设置，密钥也可以通过环境变量`CRM_JWT_SECRET`、`CRM_JWT_KID`和`CRM_JWT_ALGORITHM`设置，
+/// 环境变量优先于设置文件
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+#[serde(default)]
+pub struct JwtConfig {
+    /// 签发新token使用的密钥
+    kid: String,
+    /// 验证token时接受的密钥，轮换密钥时保留旧密钥直到旧token全部过期
+    keys: Vec<JwtKey>,
+    /// access token有效期，单位秒
+    access_lifetime: u64,
+    /// refresh token有效期，单位秒
+    refresh_lifetime: u64,
+}
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            kid: "default".to_owned(),
+            keys: Vec::new(),
+            access_lifetime: 30 * 60,
+            refresh_lifetime: 14 * 24 * 3600,
+        }
+    }
+}
+impl JwtConfig {
+    /// 生成带随机密钥的设置，用于首次创建设置文件
+    fn generate() -> Self {
+        let secret: String = (0..64)
+            .map(|_| format!("{:02x}", rand::random::<u8>()))
+            .collect();
+        let mut config = Self::default();
+        config.keys.push(JwtKey {
+            kid: config.kid.clone(),
+            secret,
+            algorithm: default_jwt_algorithm(),
+        });
+        config
+    }
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+    pub fn keys(&self) -> &[JwtKey] {
+        &self.keys
+    }
+    pub fn access_lifetime(&self) -> u64 {
+        self.access_lifetime
+    }
+    pub fn refresh_lifetime(&self) -> u64 {
+        self.refresh_lifetime
+    }
+}
+/// 登录失败限制，账号和IP分别计数
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+#[serde(default)]
+pub struct LoginConfig {
+    /// 同一账号连续失败该次数后锁定
+    max_failures: u32,
+    /// 同一IP连续失败该次数后锁定
+    ip_max_failures: u32,
+    /// 锁定时长，单位秒，超过该时长没有失败时计数清零
+    lockout: u64,
+    /// 第一次失败后需要等待的秒数，之后每次失败翻倍
+    backoff: u64,
+}
+impl Default for LoginConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            ip_max_failures: 20,
+            lockout: 15 * 60,
+            backoff: 1,
+        }
+    }
+}
+impl LoginConfig {
+    pub fn max_failures(&self) -> u32 {
+        self.max_failures
+    }
+    pub fn ip_max_failures(&self) -> u32 {
+        self.ip_max_failures
+    }
+    pub fn lockout(&self) -> u64 {
+        self.lockout
+    }
+    pub fn backoff(&self) -> u64 {
+        self.backoff
+    }
+}
+/// 库存设置
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default)]
+#[serde(default)]
+pub struct InventoryConfig {
+    /// 库存不足时是否仍然允许发货和预留，允许时库存可以为负数
+    allow_negative: bool,
+}
+impl InventoryConfig {
+    pub fn allow_negative(&self) -> bool {
+        self.allow_negative
+    }
+}
+/// 反向代理设置
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// 可信的反向代理地址，只有来自这些地址的请求才使用`X-Forwarded-For`和`X-Real-IP`
+    trusted: Vec<std::net::IpAddr>,
+}
+impl ProxyConfig {
+    pub fn trusted(&self) -> &[std::net::IpAddr] {
+        &self.trusted
+    }
+}
 #[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub struct Config {
     port: u16,
     mysql: MYSQL,
+    #[serde(default)]
+    pool: PoolConfig,
+    #[serde(default)]
+    schedule: ScheduleConfig,
+    #[serde(default)]
+    jwt: JwtConfig,
+    #[serde(default)]
+    login: LoginConfig,
+    #[serde(default)]
+    inventory: InventoryConfig,
+    #[serde(default)]
+    proxy: ProxyConfig,
 }
 
 impl Default for Config {
@@ -127,6 +313,12 @@ impl Default for Config {
                 port: 3306,
                 database: "crm".to_owned(),
             },
+            pool: PoolConfig::default(),
+            schedule: ScheduleConfig::default(),
+            jwt: JwtConfig::generate(),
+            login: LoginConfig::default(),
+            inventory: InventoryConfig::default(),
+            proxy: ProxyConfig::default(),
         }
     }
 }
@@ -157,6 +349,24 @@ impl Config {
     pub fn mysql_addr(&self) -> String {
         self.mysql.uri()
     }
+    pub fn pool(&self) -> &PoolConfig {
+        &self.pool
+    }
+    pub fn schedule(&self) -> &ScheduleConfig {
+        &self.schedule
+    }
+    pub fn jwt(&self) -> &JwtConfig {
+        &self.jwt
+    }
+    pub fn login(&self) -> &LoginConfig {
+        &self.login
+    }
+    pub fn inventory(&self) -> &InventoryConfig {
+        &self.inventory
+    }
+    pub fn proxy(&self) -> &ProxyConfig {
+        &self.proxy
+    }
 }
 pub fn read_data() {
     use std::fs::read_to_string;
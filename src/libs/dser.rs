@@ -3,40 +3,10 @@ use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 use std::fmt::Display;
 
-use crate::perm::roles::ROLE_TABLES;
+use crate::perm::roles::role_tables;
 
 use super::cache::STORE_HOUSE_CACHE;
 
-pub fn deser_f32<'de, D>(de: D) -> Result<f32, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let value: Value = Deserialize::deserialize(de)?;
-    match value {
-        Value::Number(value) => {
-            if let Some(f) = value.as_f64() {
-                Ok(f as f32)
-            } else if let Some(f) = value.as_i64() {
-                Ok(f as f32)
-            } else {
-                Ok(value.as_u64().unwrap_or(0) as f32)
-            }
-        }
-        Value::String(value) => {
-            if let Ok(f) = value.parse::<f32>() {
-                Ok(f)
-            } else {
-                Err(serde::de::Error::custom(
-                    "浮点数格式格式错误，请检查所有字符串浮点数是否格式正确",
-                ))
-            }
-        }
-        _ => Err(serde::de::Error::custom(
-            "浮点数格式格式错误，请检查所有字符串浮点数是否格式正确",
-        )),
-    }
-}
-
 pub fn split_files<S>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -49,13 +19,6 @@ where
     Serialize::serialize(&split, serializer)
 }
 
-pub fn serialize_f32_to_string<S>(value: &f32, serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    serializer.serialize_str(value.to_string().as_str())
-}
-
 struct BoolVisitor;
 
 impl<'de> Visitor<'de> for BoolVisitor {
@@ -100,6 +63,14 @@ where
     let value: bool = Deserialize::deserialize(de)?;
     Ok(op::ternary!(value => 0; 1))
 }
+/// 区分缺少的字段和`null`，缺少时为`None`，`null`为`Some(None)`，字段需要同时加上`#[serde(default)]`
+pub fn deser_double_option<'de, T, D>(de: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(de).map(Some)
+}
 pub fn serialize_empty_to_none<S>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -169,11 +140,9 @@ pub fn serialize_role<S>(id: &String, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    let name = unsafe {
-        ROLE_TABLES
-            .get_name(id)
-            .map_or(id.into(), |v| v.to_string())
-    };
+    let name = role_tables()
+        .get_name(id)
+        .map_or(id.into(), |v| v.to_string());
 
     serializer.serialize_str(&name)
 }
@@ -225,7 +194,7 @@ where
     D: Deserializer<'de>,
 {
     let name: String = Deserialize::deserialize(de)?;
-    let id = unsafe { ROLE_TABLES.get_id(&name).map_or(name, |v| v.to_string()) };
+    let id = role_tables().get_id(&name).map_or(name, |v| v.to_string());
     Ok(id)
 }
 
@@ -234,12 +203,11 @@ where
     D: Deserializer<'de>,
 {
     let roles: Vec<String> = Deserialize::deserialize(de)?;
-    Ok(unsafe {
-        roles
-            .into_iter()
-            .map(|r| ROLE_TABLES.get_id(&r).map_or(r, |v| v.to_string()))
-    }
-    .collect())
+    let tables = role_tables();
+    Ok(roles
+        .into_iter()
+        .map(|r| tables.get_id(&r).map_or(r, |v| v.to_string()))
+        .collect())
 }
 
 pub fn deserialize_mm_dd<'de, D>(de: D) -> Result<String, D::Error>
@@ -1,5 +0,0 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Float {
-    integer: u64,
-    decimal: u64,
-}
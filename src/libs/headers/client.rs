@@ -0,0 +1,103 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::http::{header::USER_AGENT, HeaderMap};
+
+use crate::CONFIG;
+
+/// 发起请求的客户端信息，用于记录登录会话
+#[derive(Debug, Default, Clone)]
+pub struct ClientInfo {
+    pub ip: String,
+    pub user_agent: String,
+}
+
+impl ClientInfo {
+    /// 请求来自设置中的可信反向代理时才使用`X-Forwarded-For`和`X-Real-IP`，
+    /// 否则使用连接的地址
+    pub fn new(headers: &HeaderMap, addr: Option<SocketAddr>) -> Self {
+        Self::with_proxies(headers, addr, CONFIG.proxy().trusted())
+    }
+
+    fn with_proxies(headers: &HeaderMap, addr: Option<SocketAddr>, trusted: &[IpAddr]) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.trim().to_owned())
+                .filter(|s| !s.is_empty())
+        };
+        let peer = addr.map(|a| a.ip());
+        let forwarded = || {
+            // 从右往左跳过可信代理，第一个不是可信代理的地址为客户端，左边的地址可以被客户端伪造
+            let list = header("x-forwarded-for")?;
+            let hops: Vec<&str> = list.split(',').map(str::trim).collect();
+            hops.iter()
+                .rev()
+                .find(|ip| !ip.parse().is_ok_and(|ip: IpAddr| trusted.contains(&ip)))
+                .or(hops.first())
+                .map(|ip| ip.to_string())
+        };
+        let ip = match peer {
+            Some(p) if trusted.contains(&p) => forwarded()
+                .or_else(|| header("x-real-ip"))
+                .unwrap_or_else(|| p.to_string()),
+            Some(p) => p.to_string(),
+            None => String::new(),
+        };
+        let user_agent = headers
+            .get(USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_owned();
+        Self { ip, user_agent }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use axum::http::HeaderMap;
+
+    use super::ClientInfo;
+
+    fn ip(headers: &[(&'static str, &str)], peer: &str, trusted: &[&str]) -> String {
+        let mut map = HeaderMap::new();
+        for (k, v) in headers {
+            map.insert(*k, v.parse().unwrap());
+        }
+        let addr: SocketAddr = format!("{peer}:443").parse().unwrap();
+        let trusted: Vec<IpAddr> = trusted.iter().map(|t| t.parse().unwrap()).collect();
+        ClientInfo::with_proxies(&map, Some(addr), &trusted).ip
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_headers() {
+        let headers = [("x-forwarded-for", "1.1.1.1"), ("x-real-ip", "2.2.2.2")];
+        assert_eq!(ip(&headers, "9.9.9.9", &[]), "9.9.9.9");
+        assert_eq!(ip(&headers, "9.9.9.9", &["10.0.0.1"]), "9.9.9.9");
+    }
+
+    #[test]
+    fn trusted_proxy() {
+        let proxy = ["10.0.0.1", "10.0.0.2"];
+        assert_eq!(
+            ip(&[("x-forwarded-for", "1.1.1.1")], "10.0.0.1", &proxy),
+            "1.1.1.1"
+        );
+        // 客户端伪造的地址在左边，不被采用
+        assert_eq!(
+            ip(
+                &[("x-forwarded-for", "6.6.6.6, 1.1.1.1, 10.0.0.2")],
+                "10.0.0.1",
+                &proxy
+            ),
+            "1.1.1.1"
+        );
+        assert_eq!(
+            ip(&[("x-real-ip", "2.2.2.2")], "10.0.0.1", &proxy),
+            "2.2.2.2"
+        );
+        assert_eq!(ip(&[], "10.0.0.1", &proxy), "10.0.0.1");
+    }
+}
@@ -1,5 +1,7 @@
 mod basic;
+mod client;
 pub use basic::Bearer;
+pub use client::ClientInfo;
 
 use axum::http;
 use base64::DecodeError;
@@ -1,9 +1,12 @@
 pub mod perm;
 pub mod cache;
-mod float;
+pub mod money;
 pub mod dser;
 pub mod headers;
 pub mod lazy;
+pub mod password;
+pub mod scheduler;
+pub mod sheet;
 pub mod time;
 pub use dser::deserialize_any_to_bool;
 use axum::extract::Multipart;
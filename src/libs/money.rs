@@ -0,0 +1,313 @@
+//! 金额和比例
+//!
+//! 金额精确到分，数据库中为`DECIMAL(15, 2)`，JSON中为字符串，例如`"12.30"`。
+//! 比例(折扣)精确到万分之一，数据库中为`DECIMAL(5, 4)`。
+//! 所有舍入都使用四舍五入，并且只在[`Money::new`]中进行，
+//! 折扣按产品行计算后舍入，订单总额为各行金额之和
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Neg, Sub},
+    str::FromStr,
+};
+
+use mysql::{prelude::FromValue, FromValueError, Value};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// 金额的小数位数
+const MONEY_SCALE: u32 = 2;
+/// 比例的小数位数
+const RATE_SCALE: u32 = 4;
+
+/// 解析JSON中的字符串或数字，数字按原文解析，不经过浮点数
+fn parse_json_decimal<'de, D: Deserializer<'de>>(de: D) -> Result<Decimal, D::Error> {
+    let value = serde_json::Value::deserialize(de)?;
+    let text = match &value {
+        serde_json::Value::String(s) => s.trim().to_owned(),
+        serde_json::Value::Number(n) => n.to_string(),
+        _ => return Err(serde::de::Error::custom("金额必须是字符串或数字")),
+    };
+    Decimal::from_str(&text)
+        .or_else(|_| Decimal::from_scientific(&text))
+        .map_err(|_| serde::de::Error::custom(format!("`{text}`不是正确的数字格式")))
+}
+
+/// 数据库中的DECIMAL以字符串返回，旧数据可能是整数或浮点数
+fn parse_value_decimal(value: &Value) -> Option<Decimal> {
+    match value {
+        Value::Bytes(bytes) => Decimal::from_str(std::str::from_utf8(bytes).ok()?.trim()).ok(),
+        Value::Int(i) => Some(Decimal::from(*i)),
+        Value::UInt(u) => Some(Decimal::from(*u)),
+        Value::Float(f) => Decimal::from_str(&f.to_string()).ok(),
+        Value::Double(f) => Decimal::from_str(&f.to_string()).ok(),
+        _ => None,
+    }
+}
+
+/// 金额，精确到分
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(Decimal);
+
+impl Money {
+    pub const ZERO: Money = Money(Decimal::ZERO);
+
+    /// 四舍五入到分
+    pub fn new(value: Decimal) -> Self {
+        let mut value =
+            value.round_dp_with_strategy(MONEY_SCALE, RoundingStrategy::MidpointAwayFromZero);
+        value.rescale(MONEY_SCALE);
+        Self(value)
+    }
+    /// 以分为单位
+    pub fn from_fen(fen: i64) -> Self {
+        Self(Decimal::new(fen, MONEY_SCALE))
+    }
+    pub fn value(self) -> Decimal {
+        self.0
+    }
+    pub fn is_negative(self) -> bool {
+        self.0.is_sign_negative() && !self.0.is_zero()
+    }
+    /// 单价乘以数量，不需要舍入
+    pub fn times(self, amount: usize) -> Self {
+        Self::new(self.0 * Decimal::from(amount))
+    }
+    /// 扣除折扣后的金额，`discount`为折扣比例，结果四舍五入到分
+    pub fn discounted(self, discount: Rate) -> Self {
+        Self::new(self.0 * (Decimal::ONE - discount.0))
+    }
+    /// 分成`count`份，每份相差不超过一分，多出的分从第一份开始依次分配，各份之和等于原金额
+    pub fn split(self, count: usize) -> Vec<Money> {
+        if count == 0 {
+            return Vec::new();
+        }
+        let total = (self.0 * Decimal::from(100)).trunc();
+        let count_d = Decimal::from(count);
+        let base = (total / count_d).trunc();
+        let remainder = total - base * count_d;
+        let remainder = usize::try_from(remainder.abs().trunc().mantissa()).unwrap_or(0);
+        let unit = if total.is_sign_negative() {
+            -Decimal::ONE
+        } else {
+            Decimal::ONE
+        };
+        (0..count)
+            .map(|i| {
+                let fen = op::ternary!(i < remainder => base + unit; base);
+                Self(fen / Decimal::from(100)).rescaled()
+            })
+            .collect()
+    }
+    fn rescaled(mut self) -> Self {
+        self.0.rescale(MONEY_SCALE);
+        self
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+    fn add(self, rhs: Self) -> Self::Output {
+        Self(self.0 + rhs.0).rescaled()
+    }
+}
+impl AddAssign for Money {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+impl Sub for Money {
+    type Output = Money;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self(self.0 - rhs.0).rescaled()
+    }
+}
+impl Neg for Money {
+    type Output = Money;
+    fn neg(self) -> Self::Output {
+        Self(-self.0)
+    }
+}
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
+impl<'a> Sum<&'a Money> for Money {
+    fn sum<I: Iterator<Item = &'a Money>>(iter: I) -> Self {
+        iter.copied().sum()
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// 前端传入的金额不能为负数，也不能超过两位小数，不做舍入
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let value = parse_json_decimal(de)?.normalize();
+        if value.is_sign_negative() && !value.is_zero() {
+            return Err(serde::de::Error::custom("金额不能为负数"));
+        }
+        if value.scale() > MONEY_SCALE {
+            return Err(serde::de::Error::custom(format!(
+                "金额`{value}`最多只能有两位小数"
+            )));
+        }
+        Ok(Self::new(value))
+    }
+}
+
+impl TryFrom<Value> for Money {
+    type Error = FromValueError;
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match parse_value_decimal(&value) {
+            Some(d) => Ok(Self::new(d)),
+            None => Err(FromValueError(value)),
+        }
+    }
+}
+impl FromValue for Money {
+    type Intermediate = Money;
+}
+impl From<Money> for Value {
+    fn from(value: Money) -> Self {
+        Value::Bytes(value.to_string().into_bytes())
+    }
+}
+
+/// 比例，0到1之间，精确到万分之一，用于折扣
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rate(Decimal);
+
+impl Rate {
+    pub const ZERO: Rate = Rate(Decimal::ZERO);
+
+    /// 超过0到1的范围或超过四位小数时返回None
+    pub fn new(value: Decimal) -> Option<Self> {
+        let value = value.normalize();
+        if value < Decimal::ZERO || value > Decimal::ONE || value.scale() > RATE_SCALE {
+            return None;
+        }
+        Some(Self(value))
+    }
+    pub fn value(self) -> Decimal {
+        self.0
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.normalize().fmt(f)
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let value = parse_json_decimal(de)?;
+        Self::new(value).ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "比例`{value}`必须在0到1之间，并且最多只能有四位小数"
+            ))
+        })
+    }
+}
+
+impl TryFrom<Value> for Rate {
+    type Error = FromValueError;
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        // 旧数据中的浮点数先舍入到四位小数
+        match parse_value_decimal(&value)
+            .map(|d| d.round_dp_with_strategy(RATE_SCALE, RoundingStrategy::MidpointAwayFromZero))
+        {
+            Some(d) => Self::new(d).ok_or(FromValueError(value)),
+            None => Err(FromValueError(value)),
+        }
+    }
+}
+impl FromValue for Rate {
+    type Intermediate = Rate;
+}
+impl From<Rate> for Value {
+    fn from(value: Rate) -> Self {
+        Value::Bytes(value.to_string().into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn money(s: &str) -> Money {
+        serde_json::from_value(serde_json::json!(s)).unwrap()
+    }
+    fn rate(s: &str) -> Rate {
+        serde_json::from_value(serde_json::json!(s)).unwrap()
+    }
+
+    #[test]
+    fn test_money_serde() {
+        assert_eq!(serde_json::to_value(money("12.3")).unwrap(), "12.30");
+        let m: Money = serde_json::from_value(serde_json::json!(0.1)).unwrap();
+        assert_eq!(m.to_string(), "0.10");
+        assert!(serde_json::from_value::<Money>(serde_json::json!("1.234")).is_err());
+        assert!(serde_json::from_value::<Money>(serde_json::json!("-1")).is_err());
+        assert!(serde_json::from_value::<Rate>(serde_json::json!("1.5")).is_err());
+        assert!(serde_json::from_value::<Rate>(serde_json::json!("0.12345")).is_err());
+    }
+
+    #[test]
+    fn test_sum_is_exact() {
+        // f32下0.01的累加会产生误差
+        let total: Money = (0..100).map(|_| money("0.01")).sum();
+        assert_eq!(total, money("1"));
+        assert_eq!(money("0.1") + money("0.2"), money("0.3"));
+    }
+
+    #[test]
+    fn test_discount_rounding() {
+        // 3 * 3.33 * 0.85 = 8.4915 -> 8.49
+        assert_eq!(
+            money("3.33").times(3).discounted(rate("0.15")),
+            money("8.49")
+        );
+        // 0.05 * 0.5 = 0.025 -> 0.03 四舍五入
+        assert_eq!(money("0.05").discounted(rate("0.5")), money("0.03"));
+        assert_eq!(money("10").discounted(Rate::ZERO), money("10"));
+    }
+
+    #[test]
+    fn test_split() {
+        let parts = money("100").split(3);
+        assert_eq!(parts, [money("33.34"), money("33.33"), money("33.33")]);
+        assert_eq!(parts.iter().sum::<Money>(), money("100"));
+        assert_eq!(money("0.01").split(2), [money("0.01"), Money::ZERO]);
+        assert!(money("1").split(0).is_empty());
+    }
+
+    #[test]
+    fn test_from_value() {
+        let m = Money::try_from(Value::Bytes(b"12.50".to_vec())).unwrap();
+        assert_eq!(m, money("12.5"));
+        let m = Money::try_from(Value::Float(0.1)).unwrap();
+        assert_eq!(m, money("0.1"));
+        let r = Rate::try_from(Value::Float(0.15)).unwrap();
+        assert_eq!(r, rate("0.15"));
+        assert_eq!(Value::from(money("3")), Value::Bytes(b"3.00".to_vec()));
+    }
+}
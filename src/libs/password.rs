@@ -0,0 +1,90 @@
+//! 密码哈希
+//!
+//! 新密码使用Argon2id，算法参数和盐保存在PHC格式的字符串中；
+//! 旧版本保存的是不加盐的md5，登录成功后会被转换为Argon2id
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+
+use crate::Response;
+
+/// 新建员工账号的默认密码，第一次登录后必须修改
+pub static DEFAULT_PASSWORD: &str = "12345678";
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, Params::default())
+}
+
+pub fn hash_password(password: &str) -> Result<String, Response> {
+    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    argon2()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(Response::internal_server_error)
+}
+
+/// 参数错误的哈希视为密码错误
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        argon2()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+pub fn verify_md5(password: &str, digest: &[u8]) -> bool {
+    md5::compute(password.as_bytes()).0.as_slice() == digest
+}
+
+/// 哈希的算法或参数与当前设置不同时需要重新计算
+pub fn needs_rehash(hash: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(hash) else {
+        return true;
+    };
+    let current = Params::default();
+    hash.algorithm != Algorithm::Argon2id.ident()
+        || Params::try_from(&hash).map_or(true, |p| {
+            (p.m_cost(), p.t_cost(), p.p_cost())
+                != (current.m_cost(), current.t_cost(), current.p_cost())
+        })
+}
+
+/// 密码长度不能少于`MIN_PASSWORD_LENGTH`，不能使用默认密码和手机号
+pub fn check_password_policy(password: &str, smartphone: &str) -> Result<(), Response> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        Err(Response::invalid_value(format!(
+            "密码长度不能少于{MIN_PASSWORD_LENGTH}位"
+        )))
+    } else if password == DEFAULT_PASSWORD {
+        Err(Response::invalid_value("不能使用默认密码"))
+    } else if password == smartphone {
+        Err(Response::invalid_value("不能使用手机号作为密码"))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_and_verify() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+        assert!(!needs_rehash(&hash));
+        assert!(verify_md5(DEFAULT_PASSWORD, &md5::compute("12345678").0));
+    }
+
+    #[test]
+    fn test_policy() {
+        assert!(check_password_policy("short", "").is_err());
+        assert!(check_password_policy(DEFAULT_PASSWORD, "").is_err());
+        assert!(check_password_policy("13800138000", "13800138000").is_err());
+        assert!(check_password_policy("a-good-password", "13800138000").is_ok());
+    }
+}
@@ -1,188 +1,511 @@
-//! 新的权限设置，还没有完全设计好
-//! 
-//! 
-use dashmap::DashMap;
+//! 权限树
+//!
+//! 每个角色的权限是一棵树，第一层为权限组，叶子为具体的操作，
+//! 中间可以有用于分类的节点。勾选节点时同时勾选所有子节点，
+//! 父节点在有子节点被勾选时视为勾选。只有从权限组开始整条路径都被勾选的叶子才有效。
+//!
+//! 数据库中保存的是`权限组 -> 操作 -> 数据范围`的扁平结构，加载时转换成权限树
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+
+use crate::{
+    perm::{action::*, PermissionGroupMap},
+    Response,
+};
+
+/// 数据范围选项，表示可以选择任意角色名称
+pub const ROLE_SCOPE: &str = "@role";
+
+/// 生成权限树，`(名称, 值, [可选的数据范围], 说明)`，可以用`=> { ... }`添加子节点
 #[macro_export]
-macro_rules! pstr {
-    (@1 $t:expr) => {
-        $t.to_string()
+macro_rules! gen_perm {
+    (@children) => {
+        Vec::new()
     };
-    (@op $t:expr) => {
-        $t.map(|v|
-            $crate::pstr!(@1 v)
-        )
+    (@children $($t:tt)+) => {
+        $crate::gen_perm!($($t)+)
     };
-    (@arr $t:expr) => {
-        $t.iter().map(|v| serde_json::json!(v)).collect()
+    ($( ($name:expr, $value:expr, [$($scope:expr),* $(,)?], $comment:expr) $(=> { $($children:tt)* })? ),* $(,)?) => {
+        vec![$(
+            $crate::libs::perm::Permission::new(
+                $name,
+                $value,
+                &[$($scope),*],
+                $comment,
+                $crate::gen_perm!(@children $($($children)*)?),
+            )
+        ),*]
     };
 }
 
-#[macro_export]
-macro_rules! gen_perm {
-    (@map ($ ($arg:expr, ) *)) => {
-        {
-            [$( ($arg.value.clone(), $arg)), +].into_iter().collect::<dashmap::DashMap<_, _>>()
-        }
-    };
-    // 入口
-    ( $(@root {
-        ($root_name:expr, $root_value:expr, $root_selected:expr, $root_comment:expr) =>
-        {
-            $(
-                #1 ( $name1:expr, $value1:expr, $data1:expr, $selected1:expr, $comment1:expr)
-            ),*
-            $(
-                #2 ($name2:expr, $value2:expr, $data2:expr, $selected2:expr, $comment2:expr) => {
-                    $( #3 ( $name21:expr, $value21:expr, $data21:expr, $selected21:expr, $comment21:expr)), +
-                }
-            ), *
-        }
-
-    }), +) => {
-        {
-            let empty: [i32; 0] = [];
-            $crate::gen_perm![
-                @map
-                ($(
-                    (
-
-                        $crate::gen_perm!(
-                            @perm
-                            $root_name, &empty, $root_value, $root_selected, 0, "",
-                            $crate::gen_perm![
-                                @map
-                                (
-                                    $(
-                                        $crate::gen_perm!(
-                                            @perm
-                                            $name1, $data1, $value1, $selected1, 1, $root_value,
-                                            dashmap::DashMap::new(), $comment1
-                                        ),
-                                    ) *
-                                    $(
-                                        $crate::gen_perm!(
-                                            @perm
-                                            $name2, $data2, $value2, $selected2, 1, $root_value,
-                                            $crate::gen_perm![
-                                                @map
-                                                ($(
-                                                    $crate::gen_perm!(
-                                                        @perm
-                                                        $name21, $data21, $value21, $selected21, 1, $value2,
-                                                        dashmap::DashMap::new(), $comment21
-                                                    ),
-                                                ) +)
-                                            ],
-                                            $comment2
-                                        ),
-                                    )*
-                                )
-                                
-                            ],
-                            $root_comment
-                        )
-                    ),
-                ) *)
-            ]
-        }
-    };
-    (@perm $name:expr, $data:expr, $value:expr, $selected:expr, $level:expr, $parent:expr, $children:expr, $comment:expr) => {
-        $crate::libs::perm::Permission {
-            name: $crate::pstr!(@1 $name),
-            value: $crate::pstr!(@1 $value),
-            selected: $selected,
-            data: $crate::pstr!(@arr $data),
-            level: $level,
-            children: $children,
-            parent: if $parent.is_empty() { None } else { Some( $crate::pstr!(@1 $parent) )},
-            comment: $crate::pstr!(@1 $comment)
-        }
-    }
+lazy_static::lazy_static! {
+    /// 所有权限都未勾选的权限树
+    pub static ref PERMISSION_TREE: RolePermission = RolePermission::template();
 }
 
-pub fn default_role_perms() -> DashMap<String, Permission> {
-    let _empty: [i32; 0] = [];
+/// 所有权限节点，新增权限时在这里添加
+pub fn default_role_perms() -> Vec<Permission> {
     gen_perm![
-        @root {
-            ("职务管理权限组", "role", 0, "管理职务的权限组") => {
-                #1 ("创建职务", "cr1", &_empty, 0, "需要指定可创建的职务"),
-                #1 ("删除职务", "dr1", &_empty, 0, "需要指定可删除的职务"),
-                #1 ("更改职务", "ur1", &_empty, 0, "需要指定可更改的职务"),
-                #1 ("职务调动", "cr2", &_empty, 0, "需要指定可调动的职务，功能相当于员工的升职或降级")
-            }
+        ("职务管理权限组", RoleGroup::NAME, [], "管理职务的权限组") => {
+            ("创建职务", RoleGroup::CREATE, [], "可以创建新的职务"),
+            ("更改职务", RoleGroup::UPDATE, ["all"], "默认只能更改本部门创建的职务，all为所有职务"),
+            ("删除职务", RoleGroup::DELETE, ["all"], "默认只能删除本部门创建的职务，all为所有职务"),
+            ("职务调动", RoleGroup::CHANGE_ROLE, ["all"], "相当于员工的升职或降级，默认只能调动本部门的员工，all为所有员工"),
         },
-        @root {
-            ("账号权限组", "account", 0, "管理用户账号的权限组") => {
-                #1 ("创建账号", "ca1", &_empty, 0, "需要指定可创建哪一类(职务)的员工账号和允许的部门范围"),
-                #1 ("删除账号", "da1", &_empty, 0, "需要指定可删除哪一类(职务)的员工账号和允许的部门范围")
-            }
+        ("账号权限组", AccountGroup::NAME, [], "管理用户账号的权限组") => {
+            ("创建账号", AccountGroup::CREATE, ["all_department", "all", ROLE_SCOPE], "需要指定可创建哪一类(职务)的员工账号和允许的部门范围"),
+            ("删除账号", AccountGroup::DELETE, ["all_department", "all", ROLE_SCOPE], "需要指定可删除哪一类(职务)的员工账号和允许的部门范围"),
         },
-        @root {
-            ("客户管理权限组", "customer", 0, "管理客户的权限组，不勾选无法使用客户模块") => {
-                #1 ("录入客户数据", "cc1", &_empty, 0, "不勾选无法添加客户"),
-                #1 ("删除客户数据", "dc1", &_empty, 0, "勾选后仅可删除自己的客户，不勾选无法删除客户"),
-                #1 ("修改客户数据", "uc1", &_empty, 0, "勾选后仅可修改自己的客户数据，不勾选无法修改客户数据"),
-                #1 ("查询客户数据", "qc1", &_empty, 0, "不勾选仅可查看自己和共享的客户数据，勾选后默认可查看本部门的客户数据，也可设置为可查看全公司的客户数据"),
-                #1 ("导出客户数据", "exc1", &_empty, 0, "勾选后可将客户数据导出成表格"),
-                #1 ("安排客户拜访", "aa1", &_empty, 0, "勾选后可给其他业务员安排客户拜访")
-            }
+        ("客户管理权限组", CustomerGroup::NAME, [], "管理客户的权限组") => {
+            ("使用客户模块", CustomerGroup::ACTIVATION, [], "不勾选无法使用客户模块"),
+            ("查询客户数据", CustomerGroup::QUERY, ["department", "all"], "不勾选仅可查看自己和共享的客户数据"),
+            ("录入客户数据", CustomerGroup::ENTER_CUSTOMER_DATA, [], "不勾选无法添加客户"),
+            ("修改客户数据", CustomerGroup::UPDATE_CUSTOMER_DATA, [], "仅可修改自己的客户数据"),
+            ("删除客户数据", CustomerGroup::DELETE_CUSTOMER_DATA, [], "仅可删除自己的客户"),
+            ("查询公海", CustomerGroup::QUERY_PUB_SEA, ["department", "all"], "department为本部门及下级部门的公海，all为所有部门"),
+            ("转移客户", CustomerGroup::TRANSFER_CUSTOMER, [], "可以将客户转交给其他业务员"),
+            ("导出客户数据", CustomerGroup::EXPORT_DATA, ["department", "all"], "可将客户数据导出成表格"),
+            ("释放客户", CustomerGroup::RELEASE_CUSTOMER, [], "可以将自己的客户放入公海"),
+            ("安排客户拜访", CustomerGroup::ADD_APPOINT, [], "可给其他业务员安排客户拜访"),
         },
-        @root {
-            ("公海权限组", "sea", 0, "管理公海的权限组，不勾选无法使用公海模块") => {
-                #1 ("释放客户", "scc1", &_empty, 0, "勾选后可释放客户到公司公海，不勾选仅可释放客户到部门公海"),
-                #1 ("领取公海客户", "sdc1", &_empty, 0, "勾选后可从公海中领取客户（需要勾选客户模块的权限组）")
-            }
+        ("库房权限组", StorehouseGroup::NAME, [], "管理库房的权限组") => {
+            ("使用库房模块", StorehouseGroup::ACTIVATION, [], "不勾选无法使用库房模块"),
+            ("产品管理", "product", [], "") => {
+                ("录入产品", StorehouseGroup::ADD_PRODUCT, [], "可录入产品信息"),
+                ("调整产品信息", StorehouseGroup::UPDATE_PRODUCT, [], "可调整产品信息（不包括库存）"),
+                ("删除产品", StorehouseGroup::DELETE_PRODUCT, [], "可删除产品"),
+                ("调整产品库存", StorehouseGroup::ADJUSTING_PRODUCT_INVENTORY, [], "可调整产品库存"),
+            },
+            ("仓库管理", "storehouse_manage", [], "") => {
+                ("添加仓库", StorehouseGroup::ADD_STOREHOUSE, [], "可添加产品仓库"),
+                ("更新仓库信息", StorehouseGroup::UPDATE_STOREHOUSE, [], "可修改仓库信息"),
+                ("删除仓库", StorehouseGroup::DELETE_STOREHOUSE, [], "可删除仓库"),
+                ("库存调拨", StorehouseGroup::TRANSFER_STOCK, [], "可创建、发出和接收库房之间的调拨单"),
+            },
         },
-        @root {
-            ("库房权限组", "storehouse", 0, "管理库房的权限组，不勾选无法使用库房模块") => {
-                #2 ("产品管理", "sp1", &_empty, 0, "") => {
-                    #3 ("录入产品", "sp1_1", &_empty, 0, "勾选后可录入产品信息"),
-                    #3 ("调整产品信息", "sp1_2", &_empty, 0, "勾选后可调整产品信息（不包括库存）"),
-                    #3 ("调整产品库存", "sp1_3", &_empty, 0, "勾选后可调整产品库存"),
-                    #3 ("删除产品", "sp1_4", &_empty, 0, "勾选后可删除产品")
-                },
-                #2 ("仓库管理", "sh1", &_empty, 0, "") => {
-                    #3 ("添加仓库", "sh1_1", &_empty, 0, "勾选后可添加产品仓库"),
-                    #3 ("更新仓库信息", "sh1_2", &_empty, 0, "勾选后可"),
-                    #3 ("删除仓库", "sh1_3", &_empty, 0, "勾选后可调整产品库存")
-                }
-            }
+        ("采购权限组", PurchaseGroup::NAME, [], "管理采购的权限组") => {
+            ("使用采购模块", PurchaseGroup::ACTIVATION, [], "不勾选无法使用采购模块"),
+            ("查询采购数据", PurchaseGroup::QUERY, [], "可查看采购单和供应商应付款"),
+            ("管理采购单", PurchaseGroup::MANAGE_ORDER, [], "可创建、修改和下达采购单"),
+            ("采购入库", PurchaseGroup::RECEIVE_GOODS, [], "可登记采购单的到货并计入库存"),
+            ("登记付款", PurchaseGroup::PAYMENT, [], "可登记向供应商的付款"),
+        },
+        ("财务权限组", FinanceGroup::NAME, [], "管理财务的权限组") => {
+            ("使用财务模块", FinanceGroup::ACTIVATION, [], "不勾选无法使用财务模块"),
+            ("查询财务数据", FinanceGroup::QUERY, [], ""),
+        },
+        ("其他权限组", OtherGroup::NAME, [], "零散的权限设置") => {
+            ("查看签到", OtherGroup::QUERY_SIGN_IN, ["all"], "默认只能查看本部门，all为所有部门"),
+            ("自定义字段", OtherGroup::CUSTOM_FIELD, [], "可以添加和修改自定义字段"),
+            ("下拉框选项", OtherGroup::DROP_DOWN_BOX, [], "可以添加和修改下拉框选项"),
+            ("公海规则", OtherGroup::SEA_RULE, ["all"], "可以修改客户放入公海的规则"),
+            ("员工数据", OtherGroup::COMPANY_STAFF_DATA, ["all"], "可以查看其他部门的员工"),
+            ("查询订单", OtherGroup::QUERY_ORDER, ["all"], "默认只能查询本部门的订单，all为所有部门"),
         },
-        @root {
-            ("其他权限组", "other", 0, "零散的权限设置") => {
-                #2 ("产品管理", "sp1", &_empty, 0, "") => {
-                    #3 ("录入产品", "sp1_1", &_empty, 0, "勾选后可录入产品信息"),
-                    #3 ("调整产品信息", "sp1_2", &_empty, 0, "勾选后可调整产品信息（不包括库存）"),
-                    #3 ("调整产品库存", "sp1_3", &_empty, 0, "勾选后可调整产品库存"),
-                    #3 ("删除产品", "sp1_4", &_empty, 0, "勾选后可删除产品")
-                },
-                #2 ("仓库管理", "sh1", &_empty, 0, "") => {
-                    #3 ("添加仓库", "sh1_1", &_empty, 0, "勾选后可添加产品仓库"),
-                    #3 ("更新仓库信息", "sh1_2", &_empty, 0, "勾选后可"),
-                    #3 ("删除仓库", "sh1_3", &_empty, 0, "勾选后可调整产品库存")
-                }
-            }
-        }
     ]
 }
 
-#[derive(Deserialize, Serialize)]
+/// 权限树的节点
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Permission {
     /// 显示的名称
     pub name: String,
     pub value: String,
-    pub data: Vec<Value>,
+    pub comment: String,
+    /// 可选的数据范围
+    pub scopes: Vec<String>,
+    /// 已选择的数据范围
+    pub data: Vec<String>,
+    /// 1 表示勾选
     pub selected: i32,
     pub level: i32,
     pub parent: Option<String>,
-    pub children: DashMap<String, Permission>,
-    pub comment: String,
+    pub children: Vec<Permission>,
 }
 
 impl Permission {
+    pub fn new(
+        name: &str,
+        value: &str,
+        scopes: &[&str],
+        comment: &str,
+        children: Vec<Permission>,
+    ) -> Self {
+        let mut perm = Self {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            comment: comment.to_owned(),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            data: Vec::new(),
+            selected: 0,
+            level: 0,
+            parent: None,
+            children,
+        };
+        perm.link(None, 0);
+        perm
+    }
+    fn link(&mut self, parent: Option<&str>, level: i32) {
+        self.parent = parent.map(|p| p.to_owned());
+        self.level = level;
+        for child in &mut self.children {
+            child.link(Some(&self.value), level + 1);
+        }
+    }
     pub fn is_selected(&self) -> bool {
         self.selected == 1
     }
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+    /// 勾选或取消节点及所有子节点
+    pub fn select(&mut self, selected: bool) {
+        self.selected = selected as i32;
+        if !selected {
+            self.data.clear();
+        }
+        for child in &mut self.children {
+            child.select(selected);
+        }
+    }
+    /// 勾选的父节点没有勾选任何子节点时视为勾选所有子节点，
+    /// 否则父节点是否勾选由子节点决定
+    fn normalize(&mut self) {
+        if self.is_leaf() {
+            return;
+        }
+        self.data.clear();
+        if self.is_selected() && !self.children.iter().any(|c| c.is_selected()) {
+            self.select(true);
+        }
+        for child in &mut self.children {
+            child.normalize();
+        }
+        self.selected = self.children.iter().any(|c| c.is_selected()) as i32;
+    }
+    fn find_mut(&mut self, value: &str) -> Option<&mut Permission> {
+        if self.is_leaf() {
+            return op::ternary!(self.value == value => Some(self), None);
+        }
+        self.children.iter_mut().find_map(|c| c.find_mut(value))
+    }
+    /// 已勾选的操作的数据范围，路径上有未勾选的节点时返回`None`
+    pub fn get(&self, action: &str) -> Option<&Vec<String>> {
+        if !self.is_selected() {
+            return None;
+        }
+        if self.is_leaf() {
+            return op::ternary!(self.value == action => Some(&self.data), None);
+        }
+        self.children.iter().find_map(|c| c.get(action))
+    }
+    fn leaves(&self) -> Vec<&Permission> {
+        if self.is_leaf() {
+            vec![self]
+        } else {
+            self.children.iter().flat_map(|c| c.leaves()).collect()
+        }
+    }
+    fn leaves_mut(&mut self) -> Vec<&mut Permission> {
+        if self.is_leaf() {
+            vec![self]
+        } else {
+            self.children
+                .iter_mut()
+                .flat_map(|c| c.leaves_mut())
+                .collect()
+        }
+    }
+    /// 按`value`复制`other`中的勾选状态和数据范围
+    fn copy_selection(&mut self, other: &Permission) {
+        self.selected = other.selected;
+        self.data = other.data.clone();
+        for child in &mut self.children {
+            match other.children.iter().find(|c| c.value == child.value) {
+                Some(o) => child.copy_selection(o),
+                None => child.select(false),
+            }
+        }
+    }
+    fn check_scopes(&self, roles: &[String]) -> Result<(), Response> {
+        for leaf in self.leaves() {
+            for scope in &leaf.data {
+                let allowed = leaf.scopes.contains(scope)
+                    || (leaf.scopes.iter().any(|s| s == ROLE_SCOPE) && roles.contains(scope));
+                if !allowed {
+                    return Err(Response::invalid_value(format!(
+                        "`{}`不能选择数据范围`{scope}`",
+                        leaf.name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 一个角色的权限树
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+#[serde(transparent)]
+pub struct RolePermission {
+    groups: Vec<Permission>,
+}
+
+impl RolePermission {
+    fn template() -> Self {
+        Self {
+            groups: default_role_perms(),
+        }
+    }
+    /// 已勾选的权限组
+    pub fn get(&self, group: &str) -> Option<&Permission> {
+        self.groups
+            .iter()
+            .find(|g| g.value == group && g.is_selected())
+    }
+    pub fn groups(&self) -> &[Permission] {
+        &self.groups
+    }
+    fn normalize(&mut self) {
+        self.groups.iter_mut().for_each(Permission::normalize);
+    }
+    /// 从扁平结构转换，不存在的权限组和操作被忽略
+    pub fn from_group_map(map: &PermissionGroupMap) -> Self {
+        let mut tree = PERMISSION_TREE.clone();
+        for group in &mut tree.groups {
+            let Some(actions) = map.get(&group.value) else {
+                continue;
+            };
+            for (action, scopes) in actions {
+                if let Some(leaf) = group.find_mut(action) {
+                    leaf.selected = 1;
+                    leaf.data = scopes.clone();
+                }
+            }
+        }
+        tree.normalize();
+        tree
+    }
+    /// 从扁平结构转换，存在未知的权限组、操作或数据范围时返回错误，
+    /// `roles`为所有角色名称
+    pub fn from_group_map_checked(
+        map: &PermissionGroupMap,
+        roles: &[String],
+    ) -> Result<Self, Response> {
+        for (group, actions) in map {
+            let node = op::some!(PERMISSION_TREE.groups.iter().find(|g| g.value.eq(group));
+                ret Err(Response::invalid_value(format!("权限组`{group}`不存在"))));
+            let leaves = node.leaves();
+            for action in actions.keys() {
+                if !leaves.iter().any(|l| l.value.eq(action)) {
+                    return Err(Response::invalid_value(format!(
+                        "权限组`{group}`中不存在操作`{action}`"
+                    )));
+                }
+            }
+        }
+        let tree = Self::from_group_map(map);
+        tree.check_scopes(roles)?;
+        Ok(tree)
+    }
+    /// 前端提交的权限树，只使用其中的勾选状态和数据范围
+    pub fn from_selection(selection: &RolePermission, roles: &[String]) -> Result<Self, Response> {
+        let mut tree = PERMISSION_TREE.clone();
+        for group in &mut tree.groups {
+            match selection.groups.iter().find(|g| g.value == group.value) {
+                Some(s) => group.copy_selection(s),
+                None => group.select(false),
+            }
+        }
+        tree.normalize();
+        tree.check_scopes(roles)?;
+        Ok(tree)
+    }
+    fn check_scopes(&self, roles: &[String]) -> Result<(), Response> {
+        self.groups.iter().try_for_each(|g| g.check_scopes(roles))
+    }
+    /// 转换成扁平结构，只包含有效的操作
+    pub fn to_group_map(&self) -> PermissionGroupMap {
+        self.groups
+            .iter()
+            .filter(|g| g.is_selected())
+            .map(|g| {
+                let actions: HashMap<String, Vec<String>> = g
+                    .leaves()
+                    .into_iter()
+                    .filter_map(|l| g.get(&l.value).map(|d| (l.value.clone(), d.clone())))
+                    .collect();
+                (g.value.clone(), actions)
+            })
+            .collect()
+    }
+    /// 相对于`current`新增的操作和数据范围中`granter`没有的第一个，
+    /// `granter`拥有某个操作的`all`数据范围时可以授予该操作的任意数据范围
+    pub fn ungranted(&self, current: &RolePermission, granter: &RolePermission) -> Option<String> {
+        let (current, held) = (current.to_group_map(), granter.to_group_map());
+        for (group, actions) in self.to_group_map() {
+            for (action, scopes) in actions {
+                let old = current.get(&group).and_then(|a| a.get(&action));
+                let Some(own) = held.get(&group).and_then(|a| a.get(&action)) else {
+                    if old.is_none() {
+                        return Some(format!("`{group}.{action}`"));
+                    }
+                    continue;
+                };
+                if own.iter().any(|s| s == "all") {
+                    continue;
+                }
+                let added = scopes
+                    .iter()
+                    .find(|s| !own.contains(s) && !old.is_some_and(|o| o.contains(s)));
+                if let Some(scope) = added {
+                    return Some(format!("`{group}.{action}`的数据范围`{scope}`"));
+                }
+            }
+        }
+        None
+    }
+    /// 角色改名或删除时修改数据范围中的角色名称
+    pub fn rename_scope(&mut self, old: &str, new: Option<&str>) {
+        for leaf in self.groups.iter_mut().flat_map(|g| g.leaves_mut()) {
+            match new {
+                Some(new) => leaf
+                    .data
+                    .iter_mut()
+                    .filter(|s| s.as_str() == old)
+                    .for_each(|s| *s = new.to_owned()),
+                None => leaf.data.retain(|s| s != old),
+            }
+        }
+    }
+    /// 将`ROLE_SCOPE`展开为所有角色名称，用于前端显示
+    pub fn expand_role_scopes(&mut self, roles: &[String]) {
+        for leaf in self.groups.iter_mut().flat_map(|g| g.leaves_mut()) {
+            if let Some(i) = leaf.scopes.iter().position(|s| s == ROLE_SCOPE) {
+                leaf.scopes.splice(i..=i, roles.iter().cloned());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perm::PERMISSION_GROUPS;
+    use serde_json::json;
+
+    fn map(value: serde_json::Value) -> PermissionGroupMap {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_tree_matches_groups() {
+        assert_eq!(PERMISSION_TREE.groups().len(), PERMISSION_GROUPS.len());
+        for group in PERMISSION_TREE.groups() {
+            let mut leaves: Vec<&str> = group.leaves().iter().map(|l| l.value.as_str()).collect();
+            let mut actions = PERMISSION_GROUPS[group.value.as_str()].clone();
+            leaves.sort();
+            actions.sort();
+            assert_eq!(leaves, actions, "{}", group.value);
+        }
+    }
+
+    #[test]
+    fn test_group_map_round_trip() {
+        let perms = map(json!({
+            "customer": {"query": ["all"], "activation": []},
+            "storehouse": {"add_product": []},
+            "unknown": {"x": []}
+        }));
+        let tree = RolePermission::from_group_map(&perms);
+        assert_eq!(tree.get("customer").unwrap().get("query").unwrap(), &["all"]);
+        assert!(tree.get("customer").unwrap().get("export_data").is_none());
+        assert!(tree.get("role").is_none());
+        let storehouse = tree.get("storehouse").unwrap();
+        assert!(storehouse.get("add_product").is_some());
+        assert!(storehouse.get("delete_product").is_none());
+        let mut expected = perms.clone();
+        expected.remove("unknown");
+        assert_eq!(tree.to_group_map(), expected);
+    }
+
+    #[test]
+    fn test_selection() {
+        let mut selection = PERMISSION_TREE.clone();
+        // 勾选分类节点而不勾选子节点，视为勾选所有子节点
+        let storehouse = &mut selection.groups[3];
+        storehouse.selected = 1;
+        storehouse.children[1].selected = 1;
+        let tree = RolePermission::from_selection(&selection, &[]).unwrap();
+        let storehouse = tree.get("storehouse").unwrap();
+        assert!(storehouse.get("add_product").is_some());
+        assert!(storehouse.get("delete_product").is_some());
+        assert!(storehouse.get("add_storehouse").is_none());
+
+        // 父节点未勾选时子节点无效
+        let mut tree = tree;
+        tree.groups[3].children[1].selected = 0;
+        assert!(tree.get("storehouse").unwrap().get("add_product").is_none());
+    }
+
+    #[test]
+    fn test_rename_scope() {
+        let mut tree = RolePermission::from_group_map(&map(json!({
+            "account": {"create": ["销售员", "主管"], "delete": ["销售员"]},
+            "customer": {"query": ["all"]}
+        })));
+        tree.rename_scope("销售员", Some("业务员"));
+        let perms = tree.to_group_map();
+        assert_eq!(perms["account"]["create"], ["业务员", "主管"]);
+        assert_eq!(perms["account"]["delete"], ["业务员"]);
+        tree.rename_scope("业务员", None);
+        let perms = tree.to_group_map();
+        assert_eq!(perms["account"]["create"], ["主管"]);
+        assert!(perms["account"]["delete"].is_empty());
+        assert_eq!(perms["customer"]["query"], ["all"]);
+    }
+
+    #[test]
+    fn test_checked_scopes() {
+        let roles = vec!["销售员".to_owned()];
+        let ok = map(json!({"account": {"create": ["销售员", "all"]}}));
+        assert!(RolePermission::from_group_map_checked(&ok, &roles).is_ok());
+        let bad_scope = map(json!({"customer": {"query": ["销售员"]}}));
+        assert!(RolePermission::from_group_map_checked(&bad_scope, &roles).is_err());
+        let bad_action = map(json!({"customer": {"fly": []}}));
+        assert!(RolePermission::from_group_map_checked(&bad_action, &roles).is_err());
+    }
+
+    #[test]
+    fn test_ungranted() {
+        let granter = RolePermission::from_group_map(&map(json!({
+            "customer": {"query": ["department"], "export_data": ["all"]},
+            "storehouse": {"add_product": []}
+        })));
+        let current = RolePermission::from_group_map(&map(json!({
+            "customer": {"query": ["department"]},
+            "role": {"update": ["department"]}
+        })));
+        // 已有的权限可以保留，自己拥有的操作和数据范围可以授予
+        let ok = RolePermission::from_group_map(&map(json!({
+            "customer": {"query": ["department"], "export_data": ["department", "all"]},
+            "storehouse": {"add_product": []},
+            "role": {"update": ["department"]}
+        })));
+        assert_eq!(ok.ungranted(&current, &granter), None);
+        let wider = RolePermission::from_group_map(&map(json!({
+            "customer": {"query": ["department", "all"]}
+        })));
+        assert_eq!(
+            wider.ungranted(&current, &granter).unwrap(),
+            "`customer.query`的数据范围`all`"
+        );
+        let action = RolePermission::from_group_map(&map(json!({
+            "role": {"update": ["department"], "create": []}
+        })));
+        assert_eq!(action.ungranted(&current, &granter).unwrap(), "`role.create`");
+        // 没有任何权限时只能保留或删除已有的权限
+        assert_eq!(current.ungranted(&current, &RolePermission::default()), None);
+    }
 }
@@ -0,0 +1,144 @@
+//! 后台定时任务
+//!
+//! 缓存每10分钟清空一次；每日任务在`config.json`中`schedule`设置的时间之后执行，
+//! 每天只执行一次，执行前在`job_claim`表中获取当天的执行权，多台服务器只有一台执行，
+//! 每次执行的结果记录在`job_history`表中
+use std::{future::Future, pin::Pin, time::Duration};
+
+use mysql::{params, prelude::Queryable, PooledConn};
+
+use crate::{
+    database::__get_conn,
+    libs::{cache::clear_cache, gen_id, TimeFormat, TIME},
+    log,
+    pages::{func::customer::sea::auto_release_customers, purge_login_failures},
+    perm::store,
+    token::purge_refresh_tokens,
+    Response, CONFIG,
+};
+
+type JobFuture<'a> = Pin<Box<dyn Future<Output = Result<String, Response>> + 'a>>;
+
+/// 每日任务，执行成功时返回执行摘要
+pub struct Job {
+    pub name: &'static str,
+    run: for<'a> fn(&'a mut PooledConn) -> JobFuture<'a>,
+}
+
+pub static DAILY_JOBS: &[Job] = &[
+    Job {
+        name: "release_stale_customers",
+        run: release_stale_customers,
+    },
+    Job {
+        name: "purge_refresh_tokens",
+        run: purge_expired_refresh_tokens,
+    },
+    Job {
+        name: "purge_login_failures",
+        run: purge_expired_login_failures,
+    },
+];
+
+fn release_stale_customers(conn: &mut PooledConn) -> JobFuture<'_> {
+    Box::pin(async move {
+        let count = auto_release_customers(conn).await?;
+        Ok(format!("将{count}个超期客户放入公海"))
+    })
+}
+
+fn purge_expired_refresh_tokens(conn: &mut PooledConn) -> JobFuture<'_> {
+    Box::pin(async move {
+        let count = purge_refresh_tokens(conn)?;
+        Ok(format!("删除{count}个过期的refresh token"))
+    })
+}
+
+fn purge_expired_login_failures(conn: &mut PooledConn) -> JobFuture<'_> {
+    Box::pin(async move {
+        let count = purge_login_failures(conn)?;
+        Ok(format!("删除{count}条过期的登录失败记录"))
+    })
+}
+
+const CLEAR_CACHE_INTERVAL: u64 = 600;
+const TICK: u64 = 60;
+
+/// 在新的线程中启动定时任务
+pub fn start() {
+    std::thread::spawn(|| {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut interval = tokio::time::interval(Duration::from_secs(TICK));
+            let mut elapsed = 0;
+            loop {
+                interval.tick().await;
+                if elapsed >= CLEAR_CACHE_INTERVAL {
+                    clear_cache();
+                    elapsed = 0;
+                }
+                elapsed += TICK;
+                if let Err(e) = sync_role_map().await {
+                    log!("同步角色权限失败：{:?}", e);
+                }
+                if let Err(e) = run_daily_jobs().await {
+                    log!("执行每日任务失败：{:?}", e);
+                }
+            }
+        })
+    });
+}
+
+async fn sync_role_map() -> Result<(), Response> {
+    let mut conn = __get_conn()?;
+    store::sync_role_map(&mut conn).await
+}
+
+async fn run_daily_jobs() -> Result<(), Response> {
+    let now = TIME::now()?;
+    let schedule = CONFIG.schedule();
+    if (now.hour(), now.minute()) < (schedule.hour(), schedule.minute()) {
+        return Ok(());
+    }
+    let mut conn = __get_conn()?;
+    let today = now.format(TimeFormat::YYYYMMDD);
+    for job in DAILY_JOBS {
+        if claim_job(&mut conn, job, &today, &now)? {
+            run_job(&mut conn, job).await?;
+        }
+    }
+    Ok(())
+}
+
+/// 获取任务当天的执行权，已被本服务器或其他服务器获取时返回false
+fn claim_job(conn: &mut PooledConn, job: &Job, today: &str, now: &TIME) -> Result<bool, Response> {
+    conn.exec_drop(
+        "INSERT IGNORE INTO job_claim (name, run_date, claim_time) VALUES (?, ?, ?)",
+        (job.name, today, now.format(TimeFormat::YYYYMMDD_HHMMSS)),
+    )?;
+    Ok(conn.affected_rows() == 1)
+}
+
+/// 执行任务并记录结果，任务本身失败时同样记录
+pub async fn run_job(conn: &mut PooledConn, job: &Job) -> Result<(), Response> {
+    let start = TIME::now()?;
+    log!("开始执行每日任务 {}", job.name);
+    let (success, summary) = match (job.run)(conn).await {
+        Ok(summary) => (1, summary),
+        Err(e) => (0, format!("{:?}", e)),
+    };
+    let end = TIME::now()?;
+    log!("每日任务 {} 执行结束：{}", job.name, summary);
+    conn.exec_drop(
+        "INSERT INTO job_history (id, name, start_time, end_time, success, summary)
+        VALUES (:id, :name, :start_time, :end_time, :success, :summary)",
+        params! {
+            "id" => gen_id(&start, job.name),
+            "name" => job.name,
+            "start_time" => start.format(TimeFormat::YYYYMMDD_HHMMSS),
+            "end_time" => end.format(TimeFormat::YYYYMMDD_HHMMSS),
+            "success" => success,
+            "summary" => summary
+        },
+    )?;
+    Ok(())
+}
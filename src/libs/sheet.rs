@@ -0,0 +1,148 @@
+//! 表格文件(xlsx, csv)的读写
+use std::io::Cursor;
+
+use calamine::{open_workbook_from_rs, Data, Reader, Xlsx};
+use chrono::Timelike;
+use rust_xlsxwriter::{Format, Workbook};
+
+use crate::Response;
+
+/// 表格的第一行为表头，其余为数据，所有单元格都转换成字符串
+#[derive(Debug, Default)]
+pub struct Sheet {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Sheet {
+    /// 根据文件后缀读取xlsx或csv文件
+    pub fn read(filename: &str, bytes: &[u8]) -> Result<Self, Response> {
+        let filename = filename.to_lowercase();
+        let mut rows = if filename.ends_with(".xlsx") {
+            read_xlsx(bytes)?
+        } else if filename.ends_with(".csv") {
+            read_csv(bytes)?
+        } else {
+            return Err(Response::invalid_format("仅支持xlsx和csv文件"));
+        };
+        if rows.is_empty() {
+            return Ok(Self::default());
+        }
+        let headers = rows
+            .remove(0)
+            .into_iter()
+            .map(|h| h.trim_start_matches('\u{feff}').trim().to_owned())
+            .collect();
+        rows.retain(|r| r.iter().any(|c| !c.trim().is_empty()));
+        Ok(Self { headers, rows })
+    }
+
+    /// 带BOM的UTF-8 csv，Excel打开时不会乱码。单元格会经过[`escape_formula`]，避免被当作公式执行
+    pub fn to_csv(&self) -> Result<Vec<u8>, Response> {
+        let mut writer = csv::Writer::from_writer("\u{feff}".as_bytes().to_vec());
+        writer
+            .write_record(self.headers.iter().map(|c| escape_formula(c)))
+            .map_err(Response::internal_server_error)?;
+        for row in &self.rows {
+            writer
+                .write_record(row.iter().map(|c| escape_formula(c)))
+                .map_err(Response::internal_server_error)?;
+        }
+        writer
+            .into_inner()
+            .map_err(|e| Response::internal_server_error(e.error()))
+    }
+
+    pub fn to_xlsx(&self) -> Result<Vec<u8>, Response> {
+        let mut workbook = Workbook::new();
+        let worksheet = workbook.add_worksheet();
+        let bold = Format::new().set_bold();
+        for (col, header) in self.headers.iter().enumerate() {
+            worksheet
+                .write_string_with_format(0, col as u16, header, &bold)
+                .map_err(Response::internal_server_error)?;
+        }
+        for (row, cells) in self.rows.iter().enumerate() {
+            for (col, cell) in cells.iter().enumerate() {
+                worksheet
+                    .write_string(row as u32 + 1, col as u16, cell)
+                    .map_err(Response::internal_server_error)?;
+            }
+        }
+        workbook
+            .save_to_buffer()
+            .map_err(Response::internal_server_error)
+    }
+}
+
+/// 以`=`、`+`、`-`、`@`、制表符或回车开头的单元格在Excel中会被当作公式，前面加上`'`
+fn escape_formula(cell: &str) -> String {
+    if cell.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{cell}")
+    } else {
+        cell.to_owned()
+    }
+}
+
+fn read_xlsx(bytes: &[u8]) -> Result<Vec<Vec<String>>, Response> {
+    let mut workbook: Xlsx<_> = open_workbook_from_rs(Cursor::new(bytes))
+        .map_err(|e| Response::invalid_format(format!("xlsx文件格式错误：{e}")))?;
+    let range = op::some!(workbook.worksheet_range_at(0); ret Ok(Vec::new()))
+        .map_err(|e| Response::invalid_format(format!("xlsx文件格式错误：{e}")))?;
+    Ok(range
+        .rows()
+        .map(|row| row.iter().map(cell_to_string).collect())
+        .collect())
+}
+
+fn cell_to_string(cell: &Data) -> String {
+    match cell {
+        // 手机号等整数在xlsx中通常被存为浮点数
+        Data::Float(f) if f.fract() == 0.0 && f.abs() < 1e15 => format!("{}", *f as i64),
+        Data::DateTime(d) => match d.as_datetime() {
+            Some(t) if t.num_seconds_from_midnight() == 0 => t.format("%Y-%m-%d").to_string(),
+            Some(t) => t.format("%Y-%m-%d %H:%M:%S").to_string(),
+            None => d.to_string(),
+        },
+        _ => cell.to_string(),
+    }
+}
+
+fn read_csv(bytes: &[u8]) -> Result<Vec<Vec<String>>, Response> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(bytes);
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| {
+            Response::invalid_format(format!("csv文件格式错误，请使用UTF-8编码：{e}"))
+        })?;
+        rows.push(record.iter().map(|s| s.to_owned()).collect());
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_escapes_formulas() {
+        let sheet = Sheet {
+            headers: vec!["名称".to_owned(), "备注".to_owned()],
+            rows: vec![
+                vec!["=HYPERLINK(\"http://x\")".to_owned(), "+1".to_owned()],
+                vec!["-2".to_owned(), "@SUM(A1)".to_owned()],
+                vec!["\tcmd".to_owned(), "\rcmd".to_owned()],
+                vec!["张三".to_owned(), "a=b".to_owned()],
+            ],
+        };
+        let bytes = sheet.to_csv().unwrap();
+        let rows = read_csv(&bytes).unwrap();
+        assert_eq!(rows[1], ["'=HYPERLINK(\"http://x\")", "'+1"]);
+        assert_eq!(rows[2], ["'-2", "'@SUM(A1)"]);
+        assert_eq!(rows[3], ["'\tcmd", "'\rcmd"]);
+        assert_eq!(rows[4], ["张三", "a=b"]);
+    }
+}
@@ -1,21 +1,34 @@
-use std::{fs::create_dir, time::Duration};
+use std::{fs::create_dir, net::SocketAddr};
 
 use axum::{extract::DefaultBodyLimit, http::Method, Router};
 use crm_rust::{
-    database::__get_conn,
-    libs::cache::clear_cache,
+    database::{__get_conn, migrate},
+    libs::scheduler,
     pages::{DROP_DOWN_BOX, STATIC_CUSTOM_BOX_OPTIONS, STATIC_CUSTOM_FIELDS},
-    perm::roles::ROLE_TABLES,
-    read_data, CONFIG,
+    perm::{roles::reload_roles, store},
+    read_data, token, CONFIG,
 };
 use tower_http::cors::{Any, CorsLayer};
 #[tokio::main]
 async fn main() {
     _create_all_dir().unwrap();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|s| s.eq("migrate")) {
+        migrate_command(&args[1..]);
+        return;
+    }
     read_data();
+    token::init_keys();
 
-    crm_rust::database::create_table().unwrap();
+    let mut conn = __get_conn().expect("数据库连接失败");
+    if let Err(e) = migrate::startup(&mut conn) {
+        panic!("数据库迁移失败，拒绝启动：{e}");
+    }
     unsafe { init_static() };
+    if let Err(e) = store::init_role_map(&mut conn).await {
+        panic!("加载角色权限失败：{e:?}");
+    }
+    drop(conn);
     let router = Router::new()
         .merge(crm_rust::pages::pages_router())
         .merge(crm_rust::perm::perm_router())
@@ -26,29 +39,56 @@ async fn main() {
                 .allow_headers(Any),
         )
         .layer(DefaultBodyLimit::max(20 * 1024 * 1024));
-    std::thread::spawn(|| { // 定时任务，每过10分钟清空所有缓存
-        tokio::runtime::Runtime::new().unwrap().block_on(async {
-            let mut interval = tokio::time::interval(Duration::from_secs(600));
-            loop {
-                interval.tick().await;
-                clear_cache();
-            }
-        })
-    });
+    scheduler::start();
     axum::serve(
         tokio::net::TcpListener::bind(format!("0.0.0.0:{}", CONFIG.port()))
             .await
             .unwrap(),
-        router,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
     )
     .await
     .unwrap()
 }
+/// crm-rust migrate status|up [version]|down [steps]
+fn migrate_command(args: &[String]) {
+    let mut conn = __get_conn().expect("数据库连接失败");
+    let arg = args.get(1).map(|s| s.parse().expect("参数必须为数字"));
+    let result = match args.first().map(|s| s.as_str()) {
+        Some("status") | None => migrate::status(&mut conn).map(|list| {
+            for (m, time) in list {
+                println!(
+                    "{:>4}  {:<30} {}",
+                    m.version,
+                    m.name,
+                    time.unwrap_or_else(|| "未执行".to_owned())
+                );
+            }
+        }),
+        Some("up") => migrate::up(&mut conn, arg).map(|done| {
+            println!("已执行{}个版本", done.len());
+        }),
+        Some("down") => {
+            migrate::down(&mut conn, arg.unwrap_or(1) as usize).map(|done| {
+                for m in &done {
+                    println!("已回滚 {}", m.name);
+                }
+            })
+        }
+        Some(other) => {
+            eprintln!("未知的命令`{other}`，可用命令：status, up [version], down [steps]");
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
 /// 初始化静态数据
 unsafe fn init_static() {
     let mut conn = __get_conn().expect("初始化失败");
 
-    ROLE_TABLES.init(&mut conn);
+    reload_roles(&mut conn).expect("初始化角色表时查询失败");
     STATIC_CUSTOM_FIELDS
         .init(&mut conn, "custom_fields")
         .expect("err code: 1");
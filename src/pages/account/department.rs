@@ -0,0 +1,427 @@
+//! 部门
+//!
+//! 部门保存在`department`表中，可以有上级部门和负责人，下拉框中的`department`选项与该表同步。
+//! 员工、角色和公海保存的是部门名称，改名时一起修改。
+//! 权限中的本部门包括所有下级部门
+use std::collections::HashMap;
+
+use axum::{extract::Path, Json};
+use mysql::{prelude::Queryable, PooledConn};
+use mysql_common::prelude::FromRow;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::get_user;
+use crate::{
+    commit_or_rollback,
+    database::get_db,
+    libs::{cache::USER_CACHE, dser::deser_double_option, TimeFormat, TIME},
+    log,
+    pages::reload_drop_down_box,
+    perm::auth::{AuthUser, OtherPerm, Scope},
+    Response, ResponseResult,
+};
+
+/// 最高部门，不能修改或删除
+pub const ROOT_DEPARTMENT: &str = "总经办";
+
+/// 部门的上下级关系
+#[derive(Debug, Default)]
+pub struct DepartmentTree {
+    parents: HashMap<String, Option<String>>,
+}
+
+impl DepartmentTree {
+    pub fn load(conn: &mut PooledConn) -> Result<Self, Response> {
+        let rows: Vec<(String, Option<String>)> =
+            conn.query("SELECT name, parent FROM department")?;
+        Ok(Self {
+            parents: rows.into_iter().collect(),
+        })
+    }
+    /// `department`是否为`ancestor`或其下级部门
+    pub fn contains(&self, ancestor: &str, department: &str) -> bool {
+        let mut current = Some(department);
+        // 数据有环时最多向上查找所有部门的个数
+        for _ in 0..=self.parents.len() {
+            match current {
+                Some(d) if d == ancestor => return true,
+                Some(d) => current = self.parents.get(d).and_then(|p| p.as_deref()),
+                None => return false,
+            }
+        }
+        false
+    }
+    /// `name`及其所有下级部门
+    pub fn descendants(&self, name: &str) -> Vec<String> {
+        let mut list = vec![name.to_owned()];
+        list.extend(
+            self.parents
+                .keys()
+                .filter(|d| d.as_str() != name && self.contains(name, d))
+                .cloned(),
+        );
+        list
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct DepartmentData {
+    name: String,
+    parent: Option<String>,
+    head: Option<String>,
+    head_name: Option<String>,
+    create_time: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DepartmentNode {
+    #[serde(flatten)]
+    data: DepartmentData,
+    children: Vec<DepartmentNode>,
+}
+
+fn query_department_data(conn: &mut PooledConn) -> Result<Vec<DepartmentData>, Response> {
+    Ok(conn.query(
+        "SELECT d.name, d.parent, d.head, u.name as head_name, d.create_time
+        FROM department d LEFT JOIN user u ON u.id = d.head
+        ORDER BY d.create_time",
+    )?)
+}
+
+fn build_tree(
+    parent: Option<&str>,
+    children: &mut HashMap<Option<String>, Vec<DepartmentData>>,
+) -> Vec<DepartmentNode> {
+    let list = children
+        .remove(&parent.map(str::to_owned))
+        .unwrap_or_default();
+    list.into_iter()
+        .map(|data| {
+            let children = build_tree(Some(&data.name), children);
+            DepartmentNode { data, children }
+        })
+        .collect()
+}
+
+/// 所有部门，按创建时间排序
+pub async fn query_departments(_user: AuthUser) -> ResponseResult {
+    let mut conn = get_db().await?;
+    Ok(Response::ok(json!(query_department_data(&mut conn)?)))
+}
+
+/// 部门树，上级部门不存在的部门作为顶级部门
+pub async fn query_department_tree(_user: AuthUser) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let list = query_department_data(&mut conn)?;
+    let names: Vec<String> = list.iter().map(|d| d.name.clone()).collect();
+    let mut children: HashMap<Option<String>, Vec<DepartmentData>> = HashMap::new();
+    for mut d in list {
+        if d.parent.as_ref().is_some_and(|p| !names.contains(p)) {
+            d.parent = None;
+        }
+        children.entry(d.parent.clone()).or_default().push(d);
+    }
+    Ok(Response::ok(json!(build_tree(None, &mut children))))
+}
+
+#[derive(Deserialize)]
+struct DepartmentParams {
+    name: String,
+    /// 修改时的新名称，为空表示不改名
+    #[serde(default)]
+    new_name: String,
+    /// 修改时没有该字段表示不修改，`null`表示清空
+    #[serde(default, deserialize_with = "deser_double_option")]
+    parent: Option<Option<String>>,
+    #[serde(default, deserialize_with = "deser_double_option")]
+    head: Option<Option<String>>,
+}
+
+impl DepartmentParams {
+    fn parent(&self) -> Option<&str> {
+        self.parent.as_ref().and_then(|p| p.as_deref())
+    }
+    fn head(&self) -> Option<&str> {
+        self.head.as_ref().and_then(|h| h.as_deref())
+    }
+}
+
+fn check_department_name(conn: &mut PooledConn, name: &str) -> Result<(), Response> {
+    if name.is_empty() || name.chars().count() > 30 {
+        return Err(Response::invalid_value(
+            "部门名称不能为空且不能超过30个字符",
+        ));
+    }
+    let exist: Option<i32> =
+        conn.exec_first("SELECT 1 FROM department WHERE name = ? LIMIT 1", (name,))?;
+    if exist.is_some() {
+        return Err(Response::already_exist("部门已存在"));
+    }
+    Ok(())
+}
+
+/// 检查上级部门存在且不是自己或自己的下级部门
+fn check_parent(conn: &mut PooledConn, name: &str, parent: Option<&str>) -> Result<(), Response> {
+    let Some(parent) = parent else {
+        return Ok(());
+    };
+    let tree = DepartmentTree::load(conn)?;
+    if !tree.parents.contains_key(parent) {
+        return Err(Response::not_exist(format!("上级部门`{parent}`不存在")));
+    }
+    if tree.contains(name, parent) {
+        return Err(Response::invalid_value(
+            "上级部门不能是自己或自己的下级部门",
+        ));
+    }
+    Ok(())
+}
+
+async fn check_head(conn: &mut PooledConn, head: Option<&str>) -> Result<(), Response> {
+    if let Some(head) = head {
+        get_user(head, conn).await?;
+    }
+    Ok(())
+}
+
+/// 添加部门，已存在时忽略
+pub fn __insert_department(
+    conn: &mut PooledConn,
+    name: &str,
+    parent: Option<&str>,
+    head: Option<&str>,
+    time: &TIME,
+) -> Result<(), Response> {
+    conn.exec_drop(
+        "INSERT IGNORE INTO department (name, parent, head, create_time) VALUES (?, ?, ?, ?)",
+        (name, parent, head, time.format(TimeFormat::YYYYMMDD_HHMMSS)),
+    )?;
+    Ok(())
+}
+
+/// 部门改名，修改所有保存了部门名称的数据，不包括下拉框
+pub fn __rename_department(conn: &mut PooledConn, old: &str, new: &str) -> Result<(), Response> {
+    conn.exec_drop("UPDATE department SET name = ? WHERE name = ?", (new, old))?;
+    for stmt in [
+        "UPDATE department SET parent = ? WHERE parent = ?",
+        "UPDATE user SET department = ? WHERE department = ?",
+        "UPDATE roles SET department = ? WHERE department = ?",
+        "UPDATE customer_sea SET department = ? WHERE department = ?",
+    ] {
+        conn.exec_drop(stmt, (new, old))?;
+    }
+    Ok(())
+}
+
+/// 把部门合并到`into`，下级部门、员工、角色和公海客户都归到`into`，不包括下拉框
+pub fn __merge_department(conn: &mut PooledConn, from: &str, into: &str) -> Result<(), Response> {
+    // `into`是`from`的下级部门时，先把`into`挂到`from`的上级部门下，避免成环
+    let parent: Option<Option<String>> = conn.exec_first(
+        "SELECT parent FROM department WHERE name = ? LIMIT 1",
+        (from,),
+    )?;
+    conn.exec_drop(
+        "UPDATE department SET parent = ? WHERE name = ? AND parent = ? LIMIT 1",
+        (parent.flatten(), into, from),
+    )?;
+    for stmt in [
+        "UPDATE department SET parent = ? WHERE parent = ?",
+        "UPDATE user SET department = ? WHERE department = ?",
+        "UPDATE roles SET department = ? WHERE department = ?",
+        "UPDATE customer_sea SET department = ? WHERE department = ?",
+    ] {
+        conn.exec_drop(stmt, (into, from))?;
+    }
+    conn.exec_drop("DELETE FROM department WHERE name = ? LIMIT 1", (from,))?;
+    Ok(())
+}
+
+async fn ver_department_perm(user: &AuthUser, action: &str) -> Result<(), Response> {
+    if let Err(e) = user.can(OtherPerm::DropDownBox, Scope::Any).await {
+        log!("{user} {action}失败，原因权限不足");
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+fn __create_department(
+    conn: &mut PooledConn,
+    params: &DepartmentParams,
+    time: &TIME,
+) -> Result<(), Response> {
+    let name = params.name.trim();
+    __insert_department(
+        conn,
+        name,
+        params.parent(),
+        params.head(),
+        time,
+    )?;
+    conn.exec_drop(
+        "INSERT IGNORE INTO drop_down_box (name, value, create_time) VALUES ('department', ?, ?)",
+        (name, time.format(TimeFormat::YYYYMMDD_HHMMSS)),
+    )?;
+    Ok(())
+}
+
+pub async fn create_department(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let params: DepartmentParams = serde_json::from_value(value)?;
+    let name = params.name.trim();
+    log!("{user} 请求创建部门 {name}");
+    ver_department_perm(&user, "创建部门").await?;
+    let mut conn = get_db().await?;
+    check_department_name(&mut conn, name)?;
+    check_parent(&mut conn, name, params.parent())?;
+    check_head(&mut conn, params.head()).await?;
+    let time = TIME::now()?;
+    commit_or_rollback!(__create_department, &mut conn, &params, &time)?;
+    reload_drop_down_box(&mut conn)?;
+    log!("{user} 成功创建部门 {name}");
+    Ok(Response::empty())
+}
+
+fn __update_department(conn: &mut PooledConn, params: &DepartmentParams) -> Result<(), Response> {
+    let name = params.name.trim();
+    let new_name = params.new_name.trim();
+    if params.parent.is_some() {
+        conn.exec_drop(
+            "UPDATE department SET parent = ? WHERE name = ? LIMIT 1",
+            (params.parent(), name),
+        )?;
+    }
+    if params.head.is_some() {
+        conn.exec_drop(
+            "UPDATE department SET head = ? WHERE name = ? LIMIT 1",
+            (params.head(), name),
+        )?;
+    }
+    if !new_name.is_empty() && new_name != name {
+        __rename_department(conn, name, new_name)?;
+        conn.exec_drop(
+            "UPDATE drop_down_box SET value = ? WHERE name = 'department' AND value = ? LIMIT 1",
+            (new_name, name),
+        )?;
+    }
+    Ok(())
+}
+
+/// 修改上级部门和负责人，只修改请求中有的字段，`new_name`不为空时同时改名
+pub async fn update_department(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let params: DepartmentParams = serde_json::from_value(value)?;
+    let name = params.name.trim();
+    let new_name = params.new_name.trim();
+    log!("{user} 请求修改部门 {name}");
+    ver_department_perm(&user, "修改部门").await?;
+    let mut conn = get_db().await?;
+    let exist: Option<i32> =
+        conn.exec_first("SELECT 1 FROM department WHERE name = ? LIMIT 1", (name,))?;
+    if exist.is_none() {
+        return Err(Response::not_exist("部门不存在"));
+    }
+    if !new_name.is_empty() && new_name != name {
+        if name == ROOT_DEPARTMENT {
+            return Err(Response::invalid_value("总经办这个部门不允许被修改"));
+        }
+        check_department_name(&mut conn, new_name)?;
+    }
+    if name == ROOT_DEPARTMENT && params.parent().is_some() {
+        return Err(Response::invalid_value("总经办不能有上级部门"));
+    }
+    check_parent(&mut conn, name, params.parent())?;
+    check_head(&mut conn, params.head()).await?;
+    commit_or_rollback!(__update_department, &mut conn, &params)?;
+    USER_CACHE.clear();
+    reload_drop_down_box(&mut conn)?;
+    log!("{user} 成功修改部门 {name}");
+    Ok(Response::empty())
+}
+
+fn __delete_department(conn: &mut PooledConn, name: &str) -> Result<(), Response> {
+    conn.exec_drop("DELETE FROM department WHERE name = ? LIMIT 1", (name,))?;
+    conn.exec_drop(
+        "DELETE FROM drop_down_box WHERE name = 'department' AND value = ? LIMIT 1",
+        (name,),
+    )?;
+    Ok(())
+}
+
+/// 删除部门，部门中还有员工(包括离职员工)或下级部门时不能删除
+pub async fn delete_department(user: AuthUser, Path(name): Path<String>) -> ResponseResult {
+    log!("{user} 请求删除部门 {name}");
+    if name == ROOT_DEPARTMENT {
+        return Err(Response::invalid_value("总经办这个部门不允许被删除"));
+    }
+    ver_department_perm(&user, "删除部门").await?;
+    let mut conn = get_db().await?;
+    let users: Option<usize> =
+        conn.exec_first("SELECT COUNT(*) FROM user WHERE department = ?", (&name,))?;
+    if users.unwrap_or(0) > 0 {
+        return Err(Response::dissatisfy("部门中还有员工，请先调动这些员工"));
+    }
+    let children: Option<usize> =
+        conn.exec_first("SELECT COUNT(*) FROM department WHERE parent = ?", (&name,))?;
+    if children.unwrap_or(0) > 0 {
+        return Err(Response::dissatisfy("请先删除或移走下级部门"));
+    }
+    commit_or_rollback!(__delete_department, &mut conn, &name)?;
+    reload_drop_down_box(&mut conn)?;
+    log!("{user} 成功删除部门 {name}");
+    Ok(Response::empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree() -> DepartmentTree {
+        let parents = [
+            ("总经办", None),
+            ("华东区", Some("总经办")),
+            ("上海", Some("华东区")),
+            ("杭州", Some("华东区")),
+            ("华南区", Some("总经办")),
+            ("环1", Some("环2")),
+            ("环2", Some("环1")),
+        ];
+        DepartmentTree {
+            parents: parents
+                .into_iter()
+                .map(|(k, v)| (k.to_owned(), v.map(str::to_owned)))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_contains() {
+        let tree = tree();
+        assert!(tree.contains("华东区", "华东区"));
+        assert!(tree.contains("华东区", "上海"));
+        assert!(tree.contains("总经办", "杭州"));
+        assert!(!tree.contains("华东区", "华南区"));
+        assert!(!tree.contains("上海", "华东区"));
+        assert!(!tree.contains("华东区", "不存在"));
+        assert!(tree.contains("不存在", "不存在"));
+        assert!(!tree.contains("华东区", "环1"));
+    }
+
+    #[test]
+    fn test_descendants() {
+        let tree = tree();
+        let mut list = tree.descendants("华东区");
+        list.sort();
+        assert_eq!(list, ["上海", "华东区", "杭州"]);
+        assert_eq!(tree.descendants("上海"), ["上海"]);
+        assert_eq!(tree.descendants("不存在"), ["不存在"]);
+    }
+
+    #[test]
+    fn test_update_params() {
+        let params = |value| serde_json::from_value::<DepartmentParams>(value).unwrap();
+        let rename = params(json!({"name": "上海", "new_name": "上海分公司"}));
+        assert_eq!((rename.parent, rename.head), (None, None));
+        let clear = params(json!({"name": "上海", "parent": null, "head": "u1"}));
+        assert_eq!(clear.parent, Some(None));
+        assert_eq!(clear.head(), Some("u1"));
+    }
+}
@@ -0,0 +1,243 @@
+//! 登录失败限制和登录记录
+//!
+//! 账号和IP分别计数，每次失败后需要等待的时间翻倍，
+//! 失败次数达到上限后锁定一段时间，超过锁定时长没有失败时计数清零。
+//! IP为`ClientInfo`中的地址，只有可信代理转发的请求才使用请求头中的地址。
+//!
+//! 每次尝试在一个事务中执行：`check_login`锁定账号和IP的记录，直到`finish_attempt`提交，
+//! 同一账号或IP的并发尝试只能依次检查和计数
+use std::net::SocketAddr;
+
+use axum::{extract::ConnectInfo, http::HeaderMap, Json};
+use mysql::{params, prelude::Queryable, PooledConn};
+use serde_json::Value;
+
+use crate::{
+    database::get_db,
+    libs::{gen_id, headers::ClientInfo, TimeFormat, TIME},
+    log,
+    perm::auth::AuthUser,
+    LoginConfig, Response, ResponseResult, CONFIG,
+};
+
+/// 账号类型，员工和客户的手机号可能相同，失败次数分别计数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountType {
+    #[default]
+    Staff,
+    Customer,
+}
+
+fn account_key(ty: AccountType, smartphone: &str) -> String {
+    match ty {
+        AccountType::Staff => format!("account:{smartphone}"),
+        AccountType::Customer => format!("customer:{smartphone}"),
+    }
+}
+fn ip_key(ip: &str) -> String {
+    format!("ip:{ip}")
+}
+
+/// 当前的unix时间戳，单位秒
+pub fn now_secs() -> Result<i64, Response> {
+    Ok((TIME::now()?.naos() / 1_000_000_000) as i64)
+}
+
+/// 连续失败`failures`次、最后一次失败在`last_failure`时，允许再次尝试的时间
+fn retry_at(failures: u32, last_failure: i64, max_failures: u32, config: &LoginConfig) -> i64 {
+    if failures == 0 {
+        return last_failure;
+    }
+    let wait = if failures >= max_failures {
+        config.lockout()
+    } else {
+        config
+            .backoff()
+            .saturating_mul(1 << (failures - 1).min(32))
+            .min(config.lockout())
+    };
+    last_failure.saturating_add(wait as i64)
+}
+
+/// 开启一次登录尝试的事务
+pub fn begin_attempt(conn: &mut PooledConn) -> Result<(), Response> {
+    conn.query_drop("begin")?;
+    Ok(())
+}
+
+/// 结束登录尝试，登录失败时同样提交，保证失败次数被记录，只有内部错误时回滚
+pub fn finish_attempt<T>(
+    conn: &mut PooledConn,
+    result: &Result<T, Response>,
+) -> Result<(), Response> {
+    match result {
+        Err(e) if e.status() < 0 => conn.query_drop("rollback")?,
+        _ => conn.query_drop("commit")?,
+    }
+    Ok(())
+}
+
+/// 锁定账号和IP的失败记录，账号或IP处于等待或锁定状态时返回剩余的秒数，
+/// 必须在`begin_attempt`之后调用
+pub fn check_login(
+    conn: &mut PooledConn,
+    ty: AccountType,
+    smartphone: &str,
+    ip: &str,
+    now: i64,
+) -> Result<Option<u64>, Response> {
+    let config = CONFIG.login();
+    let keys = [account_key(ty, smartphone), ip_key(ip)];
+    // 没有记录时插入失败次数为0的记录，保证并发的尝试锁定同一行
+    conn.exec_batch(
+        "INSERT INTO login_failure (k, failures, last_failure) VALUES (?, 0, 0)
+        ON DUPLICATE KEY UPDATE k = k",
+        keys.iter().map(|k| (k,)),
+    )?;
+    let mut until = now;
+    for (key, max) in keys
+        .into_iter()
+        .zip([config.max_failures(), config.ip_max_failures()])
+    {
+        let row: Option<(u32, i64)> = conn.exec_first(
+            "SELECT failures, last_failure FROM login_failure WHERE k = ? LIMIT 1 FOR UPDATE",
+            (key,),
+        )?;
+        if let Some((failures, last_failure)) = row {
+            until = until.max(retry_at(failures, last_failure, max, config));
+        }
+    }
+    Ok(op::ternary!(until > now => Some((until - now) as u64), None))
+}
+
+/// 账号和IP的失败次数加一，距离上次失败超过锁定时长时从一开始计数
+pub fn record_failure(
+    conn: &mut PooledConn,
+    ty: AccountType,
+    smartphone: &str,
+    ip: &str,
+    now: i64,
+) -> Result<(), Response> {
+    let lockout = CONFIG.login().lockout() as i64;
+    conn.exec_batch(
+        "INSERT INTO login_failure (k, failures, last_failure) VALUES (:k, 1, :now)
+        ON DUPLICATE KEY UPDATE
+        failures = IF(:now - last_failure > :lockout, 1, failures + 1), last_failure = :now",
+        [account_key(ty, smartphone), ip_key(ip)].iter().map(|k| {
+            params! {
+                "k" => k,
+                "now" => now,
+                "lockout" => lockout
+            }
+        }),
+    )?;
+    Ok(())
+}
+
+/// 登录成功后清空该账号的失败次数，IP的失败次数保留
+pub fn record_success(
+    conn: &mut PooledConn,
+    ty: AccountType,
+    smartphone: &str,
+) -> Result<(), Response> {
+    conn.exec_drop(
+        "DELETE FROM login_failure WHERE k = ?",
+        (account_key(ty, smartphone),),
+    )?;
+    Ok(())
+}
+
+/// 写入登录记录，`user`为`None`表示该手机号不存在
+pub fn audit_login(
+    conn: &mut PooledConn,
+    smartphone: &str,
+    user: Option<&str>,
+    client: &ClientInfo,
+    success: bool,
+    reason: &str,
+) -> Result<(), Response> {
+    let time = TIME::now()?;
+    conn.exec_drop(
+        "INSERT INTO login_audit (id, smartphone, user, ip, user_agent, success, reason, time)
+        VALUES (:id, :smartphone, :user, :ip, :user_agent, :success, :reason, :time)",
+        params! {
+            "id" => gen_id(&time, smartphone),
+            "smartphone" => smartphone,
+            "user" => user,
+            "ip" => &client.ip,
+            "user_agent" => &client.user_agent,
+            "success" => success as i32,
+            "reason" => reason,
+            "time" => time.format(TimeFormat::YYYYMMDD_HHMMSS)
+        },
+    )?;
+    Ok(())
+}
+
+/// 删除已经超过锁定时长的失败记录
+pub fn purge_login_failures(conn: &mut PooledConn) -> Result<u64, Response> {
+    let expire = now_secs()? - CONFIG.login().lockout() as i64;
+    conn.exec_drop(
+        "DELETE FROM login_failure WHERE last_failure < ?",
+        (expire,),
+    )?;
+    Ok(conn.affected_rows())
+}
+
+#[derive(serde::Deserialize)]
+struct UnlockParams {
+    smartphone: String,
+    /// 默认为员工账号
+    #[serde(default)]
+    ty: AccountType,
+}
+
+/// 管理员解除账号的登录锁定，并写入登录记录
+pub async fn unlock_account(
+    user: AuthUser,
+    headers: HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    Json(value): Json<Value>,
+) -> ResponseResult {
+    let mut conn = get_db().await?;
+    if !user.role.eq("root") {
+        return Err(Response::permission_denied());
+    }
+    let client = ClientInfo::new(&headers, addr.map(|a| a.0));
+    let params: UnlockParams = serde_json::from_value(value)?;
+    let failures: Option<u32> = conn.exec_first(
+        "SELECT failures FROM login_failure WHERE k = ? LIMIT 1",
+        (account_key(params.ty, &params.smartphone),),
+    )?;
+    if failures.unwrap_or(0) == 0 {
+        return Err(Response::not_exist("该账号没有被锁定"));
+    }
+    begin_attempt(&mut conn)?;
+    let result = record_success(&mut conn, params.ty, &params.smartphone).and_then(|_| {
+        let reason = format!("管理员{}解锁", user.id);
+        audit_login(&mut conn, &params.smartphone, None, &client, true, &reason)
+    });
+    finish_attempt(&mut conn, &result)?;
+    result?;
+    log!("{user} 解除了账号 {} 的登录锁定", params.smartphone);
+    Ok(Response::empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_at() {
+        let config = LoginConfig::default();
+        let wait = |failures| retry_at(failures, 1000, 5, &config) - 1000;
+        assert_eq!(wait(0), 0);
+        assert_eq!(wait(1), 1);
+        assert_eq!(wait(2), 2);
+        assert_eq!(wait(4), 8);
+        assert_eq!(wait(5), 900);
+        assert_eq!(wait(100), 900);
+        assert_eq!(retry_at(60, 1000, 100, &config) - 1000, 900);
+    }
+}
@@ -0,0 +1,213 @@
+use axum::{extract::Path, Json};
+use mysql::{params, prelude::Queryable, PooledConn};
+use mysql_common::prelude::FromRow;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{get_user, DepartmentTree, User};
+use crate::{
+    commit_or_rollback,
+    database::{
+        get_db,
+        query::{Cond, Query},
+    },
+    libs::{
+        cache::{CUSTOMER_CACHE, ORDER_CACHE, ORDER_CACHE_WITH_ID, USER_CACHE},
+        TimeFormat, TIME,
+    },
+    log,
+    pages::func::customer::sea::__push_to_sea,
+    perm::{
+        auth::{AccountPerm, AuthUser, Scope},
+        roles::role_to_name,
+    },
+    token::revoke_sessions,
+    Response, ResponseResult,
+};
+
+#[derive(Deserialize)]
+struct LeaveParams {
+    id: String,
+    /// 接手预约、意向订单和待审批报告的同事
+    successor: String,
+    /// 为true时客户放入公海，否则交给`successor`
+    #[serde(default)]
+    customers_to_sea: bool,
+    #[serde(default)]
+    reason: String,
+}
+
+/// 离职交接的结果
+#[derive(Serialize, Default, Debug)]
+struct LeaveSummary {
+    customers: u64,
+    sea_customers: u64,
+    appointments: u64,
+    orders: u64,
+    reports: u64,
+}
+
+/// 验证用户办理离职的权限，与创建账号相同，只能办理本部门的员工
+async fn ver_leave_perm(adm: &User, user: &User, tree: &DepartmentTree) -> Result<(), Response> {
+    let role_name = role_to_name(&user.role);
+    if role_name.is_empty() {
+        return Err(Response::permission_denied());
+    }
+    if adm.role.eq("root") {
+        return Ok(());
+    }
+    // 本部门包括下级部门，其他部门需要`all_department`
+    if !tree.contains(&adm.department, &user.department) {
+        adm.can(AccountPerm::Delete, Scope::AllDepartment).await?;
+    }
+    adm.can(AccountPerm::Delete, Scope::Role(&role_name)).await?;
+    Ok(())
+}
+
+fn __leave(
+    conn: &mut PooledConn,
+    adm: &User,
+    user: &User,
+    successor: &User,
+    params: &LeaveParams,
+    time: &TIME,
+) -> Result<LeaveSummary, Response> {
+    let mut summary = LeaveSummary::default();
+    let leave_time = time.format(TimeFormat::YYYYMMDD_HHMMSS);
+    conn.exec_drop(
+        "INSERT INTO leaver (id, leave_time, operator, successor, reason)
+        VALUES (:id, :leave_time, :operator, :successor, :reason)",
+        params! {
+            "id" => &user.id,
+            "leave_time" => &leave_time,
+            "operator" => &adm.id,
+            "successor" => &successor.id,
+            "reason" => params.reason.trim()
+        },
+    )?;
+    if params.customers_to_sea {
+        let customers: Vec<String> = conn.exec(
+            "SELECT ex.id FROM extra_customer_data ex WHERE ex.salesman = ?
+            AND NOT EXISTS (SELECT 1 FROM customer_sea cs WHERE cs.id = ex.id)",
+            (&user.id,),
+        )?;
+        let reason = format!("业务员{}离职，客户放入公海", user.name);
+        for id in &customers {
+            __push_to_sea(conn, id, user, &reason, time)?;
+        }
+        summary.sea_customers = customers.len() as u64;
+    } else {
+        conn.exec_drop(
+            "UPDATE extra_customer_data SET salesman = ? WHERE salesman = ?",
+            (&successor.id, &user.id),
+        )?;
+        summary.customers = conn.affected_rows();
+    }
+    conn.exec_drop(
+        "UPDATE appointment SET salesman = ? WHERE salesman = ? AND finish_time IS NULL",
+        (&successor.id, &user.id),
+    )?;
+    summary.appointments = conn.affected_rows();
+    conn.exec_drop(
+        "UPDATE order_data SET salesman = ? WHERE salesman = ? AND status = 0",
+        (&successor.id, &user.id),
+    )?;
+    summary.orders = conn.affected_rows();
+    conn.exec_drop(
+        "UPDATE report SET reviewer = ? WHERE reviewer = ? AND status = 2",
+        (&successor.id, &user.id),
+    )?;
+    summary.reports = conn.affected_rows();
+    revoke_sessions(conn, true, &user.id)?;
+    Ok(summary)
+}
+
+/// 办理离职，注销该员工的所有登录，并交接客户、未完成的预约、意向订单和待审批的报告
+pub async fn user_leave(adm: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let params: LeaveParams = serde_json::from_value(value)?;
+    let user = get_user(&params.id, &mut conn).await?;
+    log!("{adm} 请求为 {user} 办理离职");
+    if user.id.eq(&adm.id) || user.role.eq("root") {
+        return Err(Response::invalid_value("不能为自己或管理员办理离职"));
+    }
+    let tree = DepartmentTree::load(&mut conn)?;
+    if let Err(e) = ver_leave_perm(&adm, &user, &tree).await {
+        log!("{adm} 为 {user} 办理离职失败，原因权限不足");
+        return Err(e);
+    }
+    if params.successor.eq(&user.id) {
+        return Err(Response::invalid_value("交接人不能是离职员工本人"));
+    }
+    let successor = get_user(&params.successor, &mut conn).await?;
+    let time = TIME::now()?;
+    let summary = commit_or_rollback!(__leave, &mut conn, &adm, &user, &successor, &params, &time)?;
+    USER_CACHE.remove(&user.id);
+    CUSTOMER_CACHE.clear();
+    ORDER_CACHE.clear();
+    ORDER_CACHE_WITH_ID.clear();
+    log!(
+        "{adm} 成功为 {user} 办理离职，交接给 {successor}：{:?}",
+        summary
+    );
+    Ok(Response::ok(json!(summary)))
+}
+
+/// 恢复离职员工的账号，已经交接的数据不会退回
+pub async fn reinstate_user(adm: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let user: User = op::some!(conn.exec_first(
+        "SELECT u.* FROM user u JOIN leaver l ON l.id = u.id WHERE u.id = ? LIMIT 1",
+        (&id,)
+    )?; ret Err(Response::not_exist("该员工不存在或没有离职")));
+    let tree = DepartmentTree::load(&mut conn)?;
+    if let Err(e) = ver_leave_perm(&adm, &user, &tree).await {
+        log!("{adm} 恢复 {user} 的账号失败，原因权限不足");
+        return Err(e);
+    }
+    conn.exec_drop("DELETE FROM leaver WHERE id = ? LIMIT 1", (&id,))?;
+    USER_CACHE.remove(&id);
+    log!("{adm} 恢复了 {user} 的账号");
+    Ok(Response::empty())
+}
+
+#[derive(Serialize, FromRow)]
+struct LeaverData {
+    id: String,
+    smartphone: String,
+    name: String,
+    department: String,
+    role: Option<String>,
+    leave_time: Option<String>,
+    operator: Option<String>,
+    operator_name: Option<String>,
+    successor: Option<String>,
+    successor_name: Option<String>,
+    reason: Option<String>,
+}
+
+/// 离职员工列表，管理员可以查看所有部门，其他人只能查看本部门及下级部门
+pub async fn query_leavers(adm: AuthUser) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let departments = if adm.role.eq("root") {
+        Cond::any()
+    } else {
+        adm.can(AccountPerm::Delete, Scope::Any).await?;
+        let tree = DepartmentTree::load(&mut conn)?;
+        Cond::in_list("u.department", tree.descendants(&adm.department))
+    };
+    let list: Vec<LeaverData> = Query::new(
+        "SELECT u.id, u.smartphone, u.name, u.department, r.name as role, l.leave_time,
+            l.operator, o.name as operator_name, l.successor, s.name as successor_name, l.reason
+        FROM leaver l
+        JOIN user u ON u.id = l.id
+        LEFT JOIN roles r ON r.id = u.role
+        LEFT JOIN user o ON o.id = l.operator
+        LEFT JOIN user s ON s.id = l.successor
+        WHERE ",
+    )
+    .cond(&departments)
+    .push(" ORDER BY l.leave_time DESC")
+    .fetch(&mut conn)?;
+    Ok(Response::ok(json!(list)))
+}
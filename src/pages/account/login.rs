@@ -1,35 +1,53 @@
-use axum::{http::HeaderMap, Json};
-use mysql::PooledConn;
+use std::net::SocketAddr;
+
+use axum::{extract::ConnectInfo, http::HeaderMap, Json};
+use mysql::{prelude::Queryable, PooledConn};
 use serde_json::{json, Value};
 
 use crate::{
     bearer,
-    database::{get_db, DB},
-    libs::headers::Bearer,
+    database::get_db,
+    libs::{
+        headers::{Bearer, ClientInfo},
+        password, TimeFormat, TIME,
+    },
     log,
     pages::account::get_user,
     perm::{roles::role_to_name, ROLES_GROUP_MAP},
     response::Response,
-    token::{generate_jwt, parse_jwt, TokenVerification},
-    ResponseResult,
+    token::{
+        create_session, generate_jwt, generate_password_jwt, issue_refresh_token, parse_jwt, rotate_refresh_token,
+        RefreshResult, TokenVerification,
+    },
+    commit_or_rollback, ResponseResult,
 };
 
-use super::get_user_with_phone_number;
+use super::{
+    get_user_with_phone_number,
+    guard::{self, AccountType},
+};
 
 #[derive(serde::Deserialize)]
 struct LoginID {
     smartphone: String,
     password: String,
+    /// 设备名称，用于在会话列表中区分不同设备
+    #[serde(default)]
+    device: String,
 }
 
-pub async fn user_login(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
+pub async fn user_login(
+    headers: HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    Json(value): Json<Value>,
+) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let client = ClientInfo::new(&headers, addr.map(|a| a.0));
     if let Some(bearer) = bearer!(&headers, Allow Missing) {
         match verify_login_token(&bearer, &mut conn).await {
             Ok(res) => Ok(res) ,
             Err(err) => {
-                if let Ok(value) = verify_password(value, &mut conn).await {
+                if let Ok(value) = verify_password(value, &mut conn, &client).await {
                     Ok(value)
                 } else {
                     Err(err)
@@ -37,13 +55,16 @@ pub async fn user_login(headers: HeaderMap, Json(value): Json<Value>) -> Respons
             }
         }
     } else {
-        verify_password(value, &mut conn).await
+        verify_password(value, &mut conn, &client).await
     }
 }
 
-async fn verify_login_token<'err>(bearer: &Bearer, conn: &mut DB<'err>) -> ResponseResult {
+async fn verify_login_token(bearer: &Bearer, conn: &mut PooledConn) -> ResponseResult {
     let token = match parse_jwt(bearer) {
         Some(token) if !token.sub => return Err(Response::token_error("客户账号无法进行员工登录")),
+        Some(token) if token.password_only => {
+            return Err(Response::token_error("请先修改默认密码"))
+        }
         None => {
             log(format_args!("非法token登录"));
             return Err(Response::token_error("Invalid token"));
@@ -69,39 +90,14 @@ async fn verify_login_token<'err>(bearer: &Bearer, conn: &mut DB<'err>) -> Respo
                 let perms = ROLES_GROUP_MAP.lock().await;
                 Ok(Response::ok(json!({
                     "token": bearer.token(),
-                    "perm": perms.get(&user.role),
+                    "perm": perms.get(&user.role).map(|p| p.to_group_map()),
                     "info": user.as_ref()
                 })))
             }
         }
         TokenVerification::Expired => {
-            if token.is_refresh() {
-                let user = get_user(&token.id, conn).await?;
-                let token = generate_jwt(true, &token.id);
-                log!(
-                    "{}-{}({})登录成功, token已刷新",
-                    role_to_name(&user.role),
-                    user.name,
-                    user.id
-                );
-                if user.role.eq("root") {
-                    Ok(Response::ok(json!({
-                        "token": token,
-                        "info": user,
-                        "perm": "all"
-                    })))
-                } else {
-                    let perms = ROLES_GROUP_MAP.lock().await;
-                    Ok(Response::ok(json!({
-                        "token": token,
-                        "info": user,
-                        "perm": perms.get(&user.role)
-                    })))
-                }
-            } else {
-                log!("用户登录失败，原因: token已过期");
-                Err(Response::token_error("Token已过期"))
-            }
+            log!("用户登录失败，原因: token已过期");
+            Err(Response::token_error("Token已过期"))
         }
         TokenVerification::Error => {
             log!("用户登录失败，原因: token非法");
@@ -109,36 +105,188 @@ async fn verify_login_token<'err>(bearer: &Bearer, conn: &mut DB<'err>) -> Respo
         }
     }
 }
-async fn verify_password(value: Value, conn: &mut PooledConn) -> ResponseResult {
+async fn verify_password(
+    value: Value,
+    conn: &mut PooledConn,
+    client: &ClientInfo,
+) -> ResponseResult {
     let params: LoginID = serde_json::from_value(value)?;
-    let digest = md5::compute(&params.password);
-    let user = get_user_with_phone_number(&params.smartphone, conn)?;
-    if user.password.as_slice() != digest.0.as_slice() {
-        log!(
-            "{}-{}(手机号：{})登录失败，密码错误",
-            user.role,
-            user.name,
-            user.smartphone
-        );
+    guard::begin_attempt(conn)?;
+    let result = __verify_password(&params, conn, client).await;
+    guard::finish_attempt(conn, &result)?;
+    result
+}
+
+async fn __verify_password(
+    params: &LoginID,
+    conn: &mut PooledConn,
+    client: &ClientInfo,
+) -> ResponseResult {
+    let ty = AccountType::Staff;
+    let now = guard::now_secs()?;
+    if let Some(retry_after) = guard::check_login(conn, ty, &params.smartphone, &client.ip, now)? {
+        guard::audit_login(conn, &params.smartphone, None, client, false, "失败次数过多")?;
+        return Err(Response::too_many_attempts(retry_after));
+    }
+    let user = match get_user_with_phone_number(&params.smartphone, conn) {
+        Ok(user) => user,
+        Err(e) => {
+            guard::record_failure(conn, ty, &params.smartphone, &client.ip, now)?;
+            guard::audit_login(conn, &params.smartphone, None, client, false, "用户不存在")?;
+            return Err(e);
+        }
+    };
+    let matched = match (&user.password_hash, &user.password) {
+        (Some(hash), _) => password::verify_password(&params.password, hash),
+        (None, Some(digest)) => password::verify_md5(&params.password, digest),
+        (None, None) => false,
+    };
+    if !matched {
+        guard::record_failure(conn, ty, &params.smartphone, &client.ip, now)?;
+        guard::audit_login(conn, &params.smartphone, Some(&user.id), client, false, "密码错误")?;
+        log!("{}-{}({})登录失败，密码错误", user.role, user.name, user.id);
         Err(Response::wrong_password())
     } else {
-        let token = generate_jwt(true, &user.id);
+        guard::record_success(conn, ty, &params.smartphone)?;
+        guard::audit_login(conn, &params.smartphone, Some(&user.id), client, true, "")?;
+        if user.password_hash.as_deref().is_none_or(password::needs_rehash) {
+            conn.exec_drop(
+                "UPDATE user SET password_hash = ?, password = NULL WHERE id = ? LIMIT 1",
+                (password::hash_password(&params.password)?, &user.id),
+            )?;
+            log!("{}-{} 的密码已转换为新的哈希格式", user.department, user.name);
+        }
+        let sid = create_session(conn, true, &user.id, &params.device, client)?;
+        if user.must_change_password != 0 {
+            log!(
+                "{}-{} 使用默认密码登录，需要先修改密码",
+                user.department,
+                user.name
+            );
+            return Ok(Response::ok(json!({
+                "token": generate_password_jwt(&user.id, &sid),
+                "must_change_password": true,
+                "info": user
+            })));
+        }
+        let token = generate_jwt(true, &user.id, &sid);
+        let refresh_token = issue_refresh_token(conn, &user.id, &sid)?;
 
-        log!(
-            "{}-{}(手机号：{})登录成功",
-            user.role,
-            user.name,
-            user.smartphone
-        );
+        log!("{}-{}({})登录成功", user.role, user.name, user.id);
         if user.role.eq("root") {
             Ok(Response::ok(
-                json!({"token": token, "info": user, "perms": "all"}),
+                json!({"token": token, "refresh_token": refresh_token, "info": user, "perms": "all"}),
             ))
         } else {
             let perms = ROLES_GROUP_MAP.lock().await;
             Ok(Response::ok(
-                json!({"token": token, "info": user, "perms": perms.get(&user.role)}),
+                json!({"token": token, "refresh_token": refresh_token, "info": user, "perms": perms.get(&user.role).map(|p| p.to_group_map())}),
             ))
         }
     }
 }
+
+/// 客户登录，账号需要员工邀请并由客户设置密码后才能使用
+pub async fn customer_login(
+    headers: HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    Json(value): Json<Value>,
+) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let client = ClientInfo::new(&headers, addr.map(|a| a.0));
+    let params: LoginID = serde_json::from_value(value)?;
+    guard::begin_attempt(&mut conn)?;
+    let result = __customer_login(&mut conn, &params, &client);
+    guard::finish_attempt(&mut conn, &result)?;
+    result
+}
+
+fn __customer_login(
+    conn: &mut PooledConn,
+    params: &LoginID,
+    client: &ClientInfo,
+) -> ResponseResult {
+    let ty = AccountType::Customer;
+    let now = guard::now_secs()?;
+    if let Some(retry_after) = guard::check_login(conn, ty, &params.smartphone, &client.ip, now)? {
+        guard::audit_login(conn, &params.smartphone, None, client, false, "失败次数过多")?;
+        return Err(Response::too_many_attempts(retry_after));
+    }
+    let account: Option<(String, String, Option<String>)> = conn.exec_first(
+        "SELECT c.id, c.name, cl.password_hash FROM customer_login cl
+        JOIN customer c ON c.id = cl.id
+        WHERE c.smartphone = ? LIMIT 1",
+        (&params.smartphone,),
+    )?;
+    let (id, name, hash) = match account {
+        Some((id, name, Some(hash))) => (id, name, hash),
+        _ => {
+            guard::record_failure(conn, ty, &params.smartphone, &client.ip, now)?;
+            guard::audit_login(conn, &params.smartphone, None, client, false, "客户账号不存在")?;
+            return Err(Response::not_exist("手机号错误，账号不存在或未激活"));
+        }
+    };
+    if !password::verify_password(&params.password, &hash) {
+        guard::record_failure(conn, ty, &params.smartphone, &client.ip, now)?;
+        guard::audit_login(conn, &params.smartphone, Some(&id), client, false, "密码错误")?;
+        log!("客户{name}({id})登录失败，密码错误");
+        return Err(Response::wrong_password());
+    }
+    guard::record_success(conn, ty, &params.smartphone)?;
+    guard::audit_login(conn, &params.smartphone, Some(&id), client, true, "")?;
+    let time = TIME::now()?;
+    conn.exec_drop(
+        "UPDATE customer_login SET last_login = ? WHERE id = ? LIMIT 1",
+        (time.format(TimeFormat::YYYYMMDD_HHMMSS), &id),
+    )?;
+    let sid = create_session(conn, false, &id, &params.device, client)?;
+    let token = generate_jwt(false, &id, &sid);
+    let refresh_token = issue_refresh_token(conn, &id, &sid)?;
+    log!("客户{name}({id})登录成功");
+    Ok(Response::ok(json!({
+        "token": token,
+        "refresh_token": refresh_token,
+        "info": {"id": id, "name": name, "smartphone": params.smartphone}
+    })))
+}
+
+#[derive(serde::Deserialize)]
+struct RefreshParams {
+    refresh_token: String,
+}
+
+/// 使用refresh token换取新的access token和refresh token，
+/// 每个refresh token只能使用一次
+pub async fn refresh_token(Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let params: RefreshParams = serde_json::from_value(value)?;
+    let result = commit_or_rollback!(rotate_refresh_token, &mut conn, &params.refresh_token)?;
+    match result {
+        RefreshResult::Rotated { id, sid, token } => {
+            // 会话类型 0 员工，1 客户
+            let ty: Option<i32> =
+                conn.exec_first("SELECT ty FROM session WHERE id = ? LIMIT 1", (&sid,))?;
+            let sub = match ty {
+                Some(0) => true,
+                Some(1) => false,
+                _ => return Err(Response::token_error("Invalid refresh token")),
+            };
+            if sub {
+                let user = get_user(&id, &mut conn).await?;
+                log!("{user} 刷新token成功");
+            } else {
+                log!("客户{id} 刷新token成功");
+            }
+            Ok(Response::ok(json!({
+                "token": generate_jwt(sub, &id, &sid),
+                "refresh_token": token
+            })))
+        }
+        RefreshResult::Reused { id } => {
+            log!("用户{id}的refresh token被重复使用，已撤销该登录下的所有refresh token");
+            Err(Response::token_error("Invalid refresh token"))
+        }
+        RefreshResult::Expired => Err(Response::token_error("Refresh token已过期")),
+        RefreshResult::Invalid => Err(Response::token_error("Invalid refresh token")),
+    }
+}
@@ -0,0 +1,12 @@
+use crate::{
+    commit_or_rollback, database::get_db, log, perm::auth::AuthUser, token::delete_session,
+    Response, ResponseResult,
+};
+
+/// 退出登录，只注销当前token所在的会话
+pub async fn user_logout(user: AuthUser) -> ResponseResult {
+    let mut conn = get_db().await?;
+    commit_or_rollback!(delete_session, &mut conn, user.sid())?;
+    log!("{user} 退出登录");
+    Ok(Response::empty())
+}
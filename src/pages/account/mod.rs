@@ -3,26 +3,42 @@ use std::{collections::HashMap, sync::Arc};
 use axum::{
     extract::Path,
     http::HeaderMap,
-    routing::{get, post},
+    routing::{delete, get, post},
     Json, Router,
 };
 use mysql::{params, prelude::Queryable, PooledConn};
 use mysql_common::prelude::FromRow;
 use serde_json::{json, Value};
 
+mod department;
+pub mod guard;
+mod leaver;
 mod login;
 mod logout;
 mod register;
+mod role;
+mod session;
+pub use department::{
+    DepartmentTree, __insert_department, __merge_department, __rename_department,
+    ROOT_DEPARTMENT,
+};
+pub use guard::purge_login_failures;
 use crate::{
     bearer,
-    database::{get_db, DB},
+    database::{
+        get_db,
+        query::{Cond, Query},
+    },
     libs::{
         cache::{TOKEN_CACHE, USER_CACHE},
         dser::*,
+        password::{check_password_policy, hash_password},
         time::TIME,
     },
+    log,
     parse_jwt_macro,
-    perm::verify_permissions,
+    perm::auth::{check, Action, AuthUser, Denied, OtherPerm, Scope},
+    token::{parse_jwt, revoke_sessions},
     Response, ResponseResult,
 };
 
@@ -33,10 +49,14 @@ pub struct User {
     pub id: String,
     pub smartphone: String,
     pub name: String,
-    #[allow(unused)]
-    #[serde(skip_serializing)]
-    #[serde(skip_deserializing)]
-    pub password: Vec<u8>,
+    /// 旧版本的md5密码，登录后转换为`password_hash`
+    #[serde(skip)]
+    pub password: Option<Vec<u8>>,
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    /// 1 表示仍在使用默认密码
+    #[serde(skip)]
+    pub must_change_password: i32,
     #[serde(default)]
     pub department: String,
     #[serde(deserialize_with = "deserialize_role")]
@@ -46,6 +66,12 @@ pub struct User {
     #[serde(serialize_with = "serialize_i32_to_bool")]
     pub sex: i32,
 }
+impl User {
+    /// 检查权限，失败时返回原因
+    pub async fn can<A: Action>(&self, action: A, scope: Scope<'_>) -> Result<(), Denied> {
+        check(&self.role, action, scope).await
+    }
+}
 impl std::fmt::Display for User {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("{}-{}", self.department, self.name))
@@ -54,20 +80,44 @@ impl std::fmt::Display for User {
 pub fn account_router() -> Router {
     Router::new()
         .route("/user/login", post(login::user_login))
+        .route("/user/token/refresh", post(login::refresh_token))
+        .route("/user/logout", post(logout::user_logout))
+        .route("/user/sessions", get(session::query_sessions))
+        .route("/user/sessions/:id", delete(session::revoke_session))
         .route("/root/register", post(register::register_root))
+        .route("/root/unlock", post(guard::unlock_account))
         .route("/user/list/:id", post(query_list_data))
         .route("/user/count/:id", post(query_depart_count))
-        // .route("/customer/login", post(login::customer_login))
+        .route("/customer/login", post(login::customer_login))
         .route("/user/register", post(register::register_user))
         .route("/user/set/psw", post(set_user_password))
+        .route("/user/leave", post(leaver::user_leave))
+        .route("/user/reinstate/:id", post(leaver::reinstate_user))
+        .route("/user/leavers", get(leaver::query_leavers))
         .route("/user/full/data/:id", post(query_full_data))
-        // .route("/customer/set/psw", post(set_customer_password))
+        .route("/customer/set/psw", post(set_customer_password))
+        .route("/department/list", get(department::query_departments))
+        .route("/department/tree", get(department::query_department_tree))
+        .route("/department/create", post(department::create_department))
+        .route("/department/update", post(department::update_department))
+        .route("/department/:name", delete(department::delete_department))
         .route("/role/infos", get(get_role))
+        .route("/role/create", post(role::create_role))
+        .route("/role/update", post(role::update_role))
+        .route("/role/:id", delete(role::delete_role))
+        .route("/role/change", post(role::change_role))
+        .route(
+            "/perm/role/:id",
+            get(role::query_role_perms).post(role::update_role_perms),
+        )
+        .route(
+            "/perm/tree/:id",
+            get(role::query_role_tree).post(role::update_role_tree),
+        )
 }
 
 async fn get_role() -> ResponseResult {
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
+    let mut conn = get_db().await?;
     let roles = conn.query_map("SELECT name FROM roles WHERE id != 'root'", |s: String| s)?;
     Ok(Response::ok(json!(roles)))
 }
@@ -76,62 +126,78 @@ async fn get_role() -> ResponseResult {
 struct Password {
     password: String,
 }
-// async fn set_customer_password(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-//     let bearer = bearer!(&headers);
-//     let mut conn = get_conn()?;
-//     let id = parse_jwt_macro!(&bearer, &mut conn => false);
-//     let password: Password = serde_json::from_value(value)?;
-//     let digest = md5::compute(password.password);
-//     let time = TIME::now()?;
-//     conn.exec_drop(
-//         "UPDATE customer_login SET password = :password WHERE id = :id",
-//         params! {
-//             "password" => digest.0,
-//             "id" => &id
-//         },
-//     )?;
-//     conn.query_drop(format!(
-//         "INSERT INTO token (ty, id, tbn) VALUES (1, '{}', {}) ON DUPLICATE KEY UPDATE tbn = {}",
-//         id,
-//         time.naos(),
-//         time.naos()
-//     ))?;
-//     Ok(Response::empty())
-// }
+/// 客户修改密码，注销客户所有的登录
+async fn set_customer_password(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
+    let bearer = bearer!(&headers);
+    let mut conn = get_db().await?;
+    let id = parse_jwt_macro!(&bearer, &mut conn => false);
+    let smartphone: String = op::some!(
+        conn.exec_first("SELECT smartphone FROM customer WHERE id = ? LIMIT 1", (&id,))?;
+        ret Err(Response::not_exist("客户不存在"))
+    );
+    let password: Password = serde_json::from_value(value)?;
+    check_password_policy(&password.password, &smartphone)?;
+    let time = TIME::now()?;
+    conn.exec_drop(
+        "UPDATE customer_login SET password_hash = ? WHERE id = ? LIMIT 1",
+        (hash_password(&password.password)?, &id),
+    )?;
+    conn.exec_drop(
+        "INSERT INTO token (ty, id, tbn) VALUES (1, :id, :tbn) ON DUPLICATE KEY UPDATE tbn = :tbn",
+        params! {
+            "id" => &id,
+            "tbn" => time.naos() as i64
+        },
+    )?;
+    revoke_sessions(&mut conn, false, &id)?;
+    log!("客户{id} 修改了密码");
+    Ok(Response::empty())
+}
 async fn set_user_password(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
     let bearer = bearer!(&headers);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = parse_jwt_macro!(&bearer, &mut conn => true);
+    let mut conn = get_db().await?;
+    // 仍在使用默认密码的用户只有修改密码用的token，不能通过`parse_jwt_macro`
+    let jwt = op::some!(parse_jwt(&bearer); ret Err(Response::token_error("Invalid Token")));
+    if !jwt.sub || !jwt.verify(&mut conn)?.is_ok() {
+        return Err(Response::token_error("Invalid Token"));
+    }
+    let id = jwt.id;
+    let user = get_user(&id, &mut conn).await?;
     let password: Password = serde_json::from_value(value)?;
-    let digest = md5::compute(password.password);
+    check_password_policy(&password.password, &user.smartphone)?;
+    let hash = hash_password(&password.password)?;
     let time = TIME::now()?;
     conn.exec_drop(
-        "UPDATE user SET password = :password WHERE id = :id",
+        "UPDATE user SET password_hash = :hash, password = NULL, must_change_password = 0
+        WHERE id = :id",
         params! (
-            "password" => digest.0,
+            "hash" => hash,
             "id" => &id
         ),
     )?;
-    conn.query_drop(format!(
-        "INSERT INTO token (ty, id, tbn) VALUES (0, '{}', {}) ON DUPLICATE KEY UPDATE tbn = {}",
-        id,
-        time.naos(),
-        time.naos()
-    ))?;
+    conn.exec_drop(
+        "INSERT INTO token (ty, id, tbn) VALUES (0, :id, :tbn) ON DUPLICATE KEY UPDATE tbn = :tbn",
+        params! {
+            "id" => &id,
+            "tbn" => time.naos() as i64
+        },
+    )?;
+    revoke_sessions(&mut conn, true, &id)?;
+    USER_CACHE.remove(&id);
     TOKEN_CACHE.clear();
+    log!("{user} 修改了密码");
     Ok(Response::empty())
 }
 
-pub async fn get_user<'err>(id: &str, conn: &mut DB<'err>) -> Result<Arc<User>, Response> {
+pub async fn get_user(id: &str, conn: &mut PooledConn) -> Result<Arc<User>, Response> {
     if let Some(user) = USER_CACHE.get(id) {
         Ok(Arc::clone(user.value()))
     } else {
-        let query = format!(
-            "SELECT u.* FROM user u WHERE u.id = '{id}' 
-        AND NOT EXISTS (SELECT 1 FROM leaver l WHERE l.id=u.id) LIMIT 1"
-        );
-        let result = conn.query_first(query)?;
+        let result = conn.exec_first(
+            "SELECT u.* FROM user u WHERE u.id = ? 
+        AND NOT EXISTS (SELECT 1 FROM leaver l WHERE l.id=u.id) LIMIT 1",
+            (id,),
+        )?;
         let u: User = op::some!(result; ret Err(Response::not_exist("用户不存在")));
         let u = Arc::new(u);
         USER_CACHE.insert(id.to_owned(), Arc::clone(&u));
@@ -139,17 +205,44 @@ pub async fn get_user<'err>(id: &str, conn: &mut DB<'err>) -> Result<Arc<User>,
     }
 }
 pub fn get_user_with_phone_number(number: &str, conn: &mut PooledConn) -> Result<User, Response> {
-    let u: User = op::some!(conn.query_first(format!("SELECT u.* FROM user u WHERE u.smartphone = '{number}' 
-        AND NOT EXISTS (SELECT 1 FROM leaver l WHERE l.id=u.id) LIMIT 1"))?; ret Err(Response::not_exist("手机号错误，用户不存在")));
+    let u: User = op::some!(conn.exec_first("SELECT u.* FROM user u WHERE u.smartphone = ? 
+        AND NOT EXISTS (SELECT 1 FROM leaver l WHERE l.id=u.id) LIMIT 1", (number,))?; ret Err(Response::not_exist("手机号错误，用户不存在")));
     Ok(u)
 }
 
-async fn query_depart_count(header: HeaderMap, Path(depart): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = parse_jwt_macro!(&bearer, &mut conn => true);
-    let u = get_user(&id, &mut conn).await?;
+/// 本部门及下级部门不需要权限，其他部门需要`CompanyStaffData`
+async fn ver_depart_perm(
+    u: &User,
+    tree: &DepartmentTree,
+    depart: &str,
+) -> Result<(), Response> {
+    if !tree.contains(&u.department, depart) {
+        u.can(OtherPerm::CompanyStaffData, Scope::Any).await?;
+    }
+    Ok(())
+}
+
+/// 部门及其下级部门的在职员工
+fn query_depart_users(
+    conn: &mut PooledConn,
+    tree: &DepartmentTree,
+    depart: &str,
+) -> Result<Vec<User>, Response> {
+    Ok(Query::new("SELECT u.* FROM user u WHERE ")
+        .cond(&Cond::in_list("u.department", tree.descendants(depart)))
+        .push(" AND NOT EXISTS (SELECT 1 FROM leaver l WHERE l.id=u.id)")
+        .fetch(conn)?)
+}
+
+async fn query_depart_count(u: AuthUser, Path(depart): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let tree = DepartmentTree::load(&mut conn)?;
+    let depart = op::ternary!(depart.eq("my") => u.department.clone(); depart);
+    if depart.ne("all") {
+        ver_depart_perm(&u, &tree, &depart).await?;
+    } else {
+        u.can(OtherPerm::CompanyStaffData, Scope::Any).await?;
+    }
     let count: usize = match depart.as_str() {
         "all" => conn
             .query::<i32, &str>(
@@ -157,74 +250,53 @@ async fn query_depart_count(header: HeaderMap, Path(depart): Path<String>) -> Re
                 (SELECT 1 FROM leaver l WHERE l.id=u.id)",
             )?
             .len(),
-        _ => conn
-            .query::<i32, String>(format!(
-                "SELECT 1 FROM user u WHERE u.department='{}' AND NOT EXISTS 
-                (SELECT 1 FROM leaver l WHERE l.id=u.id)",
-                op::ternary!(depart.eq("my")
-            => &u.department; &depart)
-            ))?
-            .len(),
+        _ => query_depart_users(&mut conn, &tree, &depart)?.len(),
     };
     Ok(Response::ok(json!(count)))
 }
 
-async fn query_full_data(header: HeaderMap, Path(id): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let _id = parse_jwt_macro!(&bearer, &mut conn => true);
+async fn query_full_data(u: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let user: Option<User> =
-        conn.query_first(format!("SELECT * FROM user WHERE id = '{id}' LIMIT 1"))?;
+        conn.exec_first("SELECT * FROM user WHERE id = ? LIMIT 1", (&id,))?;
+    if let Some(user) = &user {
+        if user.id != u.id {
+            let tree = DepartmentTree::load(&mut conn)?;
+            ver_depart_perm(&u, &tree, &user.department).await?;
+        }
+    }
     Ok(Response::ok(json!(user)))
 }
 
-async fn query_list_data(header: HeaderMap, Path(depart): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = parse_jwt_macro!(&bearer, &mut conn => true);
-    let u = get_user(&id, &mut conn).await?;
-    let data: Vec<Value> = match depart.as_str() {
+async fn query_list_data(u: AuthUser, Path(depart): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let tree = DepartmentTree::load(&mut conn)?;
+    let users: Vec<User> = match depart.as_str() {
         "all" => {
-            if !verify_permissions(&u.role, "other", "company_staff_data", None).await {
-                return Err(Response::permission_denied());
-            }
-            let users: Vec<User> = conn.query(
+            u.can(OtherPerm::CompanyStaffData, Scope::Any).await?;
+            conn.query(
                 "SELECT u.* FROM user u WHERE NOT EXISTS 
                    (SELECT 1 FROM leaver l WHERE l.id=u.id)",
-            )?;
-            let mut map: HashMap<String, Vec<User>> = HashMap::new();
-            for u in users {
-                map.entry(u.department.clone()).or_default().push(u);
-            }
-            map.into_iter()
-                .map(|(k, v)| {
-                    json!({
-                        "department": k,
-                        "data": v
-                    })
-                })
-                .collect()
+            )?
         }
         _ => {
-            if !depart.eq("my")
-                && !verify_permissions(&u.role, "other", "company_staff_data", None).await
-            {
-                return Err(Response::permission_denied());
-            }
             let d = op::ternary!(depart.eq("my") => &u.department; &depart);
-            let query = format!(
-                "SELECT u.* FROM user u WHERE u.department='{d}' AND NOT EXISTS 
-                (SELECT 1 FROM leaver l WHERE l.id=u.id)"
-            );
-            println!("{}", query);
-            let users: Vec<User> = conn.query(query)?;
-            vec![json!({
-                "department": d,
-                "data": users
-            })]
+            ver_depart_perm(&u, &tree, d).await?;
+            query_depart_users(&mut conn, &tree, d)?
         }
     };
+    let mut map: HashMap<String, Vec<User>> = HashMap::new();
+    for u in users {
+        map.entry(u.department.clone()).or_default().push(u);
+    }
+    let data: Vec<Value> = map
+        .into_iter()
+        .map(|(k, v)| {
+            json!({
+                "department": k,
+                "data": v
+            })
+        })
+        .collect();
     Ok(Response::ok(json!(data)))
 }
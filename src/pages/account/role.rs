@@ -0,0 +1,344 @@
+use axum::{extract::Path, Json};
+use mysql::{params, prelude::Queryable, PooledConn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{get_user, DepartmentTree, User};
+use crate::{
+    database::get_db,
+    libs::{
+        cache::USER_CACHE,
+        gen_id,
+        perm::{RolePermission, PERMISSION_TREE},
+        TIME,
+    },
+    log,
+    perm::{
+        auth::{AuthUser, RolePerm, Scope},
+        roles::{self, role_to_name},
+        store::{modify_role_map, modify_role_map_with},
+        PermissionGroupMap, ROLES_GROUP_MAP,
+    },
+    Response, ResponseResult,
+};
+
+/// 内置角色，不能修改或删除
+const BUILTIN_ROLES: [&str; 4] = ["root", "admin", "manager", "salesman"];
+
+/// 验证角色管理的权限，`all`可以管理所有角色和员工，
+/// 否则只能管理本部门及下级部门创建的角色和员工
+async fn ver_role_perm(
+    conn: &mut PooledConn,
+    adm: &User,
+    action: RolePerm,
+    department: Option<&str>,
+) -> Result<(), Response> {
+    let denied = match adm.can(action, Scope::All).await {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    adm.can(action, Scope::Any).await?;
+    let tree = DepartmentTree::load(conn)?;
+    if department.is_some_and(|d| tree.contains(&adm.department, d)) {
+        Ok(())
+    } else {
+        Err(denied.into())
+    }
+}
+
+/// 角色修改后重新加载角色表
+fn reload_roles(conn: &mut PooledConn) -> Result<(), Response> {
+    roles::reload_roles(conn)?;
+    Ok(())
+}
+
+fn role_department(conn: &mut PooledConn, id: &str) -> Result<Option<String>, Response> {
+    let department: Option<Option<String>> =
+        conn.exec_first("SELECT department FROM roles WHERE id = ? LIMIT 1", (id,))?;
+    Ok(op::some!(department; ret Err(Response::not_exist("角色不存在"))))
+}
+
+fn check_role_name(conn: &mut PooledConn, name: &str) -> Result<(), Response> {
+    if name.is_empty() || name.chars().count() > 50 {
+        return Err(Response::invalid_value(
+            "角色名称不能为空且不能超过50个字符",
+        ));
+    }
+    let exist: Option<i32> =
+        conn.exec_first("SELECT 1 FROM roles WHERE name = ? LIMIT 1", (name,))?;
+    if exist.is_some() {
+        return Err(Response::already_exist("角色名称已存在"));
+    }
+    Ok(())
+}
+
+/// 所有角色名称，用于检查数据范围
+fn role_names(conn: &mut PooledConn) -> Result<Vec<String>, Response> {
+    Ok(conn.query("SELECT name FROM roles")?)
+}
+
+#[derive(Deserialize)]
+struct CreateParams {
+    name: String,
+}
+
+pub async fn create_role(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let params: CreateParams = serde_json::from_value(value)?;
+    let name = params.name.trim();
+    log!("{user} 请求创建角色 {name}");
+    if let Err(e) = user.can(RolePerm::Create, Scope::Any).await {
+        log!("{user} 创建角色失败，原因权限不足");
+        return Err(e.into());
+    }
+    check_role_name(&mut conn, name)?;
+    let id = gen_id(&TIME::now()?, name);
+    let insert = |conn: &mut PooledConn| {
+        conn.exec_drop(
+            "INSERT INTO roles (id, name, department) VALUES (:id, :name, :department)",
+            params! {
+                "id" => &id,
+                "name" => name,
+                "department" => &user.department
+            },
+        )?;
+        Ok(())
+    };
+    modify_role_map_with(&mut conn, &user.id, insert, |map| {
+        map.insert(id.clone(), RolePermission::default());
+    })
+    .await?;
+    reload_roles(&mut conn)?;
+    log!("{user} 成功创建角色 {name}");
+    Ok(Response::ok(json!(id)))
+}
+
+#[derive(Deserialize)]
+struct UpdateParams {
+    id: String,
+    name: String,
+}
+
+pub async fn update_role(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let params: UpdateParams = serde_json::from_value(value)?;
+    let name = params.name.trim();
+    log!("{user} 请求将角色 {} 改名为 {name}", params.id);
+    if BUILTIN_ROLES.contains(&params.id.as_str()) {
+        return Err(Response::dissatisfy("内置角色不能修改"));
+    }
+    let department = role_department(&mut conn, &params.id)?;
+    if let Err(e) = ver_role_perm(&mut conn, &user, RolePerm::Update, department.as_deref()).await {
+        log!("{user} 修改角色失败，原因权限不足");
+        return Err(e);
+    }
+    check_role_name(&mut conn, name)?;
+    let old = role_to_name(&params.id);
+    let rename = |conn: &mut PooledConn| {
+        conn.exec_drop(
+            "UPDATE roles SET name = ? WHERE id = ? LIMIT 1",
+            (name, &params.id),
+        )?;
+        Ok(())
+    };
+    modify_role_map_with(&mut conn, &user.id, rename, |map| {
+        for perms in map.values_mut() {
+            perms.rename_scope(&old, Some(name));
+        }
+    })
+    .await?;
+    reload_roles(&mut conn)?;
+    log!("{user} 成功将角色 {old} 改名为 {name}");
+    Ok(Response::empty())
+}
+
+pub async fn delete_role(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    log!("{user} 请求删除角色 {id}");
+    if BUILTIN_ROLES.contains(&id.as_str()) {
+        return Err(Response::dissatisfy("内置角色不能删除"));
+    }
+    let department = role_department(&mut conn, &id)?;
+    if let Err(e) = ver_role_perm(&mut conn, &user, RolePerm::Delete, department.as_deref()).await {
+        log!("{user} 删除角色失败，原因权限不足");
+        return Err(e);
+    }
+    // 包括离职员工，恢复账号后角色仍然需要存在
+    let count: Option<usize> =
+        conn.exec_first("SELECT COUNT(*) FROM user WHERE role = ?", (&id,))?;
+    if count.unwrap_or(0) > 0 {
+        return Err(Response::dissatisfy(format!(
+            "还有{}个员工属于该角色，请先调动这些员工",
+            count.unwrap_or(0)
+        )));
+    }
+    let name = role_to_name(&id);
+    let delete = |conn: &mut PooledConn| {
+        conn.exec_drop("DELETE FROM roles WHERE id = ? LIMIT 1", (&id,))?;
+        Ok(())
+    };
+    modify_role_map_with(&mut conn, &user.id, delete, |map| {
+        map.remove(&id);
+        for perms in map.values_mut() {
+            perms.rename_scope(&name, None);
+        }
+    })
+    .await?;
+    reload_roles(&mut conn)?;
+    log!("{user} 成功删除角色 {name}");
+    Ok(Response::empty())
+}
+
+#[derive(Deserialize)]
+struct ChangeParams {
+    user: String,
+    role: String,
+}
+
+/// 需要可以管理员工所在的部门和调动到的角色，调动到内置角色需要`all`的数据范围
+async fn ver_change_role(
+    conn: &mut PooledConn,
+    adm: &User,
+    target: &User,
+    role: &str,
+    department: Option<String>,
+) -> Result<(), Response> {
+    if BUILTIN_ROLES.contains(&role) {
+        adm.can(RolePerm::ChangeRole, Scope::All).await?;
+    } else {
+        ver_role_perm(conn, adm, RolePerm::ChangeRole, department.as_deref()).await?;
+    }
+    ver_role_perm(conn, adm, RolePerm::ChangeRole, Some(&target.department)).await
+}
+
+/// 角色调动
+pub async fn change_role(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let params: ChangeParams = serde_json::from_value(value)?;
+    let target = get_user(&params.user, &mut conn).await?;
+    log!("{user} 请求将 {target} 调动到角色 {}", params.role);
+    if target.role.eq("root") || params.role.eq("root") || target.id.eq(&user.id) {
+        return Err(Response::dissatisfy("不能调动自己或总经理"));
+    }
+    let department = role_department(&mut conn, &params.role)?;
+    if let Err(e) = ver_change_role(&mut conn, &user, &target, &params.role, department).await {
+        log!("{user} 调动 {target} 失败，原因权限不足");
+        return Err(e);
+    }
+    conn.exec_drop(
+        "UPDATE user SET role = ? WHERE id = ? LIMIT 1",
+        (&params.role, &target.id),
+    )?;
+    USER_CACHE.remove(&target.id);
+    log!("{user} 成功将 {target} 调动到角色 {}", params.role);
+    Ok(Response::empty())
+}
+
+/// 可以查询自己的角色和有权修改的角色的权限
+async fn ver_query_perms(conn: &mut PooledConn, user: &User, id: &str) -> Result<(), Response> {
+    let department = role_department(conn, id)?;
+    if user.role.eq(id) {
+        return Ok(());
+    }
+    ver_role_perm(conn, user, RolePerm::Update, department.as_deref()).await
+}
+
+async fn ver_update_perms(conn: &mut PooledConn, user: &User, id: &str) -> Result<(), Response> {
+    if id.eq("root") {
+        return Err(Response::dissatisfy("总经理拥有所有权限，不能修改"));
+    }
+    if user.role.eq(id) {
+        log!("{user} 修改角色权限失败，不能修改自己的角色");
+        return Err(Response::dissatisfy("不能修改自己角色的权限"));
+    }
+    let department = role_department(conn, id)?;
+    let result = ver_role_perm(conn, user, RolePerm::Update, department.as_deref()).await;
+    if result.is_err() {
+        log!("{user} 修改角色权限失败，原因权限不足");
+    }
+    result
+}
+
+/// 只能授予自己拥有的操作和数据范围，root除外
+async fn check_grants(user: &User, id: &str, tree: &RolePermission) -> Result<(), Response> {
+    if user.role.eq("root") {
+        return Ok(());
+    }
+    let map = ROLES_GROUP_MAP.lock().await;
+    let granter = map.get(&user.role).cloned().unwrap_or_default();
+    let current = map.get(id).cloned().unwrap_or_default();
+    drop(map);
+    match tree.ungranted(&current, &granter) {
+        Some(perm) => {
+            log!("{user} 修改角色权限失败，没有{perm}的权限");
+            Err(Response::dissatisfy(format!("不能授予自己没有的权限{perm}")))
+        }
+        None => Ok(()),
+    }
+}
+
+/// 查询角色的权限，`权限组 -> 操作 -> 数据范围`
+pub async fn query_role_perms(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    ver_query_perms(&mut conn, &user, &id).await?;
+    let map = ROLES_GROUP_MAP.lock().await;
+    Ok(Response::ok(json!(map
+        .get(&id)
+        .map(RolePermission::to_group_map)
+        .unwrap_or_default())))
+}
+
+/// 覆盖角色的权限，修改记录在`role_perm_log`中
+pub async fn update_role_perms(
+    user: AuthUser,
+    Path(id): Path<String>,
+    Json(value): Json<Value>,
+) -> ResponseResult {
+    let mut conn = get_db().await?;
+    log!("{user} 请求修改角色 {} 的权限", role_to_name(&id));
+    ver_update_perms(&mut conn, &user, &id).await?;
+    let perms: PermissionGroupMap = serde_json::from_value(value)?;
+    let tree = RolePermission::from_group_map_checked(&perms, &role_names(&mut conn)?)?;
+    check_grants(&user, &id, &tree).await?;
+    modify_role_map(&mut conn, &user.id, |map| {
+        map.insert(id.clone(), tree);
+    })
+    .await?;
+    log!("{user} 成功修改角色 {} 的权限", role_to_name(&id));
+    Ok(Response::empty())
+}
+
+/// 查询角色的权限树，用于前端显示勾选框
+pub async fn query_role_tree(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    ver_query_perms(&mut conn, &user, &id).await?;
+    let mut tree = ROLES_GROUP_MAP
+        .lock()
+        .await
+        .get(&id)
+        .filter(|t| !t.groups().is_empty())
+        .cloned()
+        .unwrap_or_else(|| PERMISSION_TREE.clone());
+    tree.expand_role_scopes(&role_names(&mut conn)?);
+    Ok(Response::ok(json!(tree)))
+}
+
+/// 使用前端提交的权限树覆盖角色的权限
+pub async fn update_role_tree(
+    user: AuthUser,
+    Path(id): Path<String>,
+    Json(value): Json<Value>,
+) -> ResponseResult {
+    let mut conn = get_db().await?;
+    log!("{user} 请求修改角色 {} 的权限", role_to_name(&id));
+    ver_update_perms(&mut conn, &user, &id).await?;
+    let selection: RolePermission = serde_json::from_value(value)?;
+    let tree = RolePermission::from_selection(&selection, &role_names(&mut conn)?)?;
+    check_grants(&user, &id, &tree).await?;
+    modify_role_map(&mut conn, &user.id, |map| {
+        map.insert(id.clone(), tree);
+    })
+    .await?;
+    log!("{user} 成功修改角色 {} 的权限", role_to_name(&id));
+    Ok(Response::empty())
+}
@@ -0,0 +1,55 @@
+use axum::extract::Path;
+use mysql::prelude::Queryable;
+use mysql_common::prelude::FromRow;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    commit_or_rollback, database::get_db, log, perm::auth::AuthUser, token::delete_session,
+    Response, ResponseResult,
+};
+
+#[derive(Debug, Serialize, FromRow)]
+struct Session {
+    id: String,
+    device: String,
+    ip: String,
+    user_agent: String,
+    create_time: String,
+    last_seen: String,
+}
+
+/// 当前用户所有登录中的会话，`current`表示发起请求的会话
+pub async fn query_sessions(user: AuthUser) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let sessions: Vec<Session> = conn.exec(
+        "SELECT id, device, ip, user_agent, create_time, last_seen FROM session
+        WHERE ty = 0 AND user = ? ORDER BY last_seen DESC",
+        (&user.id,),
+    )?;
+    let data: Vec<_> = sessions
+        .into_iter()
+        .map(|s| {
+            let current = s.id == user.sid();
+            let mut value = json!(s);
+            value["current"] = json!(current);
+            value
+        })
+        .collect();
+    Ok(Response::ok(json!(data)))
+}
+
+/// 注销自己的某个会话，该会话的token立即失效
+pub async fn revoke_session(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let exist: Option<i32> = conn.exec_first(
+        "SELECT 1 FROM session WHERE id = ? AND ty = 0 AND user = ? LIMIT 1",
+        (&id, &user.id),
+    )?;
+    if exist.is_none() {
+        return Err(Response::not_exist("会话不存在"));
+    }
+    commit_or_rollback!(delete_session, &mut conn, &id)?;
+    log!("{user} 注销了会话 {id}");
+    Ok(Response::empty())
+}
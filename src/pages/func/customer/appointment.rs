@@ -1,20 +1,19 @@
 use axum::extract::Path;
 use axum::routing::{delete, get, post};
-use axum::{http::HeaderMap, Json, Router};
+use axum::{Json, Router};
 use mysql::{prelude::Queryable, PooledConn};
 use mysql_common::prelude::FromRow;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use crate::database::{get_db, DB};
+use crate::database::get_db;
 use crate::libs::dser::deser_yyyy_mm_dd_hh_mm_ss;
 use crate::libs::TimeFormat;
-use crate::perm::action::CustomerGroup;
-use crate::perm::get_role;
+use crate::pages::User;
+use crate::perm::auth::{AuthUser, CustomerPerm, Scope};
 use crate::{
-    bearer,
     libs::{gen_id, TIME},
-    parse_jwt_macro, Response, ResponseResult,
+    Response, ResponseResult,
 };
 
 pub fn appointment_router() -> Router {
@@ -55,84 +54,81 @@ struct InsertParams {
 // 安排业务员拜访客户需要验证权限
 // 修改和删除拜访需要拜访发起者
 // 完成拜访需要拜访者
-use crate::{commit_or_rollback, verify_perms};
+use crate::commit_or_rollback;
 
 use super::CUSTOMER_CACHE;
 async fn add_appointments(
-    header: HeaderMap,
+    user: AuthUser,
     Json(value): Json<serde_json::Value>,
 ) -> ResponseResult {
-    let bearer = bearer!(&header);
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
+    let mut conn = get_db().await?;
     let params: Vec<InsertParams> = serde_json::from_value(value)?;
-    commit_or_rollback!(async __add_appoint, &mut conn, (&params, &uid))?;
+    commit_or_rollback!(async __add_appoint, &mut conn, (&params, &user))?;
     CUSTOMER_CACHE.clear();
     Ok(Response::empty())
 }
 
 async fn __add_appoint(
     conn: &mut PooledConn,
-    (params, uid): (&[InsertParams], &str),
+    (params, user): (&[InsertParams], &User),
 ) -> Result<(), Response> {
-    let role = get_role(uid, conn)?;
-    let flag = verify_perms!(&role, CustomerGroup::NAME, CustomerGroup::ADD_APPOINT);
+    let uid = user.id.as_str();
+    let allowed = user.can(CustomerPerm::AddAppoint, Scope::Any).await;
     for param in params {
         let time = TIME::now()?;
-        if !param.salesman.eq(uid) && !flag {
-            return Err(Response::permission_denied());
+        if !param.salesman.eq(uid) {
+            allowed.clone()?;
         }
         let id = gen_id(&time, &rand::random::<i32>().to_string());
-        conn.query_drop(format!(
+        conn.exec_drop(
             "INSERT INTO appointment 
             (id, customer, applicant, salesman, appointment, finish_time, theme, content) VALUES (
-                '{}', '{}', '{}', '{}', '{}', NULL, '{}', '{}'
+                ?, ?, ?, ?, ?, NULL, ?, ?
             )",
-            id, param.customer, uid, param.salesman, param.appointment, param.theme, param.content
-        ))?;
+            (
+                &id,
+                &param.customer,
+                uid,
+                &param.salesman,
+                &param.appointment,
+                &param.theme,
+                &param.content,
+            ),
+        )?;
     }
     Ok(())
 }
 
-async fn delete_appointment(header: HeaderMap, Path(id): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header);
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    commit_or_rollback!(async __delete_appointment, &mut conn, &id, &uid)?;
+async fn delete_appointment(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    commit_or_rollback!(async __delete_appointment, &mut conn, &id, &user.id)?;
     CUSTOMER_CACHE.clear();
     Ok(Response::empty())
 }
 
-async fn __delete_appointment<'err>(conn: &mut DB<'err>, id: &str, uid: &str) -> Result<(), Response> {
-    let _: String = op::some!(conn.query_first(
-        format!("select 1 from appointment where id = '{id}' and applicant='{uid}' LIMIT 1"))?;
+async fn __delete_appointment(conn: &mut PooledConn, id: &str, uid: &str) -> Result<(), Response> {
+    let _: String = op::some!(conn.exec_first(
+        "select 1 from appointment where id = ? and applicant = ? LIMIT 1", (id, uid))?;
         ret Err(Response::permission_denied())
     );
-    conn.query_drop(format!("delete from appointment where id = '{id}' limit 1"))?;
-    conn.query_drop(format!(
-        "delete from appoint_comment where appoint = '{id}'"
-    ))?;
+    conn.exec_drop("delete from appointment where id = ? limit 1", (id,))?;
+    conn.exec_drop("delete from appoint_comment where appoint = ?", (id,))?;
 
     Ok(())
 }
 
-async fn finish_appointment(header: HeaderMap, Path(id): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header);
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let _: String = op::some!(conn.query_first(
-        format!("select 1 from appointment where id = '{id}' and salesman='{uid}' LIMIT 1"))?;
+async fn finish_appointment(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let _: String = op::some!(conn.exec_first(
+        "select 1 from appointment where id = ? and salesman = ? LIMIT 1", (&id, &user.id))?;
         ret Err(Response::permission_denied())
     );
     let time = TIME::now()?;
     let finish_time = time.format(TimeFormat::YYYYMMDD_HHMMSS);
-    conn.query_drop(format!(
-        "UPDATE appointment SET finish_time = '{}' WHERE id = '{}' LIMIT 1",
-        finish_time, id
-    ))?;
+    conn.exec_drop(
+        "UPDATE appointment SET finish_time = ? WHERE id = ? LIMIT 1",
+        (&finish_time, &id),
+    )?;
     CUSTOMER_CACHE.clear();
     Ok(Response::ok(json!(finish_time)))
 }
@@ -149,24 +145,28 @@ struct UpdateParams {
 }
 
 async fn update_appointment(
-    header: HeaderMap,
+    user: AuthUser,
     Json(value): Json<serde_json::Value>,
 ) -> ResponseResult {
-    let bearer = bearer!(&header);
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
+    let mut conn = get_db().await?;
 
     let data: UpdateParams = serde_json::from_value(value)?;
-    let _: String = op::some!(conn.query_first(
-        format!("select 1 from appointment where id = '{}' and applicant='{uid}' LIMIT 1", data.id))?;
+    let _: String = op::some!(conn.exec_first(
+        "select 1 from appointment where id = ? and applicant = ? LIMIT 1", (&data.id, &user.id))?;
         ret Err(Response::permission_denied())
     );
-    conn.query_drop(format!(
-        "update appointment set salesman='{}', appointment='{}', theme='{}', content='{}' 
-        where id='{}' and applicant='{}' limit 1",
-        data.visitor, data.appointment, data.theme, data.content, data.id, uid
-    ))?;
+    conn.exec_drop(
+        "update appointment set salesman = ?, appointment = ?, theme = ?, content = ? 
+        where id = ? and applicant = ? limit 1",
+        (
+            &data.visitor,
+            &data.appointment,
+            &data.theme,
+            &data.content,
+            &data.id,
+            &user.id,
+        ),
+    )?;
     CUSTOMER_CACHE.clear();
     Ok(Response::empty())
 }
@@ -210,23 +210,24 @@ struct Comment {
 }
 
 async fn query_appointment(Path((id, limit)): Path<(String, usize)>) -> ResponseResult {
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let res: Vec<AppointmentResponse> = conn.query(format!(
-        "SELECT app.*, a.name as applicant_name, s.name as salesman_name FROM appointment app
-        JOIN user a ON a.id = app.applicant
+    let mut conn = get_db().await?;
+    let res: Vec<AppointmentResponse> = conn.exec(
+        "SELECT app.*, COALESCE(a.name, ac.name) as applicant_name, s.name as salesman_name
+        FROM appointment app
+        LEFT JOIN user a ON a.id = app.applicant
+        LEFT JOIN customer ac ON ac.id = app.applicant
         JOIN user s ON s.id = app.salesman
-        WHERE app.customer = '{}' ORDER BY appointment DESC LIMIT {limit}",
-        id
-    ))?;
+        WHERE app.customer = ? ORDER BY appointment DESC LIMIT ?",
+        (&id, limit),
+    )?;
     let mut data = Vec::new();
     for a in res {
-        let comments = conn.query(format!(
+        let comments = conn.exec(
             "SELECT com.*, a.name as applicant_name FROM appoint_comment com 
             JOIN user a ON a.id = com.applicant
-            WHERE com.appoint = '{}'",
-            a.id
-        ))?;
+            WHERE com.appoint = ?",
+            (&a.id,),
+        )?;
         data.push(join_to_json(&a, &comments));
     }
 
@@ -238,25 +239,21 @@ struct InsertCommentParams {
     appoint: String,
 }
 
-async fn insert_comment(header: HeaderMap, Json(value): Json<serde_json::Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
+async fn insert_comment(user: AuthUser, Json(value): Json<serde_json::Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let data: InsertCommentParams = serde_json::from_value(value)?;
     let time = TIME::now()?;
     let id = gen_id(&time, "comment");
     let create_time = time.format(TimeFormat::YYYYMMDD_HHMMSS);
-    conn.query_drop(format!(
+    conn.exec_drop(
         "INSERT INTO appoint_comment (id, applicant, appoint, create_time, comment) VALUES (
-        '{id}', '{uid}', '{}', '{}', '{}'
+        ?, ?, ?, ?, ?
     )",
-        data.appoint, create_time, data.comment
-    ))?;
-    let name: Option<String> =
-        conn.query_first(format!("select name from user where id = '{uid}' limit 1"))?;
+        (&id, &user.id, &data.appoint, &create_time, &data.comment),
+    )?;
+    let name: Option<String> = conn.exec_first("select name from user where id = ? limit 1", (&user.id,))?;
     Ok(Response::ok(json!({
-        "applicant": uid,
+        "applicant": user.id,
         "applicant_name": name,
         "id": id,
         "appoint": data.appoint,
@@ -270,41 +267,33 @@ struct UpdateCommentParams {
     comment: String,
 }
 
-async fn update_comment(header: HeaderMap, Json(value): Json<serde_json::Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
+async fn update_comment(user: AuthUser, Json(value): Json<serde_json::Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let data: UpdateCommentParams = serde_json::from_value(value)?;
-    conn.query_drop(format!(
-        "UPDATE appoint_comment SET comment = '{}' WHERE id = '{}' AND applicant = '{uid}' LIMIT 1
-    ",
-        data.comment, data.id
-    ))?;
+    conn.exec_drop(
+        "UPDATE appoint_comment SET comment = ? WHERE id = ? AND applicant = ? LIMIT 1",
+        (&data.comment, &data.id, &user.id),
+    )?;
     Ok(Response::empty())
 }
 
-async fn delete_comment(header: HeaderMap, Path(id): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header);
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    conn.query_drop(format!(
-        "DELETE FROM appoint_comment WHERE id = '{id}' AND applicant = '{uid}' LIMIT 1"
-    ))?;
+async fn delete_comment(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    conn.exec_drop(
+        "DELETE FROM appoint_comment WHERE id = ? AND applicant = ? LIMIT 1",
+        (&id, &user.id),
+    )?;
     Ok(Response::empty())
 }
-async fn query_comment(header: HeaderMap, Path(id): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header);
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let _uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let comments: Vec<Comment> = conn.query(format!(
+async fn query_comment(_user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let comments: Vec<Comment> = conn.exec(
         "select c.*, u.name as applicant_name 
         from appoint_comment c 
         join user u on u.id=c.applicant 
-        where c.appoint='{id}' 
-        order by c.create_time"
-    ))?;
+        where c.appoint = ? 
+        order by c.create_time",
+        (&id,),
+    )?;
     Ok(Response::ok(json!(comments)))
 }
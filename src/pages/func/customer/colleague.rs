@@ -1,6 +1,5 @@
 use axum::{
     extract::Path,
-    http::HeaderMap,
     routing::{delete, get, post},
     Json, Router,
 };
@@ -9,12 +8,11 @@ use mysql_common::prelude::FromRow;
 use serde_json::{json, Value};
 
 use crate::{
-    bearer,
     database::get_db,
     libs::{gen_id, time::TIME, TimeFormat},
     log,
-    pages::account::get_user,
-    parse_jwt_macro, Response, ResponseResult,
+    perm::auth::AuthUser,
+    Response, ResponseResult,
 };
 
 pub fn colleague_router() -> Router {
@@ -38,33 +36,31 @@ struct Colleague {
 use super::index::check_user_customer;
 
 async fn insert_colleague(
-    headers: HeaderMap,
+    user: AuthUser,
     Path(customer): Path<String>,
     Json(value): Json<Value>,
 ) -> ResponseResult {
-    let bearer = bearer!(&headers);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&id, &mut conn).await?;
+    let mut conn = get_db().await?;
     let mut params: Colleague = serde_json::from_value(value)?;
     log!(
         "{user} 发起添加客户联系人请求，客户{}, 客户联系人{}",
         params.id,
         params.name
     );
-    check_user_customer(&id, &customer, &mut conn)?;
+    check_user_customer(&user.id, &customer, &mut conn)?;
     let time = TIME::now()?;
     params.id = gen_id(&time, &params.name);
-    conn.query_drop(format!(
+    conn.exec_drop(
         "INSERT INTO customer_colleague (id, customer, phone, name, create_time) VALUES (
-        '{}', '{}', '{}', '{}', '{}')",
-        params.id,
-        customer,
-        params.phone,
-        params.name,
-        time.format(TimeFormat::YYYYMMDD_HHMMSS)
-    ))?;
+        ?, ?, ?, ?, ?)",
+        (
+            &params.id,
+            &customer,
+            &params.phone,
+            &params.name,
+            time.format(TimeFormat::YYYYMMDD_HHMMSS),
+        ),
+    )?;
 
     log!(
         "{user} 成功添加客户联系人，客户{}, 客户联系人{}",
@@ -74,29 +70,25 @@ async fn insert_colleague(
     Ok(Response::ok(json!(params.id)))
 }
 
-async fn update_colleague(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&headers);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = parse_jwt_macro!(&bearer, &mut conn => true);
+async fn update_colleague(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let params: Colleague = serde_json::from_value(value)?;
-    check(&id, &params.id, &mut conn)?;
-    conn.query_drop(format!(
-        "UPDATE customer_colleague SET phone = '{}', name = '{}' WHERE  id = '{}' LIMIT 1",
-        params.phone, params.name, params.id,
-    ))?;
+    check(&user.id, &params.id, &mut conn)?;
+    conn.exec_drop(
+        "UPDATE customer_colleague SET phone = ?, name = ? WHERE  id = ? LIMIT 1",
+        (&params.phone, &params.name, &params.id),
+    )?;
     Ok(Response::empty())
 }
 
 fn check(id: &str, col: &str, conn: &mut PooledConn) -> Result<(), Response> {
-    let query = format!(
+    let flag: Option<String> = conn.exec_first(
         "SELECT 1 FROM user u
         JOIN extra_customer_data ex ON ex.salesman=u.id
          JOIN customer_colleague c ON c.customer = ex.id
-         WHERE c.id='{col}' AND u.id = '{id}' LIMIT 1"
-    );
-    println!("{}", query);
-    let flag: Option<String> = conn.query_first(query)?;
+         WHERE c.id = ? AND u.id = ? LIMIT 1",
+        (col, id),
+    )?;
     if flag.is_some() {
         Ok(())
     } else {
@@ -104,25 +96,18 @@ fn check(id: &str, col: &str, conn: &mut PooledConn) -> Result<(), Response> {
     }
 }
 
-async fn delete_colleague(headers: HeaderMap, Path(id): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&headers);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let user_id = parse_jwt_macro!(&bearer, &mut conn => true);
-    check(&user_id, &id, &mut conn)?;
-    conn.query_drop(format!(
-        "DELETE FROM customer_colleague WHERE id = '{}' LIMIT 1",
-        id,
-    ))?;
+async fn delete_colleague(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    check(&user.id, &id, &mut conn)?;
+    conn.exec_drop("DELETE FROM customer_colleague WHERE id = ? LIMIT 1", (&id,))?;
     Ok(Response::empty())
 }
 
 async fn query_colleagues(Path(customer): Path<String>) -> ResponseResult {
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let data: Vec<Colleague> = conn.query(format!(
-        "SELECT id, name, phone FROM customer_colleague WHERE customer='{}' ORDER BY create_time",
-        customer
-    ))?;
+    let mut conn = get_db().await?;
+    let data: Vec<Colleague> = conn.exec(
+        "SELECT id, name, phone FROM customer_colleague WHERE customer = ? ORDER BY create_time",
+        (&customer,),
+    )?;
     Ok(Response::ok(json!(data)))
 }
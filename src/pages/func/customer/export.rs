@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use axum::{routing::post, Json, Router};
+use mysql::{prelude::Queryable, PooledConn};
+use serde_json::Value;
+
+use crate::{
+    database::{get_db, query::Cond},
+    libs::{sheet::Sheet, TimeFormat, TIME},
+    log,
+    pages::{
+        account::{get_user, User},
+        DepartmentTree,
+        STATIC_CUSTOM_FIELDS,
+    },
+    perm::auth::{AuthUser, CustomerPerm, Scope},
+    response::BodyFile,
+    Response,
+};
+
+use super::index::{__query_customer_list, sex_name, ListData, QueryParams};
+
+pub fn export_router() -> Router {
+    Router::new().route("/customer/export", post(export_customer))
+}
+
+const HEADERS: [&str; 14] = [
+    "手机号",
+    "姓名",
+    "公司",
+    "业务员",
+    "客户等级",
+    "性别",
+    "地址",
+    "客户类型",
+    "客户状态",
+    "创建时间",
+    "已拜访次数",
+    "下次拜访时间",
+    "上次拜访时间",
+    "上次成交时间",
+];
+
+fn row(d: ListData) -> Vec<String> {
+    vec![
+        d.smartphone,
+        d.name,
+        d.company,
+        d.salesman_name.unwrap_or_default(),
+        d.level,
+        sex_name(d.sex).to_owned(),
+        d.address,
+        d.ty,
+        d.status,
+        d.create_time,
+        d.visited_count.to_string(),
+        d.next_visit_time.unwrap_or_default(),
+        d.last_visited_time.unwrap_or_default(),
+        d.last_transaction_time.unwrap_or_default(),
+    ]
+}
+
+/// 根据导出权限的范围(自己、部门、所有)确定业务员和部门条件，部门包括下级部门，
+/// 没有指定业务员和部门时导出权限范围内的所有客户
+async fn export_scope(
+    conn: &mut PooledConn,
+    params: &QueryParams,
+    u: &User,
+) -> Result<(Cond, Cond), Response> {
+    u.can(CustomerPerm::ExportData, Scope::Any).await?;
+    let all_perm = u.can(CustomerPerm::ExportData, Scope::All).await;
+    let all = all_perm.is_ok();
+    let department = all
+        || u.can(CustomerPerm::ExportData, Scope::Department)
+            .await
+            .is_ok();
+    let scope_denied = || {
+        all_perm
+            .clone()
+            .err()
+            .map_or_else(Response::permission_denied, Response::from)
+    };
+    let salesman = params.salesman.as_str();
+    let depart = op::ternary!(params.department.eq("my") => u.department.as_str(), params.department.as_str());
+    let tree = DepartmentTree::load(conn)?;
+    let subtree = |d: &str| Cond::in_list("u.department", tree.descendants(d));
+    let any_salesman = || Cond::not_null("ex.salesman");
+    let any_department = || Cond::not_null("u.department");
+    if salesman.eq("my") || salesman.eq(&u.id) {
+        Ok((Cond::eq("ex.salesman", u.id.as_str()), any_department()))
+    } else if !salesman.is_empty() {
+        let sl = get_user(salesman, conn).await?;
+        if all || (department && tree.contains(&u.department, &sl.department)) {
+            Ok((Cond::eq("ex.salesman", sl.id.as_str()), any_department()))
+        } else {
+            Err(scope_denied())
+        }
+    } else if !depart.is_empty() {
+        if all || (department && tree.contains(&u.department, depart)) {
+            Ok((any_salesman(), subtree(depart)))
+        } else {
+            Err(scope_denied())
+        }
+    } else if all {
+        Ok((any_salesman(), any_department()))
+    } else if department {
+        Ok((any_salesman(), subtree(&u.department)))
+    } else {
+        Ok((Cond::eq("ex.salesman", u.id.as_str()), any_department()))
+    }
+}
+
+async fn export_customer(user: AuthUser, Json(value): Json<Value>) -> Result<BodyFile, Response> {
+    let mut conn = get_db().await?;
+    let format = value
+        .get("format")
+        .and_then(|f| f.as_str())
+        .unwrap_or("xlsx")
+        .to_owned();
+    if !matches!(format.as_str(), "csv" | "xlsx") {
+        return Err(Response::invalid_value("format必须为csv或xlsx"));
+    }
+    let params: QueryParams = serde_json::from_value(value)?;
+    log!("{user} 请求导出客户数据");
+    let (salesman, department) = match export_scope(&mut conn, &params, &user).await {
+        Ok(scope) => scope,
+        Err(e) => {
+            log!("{user} 导出客户数据失败，原因权限不足");
+            return Err(e);
+        }
+    };
+    let list = __query_customer_list(&mut conn, &params, &salesman, &department)?;
+
+    let (texts, times, boxes) = unsafe { STATIC_CUSTOM_FIELDS.get_fields(0) };
+    let custom: Vec<&str> = texts.into_iter().chain(times).chain(boxes).collect();
+    let values: HashMap<(String, String), String> = conn
+        .query_map(
+            "SELECT id, display, value FROM custom_field_data WHERE fields = 0",
+            |(id, display, value)| ((id, display), value),
+        )?
+        .into_iter()
+        .collect();
+
+    let mut sheet = Sheet {
+        headers: HEADERS
+            .iter()
+            .chain(custom.iter())
+            .map(|h| h.to_string())
+            .collect(),
+        rows: Vec::with_capacity(list.len()),
+    };
+    for data in list {
+        let id = data.id.clone();
+        let mut cells = row(data);
+        for name in &custom {
+            cells.push(
+                values
+                    .get(&(id.clone(), name.to_string()))
+                    .cloned()
+                    .unwrap_or_default(),
+            );
+        }
+        sheet.rows.push(cells);
+    }
+    let date = TIME::now()?.format(TimeFormat::YYYYMMDD);
+    let (body, mime) = if format.eq("csv") {
+        (sheet.to_csv()?, "text/csv; charset=utf-8")
+    } else {
+        (
+            sheet.to_xlsx()?,
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        )
+    };
+    log!("{user} 成功导出{}个客户", sheet.rows.len());
+    Ok(BodyFile::attachment(
+        body,
+        format!("customers-{date}.{format}"),
+        mime,
+    ))
+}
@@ -0,0 +1,343 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{extract::Multipart, routing::post, Router};
+use mysql::{prelude::Queryable, PooledConn};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    commit_or_rollback,
+    database::get_db,
+    libs::{cache::CUSTOMER_CACHE, parse_multipart, sheet::Sheet},
+    log,
+    pages::{
+        setting::option::check_drop_down_box, STATIC_CUSTOM_BOX_OPTIONS, STATIC_CUSTOM_FIELDS,
+    },
+    perm::auth::{AuthUser, CustomerPerm, Scope},
+    Field, Response, ResponseResult,
+};
+
+use super::index::{__insert_customer, InsertParams};
+
+pub fn import_router() -> Router {
+    Router::new().route("/customer/upload/excel", post(upload_excel))
+}
+
+/// 客户字段，表头可以是字段名或中文名称
+const COLUMNS: [(&str, &str); 19] = [
+    ("smartphone", "手机号"),
+    ("name", "姓名"),
+    ("company", "公司"),
+    ("is_share", "是否共享"),
+    ("sex", "性别"),
+    ("chat", "聊天方式"),
+    ("need", "需求"),
+    ("fax", "传真"),
+    ("post", "邮编"),
+    ("industry", "行业"),
+    ("birthday", "生日"),
+    ("address", "地址"),
+    ("remark", "备注"),
+    ("status", "客户状态"),
+    ("source", "客户来源"),
+    ("level", "客户等级"),
+    ("role", "客户角色"),
+    ("ty", "客户类型"),
+    ("tag", "客户标签"),
+];
+
+/// 需要在下拉框中存在的字段
+const DROP_DOWN_COLUMNS: [(&str, &str); 7] = [
+    ("industry", "industry"),
+    ("status", "customer_status"),
+    ("source", "customer_source"),
+    ("level", "customer_level"),
+    ("role", "customer_role"),
+    ("ty", "customer_type"),
+    ("tag", "customer_tag"),
+];
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum RowStatus {
+    Inserted,
+    Skipped,
+    Rejected,
+}
+
+#[derive(Serialize, Debug)]
+struct RowReport {
+    /// 在表格中的行号，表头为第1行
+    row: usize,
+    name: String,
+    smartphone: String,
+    status: RowStatus,
+    reason: String,
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim() {
+        "" | "0" | "否" | "false" | "FALSE" => Some(false),
+        "1" | "是" | "true" | "TRUE" => Some(true),
+        _ => None,
+    }
+}
+
+/// 与前端一致，男为true，女或为空时为false
+fn parse_sex(value: &str) -> Option<bool> {
+    match value.trim() {
+        "男" => Some(true),
+        "" | "女" => Some(false),
+        _ => None,
+    }
+}
+
+/// 生日只保留`MM-DD`
+fn parse_birthday(value: &str) -> String {
+    let value = value.trim();
+    let bytes = value.as_bytes();
+    if bytes.len() >= 10 && bytes[4] == b'-' && bytes[7] == b'-' {
+        value[5..10].to_owned()
+    } else {
+        value.to_owned()
+    }
+}
+
+/// 将一行数据转换成`InsertParams`，失败时返回原因
+fn parse_row(
+    cells: &HashMap<&str, &str>,
+    salesman: &str,
+    custom_fields: &(Vec<&str>, Vec<&str>, Vec<&str>),
+    box_options: &HashMap<&str, Vec<&str>>,
+) -> Result<InsertParams, String> {
+    let get = |key: &str| cells.get(key).map_or("", |s| s.trim());
+    if get("smartphone").is_empty() || get("name").is_empty() {
+        return Err("手机号和姓名不能为空".to_owned());
+    }
+    if get("smartphone").chars().count() > 15 {
+        return Err("手机号过长".to_owned());
+    }
+    let mut obj = Map::new();
+    for (key, label) in COLUMNS {
+        let value = get(key);
+        let value = match key {
+            "is_share" | "sex" => {
+                let parse = op::ternary!(key == "sex" => parse_sex; parse_bool);
+                let Some(b) = parse(value) else {
+                    return Err(format!("`{label}`的值`{value}`无法识别"));
+                };
+                json!(b)
+            }
+            "birthday" => json!(parse_birthday(value)),
+            _ => json!(value),
+        };
+        obj.insert(key.to_owned(), value);
+    }
+    for (key, name) in DROP_DOWN_COLUMNS {
+        if check_drop_down_box(name, get(key)) == Some(false) {
+            return Err(format!("`{}`不在下拉框选项中", get(key)));
+        }
+    }
+    obj.insert("salesman".to_owned(), json!(salesman));
+
+    let (texts, times, boxes) = custom_fields;
+    let mut fields: HashMap<String, Vec<Field>> = HashMap::new();
+    for (ty, names) in [("texts", texts), ("times", times), ("boxes", boxes)] {
+        let list = fields.entry(ty.to_owned()).or_default();
+        for name in names {
+            let value = get(name);
+            if ty == "boxes"
+                && !value.is_empty()
+                && !box_options
+                    .get(name)
+                    .is_some_and(|options| options.contains(&value))
+            {
+                return Err(format!("自定义字段`{name}`没有`{value}`选项"));
+            }
+            list.push(Field {
+                display: name.to_string(),
+                value: value.to_owned(),
+            });
+        }
+    }
+    obj.insert("custom_fields".to_owned(), json!(fields));
+    serde_json::from_value(Value::Object(obj)).map_err(|e| e.to_string())
+}
+
+fn __import_customers(
+    conn: &mut PooledConn,
+    rows: &[(usize, InsertParams)],
+) -> Result<(), Response> {
+    for (row, params) in rows {
+        if let Err(e) = __insert_customer(conn, params) {
+            log!("导入客户失败，第{}行写入数据库失败", row);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+async fn upload_excel(user: AuthUser, part: Multipart) -> ResponseResult {
+    let mut conn = get_db().await?;
+    log!("{user} 请求导入客户");
+    if let Err(e) = user.can(CustomerPerm::EnterCustomerData, Scope::Any).await {
+        log!("{user} 导入客户失败，原因权限不足");
+        return Err(e.into());
+    }
+    let data = parse_multipart(part).await?;
+    let file = op::some!(data.files.first(); ret Err(Response::invalid_value("缺少文件")));
+    let sheet = Sheet::read(file.filename(), &file.bytes)?;
+
+    let (custom_fields, box_options) = unsafe {
+        (
+            STATIC_CUSTOM_FIELDS.get_fields(0),
+            STATIC_CUSTOM_BOX_OPTIONS.get_boxes(0),
+        )
+    };
+    // 表头对应的字段名，无法识别的列被忽略
+    let keys: Vec<Option<&str>> = sheet
+        .headers
+        .iter()
+        .map(|h| {
+            COLUMNS
+                .iter()
+                .find(|(key, label)| h.eq(key) || h.eq(label))
+                .map(|(key, _)| *key)
+                .or_else(|| {
+                    [&custom_fields.0, &custom_fields.1, &custom_fields.2]
+                        .into_iter()
+                        .flatten()
+                        .find(|name| h.eq(*name))
+                        .copied()
+                })
+        })
+        .collect();
+
+    let mut reports = Vec::with_capacity(sheet.rows.len());
+    let mut valid = Vec::new();
+    let mut phones = HashSet::new();
+    for (i, row) in sheet.rows.iter().enumerate() {
+        let line = i + 2;
+        let cells: HashMap<&str, &str> = keys
+            .iter()
+            .zip(row)
+            .filter_map(|(k, v)| k.map(|k| (k, v.as_str())))
+            .collect();
+        let mut report = RowReport {
+            row: line,
+            name: cells.get("name").map_or("", |s| s.trim()).to_owned(),
+            smartphone: cells.get("smartphone").map_or("", |s| s.trim()).to_owned(),
+            status: RowStatus::Inserted,
+            reason: String::new(),
+        };
+        let exist: Option<i32> = conn.exec_first(
+            "SELECT 1 FROM customer WHERE smartphone = ? LIMIT 1",
+            (&report.smartphone,),
+        )?;
+        if exist.is_some() {
+            report.status = RowStatus::Skipped;
+            report.reason = "该手机号的客户已存在".to_owned();
+        } else if !report.smartphone.is_empty() && !phones.insert(report.smartphone.clone()) {
+            report.status = RowStatus::Skipped;
+            report.reason = "与表格中前面的行手机号重复".to_owned();
+        } else {
+            match parse_row(&cells, &user.id, &custom_fields, &box_options) {
+                Ok(params) => valid.push((line, params)),
+                Err(reason) => {
+                    report.status = RowStatus::Rejected;
+                    report.reason = reason;
+                }
+            }
+        }
+        reports.push(report);
+    }
+
+    commit_or_rollback!(__import_customers, &mut conn, &valid)?;
+    if !valid.is_empty() {
+        CUSTOMER_CACHE.clear();
+    }
+    let count =
+        |status: fn(&RowStatus) -> bool| reports.iter().filter(|r| status(&r.status)).count();
+    let inserted = count(|s| matches!(s, RowStatus::Inserted));
+    let skipped = count(|s| matches!(s, RowStatus::Skipped));
+    let rejected = count(|s| matches!(s, RowStatus::Rejected));
+    log!("{user} 成功导入{inserted}个客户，跳过{skipped}行，拒绝{rejected}行");
+    Ok(Response::ok(json!({
+        "inserted": inserted,
+        "skipped": skipped,
+        "rejected": rejected,
+        "rows": reports
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{parse_birthday, parse_bool, parse_row, parse_sex};
+    use crate::pages::func::customer::index::sex_name;
+
+    fn parse(cells: &[(&str, &str)]) -> Result<super::InsertParams, String> {
+        let cells: HashMap<&str, &str> = cells.iter().copied().collect();
+        parse_row(&cells, "salesman", &Default::default(), &HashMap::new())
+    }
+
+    #[test]
+    fn bool_and_sex() {
+        assert_eq!(parse_bool("是"), Some(true));
+        assert_eq!(parse_bool(" 0 "), Some(false));
+        assert_eq!(parse_bool(""), Some(false));
+        assert_eq!(parse_bool("男"), None);
+        assert_eq!(parse_sex("男"), Some(true));
+        assert_eq!(parse_sex("女"), Some(false));
+        assert_eq!(parse_sex(""), Some(false));
+        assert_eq!(parse_sex("是"), None);
+        assert_eq!(parse_sex("1"), None);
+    }
+
+    #[test]
+    fn birthday() {
+        assert_eq!(parse_birthday("1990-05-17"), "05-17");
+        assert_eq!(parse_birthday("2001-12-03 00:00:00"), "12-03");
+        assert_eq!(parse_birthday(" 05-17 "), "05-17");
+        assert_eq!(parse_birthday(""), "");
+    }
+
+    #[test]
+    fn row() {
+        let params = parse(&[
+            ("smartphone", "13800000000"),
+            ("name", "张三"),
+            ("sex", "女"),
+            ("is_share", "是"),
+            ("birthday", "1990-05-17"),
+        ])
+        .unwrap();
+        assert_eq!(params.sex, 1);
+        assert_eq!(params.is_share, 0);
+        assert_eq!(params.birthday, "05-17");
+        assert_eq!(params.salesman.as_deref(), Some("salesman"));
+
+        assert!(parse(&[("name", "张三")]).is_err());
+        assert!(parse(&[("smartphone", "13800000000")]).is_err());
+        assert_eq!(
+            parse(&[("smartphone", "1380000000000000"), ("name", "张三")]).unwrap_err(),
+            "手机号过长"
+        );
+        assert_eq!(
+            parse(&[("smartphone", "13800000000"), ("name", "张三"), ("sex", "未知")])
+                .unwrap_err(),
+            "`性别`的值`未知`无法识别"
+        );
+    }
+
+    /// 导出的性别重新导入后不变
+    #[test]
+    fn sex_round_trip() {
+        for sex in ["男", "女"] {
+            let params =
+                parse(&[("smartphone", "13800000000"), ("name", "张三"), ("sex", sex)]).unwrap();
+            assert_eq!(sex_name(params.sex), sex);
+        }
+    }
+}
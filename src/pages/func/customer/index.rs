@@ -1,8 +1,7 @@
 use std::collections::HashMap;
 
 use axum::{
-    extract::{Multipart, Path},
-    http::HeaderMap,
+    extract::Path,
     routing::post,
     Json, Router,
 };
@@ -13,18 +12,24 @@ use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::{json, Value};
 
 use crate::{
-    bearer, catch, commit_or_rollback,
-    database::{get_db, DB},
+    catch, commit_or_rollback,
+    database::{
+        get_db,
+        query::{Cond, Query},
+    },
     get_cache,
-    libs::{gen_id, parse_multipart, TimeFormat, TIME},
+    libs::{gen_id, TimeFormat, TIME},
     log,
     pages::{
         account::{get_user, User},
         func::{__update_custom_fields, customer::CUSTOMER_CACHE, get_custom_fields},
+        DepartmentTree,
+    },
+    perm::{
+        auth::{AuthUser, CustomerPerm, Scope},
+        roles::role_to_name,
     },
-    parse_jwt_macro,
-    perm::{action::CustomerGroup, roles::role_to_name},
-    verify_perms, Field, Response, ResponseResult,
+    Field, Response, ResponseResult,
 };
 
 pub fn customer_router() -> Router {
@@ -33,7 +38,6 @@ pub fn customer_router() -> Router {
         .route("/customer/full/data/:id", post(query_full_data))
         .route("/customer/update", post(update_customer))
         .route("/customer/add", post(insert_customer))
-        .route("/customer/upload/excel", post(upload_excel))
 }
 
 use crate::libs::dser::{
@@ -88,26 +92,26 @@ pub struct Customer {
 
 #[derive(Serialize, Debug, FromRow)]
 pub struct ListData {
-    id: String,
-    smartphone: String,
-    name: String,
-    company: String,
-    salesman: Option<String>,
-    salesman_name: Option<String>,
-    level: String,
+    pub id: String,
+    pub smartphone: String,
+    pub name: String,
+    pub company: String,
+    pub salesman: Option<String>,
+    pub salesman_name: Option<String>,
+    pub level: String,
     #[serde(serialize_with = "serialize_null_to_default")]
-    next_visit_time: Option<String>,
+    pub next_visit_time: Option<String>,
     #[serde(serialize_with = "serialize_i32_to_bool")]
-    sex: i32,
-    address: String,
-    ty: String,
-    status: String,
-    create_time: String,
-    visited_count: usize,
+    pub sex: i32,
+    pub address: String,
+    pub ty: String,
+    pub status: String,
+    pub create_time: String,
+    pub visited_count: usize,
     #[serde(serialize_with = "serialize_null_to_default")]
-    last_visited_time: Option<String>,
+    pub last_visited_time: Option<String>,
     #[serde(serialize_with = "serialize_null_to_default")]
-    last_transaction_time: Option<String>,
+    pub last_transaction_time: Option<String>,
 }
 #[derive(Default, Debug)]
 pub struct CustomCustomerData {
@@ -142,7 +146,7 @@ impl mysql::prelude::FromValue for CustomCustomerData {
     type Intermediate = String;
 }
 
-fn __insert_customer(conn: &mut PooledConn, table: &InsertParams) -> Result<(), Response> {
+pub(super) fn __insert_customer(conn: &mut PooledConn, table: &InsertParams) -> Result<(), Response> {
     let time = TIME::now()?;
     let id = gen_id(&time, &table.name);
     let create_time = time.format(TimeFormat::YYYYMMDD_HHMMSS);
@@ -195,22 +199,27 @@ fn __insert_customer(conn: &mut PooledConn, table: &InsertParams) -> Result<(),
     Ok(())
 }
 
+/// 性别在数据库中的名称。前端男为true，经过`deserialize_bool_to_i32`后男为0，女为1
+pub(super) fn sex_name(sex: i32) -> &'static str {
+    op::ternary!(sex == 0 => "男"; "女")
+}
+
 #[derive(Deserialize, Debug)]
-struct InsertParams {
+pub(super) struct InsertParams {
     smartphone: String,
     name: String,
     company: String,
     #[serde(deserialize_with = "deserialize_bool_to_i32")]
-    is_share: i32,
+    pub(super) is_share: i32,
     #[serde(deserialize_with = "deserialize_bool_to_i32")]
-    sex: i32,
+    pub(super) sex: i32,
     chat: String,
     need: String,
     fax: String,
     post: String,
     industry: String,
     #[serde(deserialize_with = "deserialize_mm_dd")]
-    birthday: String,
+    pub(super) birthday: String,
     address: String,
     remark: String,
     status: String,
@@ -220,17 +229,13 @@ struct InsertParams {
     ty: String,
     tag: String,
     #[serde(deserialize_with = "deser_empty_to_none")]
-    salesman: Option<String>,
+    pub(super) salesman: Option<String>,
     custom_fields: HashMap<String, Vec<Field>>,
 }
 
-async fn insert_customer(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = parse_jwt_macro!(&bearer, &mut conn => true);
+async fn insert_customer(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let params: InsertParams = serde_json::from_value(value)?;
-    let user = get_user(&id, &mut conn).await?;
     let role = role_to_name(&user.role);
     log!(
         "{}-{} 进行添加客户操作，公司：{}，客户名：{}",
@@ -239,17 +244,13 @@ async fn insert_customer(header: HeaderMap, Json(value): Json<Value>) -> Respons
         params.company,
         params.name
     );
-    if !verify_perms!(
-        &user.role,
-        CustomerGroup::NAME,
-        CustomerGroup::ENTER_CUSTOMER_DATA
-    ) {
+    if let Err(e) = user.can(CustomerPerm::EnterCustomerData, Scope::Any).await {
         log!(
             "{}-{} 进行添加客户操作失败, 原因是权限不足",
             role,
             user.name
         );
-        return Err(Response::permission_denied());
+        return Err(e.into());
     }
 
     commit_or_rollback!(__insert_customer, &mut conn, &params)?;
@@ -332,7 +333,7 @@ fn __query_full_data(
     let time = TIME::now()?;
     let today = time.format(TimeFormat::YYYYMMDD);
     // 会出现重复数据，目前测试数据正确
-    let query = format!(
+    let mut data: Option<FullCustomerData> = conn.exec_first(
         "SELECT DISTINCT c.*, ex.salesman, ex.last_transaction_time, 
             MIN(app.appointment) as next_visit_time, COUNT(cou.id) as visited_count,
             MAX(cou.appointment) as last_visited_time, 1 as custom_fields,
@@ -342,26 +343,47 @@ fn __query_full_data(
             JOIN extra_customer_data ex ON ex.id = c.id 
             JOIN user uu ON uu.id = ex.salesman 
             LEFT JOIN appointment app ON app.customer = c.id AND app.salesman=ex.salesman
-                AND app.appointment > '{today}' AND app.finish_time IS NULL
+                AND app.appointment > ? AND app.finish_time IS NULL
             LEFT JOIN appointment cou ON cou.customer = c.id AND cou.salesman=ex.salesman
                 AND cou.finish_time IS NOT NULL
-            WHERE c.id = '{id}'
-            GROUP BY c.id, app.id, cou.id"
-    );
-
-    let mut data: Option<FullCustomerData> = conn.query_first(query)?;
+            WHERE c.id = ?
+            GROUP BY c.id, app.id, cou.id",
+        (&today, id),
+    )?;
     if let Some(d) = &mut data {
         d.custom_fields = get_custom_fields(conn, &d.id, 0)?;
     }
     Ok(data)
 }
 
-async fn query_full_data(header: HeaderMap, Path(id): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+/// 能否查询客户的完整信息，自己的和共享的客户可以直接查询，其他客户与客户列表的权限相同
+async fn ver_full_data_perm(conn: &mut PooledConn, user: &User, id: &str) -> Result<(), Response> {
+    let owner: Option<(Option<String>, Option<String>, i32)> = conn.exec_first(
+        "SELECT ex.salesman, u.department, c.is_share FROM customer c
+        JOIN extra_customer_data ex ON ex.id = c.id
+        LEFT JOIN user u ON u.id = ex.salesman
+        WHERE c.id = ? LIMIT 1",
+        (id,),
+    )?;
+    match owner {
+        Some((Some(salesman), department, 0)) if salesman != user.id => {
+            let tree = DepartmentTree::load(conn)?;
+            ver_query_perm(
+                user,
+                department.is_some_and(|d| tree.contains(&user.department, &d)),
+            )
+            .await
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn query_full_data(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    if let Err(e) = ver_full_data_perm(&mut conn, &user, &id).await {
+        log!("{user} 查询客户`{id}`的信息失败，原因权限不足");
+        return Err(e);
+    }
     if let Some(value) = get_cache!(CUSTOMER_CACHE, "full", &id) {
         log!("{user} 成功查询到客户`{}`的信息 缓存", id);
         Ok(Response::ok(value.clone()))
@@ -379,7 +401,7 @@ async fn query_full_data(header: HeaderMap, Path(id): Path<String>) -> ResponseR
 }
 
 #[derive(Deserialize)]
-struct QueryParams {
+pub(super) struct QueryParams {
     status: Option<String>,
     ty: Option<String>,
     ap: i32,
@@ -387,20 +409,20 @@ struct QueryParams {
     added_days: i32,
     #[allow(unused)]
     is_share: Value,
-    salesman: String,
-    department: String,
+    pub salesman: String,
+    pub department: String,
 }
 
 macro_rules! __convert {
-    ($arg:expr) => {
+    ($arg:expr, $column:expr) => {
         match &$arg {
-            Some(s) => op::ternary!(s.is_empty() => "IS NOT NULL".into(); format!("='{s}'")),
-            None => "=''".into()
+            Some(s) => op::ternary!(s.is_empty() => Cond::not_null($column); Cond::eq($column, s.as_str())),
+            None => Cond::eq($column, "")
         }
     };
     ($time:expr, $days:expr, $local:expr, $name:expr) => {
         if $days < 0 {
-            format!("IS NULL OR {} IS NOT NULL", $name)
+            Cond::any()
         } else {
             let t = if let Some(t) = $local.checked_sub_days(Days::new($days as u64)) {
                 t
@@ -408,105 +430,135 @@ macro_rules! __convert {
                 log!("查询客户失败，原因天数错误");
                 return Err(Response::invalid_value("天数错误"))
             };
-            format!(">= '{}'",TIME::from(t).format(TimeFormat::YYYYMMDD))
+            Cond::ge($name, TIME::from(t).format(TimeFormat::YYYYMMDD))
         }
     };
     ($param:expr, $time:expr, $local:expr => appointment) => {
         match $param.ap {
-            0 => (0 , "".into()),
+            0 => None,
             1 => {
                 let t = op::some!($local.checked_sub_days(Days::new($param.appointment));
                     ret Err(Response::invalid_value("天数错误")));
-                (1, format!("a.finish_time >= '{}'", TIME::from(t).format(TimeFormat::YYYYMMDD)))
+                Some(Cond::ge("a.finish_time", TIME::from(t).format(TimeFormat::YYYYMMDD)))
             }
             2 => {
                 let t = op::some!($local.checked_add_days(Days::new($param.appointment));
                     ret Err(Response::invalid_value("天数错误")));
-               (1, format!("a.finish_time IS NULL AND (a.appointment >= '{}' AND a.appointment <= '{} 24:00:00')",
-                    $time.format(TimeFormat::YYYYMMDD), TIME::from(t).format(TimeFormat::YYYYMMDD)))
+                Some(Cond::new("a.finish_time IS NULL", Vec::new())
+                    .and(Cond::ge("a.appointment", $time.format(TimeFormat::YYYYMMDD)))
+                    .and(Cond::le("a.appointment", format!("{} 24:00:00", TIME::from(t).format(TimeFormat::YYYYMMDD)))))
             }
             _ => return Err(Response::invalid_value("ap错误"))
         }
     };
-    // ()
+    // (业务员条件, 部门条件)
     ($sales:expr, $depart:expr, $u:expr, $conn:expr; auto) => {
         if $sales.is_empty() {
             if !$depart.is_empty() {
-                let (depart, root) = verify_perms!(&$u.role, CustomerGroup::NAME, CustomerGroup::QUERY, None, Some(["all"].as_slice()));
-                if root || (($depart.eq(&$u.department) || $depart.eq("my")) && depart) {
-                    ("IS NOT NULL".to_owned(), format!("='{}'", op::ternary!($depart.eq("my") => $u.department.as_str(), $depart.as_str())))
-                } else {
-                    return Err(Response::permission_denied())
-                }
+                let depart = op::ternary!($depart.eq("my") => $u.department.as_str(), $depart.as_str());
+                let tree = DepartmentTree::load($conn)?;
+                ver_query_perm($u, tree.contains(&$u.department, depart)).await?;
+                (Cond::not_null("ex.salesman"), Cond::in_list("u.department", tree.descendants(depart)))
             } else {
-                if verify_perms!(&$u.role, CustomerGroup::NAME, CustomerGroup::QUERY, Some(["all"].as_slice())) {
-                    ("IS NOT NULL".to_owned(), "IS NOT NULL".to_owned())
-                } else{
-                    return Err(Response::permission_denied())
-                }
+                $u.can(CustomerPerm::Query, Scope::All).await?;
+                (Cond::not_null("ex.salesman"), Cond::not_null("u.department"))
             }
         } else if $sales.eq("my") {
-            (format!("='{}'", $u.id), "IS NOT NULL".to_owned())
+            (Cond::eq("ex.salesman", $u.id.as_str()), Cond::not_null("u.department"))
         } else {
             let sl = get_user($sales, $conn).await?;
-
-            let (depart, root) = verify_perms!(&$u.role, CustomerGroup::NAME, CustomerGroup::QUERY, None, Some(["all"].as_slice()));
-            if root || (sl.department.eq(&$u.department) && depart) {
-                (format!("='{}'", $sales), "IS NOT NULL".to_owned())
-            } else{
-                return Err(Response::permission_denied())
-            }
+            let tree = DepartmentTree::load($conn)?;
+            ver_query_perm($u, tree.contains(&$u.department, &sl.department)).await?;
+            (Cond::eq("ex.salesman", $sales), Cond::not_null("u.department"))
         }
     }
 
 }
-async fn __query_customer_list_data<'err>(
-    conn: &mut DB<'err>,
+/// 查询客户的权限，`all`可以查询所有部门，否则只能查询本部门及下级部门
+async fn ver_query_perm(u: &User, same_department: bool) -> Result<(), Response> {
+    let all = match u.can(CustomerPerm::Query, Scope::All).await {
+        Ok(()) => return Ok(()),
+        Err(e) => e,
+    };
+    if !same_department {
+        return Err(all.into());
+    }
+    u.can(CustomerPerm::Query, Scope::Any).await?;
+    Ok(())
+}
+
+async fn __query_customer_list_data(
+    conn: &mut PooledConn,
     params: &QueryParams,
     u: &User,
 ) -> Result<Vec<ListData>, Response> {
-    let status = __convert!(params.status);
-    let ty = __convert!(params.ty);
-    let time = TIME::now()?;
+    let (salesman, department) =
+        __convert!(params.salesman.as_str(), params.department, u, conn; auto);
+    __query_customer_list(conn, params, &salesman, &department)
+}
+
+/// `salesman`和`department`为已经过权限检查的`ex.salesman`和`u.department`条件
+pub(super) fn __query_customer_list(
+    conn: &mut PooledConn,
+    params: &QueryParams,
+    salesman: &Cond,
+    department: &Cond,
+) -> Result<Vec<ListData>, Response> {
+    let list = customer_list_query(params, salesman, department, TIME::now()?)?.fetch(conn)?;
+    Ok(list)
+}
+
+fn customer_list_query(
+    params: &QueryParams,
+    salesman: &Cond,
+    department: &Cond,
+    time: TIME,
+) -> Result<Query, Response> {
+    let status = __convert!(params.status, "c.status");
+    let ty = __convert!(params.ty, "c.ty");
     let local = chrono::Local.timestamp_nanos(time.naos() as i64);
-    let (ot, appoint) = __convert!(&params, &time, local => appointment);
+    let appoint = __convert!(&params, &time, local => appointment);
     let added_time = __convert!(time, params.added_days, local, "ex.added_date");
-    let ap = if ot == 0 {
-        String::new()
-    } else {
-        format!("JOIN appointment a ON a.customer=c.id AND a.salesman=ex.salesman AND ({appoint})")
-    };
 
-    let (salesman, department) =
-        __convert!(params.salesman.as_str(), params.department, u, conn; auto);
     let today = time.format(TimeFormat::YYYYMMDD);
 
-    let query = format!(
+    let mut query = Query::new(
         "SELECT c.*,
         ex.salesman, COUNT(cou.id) as visited_count,
         u.name as salesman_name,
         MAX(cou.finish_time) AS last_visited_time,
         ex.last_transaction_time, MIN(app.appointment) as next_visit_time
-        FROM customer c JOIN extra_customer_data ex ON ex.id=c.id AND (ex.salesman {salesman}) 
-        AND (ex.added_date {added_time})
-        JOIN user u ON u.id=ex.salesman AND (u.department {department}) 
-        {ap}
-        LEFT JOIN appointment app ON app.customer=c.id AND app.salesman=ex.salesman AND app.appointment>'{today}' AND app.finish_time IS NULL
+        FROM customer c JOIN extra_customer_data ex ON ex.id=c.id AND ",
+    )
+    .cond(&salesman.clone().and(added_time))
+    .push(" JOIN user u ON u.id=ex.salesman AND ")
+    .cond(department);
+    if let Some(appoint) = &appoint {
+        query = query
+            .push(" JOIN appointment a ON a.customer=c.id AND a.salesman=ex.salesman AND ")
+            .cond(appoint);
+    }
+    let query = query
+        .push(
+            " LEFT JOIN appointment app ON app.customer=c.id AND app.salesman=ex.salesman AND app.appointment > ",
+        )
+        .bind(today)
+        .push(
+            " AND app.finish_time IS NULL
         LEFT JOIN appointment cou ON cou.customer=c.id AND cou.salesman=ex.salesman AND cou.finish_time IS NOT NULL
-        WHERE (c.status {status}) AND (c.ty {ty}) AND NOT EXISTS (select 1 from customer_sea cs where cs.id = c.id)
-        GROUP BY c.id
-        "
-    );
-    let list = conn.query(query)?;
-    Ok(list)
+        WHERE ",
+        )
+        .cond(&status.and(ty))
+        .push(
+            " AND NOT EXISTS (select 1 from customer_sea cs where cs.id = c.id)
+        GROUP BY c.id",
+        );
+    Ok(query)
 }
 
-async fn query_customer(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+async fn query_customer(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let uid = user.id.clone();
     let param_str = value.to_string();
     let params: QueryParams = serde_json::from_value(value)?;
     log!("{user} 正在查询客户信息");
@@ -565,24 +617,21 @@ pub fn check_user_customer(
     customer: &str,
     conn: &mut PooledConn,
 ) -> Result<(), Response> {
-    let flag: Option<String> = conn.query_first(format!(
+    let flag: Option<String> = conn.exec_first(
         "SELECT 1 FROM customer c 
-            JOIN extra_customer_data d ON d.id=c.id AND d.salesman='{id}'
-            WHERE c.id='{customer}'"
-    ))?;
+            JOIN extra_customer_data d ON d.id=c.id AND d.salesman = ?
+            WHERE c.id = ?",
+        (id, customer),
+    )?;
     if flag.is_some() {
         Ok(())
     } else {
         Err(Response::permission_denied())
     }
 }
-async fn update_customer(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = parse_jwt_macro!(&bearer, &mut conn => true);
+async fn update_customer(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let params: UpdateParams = serde_json::from_value(value)?;
-    let user = get_user(&id, &mut conn).await?;
     let role = role_to_name(&user.role);
     log!(
         "{}-{} 更新客户 `{}-{}`的信息",
@@ -591,12 +640,8 @@ async fn update_customer(header: HeaderMap, Json(value): Json<Value>) -> Respons
         params.company,
         params.name
     );
-    check_user_customer(&id, &params.id, &mut conn)?;
-    if !verify_perms!(
-        &user.role,
-        CustomerGroup::NAME,
-        CustomerGroup::UPDATE_CUSTOMER_DATA
-    ) {
+    check_user_customer(&user.id, &params.id, &mut conn)?;
+    if let Err(e) = user.can(CustomerPerm::UpdateCustomerData, Scope::Any).await {
         log!(
             "{}-{} 更新客户 `{}-{}`的信息失败，原因权限受阻",
             role,
@@ -604,7 +649,7 @@ async fn update_customer(header: HeaderMap, Json(value): Json<Value>) -> Respons
             params.company,
             params.name
         );
-        return Err(Response::permission_denied());
+        return Err(e.into());
     }
     commit_or_rollback!(__update_customer, &mut conn, &params)?;
     CUSTOMER_CACHE.clear();
@@ -619,15 +664,13 @@ async fn update_customer(header: HeaderMap, Json(value): Json<Value>) -> Respons
 }
 fn __update_customer(conn: &mut PooledConn, params: &UpdateParams) -> Result<(), Response> {
     conn.exec_drop(
-        format!(
-            "UPDATE customer SET smartphone=:smartphone, name=:name, company=:company,
+        "UPDATE customer SET smartphone=:smartphone, name=:name, company=:company,
         is_share=:is_share, sex=:sex, chat=:chat, level=:level,
         need=:need, fax=:fax, post=:post, industry=:industry, birthday=:birthday,
         address=:address, remark=:remark, status=:status, 
-        source=:source, role=:role, ty=:ty, tag=:tag WHERE id = '{}' LIMIT 1",
-            params.id
-        ),
+        source=:source, role=:role, ty=:ty, tag=:tag WHERE id = :id LIMIT 1",
         params! {
+                    "id" => &params.id,
                     "smartphone" => &params.smartphone,
                     "name" => &params.name,
                     "company" => &params.company,
@@ -653,10 +696,36 @@ fn __update_customer(conn: &mut PooledConn, params: &UpdateParams) -> Result<(),
     Ok(())
 }
 
-async fn upload_excel(_header: HeaderMap, part: Multipart) -> ResponseResult {
-    let data = parse_multipart(part).await?;
-    for f in &data.files {
-        println!("{:?}", f.filename())
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{customer_list_query, QueryParams};
+    use crate::{
+        database::query::{assert_bound, Cond, PAYLOADS},
+        libs::TIME,
+    };
+
+    #[test]
+    fn list_query_binds_input() {
+        for payload in PAYLOADS {
+            let params: QueryParams = serde_json::from_value(json!({
+                "status": payload,
+                "ty": payload,
+                "ap": 0,
+                "appointment": 0,
+                "added_days": -1,
+                "is_share": null,
+                "salesman": payload,
+                "department": payload
+            }))
+            .unwrap();
+            let salesman = Cond::eq("ex.salesman", payload);
+            let department = Cond::in_list("u.department", [payload]);
+            let query =
+                customer_list_query(&params, &salesman, &department, TIME::now().unwrap()).unwrap();
+            assert_bound(&query, payload);
+            assert_eq!(query.params().len(), 5);
+        }
     }
-    Ok(Response::empty())
 }
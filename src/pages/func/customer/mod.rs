@@ -1,13 +1,19 @@
 mod appointment;
 mod colleague;
+mod export;
+mod import;
 pub mod index;
+pub mod sea;
 
 use axum::Router;
 use crate::libs::cache::CUSTOMER_CACHE;
-use self::{appointment::appointment_router, colleague::colleague_router};
+use self::{appointment::appointment_router, colleague::colleague_router, export::export_router, import::import_router, sea::sea_router};
 
 pub fn customer_router() -> Router {
     index::customer_router()
         .merge(colleague_router())
         .merge(appointment_router())
+        .merge(sea_router())
+        .merge(import_router())
+        .merge(export_router())
 }
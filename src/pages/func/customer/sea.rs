@@ -0,0 +1,272 @@
+use axum::{extract::Path, routing::post, Json, Router};
+use chrono::{Days, TimeZone};
+use mysql::{params, prelude::Queryable, PooledConn};
+use mysql_common::prelude::FromRow;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    commit_or_rollback,
+    database::{
+        get_db,
+        query::{Cond, Query},
+    },
+    libs::{cache::CUSTOMER_CACHE, TimeFormat, TIME},
+    log,
+    pages::{
+        account::{get_user, User},
+        DepartmentTree,
+    },
+    perm::auth::{AuthUser, CustomerPerm, Scope},
+    Response, ResponseResult, SEA_MAX_DAY, SEA_MIN_DAY,
+};
+
+use super::index::check_user_customer;
+
+pub fn sea_router() -> Router {
+    Router::new()
+        .route("/customer/sea/data", post(query_sea))
+        .route("/customer/sea/release", post(release_customer))
+        .route("/customer/sea/claim/:id", post(claim_customer))
+}
+
+#[derive(Deserialize)]
+struct ReleaseParams {
+    id: String,
+    reason: String,
+}
+
+/// 将客户放入公海，客户必须属于`salesman`
+pub fn __push_to_sea(
+    conn: &mut PooledConn,
+    id: &str,
+    salesman: &User,
+    reason: &str,
+    time: &TIME,
+) -> Result<(), Response> {
+    let push_time = time.format(TimeFormat::YYYYMMDD_HHMMSS);
+    conn.exec_drop(
+        "INSERT INTO customer_sea (id, salesman, department, reason, push_time)
+        VALUES (:id, :salesman, :department, :reason, :push_time)",
+        params! {
+            "id" => id,
+            "salesman" => &salesman.id,
+            "department" => &salesman.department,
+            "reason" => reason,
+            "push_time" => &push_time
+        },
+    )?;
+    conn.exec_drop(
+        "UPDATE extra_customer_data SET salesman = NULL, push_to_sea_date = ? WHERE id = ? LIMIT 1",
+        (&push_time, id),
+    )?;
+    Ok(())
+}
+
+async fn release_customer(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let params: ReleaseParams = serde_json::from_value(value)?;
+    log!("{user} 请求将客户`{}`放入公海", params.id);
+    if let Err(e) = user.can(CustomerPerm::ReleaseCustomer, Scope::Any).await {
+        log!("{user} 将客户`{}`放入公海失败，原因权限不足", params.id);
+        return Err(e.into());
+    }
+    check_user_customer(&user.id, &params.id, &mut conn)?;
+    if params.reason.trim().is_empty() {
+        return Err(Response::invalid_value("放入公海的原因不能为空"));
+    }
+    let time = TIME::now()?;
+    commit_or_rollback!(
+        __push_to_sea,
+        &mut conn,
+        &params.id,
+        &user,
+        params.reason.trim(),
+        &time
+    )?;
+    CUSTOMER_CACHE.clear();
+    log!("{user} 成功将客户`{}`放入公海", params.id);
+    Ok(Response::empty())
+}
+
+#[derive(FromRow)]
+struct SeaRecord {
+    salesman: Option<String>,
+    department: String,
+    push_time: String,
+}
+
+/// 领取公海客户，锁定公海记录后再检查，避免多人同时领取同一个客户
+fn __pop_from_sea(
+    conn: &mut PooledConn,
+    id: &str,
+    department: &str,
+    user: &User,
+    time: &TIME,
+) -> Result<(), Response> {
+    let record: SeaRecord = op::some!(conn.exec_first(
+        "SELECT salesman, department, push_time FROM customer_sea WHERE id = ? FOR UPDATE",
+        (id,)
+    )?; ret Err(Response::dissatisfy("该客户已被领取")));
+    if record.department != department {
+        return Err(Response::dissatisfy("该客户已被领取"));
+    }
+    if record.salesman.as_deref() == Some(user.id.as_str()) {
+        let local = chrono::Local.timestamp_nanos(time.naos() as i64);
+        let min_day = unsafe { SEA_MIN_DAY };
+        let limit = op::some!(local.checked_sub_days(Days::new(min_day));
+            ret Err(Response::invalid_value("天数错误")));
+        if record.push_time > TIME::from(limit).format(TimeFormat::YYYYMMDD_HHMMSS) {
+            log!("{user} 领取客户`{id}`失败，原因放入公海未满{min_day}天");
+            return Err(Response::dissatisfy(format!(
+                "放入公海{min_day}天后才能重新领取"
+            )));
+        }
+    }
+    conn.exec_drop("DELETE FROM customer_sea WHERE id = ? LIMIT 1", (id,))?;
+    if conn.affected_rows() != 1 {
+        return Err(Response::dissatisfy("该客户已被领取"));
+    }
+    conn.exec_drop(
+        "UPDATE extra_customer_data SET salesman = ?, pop_from_sea_date = ? WHERE id = ? LIMIT 1",
+        (&user.id, time.format(TimeFormat::YYYYMMDD_HHMMSS), id),
+    )?;
+    Ok(())
+}
+
+async fn claim_customer(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    log!("{user} 请求从公海领取客户`{id}`");
+    let department: String = op::some!(conn.exec_first(
+        "SELECT department FROM customer_sea WHERE id = ?",
+        (&id,)
+    )?; ret Err(Response::not_exist("该客户不在公海中")));
+    let tree = DepartmentTree::load(&mut conn)?;
+    // 与查询公海相同，本部门及下级部门需要department或all，其他部门需要all
+    if let Err(e) = user.can(CustomerPerm::QueryPubSea, Scope::All).await {
+        let own = tree.contains(&user.department, &department);
+        let department_perm = user.can(CustomerPerm::QueryPubSea, Scope::Department).await;
+        if !own || department_perm.is_err() {
+            log!("{user} 领取客户`{id}`失败，原因权限不足");
+            return Err(e.into());
+        }
+    }
+    let time = TIME::now()?;
+    commit_or_rollback!(__pop_from_sea, &mut conn, &id, &department, &user, &time)?;
+    CUSTOMER_CACHE.clear();
+    log!("{user} 成功从公海领取客户`{id}`");
+    Ok(Response::empty())
+}
+
+#[derive(Deserialize)]
+struct SeaQueryParams {
+    /// 为空时查询所有部门，`my`为自己所在部门，包括下级部门
+    department: String,
+}
+
+#[derive(Serialize, FromRow)]
+struct SeaData {
+    id: String,
+    smartphone: String,
+    name: String,
+    company: String,
+    level: String,
+    address: String,
+    ty: String,
+    status: String,
+    create_time: String,
+    salesman: Option<String>,
+    salesman_name: Option<String>,
+    department: String,
+    reason: String,
+    push_time: String,
+}
+
+async fn query_sea(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let params: SeaQueryParams = serde_json::from_value(value)?;
+    log!("{user} 查询公海客户");
+    let department = if params.department.eq("my") {
+        Some(user.department.as_str())
+    } else if params.department.is_empty() {
+        None
+    } else {
+        Some(params.department.as_str())
+    };
+    let tree = DepartmentTree::load(&mut conn)?;
+    // 本部门及下级部门需要department或all，其他部门需要all
+    let all = user.can(CustomerPerm::QueryPubSea, Scope::All).await;
+    if let Err(e) = all {
+        let own = department.is_some_and(|d| tree.contains(&user.department, d));
+        let department_perm = user.can(CustomerPerm::QueryPubSea, Scope::Department).await;
+        if !own || department_perm.is_err() {
+            log!("{user} 查询公海客户失败，原因权限不足");
+            return Err(e.into());
+        }
+    }
+    let departments = department.map_or_else(Cond::any, |d| {
+        Cond::in_list("cs.department", tree.descendants(d))
+    });
+    let list: Vec<SeaData> = Query::new(
+        "SELECT c.id, c.smartphone, c.name, c.company, c.level, c.address, c.ty, c.status,
+            c.create_time, cs.salesman, u.name as salesman_name, cs.department, cs.reason,
+            cs.push_time
+        FROM customer_sea cs
+        JOIN customer c ON c.id = cs.id
+        LEFT JOIN user u ON u.id = cs.salesman
+        WHERE ",
+    )
+    .cond(&departments)
+    .push(" ORDER BY cs.push_time DESC")
+    .fetch(&mut conn)?;
+    log!("{user} 成功查询到{}个公海客户", list.len());
+    Ok(Response::ok(json!(list)))
+}
+
+#[derive(FromRow)]
+struct StaleCustomer {
+    id: String,
+    salesman: String,
+}
+
+/// 将超过`SEA_MAX_DAY`天没有完成拜访和成交的客户放入公海，返回放入的客户数量
+///
+/// 新添加或刚从公海领取的客户同样有`SEA_MAX_DAY`天的保护期
+pub async fn auto_release_customers(conn: &mut PooledConn) -> Result<usize, Response> {
+    let time = TIME::now()?;
+    let local = chrono::Local.timestamp_nanos(time.naos() as i64);
+    let max_day = unsafe { SEA_MAX_DAY };
+    let limit = op::some!(local.checked_sub_days(Days::new(max_day));
+        ret Err(Response::invalid_value("天数错误")));
+    let limit = TIME::from(limit).format(TimeFormat::YYYYMMDD_HHMMSS);
+    let stale: Vec<StaleCustomer> = conn.exec(
+        "SELECT ex.id, ex.salesman FROM extra_customer_data ex
+        WHERE ex.salesman IS NOT NULL
+            AND COALESCE(ex.pop_from_sea_date, ex.added_date) < :limit
+            AND (ex.last_transaction_time IS NULL OR ex.last_transaction_time < :limit)
+            AND NOT EXISTS (SELECT 1 FROM customer_sea cs WHERE cs.id = ex.id)
+            AND NOT EXISTS (SELECT 1 FROM appointment a WHERE a.customer = ex.id
+                AND a.salesman = ex.salesman AND a.finish_time >= :limit)
+            AND NOT EXISTS (SELECT 1 FROM order_data o WHERE o.customer = ex.id
+                AND o.salesman = ex.salesman AND o.transaction_date >= :limit)",
+        params! { "limit" => &limit },
+    )?;
+    let reason = format!("超过{max_day}天没有完成拜访或成交，系统自动放入公海");
+    let mut count = 0;
+    for c in &stale {
+        let user = match get_user(&c.salesman, conn).await {
+            Ok(user) => user,
+            Err(e) => {
+                log!("自动放入公海时查询业务员`{}`失败：{:?}", c.salesman, e);
+                continue;
+            }
+        };
+        commit_or_rollback!(__push_to_sea, conn, &c.id, &user, &reason, &time)?;
+        count += 1;
+    }
+    if count > 0 {
+        CUSTOMER_CACHE.clear();
+    }
+    log!("自动将{count}个客户放入公海");
+    Ok(count)
+}
@@ -1,9 +1,11 @@
 pub mod supper;
 pub mod store;
 mod order;
-pub use order::Order;
+pub use order::{query_customer_order, query_customer_orders, Order};
 mod product;
 pub use product::DEFAULT_PRODUCT_COVER;
+mod purchase;
+pub use purchase::count_open_purchases;
 mod report;
 use std::collections::HashMap;
 
@@ -14,7 +16,7 @@ use crate::{log, Response};
 
 use self::customer::index::CustomCustomerData;
 
-mod customer;
+pub mod customer;
 
 pub fn func_router() -> Router {
     customer::customer_router()
@@ -23,6 +25,7 @@ pub fn func_router() -> Router {
         .merge(order::order_router())
         .merge(store::store_router())
         .merge(supper::router())
+        .merge(purchase::purchase_router())
 }
 
 pub fn verify_custom_fields(ver: &[&str], data: &[crate::Field]) -> bool {
@@ -37,10 +40,11 @@ pub fn get_custom_fields(
     id: &str,
     fields: u8,
 ) -> Result<CustomCustomerData, Response> {
-    let data: Vec<(String, String, String)> = conn.query(format!(
+    let data: Vec<(String, String, String)> = conn.exec(
         "SELECT ty, display, value FROM custom_field_data WHERE
-    fields={fields} AND id = '{id}'"
-    ))?;
+    fields = ? AND id = ?",
+        (fields, id),
+    )?;
     let mut fields = CustomCustomerData::default();
     for (ty, display, value) in data {
         let text = match ty.as_str() {
@@ -77,15 +81,11 @@ pub fn __update_custom_fields(
                 return Err(Response::invalid_value("自定义字段错误"))
             }
         };
-        for f in v {
-            let state = format!(
-                "UPDATE custom_field_data SET value='{}' 
-                    WHERE fields={field} AND ty={ty} AND display='{}' AND id='{id}' LIMIT 1",
-                f.value, f.display
-            );
-            // println!("{}", state);
-            conn.query_drop(state)?;
-        }
+        conn.exec_batch(
+            "UPDATE custom_field_data SET value = ? 
+                    WHERE fields = ? AND ty = ? AND display = ? AND id = ? LIMIT 1",
+            v.iter().map(|f| (&f.value, field, ty, &f.display, id)),
+        )?;
     }
     Ok(())
 }
@@ -114,21 +114,12 @@ pub fn __insert_custom_fields(
             return Err(crate::Response::dissatisfy("自定义字段存在不匹配情况"));
         }
     }
-    let mut values = String::new();
     for (k, v) in fields {
         let s = op::some!(get_ty(k); continue);
-        for field in v {
-            values.push_str(&format!(
-                "({ty}, {s}, '{id}', '{}', '{}'),",
-                field.display, field.value
-            ));
-        }
-    }
-    values.pop();
-    if !values.is_empty() {
-        conn.query_drop(format!(
-            "INSERT INTO custom_field_data (fields, ty, id, display, value) VALUES {values}"
-        ))?;
+        conn.exec_batch(
+            "INSERT INTO custom_field_data (fields, ty, id, display, value) VALUES (?, ?, ?, ?, ?)",
+            v.iter().map(|field| (ty, s, id, &field.display, &field.value)),
+        )?;
     }
 
     Ok(())
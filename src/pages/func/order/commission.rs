@@ -1,22 +1,14 @@
-use axum::{extract::Path, http::HeaderMap};
+use axum::extract::Path;
 use serde_json::json;
 
-use crate::{
-    bearer, database::get_db, log, pages::account::get_user, parse_jwt_macro, Response,
-    ResponseResult,
-};
+use crate::{log, perm::auth::AuthUser, Response, ResponseResult};
 
 pub async fn get_commission() -> ResponseResult {
     Ok(Response::ok(json!({
         "commission": crate::get_commission()?
     })))
 }
-pub async fn set_commission(header: HeaderMap, Path(value): Path<i32>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+pub async fn set_commission(user: AuthUser, Path(value): Path<i32>) -> ResponseResult {
     if user.role.eq("root") {
         crate::set_commission(value)?;
         log!("已修改提成为{value}%");
@@ -11,6 +11,7 @@ use crate::{common::Person, mysql_stmt, Response};
 
 use super::{
     customer::Customer, invoice::Invoice, payment::Instalment, product::Product, ship::Ship,
+    status::OrderStatus,
 };
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -20,7 +21,7 @@ pub struct Order {
     #[serde(default)]
     pub create_time: String,
     pub number: String,
-    pub status: i32,
+    pub status: OrderStatus,
     pub ty: String,
     #[serde(default)]
     pub file: Option<String>,
@@ -36,6 +37,34 @@ pub struct Order {
     pub ship: Ship,
     pub comment: String,
 }
+/// 客户端看到的订单，不包括备注、收款账户、业务员和发货库房等内部数据
+#[derive(Serialize, Debug)]
+pub struct CustomerOrder {
+    pub id: String,
+    pub number: String,
+    pub status: OrderStatus,
+    pub product: Vec<Product>,
+    pub instalment: Vec<Instalment>,
+    /// 不需要发票时为`None`
+    pub invoice: Option<Invoice>,
+    /// 发货日期，未发货时为`None`
+    pub shipped_date: Option<String>,
+}
+
+impl From<&Order> for CustomerOrder {
+    fn from(order: &Order) -> Self {
+        Self {
+            id: order.id.clone(),
+            number: order.number.clone(),
+            status: order.status,
+            product: order.product.clone(),
+            instalment: order.instalment.clone(),
+            invoice: op::ternary!(order.invoice.required == 1 => Some(order.invoice.clone()); None),
+            shipped_date: op::ternary!(order.ship.shipped == 1 => order.ship.date.clone(); None),
+        }
+    }
+}
+
 impl Order {
     pub fn gen_number(&mut self, conn: &mut PooledConn) -> Result<(), Response> {
         if self.number.is_empty() {
@@ -133,9 +162,9 @@ impl Order {
             },
         )?;
         Product::insert(&order.product, &order.id, conn, false)?;
-        if order.status > 0 {
+        if order.status != OrderStatus::Intent {
             for inv in &mut order.instalment {
-                inv.finish = if order.status == 2 { 1 } else { 0 };
+                inv.finish = if order.status == OrderStatus::Complete { 1 } else { 0 };
             }
             Instalment::insert(conn, &order.id, &order.instalment, false)?;
         }
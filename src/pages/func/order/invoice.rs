@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{log, Response};
 
-#[derive(Deserialize, Serialize, FromRow, Default, Debug)]
+#[derive(Deserialize, Serialize, FromRow, Default, Debug, Clone)]
 pub struct Invoice {
     pub required: i32,
     pub deadline: String,
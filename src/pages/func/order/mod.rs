@@ -2,49 +2,49 @@ mod commission;
 pub mod data;
 mod update;
 use commission::get_commission;
-pub use data::Order;
+pub use data::{CustomerOrder, Order};
 use std::{fmt::Display, sync::Arc};
 mod customer;
 mod invoice;
 mod payment;
 mod product;
 mod ship;
+mod status;
 
 use axum::{
     extract::{Multipart, Path},
-    http::HeaderMap,
     routing::{delete, get, post},
     Json, Router,
 };
 use mysql::{params, prelude::Queryable, PooledConn};
 use payment::Instalment;
 use product::Product;
+use ship::Ship;
+use status::OrderStatus;
 use serde::Deserialize;
 use serde_json::{json, Value};
 
 use crate::{
-    bearer, commit_or_rollback,
-    database::{get_db, DB},
+    commit_or_rollback,
+    database::{get_db, query::{Cond, Query}},
     get_cache,
-    libs::{cache::{ORDER_CACHE, ORDER_CACHE_WITH_ID}, gen_file_link, gen_id, parse_multipart, TimeFormat, TIME},
+    libs::{cache::{ORDER_CACHE, ORDER_CACHE_WITH_ID, PRODUCT_CACHE}, dser::deserialize_storehouse, gen_file_link, gen_id, parse_multipart, TimeFormat, TIME},
     log,
-    pages::account::{get_user, User},
-    parse_jwt_macro,
-    perm::action::OtherGroup,
+    pages::{account::{get_user, User}, func::store::stock, DepartmentTree},
+    perm::auth::{AuthUser, OtherPerm, Scope},
     response::BodyFile,
-    verify_perms, Response, ResponseResult,
+    Response, ResponseResult,
 };
 fn verify_instalment(product: &[Product], instalment: &[Instalment]) -> Result<(), Response> {
     log!("接收到的产品信息: \n {:#?}", product);
     let sum = product::computed_products_sum(product);
 
     let instalment_sum = Instalment::computed_instalment(instalment);
-    let result = sum - instalment_sum;
-    if (-0.001f32..=0.001f32).contains(&result) {
+    if sum == instalment_sum {
         Ok(())
     } else {
         Err(Response::invalid_value(format!(
-            "回款金额错误，误差超过0.001, 预期值：{sum}, 实际值：{instalment_sum} (已包括折扣)"
+            "回款金额错误, 预期值：{sum}, 实际值：{instalment_sum} (已包括折扣)"
         )))
     }
 }
@@ -54,10 +54,17 @@ pub fn order_router() -> Router {
         .route("/order/query", post(query_order))
         .route("/order/tran", post(update::order_transaction))
         .route("/order/finish/:id", post(update::complete_order))
+        .route("/order/status/:id", post(status::change_status))
+        .route("/order/status/history/:id", get(status::query_status_history))
         .route("/order/update/order", post(update::update_order))
         .route("/order/finish/repayment", post(finish_repayment))
+        .route("/order/instalment/split", post(payment::split_instalment))
         .route("/order/upload/image/:id", post(upload_order_file))
         .route("/order/delete/:id", delete(delete_order))
+        .route(
+            "/order/reserve/:id",
+            post(reserve_order).delete(release_order),
+        )
         .route("/order/get/commission", get(get_commission))
         .route("/order/get/img/:url", get(get_order_file))
         .route(
@@ -67,29 +74,27 @@ pub fn order_router() -> Router {
 }
 
 async fn upload_order_file(
-    header: HeaderMap,
+    user: AuthUser,
     Path(id): Path<String>,
     part: Multipart,
 ) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
+    let mut conn = get_db().await?;
     let data = parse_multipart(part).await?;
     let Some(f) = data.files.first() else {
         return Err(Response::invalid_value("没有接收到附件信息"));
     };
     let order = query_order_by_id(&mut conn, &id)?;
-    if order.salesman.id != uid {
+    if order.salesman.id != user.id {
         log!("上传附件失败，该订单不存在或权限不足");
         return Err(Response::permission_denied());
     }
     let time = TIME::now()?;
     let link = gen_file_link(&time, f.filename());
     std::fs::write(format!("resources/order/{link}"), &f.bytes)?;
-    conn.query_drop(format!(
-        "update order_data set file = '{link}' where id = '{id}' limit 1"
-    ))?;
+    conn.exec_drop(
+        "update order_data set file = ? where id = ? limit 1",
+        (&link, &id),
+    )?;
     if let Some(path) = &order.file {
         std::fs::remove_file(path).unwrap_or_default();
     }
@@ -99,13 +104,9 @@ async fn upload_order_file(
     Ok(Response::ok(json!("添加订单附件成功")))
 }
 
-async fn add_order(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
+async fn add_order(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let mut order: Order = serde_json::from_value(value)?;
-    let user = get_user(&uid, &mut conn).await?;
     log!("{}-{} 发起添加订单请求", user.department, user.name);
     commit_or_rollback!(async __add_order, &mut conn, &mut order, &user)?;
 
@@ -117,6 +118,7 @@ async fn add_order(header: HeaderMap, Json(value): Json<Value>) -> ResponseResul
     );
     ORDER_CACHE.clear();
     ORDER_CACHE_WITH_ID.clear();
+    PRODUCT_CACHE.clear();
     Ok(Response::ok(json!({"id": order.id})))
 }
 pub fn gen_number(conn: &mut PooledConn, ty: i32, name: impl Display) -> Result<String, Response> {
@@ -155,31 +157,46 @@ async fn __add_order(
     order.id = gen_id(&time, &format!("order{}", user.name));
 
     match order.status {
-        1 | 2 => {
+        OrderStatus::Transaction | OrderStatus::Complete => {
+            if order.ship.shipped == 1 && order.ship.storehouse.is_none() {
+                return Err(Response::dissatisfy("ship的storehouse必须设置"));
+            }
             if order.ship.shipped == 1 && order.ship.date.is_none() {
                 order.ship.date = Some(time.format(TimeFormat::YYYYMMDD_HHMMSS));
             }
             order.transaction_date = Some(time.format(TimeFormat::YYYYMMDD_HHMMSS));
             verify_instalment(&order.product, &order.instalment)?;
         }
-        _ => {
+        OrderStatus::Intent => {
             order.ship.shipped = 0;
             order.invoice.required = 0;
         }
+        other => {
+            return Err(Response::invalid_value(format!(
+                "新订单不能是`{other}`状态"
+            )))
+        }
     }
-    
-    order.insert(conn)
+
+    order.insert(conn)?;
+    status::record(conn, &order.id, None, order.status, &user.id, "创建订单")?;
+    order
+        .ship
+        .apply_stock(conn, &order.id, None, &order.product, &user.id)
 }
 
 fn query_order_by_id(conn: &mut PooledConn, id: &str) -> Result<Arc<Order>, Response> {
     if let Some(order) = ORDER_CACHE_WITH_ID.get(id) {
         return Ok(Arc::clone(&order));
     }
-    let order: Option<Order> = conn.query_first(format!(
-        "{QUERY_ORDER}
-        where o.id = '{id}' limit 1
+    let order: Option<Order> = conn.exec_first(
+        format!(
+            "{QUERY_ORDER}
+        where o.id = ? limit 1
     "
-    ))?;
+        ),
+        (id,),
+    )?;
 
     if let Some(mut order) = order {
         order.query_other(conn)?;
@@ -193,12 +210,46 @@ fn query_order_by_id(conn: &mut PooledConn, id: &str) -> Result<Arc<Order>, Resp
     }
 }
 
+/// 客户自己的所有订单，用于客户端
+pub fn query_customer_orders(
+    conn: &mut PooledConn,
+    customer: &str,
+) -> Result<Vec<CustomerOrder>, Response> {
+    let mut orders: Vec<Order> = conn.exec(
+        format!(
+            "{QUERY_ORDER}
+        where o.customer = ?
+        order by o.create_time desc
+    "
+        ),
+        (customer,),
+    )?;
+    for order in &mut orders {
+        order.query_other(conn)?;
+    }
+    Ok(orders.iter().map(CustomerOrder::from).collect())
+}
+
+/// 客户的某个订单，不是该客户的订单时视为不存在
+pub fn query_customer_order(
+    conn: &mut PooledConn,
+    customer: &str,
+    id: &str,
+) -> Result<CustomerOrder, Response> {
+    let order = query_order_by_id(conn, id)?;
+    if order.customer.id != customer {
+        return Err(Response::not_exist("订单不存在"));
+    }
+    Ok(CustomerOrder::from(order.as_ref()))
+}
+
 #[derive(Debug, Deserialize)]
 struct QueryParams {
     ty: u8,
     data: String,
     #[serde(default)]
     limit: u32,
+    /// 不是有效的订单状态时查询所有状态，例如-1
     status: i32,
 }
 static QUERY_ORDER: &str = "select o.*, u.name as salesman_name, c.name as customer_name, 
@@ -206,11 +257,23 @@ static QUERY_ORDER: &str = "select o.*, u.name as salesman_name, c.name as custo
         from order_data o
         join user u on u.id = o.salesman
         join customer c on c.id = o.customer";
-async fn query_person_order<'err>(
-    conn: &mut DB<'err>,
+
+/// `owner`为已经过权限检查的业务员或部门条件，`status`不是有效的订单状态时查询所有状态
+fn order_list_query(owner: &Cond, status: i32, limit: u32) -> Query {
+    let status = match OrderStatus::try_from(status) {
+        Ok(status) => Cond::eq("o.status", status.code()),
+        Err(_) => Cond::ge("o.status", 0),
+    };
+    Query::new(format!("{QUERY_ORDER} where "))
+        .cond(&owner.clone().and(status))
+        .push(" order by o.create_time desc limit ")
+        .bind(limit)
+}
+
+async fn query_person_order(
+    conn: &mut PooledConn,
     param: &QueryParams,
     user: &User,
-    status: &str,
 ) -> Result<Vec<Order>, Response> {
     let id = if param.data.eq("my") || user.id == param.data {
         log!("{}-{} 正在查询自己的订单", user.department, user.name);
@@ -224,8 +287,9 @@ async fn query_person_order<'err>(
             u.department,
             u.department
         );
-        if u.department == user.department {
-            if !verify_perms!(&user.role, OtherGroup::NAME, OtherGroup::QUERY_ORDER) {
+        let tree = DepartmentTree::load(conn)?;
+        if tree.contains(&user.department, &u.department) {
+            if let Err(e) = user.can(OtherPerm::QueryOrder, Scope::Any).await {
                 log!(
                     "{}-{} 查询 {}-{} 的订单失败，因为没有查看本部门其他成员订单的权限",
                     user.department,
@@ -233,146 +297,98 @@ async fn query_person_order<'err>(
                     u.department,
                     u.department
                 );
-                return Err(Response::permission_denied());
+                return Err(e.into());
             }
             &param.data
-        } else if verify_perms!(
-            &user.role,
-            OtherGroup::NAME,
-            OtherGroup::QUERY_ORDER,
-            Some(["all"].as_slice())
-        ) {
-            log!(
-                "{}-{} 查询 {}-{} 的订单失败，因为没有查看其他部门成员订单的权限",
-                user.department,
-                user.name,
-                u.department,
-                u.department
-            );
-            &param.data
         } else {
-            log!(
-                "{}-{} 查询 {}-{} 的订单失败，因为没有查看其他成员订单的权限",
-                user.department,
-                user.name,
-                u.department,
-                u.department
-            );
-            return Err(Response::permission_denied());
+            if let Err(e) = user.can(OtherPerm::QueryOrder, Scope::All).await {
+                log!(
+                    "{}-{} 查询 {}-{} 的订单失败，因为没有查看其他部门成员订单的权限",
+                    user.department,
+                    user.name,
+                    u.department,
+                    u.department
+                );
+                return Err(e.into());
+            }
+            &param.data
         }
     };
-    let query = format!(
-        "{QUERY_ORDER}
-            where o.salesman = ? and o.status {status}
-            order by o.create_time desc
-            limit ?
-        "
-    );
-    conn.exec(&query, (&id, &param.limit)).map_err(Into::into)
+    order_list_query(&Cond::eq("o.salesman", id.as_str()), param.status, param.limit)
+        .fetch(conn)
+        .map_err(Into::into)
 }
 
 async fn query_department_order(
     conn: &mut PooledConn,
     param: &QueryParams,
     user: &User,
-    status: &str,
 ) -> Result<Vec<Order>, Response> {
-    if !verify_perms!(&user.role, OtherGroup::NAME, OtherGroup::QUERY_ORDER) {
-        return Err(Response::permission_denied());
-    }
-    let depart = if param.data.eq("my") || user.department == param.data {
-        &user.department
-    } else if verify_perms!(
-        &user.role,
-        OtherGroup::NAME,
-        OtherGroup::QUERY_ORDER,
-        Some(["all"].as_slice())
-    ) {
-        &param.data
+    user.can(OtherPerm::QueryOrder, Scope::Any).await?;
+    let tree = DepartmentTree::load(conn)?;
+    let depart = if param.data.eq("my") || tree.contains(&user.department, &param.data) {
+        op::ternary!(param.data.eq("my") => &user.department; &param.data)
     } else {
-        log!(
-            "{}-{} 查询 {} 部门的订单失败，因为没有查看其他部门订单的权限",
-            user.department,
-            user.name,
-            param.data
-        );
-        return Err(Response::permission_denied());
+        if let Err(e) = user.can(OtherPerm::QueryOrder, Scope::All).await {
+            log!(
+                "{}-{} 查询 {} 部门的订单失败，因为没有查看其他部门订单的权限",
+                user.department,
+                user.name,
+                param.data
+            );
+            return Err(e.into());
+        }
+        &param.data
     };
     log!(
         "{}-{} 正在查询 {depart} 部门的订单",
         user.department,
         user.name
     );
-    conn.exec(
-        format!(
-            "{QUERY_ORDER}
-        where o.status {status} and u.department = ?
-        order by o.create_time desc
-        limit ?
-    "
-        ),
-        (&depart, &param.limit),
-    )
-    .map_err(Into::into)
+    // 包括下级部门的订单
+    let departments = Cond::in_list("u.department", tree.descendants(depart));
+    order_list_query(&departments, param.status, param.limit)
+        .fetch(conn)
+        .map_err(Into::into)
 }
 
 async fn query_company_order(
     conn: &mut PooledConn,
     user: &User,
-    limit: u32,
-    status: &str,
+    param: &QueryParams,
 ) -> Result<Vec<Order>, Response> {
     log!("{}-{} 正在查询全公司的订单", user.department, user.name);
-    if !verify_perms!(
-        &user.role,
-        OtherGroup::NAME,
-        OtherGroup::QUERY_ORDER,
-        Some(["all"].as_slice())
-    ) {
+    if let Err(e) = user.can(OtherPerm::QueryOrder, Scope::All).await {
         log!(
             "{}-{} 查询全公司的订单失败，没有该权限",
             user.department,
             user.name
         );
-        return Err(Response::permission_denied());
+        return Err(e.into());
     }
-    conn.query(format!(
-        "{QUERY_ORDER}
-        where o.status {status}
-        order by
-            o.create_time desc
-        limit {limit}
-            "
-    ))
-    .map_err(Into::into)
+    order_list_query(&Cond::any(), param.status, param.limit)
+        .fetch(conn)
+        .map_err(Into::into)
 }
 
-async fn query_order(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+async fn query_order(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let uid = user.id.clone();
     log!("{}-{} 请求查询订单", user.department, user.name);
     let param_str = value.to_string();
     let mut param: QueryParams = serde_json::from_value(value)?;
     if param.limit == 0 {
         param.limit = 50
     }
-    let status = if param.status >= 3 {
-        ">= 0".to_string()
-    } else {
-        format!("={}", param.status)
-    };
     let value = if let Some(value) = get_cache!(ORDER_CACHE, &uid, &param_str) {
         log!("缓存命中");
         value
     } else {
         log!("缓存未命中");
         let mut data = match param.ty {
-            0 => query_person_order(&mut conn, &param, &user, &status).await?,
-            1 => query_department_order(&mut conn, &param, &user, &status).await?,
-            2 => query_company_order(&mut conn, &user, param.limit, &status).await?,
+            0 => query_person_order(&mut conn, &param, &user).await?,
+            1 => query_department_order(&mut conn, &param, &user).await?,
+            2 => query_company_order(&mut conn, &user, &param).await?,
             _ => return Ok(Response::empty()),
         };
         for o in &mut data {
@@ -399,12 +415,8 @@ struct PayParam {
     inv_index: i32,
 }
 
-async fn finish_repayment(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+async fn finish_repayment(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let param: PayParam = serde_json::from_value(value)?;
     log!(
         "{} 请求完成订单{} 第{}期 的收款",
@@ -413,12 +425,10 @@ async fn finish_repayment(header: HeaderMap, Json(value): Json<Value>) -> Respon
         param.inv_index
     );
     let time = TIME::now()?;
-    let query = format!(
-        "select inv_index from order_instalment where order_id = '{}' and inv_index = {}",
-        param.id, param.inv_index
-    );
-    // println!("{query}");
-    let key: Option<i32> = conn.query_first(query)?;
+    let key: Option<i32> = conn.exec_first(
+        "select inv_index from order_instalment where order_id = ? and inv_index = ?",
+        (&param.id, param.inv_index),
+    )?;
     if key.is_none() {
         log!("收款失败，无法找到第{}期回款", param.inv_index);
         return Err(Response::not_exist("无法找到该分期回款"));
@@ -443,29 +453,98 @@ async fn finish_repayment(header: HeaderMap, Json(value): Json<Value>) -> Respon
     Ok(Response::ok(json!("收款成功")))
 }
 
-async fn delete_order(header: HeaderMap, Path(id): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+async fn delete_order(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
     log!("{} 请求删除订单{}", user, id);
     let order = query_order_by_id(&mut conn, &id)?;
-    if order.status != 0 {
+    if order.status != OrderStatus::Intent {
         return Err(Response::dissatisfy("仅意向订单可以删除"));
     } else if order.salesman.id != user.id {
         log!("{user}删除订单{}失败，只能删除自己的订单", order.id);
         return Err(Response::permission_denied());
     }
-    order.del(&mut conn)?;
+    commit_or_rollback!(__delete_order, &mut conn, &order, &user.id)?;
     ORDER_CACHE.clear();
     ORDER_CACHE_WITH_ID.clear();
+    PRODUCT_CACHE.clear();
     log!("{} 成功删除订单{}", user, id);
     Ok(Response::ok(json!("删除订单成功")))
 }
 
+/// 释放预留，已发货时把库存退回。状态记录只追加，删除订单时保留并追加一条删除记录
+fn __delete_order(conn: &mut PooledConn, order: &Order, operator: &str) -> Result<(), Response> {
+    stock::release(conn, &order.id)?;
+    Ship::default().apply_stock(conn, &order.id, Some(&order.ship), &order.product, operator)?;
+    status::record(conn, &order.id, Some(order.status), order.status, operator, "删除订单")?;
+    order.del(conn)
+}
+
+#[derive(Deserialize)]
+struct ReserveParam {
+    #[serde(deserialize_with = "deserialize_storehouse")]
+    storehouse: String,
+}
+
+/// 意向订单在某个库房预留产品库存，替换之前的预留
+async fn reserve_order(
+    user: AuthUser,
+    Path(id): Path<String>,
+    Json(value): Json<Value>,
+) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let param: ReserveParam = serde_json::from_value(value)?;
+    log!("{user} 请求为订单{id}在库房{}预留库存", param.storehouse);
+    let order = query_order_by_id(&mut conn, &id)?;
+    if order.status != OrderStatus::Intent {
+        return Err(Response::dissatisfy("仅意向订单可以预留库存"));
+    } else if order.salesman.id != user.id {
+        log!("{user} 预留订单{id}的库存失败，只能预留自己的订单");
+        return Err(Response::permission_denied());
+    }
+    let items = product::stock_items(&order.product);
+    commit_or_rollback!(stock::reserve, &mut conn, &id, &param.storehouse, &items)?;
+    PRODUCT_CACHE.clear();
+    log!("{user} 成功为订单{id}在库房{}预留库存", param.storehouse);
+    Ok(Response::empty())
+}
+
+async fn release_order(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let order = query_order_by_id(&mut conn, &id)?;
+    if order.salesman.id != user.id {
+        log!("{user} 释放订单{id}的预留库存失败，只能释放自己的订单");
+        return Err(Response::permission_denied());
+    }
+    stock::release(&mut conn, &id)?;
+    PRODUCT_CACHE.clear();
+    log!("{user} 成功释放订单{id}的预留库存");
+    Ok(Response::empty())
+}
+
 async fn get_order_file(
     Path(url): Path<String>,
 ) -> Result<BodyFile, (axum::http::StatusCode, String)> {
     BodyFile::new_with_base64_url("resources/order", &url)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::order_list_query;
+    use crate::database::query::{assert_bound, Cond, PAYLOADS};
+
+    #[test]
+    fn list_query_binds_input() {
+        for payload in PAYLOADS {
+            for owner in [
+                Cond::eq("o.salesman", payload),
+                Cond::in_list("u.department", [payload]),
+            ] {
+                let query = order_list_query(&owner, 1, 50);
+                assert_bound(&query, payload);
+                assert!(query.sql().ends_with("(o.status = ?)) order by o.create_time desc limit ?"));
+            }
+        }
+        let all = order_list_query(&Cond::any(), -1, 50);
+        assert!(all.sql().contains("(o.status >= ?)"));
+    }
+}
@@ -1,18 +1,15 @@
-use crate::libs::{
-    dser::{deser_f32, serialize_f32_to_string},
-    TIME,
+use crate::{
+    libs::{money::Money, TIME},
+    perm::auth::AuthUser,
+    Response, ResponseResult,
 };
 use mysql::{params, prelude::Queryable, PooledConn};
 use mysql_common::prelude::FromRow;
 use serde::{Deserialize, Serialize};
-#[derive(Deserialize, FromRow, Serialize, PartialEq, Debug)]
+#[derive(Deserialize, FromRow, Serialize, PartialEq, Debug, Clone)]
 pub struct Instalment {
-    #[serde(deserialize_with = "deser_f32")]
-    #[serde(serialize_with = "serialize_f32_to_string")]
-    pub interest: f32,
-    #[serde(deserialize_with = "deser_f32")]
-    #[serde(serialize_with = "serialize_f32_to_string")]
-    pub original_amount: f32,
+    pub interest: Money,
+    pub original_amount: Money,
     #[serde(skip_deserializing)]
     pub date: Option<String>,
     #[serde(default)]
@@ -21,10 +18,8 @@ pub struct Instalment {
     pub finish: i32,
 }
 impl Instalment {
-    pub fn computed_instalment(instalment: &[Instalment]) -> f32 {
-        instalment
-            .iter()
-            .fold(0.0f32, |output, inv| output + inv.original_amount)
+    pub fn computed_instalment(instalment: &[Instalment]) -> Money {
+        instalment.iter().map(|inv| inv.original_amount).sum()
     }
 
     pub fn query(conn: &mut PooledConn, id: &str) -> mysql::Result<Vec<Instalment>> {
@@ -67,3 +62,37 @@ impl Instalment {
         Ok(())
     }
 }
+
+#[derive(Deserialize)]
+pub struct SplitParams {
+    product: Vec<super::Product>,
+    count: usize,
+}
+
+/// 按订单产品计算总额并平均分期，多出的分计入前面的分期，保证分期之和等于订单总额
+pub async fn split_instalment(
+    _user: AuthUser,
+    axum::Json(value): axum::Json<serde_json::Value>,
+) -> ResponseResult {
+    let params: SplitParams = serde_json::from_value(value)?;
+    if params.count == 0 || params.count > 120 {
+        return Err(Response::invalid_value("分期数必须在1到120之间"));
+    }
+    let sum = super::product::computed_products_sum(&params.product);
+    let plan: Vec<Instalment> = sum
+        .split(params.count)
+        .into_iter()
+        .enumerate()
+        .map(|(i, original_amount)| Instalment {
+            interest: Money::ZERO,
+            original_amount,
+            date: None,
+            inv_index: i as i32 + 1,
+            finish: 0,
+        })
+        .collect();
+    Ok(Response::ok(serde_json::json!({
+        "sum": sum,
+        "instalment": plan
+    })))
+}
@@ -1,48 +1,19 @@
-use crate::libs::dser::serialize_f32_to_string;
+use crate::{
+    libs::money::{Money, Rate},
+    pages::func::store::stock::StockItem,
+};
 use mysql::{params, prelude::Queryable, PooledConn};
 use mysql_common::prelude::FromRow;
-use serde::{Deserialize, Deserializer, Serialize};
-use serde_json::Value;
+use serde::{Deserialize, Serialize};
 
 use super::data::Order;
 
-pub fn deserialize_f32_max_1<'de, D>(de: D) -> Result<f32, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let value: Value = Deserialize::deserialize(de)?;
-    match value {
-        Value::Number(n) => {
-            if let Some(f) = n.as_f64() {
-                Ok(f as f32)
-            } else if let Some(i) = n.as_i64() {
-                Ok(i as f32)
-            } else if let Some(u) = n.as_u64() {
-                Ok(u as f32)
-            } else {
-                Err(serde::de::Error::custom("discount不是浮点数格式"))
-            }
-        }
-        Value::String(value) => {
-            if let Ok(f) = value.parse::<f32>() {
-                op::ternary!(f <= 1.0 => Ok(f), Err(serde::de::Error::custom("discount最大值为1")))
-            } else {
-                Err(serde::de::Error::custom("discount不是浮点数格式"))
-            }
-        }
-        _ => Err(serde::de::Error::custom("discount不是浮点数格式")),
-    }
-}
-#[derive(Deserialize, Serialize, Debug, FromRow)]
+#[derive(Deserialize, Serialize, Debug, Clone, FromRow)]
 pub struct Product {
     pub id: String,
     pub name: String,
-    #[serde(deserialize_with = "deserialize_f32_max_1")]
-    #[serde(serialize_with = "serialize_f32_to_string")]
-    pub discount: f32,
-    #[serde(deserialize_with = "crate::libs::dser::deser_f32")]
-    #[serde(serialize_with = "serialize_f32_to_string")]
-    pub price: f32,
+    pub discount: Rate,
+    pub price: Money,
     pub model: String,
     #[serde(skip_deserializing)]
     pub cover: String,
@@ -51,50 +22,30 @@ pub struct Product {
     pub unit: String,
 }
 
-// pub fn f32_is_eq(v1: f32, v2: f32) -> bool {
-//     (-0.001..0.001f32).contains(&(v1 - v2))
-// }
-pub fn computed_products_sum(products: &[Product]) -> f32 {
-    let mut sum = 0.0f32;
-    for product in products {
-        let prsd = product.price_sum_with_discount();
-        sum += prsd;
-    }
-    sum
+/// 订单金额，各产品行扣除折扣并舍入到分后相加
+pub fn computed_products_sum(products: &[Product]) -> Money {
+    products.iter().map(Product::price_sum_with_discount).sum()
 }
-// pub fn to_fixed(size: usize, value: f32) -> f32 {
-//     let value = value.to_string();
-//     let sp = value.splitn(2, '.').collect::<Vec<_>>();
-//     let digit = if let Some(dig) = sp.get(1) {
-//         if dig.len() >= size {
-//             if dig.len() > size {
-//                 let flag = match dig.as_bytes()[size + 1] {
-//                     b'5'..=b'9' => true,
-//                     _ => false
-//                 };
-//                 let value: i32 = (&dig[..size]).parse().unwrap();
-//                 if value <= 99 {
 
-//                 }
-//             } else {
-//                 dig.to_string()
-//             }
-//         } else {
-//             dig.chars().next().expect("Invalid").to_string()
-//         }
-//     } else {
-//         "0".to_string()
-//     }
-//     format!("{}.", sp[0]).parse().unwrap_or(0.0)
-// }
+/// 订单产品的出库数量
+pub fn stock_items(products: &[Product]) -> Vec<StockItem<'_>> {
+    products
+        .iter()
+        .map(|p| StockItem {
+            product: &p.id,
+            name: &p.name,
+            amount: p.amount as i32,
+        })
+        .collect()
+}
 
 impl Product {
-    pub fn price_sum(&self) -> f32 {
-        self.amount as f32 * self.price
+    pub fn price_sum(&self) -> Money {
+        self.price.times(self.amount)
     }
 
-    pub fn price_sum_with_discount(&self) -> f32 {
-        self.price_sum() * (1.0 - self.discount)
+    pub fn price_sum_with_discount(&self) -> Money {
+        self.price_sum().discounted(self.discount)
     }
     pub fn insert(
         products: &[Product],
@@ -1,6 +1,16 @@
-use crate::libs::dser::{op_deser_yyyy_mm_dd_hh_mm_ss, op_deserialize_storehouse};
+use crate::{
+    libs::dser::{op_deser_yyyy_mm_dd_hh_mm_ss, op_deserialize_storehouse},
+    pages::func::store::stock,
+    Response,
+};
+use mysql::{prelude::Queryable, PooledConn};
 use mysql_common::prelude::FromRow;
 use serde::{Deserialize, Serialize};
+
+use super::{
+    product::{stock_items, Product},
+    status::OrderStatus,
+};
 #[derive(Debug, Deserialize, Serialize, FromRow, Default)]
 pub struct Ship {
     pub shipped: i32,
@@ -9,3 +19,93 @@ pub struct Ship {
     #[serde(deserialize_with = "op_deserialize_storehouse")]
     pub storehouse: Option<String>,
 }
+
+impl Ship {
+    /// 已发货时为发货的库房
+    fn shipped_from(&self) -> Option<&str> {
+        op::ternary!(self.shipped == 1 => self.storehouse.as_deref(); None)
+    }
+
+    /// 发货状态从`old`变为`self`时需要退回库存和出库的库房，没有变化时都为None
+    fn stock_moves<'a>(&'a self, old: Option<&'a Ship>) -> (Option<&'a str>, Option<&'a str>) {
+        let before = old.and_then(Ship::shipped_from);
+        let after = self.shipped_from();
+        op::ternary!(before == after => (None, None); (before, after))
+    }
+
+    /// 发货状态从`old`变为`self`时调整库存并记录流水，`old`为None表示新订单。
+    /// 取消发货或更换库房时先把库存退回原来的库房
+    pub fn apply_stock(
+        &self,
+        conn: &mut PooledConn,
+        order_id: &str,
+        old: Option<&Ship>,
+        products: &[Product],
+        operator: &str,
+    ) -> Result<(), Response> {
+        let (back, out) = self.stock_moves(old);
+        let items = stock_items(products);
+        if let Some(storehouse) = back {
+            stock::ship_back(conn, order_id, storehouse, &items, operator)?;
+        }
+        if let Some(storehouse) = out {
+            stock::ship_out(conn, order_id, storehouse, &items, operator)?;
+        }
+        Ok(())
+    }
+
+    /// 在事务中锁定订单并读取当前的发货状态，不使用缓存。
+    /// 订单状态已经不是`status`时失败，避免并发的请求按过期的状态重复调整库存
+    pub fn lock(
+        conn: &mut PooledConn,
+        order_id: &str,
+        status: OrderStatus,
+    ) -> Result<Ship, Response> {
+        let ship: Option<Ship> = conn.exec_first(
+            "SELECT shipped, shipped_date AS date, shipped_storehouse AS storehouse
+            FROM order_data WHERE id = ? AND status = ? LIMIT 1 FOR UPDATE",
+            (order_id, status),
+        )?;
+        ship.ok_or_else(|| Response::dissatisfy("订单状态已被修改，请刷新后重试"))
+    }
+}
+
+/// 意向订单的产品变化后按新的产品重新预留，没有预留时什么都不做
+pub fn refresh_reservation(
+    conn: &mut PooledConn,
+    order_id: &str,
+    products: &[Product],
+) -> Result<(), Response> {
+    if let Some(storehouse) = stock::reserved_storehouse(conn, order_id)? {
+        stock::reserve(conn, order_id, &storehouse, &stock_items(products))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ship;
+
+    fn ship(shipped: i32, storehouse: &str) -> Ship {
+        Ship {
+            shipped,
+            date: None,
+            storehouse: Some(storehouse.to_owned()),
+        }
+    }
+
+    #[test]
+    fn stock_moves() {
+        let (a, a2, b) = (ship(1, "A"), ship(1, "A"), ship(1, "B"));
+        let unshipped = ship(0, "A");
+        // 新订单直接发货
+        assert_eq!(a.stock_moves(None), (None, Some("A")));
+        assert_eq!(unshipped.stock_moves(None), (None, None));
+        // 重复设置为已发货不再出库
+        assert_eq!(a2.stock_moves(Some(&a)), (None, None));
+        assert_eq!(a.stock_moves(Some(&unshipped)), (None, Some("A")));
+        assert_eq!(unshipped.stock_moves(Some(&a)), (Some("A"), None));
+        assert_eq!(b.stock_moves(Some(&a)), (Some("A"), Some("B")));
+        assert_eq!(Ship::default().stock_moves(Some(&a)), (Some("A"), None));
+    }
+}
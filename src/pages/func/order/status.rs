@@ -0,0 +1,343 @@
+//! 订单状态
+//!
+//! 所有状态变化都要经过[`TRANSITIONS`]检查，并记入`order_status_history`。
+//! 已取消、已退款和已退货是终止状态，不能再变化
+use std::fmt;
+
+use axum::{extract::Path, http::StatusCode, Json};
+use mysql::{prelude::Queryable, FromValueError, PooledConn, Value};
+use mysql_common::prelude::FromRow;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+
+use crate::{
+    commit_or_rollback,
+    database::get_db,
+    libs::{
+        cache::{ORDER_CACHE, ORDER_CACHE_WITH_ID, PRODUCT_CACHE},
+        TimeFormat, TIME,
+    },
+    log,
+    pages::func::store::stock,
+    perm::auth::{AuthUser, OtherPerm, Scope},
+    Response, ResponseResult,
+};
+
+use super::{data::Order, query_order_by_id, ship::Ship};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "i32", into = "i32")]
+pub enum OrderStatus {
+    /// 意向
+    Intent,
+    /// 成交
+    Transaction,
+    /// 完成
+    Complete,
+    /// 已取消，释放预留，已发货的库存退回
+    Cancelled,
+    /// 已退款，不退回库存
+    Refunded,
+    /// 已退货，已发货的库存退回
+    Returned,
+}
+
+/// 允许的状态变化
+pub const TRANSITIONS: &[(OrderStatus, OrderStatus)] = {
+    use OrderStatus::*;
+    &[
+        (Intent, Transaction),
+        (Intent, Cancelled),
+        (Transaction, Complete),
+        (Transaction, Cancelled),
+        (Transaction, Refunded),
+        (Transaction, Returned),
+        (Complete, Refunded),
+        (Complete, Returned),
+    ]
+};
+
+impl OrderStatus {
+    pub fn code(self) -> i32 {
+        match self {
+            Self::Intent => 0,
+            Self::Transaction => 1,
+            Self::Complete => 2,
+            Self::Cancelled => 3,
+            Self::Refunded => 4,
+            Self::Returned => 5,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Intent => "意向",
+            Self::Transaction => "成交",
+            Self::Complete => "完成",
+            Self::Cancelled => "已取消",
+            Self::Refunded => "已退款",
+            Self::Returned => "已退货",
+        }
+    }
+
+    /// 可以变为的状态
+    pub fn next(self) -> Vec<OrderStatus> {
+        TRANSITIONS
+            .iter()
+            .filter(|(from, _)| *from == self)
+            .map(|(_, to)| *to)
+            .collect()
+    }
+
+    pub fn is_terminal(self) -> bool {
+        self.next().is_empty()
+    }
+
+    pub fn check(self, to: OrderStatus) -> Result<(), IllegalTransition> {
+        if TRANSITIONS.contains(&(self, to)) {
+            Ok(())
+        } else {
+            Err(IllegalTransition { from: self, to })
+        }
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl TryFrom<i32> for OrderStatus {
+    type Error = String;
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::Intent,
+            1 => Self::Transaction,
+            2 => Self::Complete,
+            3 => Self::Cancelled,
+            4 => Self::Refunded,
+            5 => Self::Returned,
+            _ => return Err(format!("未知的订单状态`{value}`")),
+        })
+    }
+}
+
+impl From<OrderStatus> for i32 {
+    fn from(value: OrderStatus) -> Self {
+        value.code()
+    }
+}
+
+impl TryFrom<Value> for OrderStatus {
+    type Error = FromValueError;
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match mysql::from_value_opt::<i32>(value.clone()).map(OrderStatus::try_from) {
+            Ok(Ok(status)) => Ok(status),
+            _ => Err(FromValueError(value)),
+        }
+    }
+}
+impl mysql::prelude::FromValue for OrderStatus {
+    type Intermediate = OrderStatus;
+}
+impl From<OrderStatus> for Value {
+    fn from(value: OrderStatus) -> Self {
+        Value::Int(value.code() as i64)
+    }
+}
+
+/// 不允许的状态变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IllegalTransition {
+    pub from: OrderStatus,
+    pub to: OrderStatus,
+}
+
+impl fmt::Display for IllegalTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "订单不能从`{}`变为`{}`", self.from, self.to)
+    }
+}
+
+impl From<IllegalTransition> for Response {
+    fn from(value: IllegalTransition) -> Self {
+        Response::new(
+            StatusCode::OK,
+            8,
+            json!({
+                "message": value.to_string(),
+                "from": value.from,
+                "to": value.to,
+                "allowed": value.from.next(),
+            }),
+        )
+    }
+}
+
+/// 记录一次状态变化，`from`为None表示创建订单
+pub fn record(
+    conn: &mut PooledConn,
+    order_id: &str,
+    from: Option<OrderStatus>,
+    to: OrderStatus,
+    operator: &str,
+    reason: &str,
+) -> mysql::Result<()> {
+    conn.exec_drop(
+        "INSERT INTO order_status_history (order_id, from_status, to_status, operator, create_time, reason)
+        VALUES (?, ?, ?, ?, ?, ?)",
+        (
+            order_id,
+            from,
+            to,
+            operator,
+            TIME::now().unwrap_or_default().format(TimeFormat::YYYYMMDD_HHMMSS),
+            reason,
+        ),
+    )
+}
+
+/// 检查并修改订单状态，同时记录变化。订单在读取之后被其他请求修改了状态时失败
+pub fn transition(
+    conn: &mut PooledConn,
+    order: &Order,
+    to: OrderStatus,
+    operator: &str,
+    reason: &str,
+) -> Result<(), Response> {
+    order.status.check(to)?;
+    conn.exec_drop(
+        "UPDATE order_data SET status = ? WHERE id = ? AND status = ? LIMIT 1",
+        (to, &order.id, order.status),
+    )?;
+    if conn.affected_rows() == 0 {
+        return Err(Response::dissatisfy("订单状态已被修改，请刷新后重试"));
+    }
+    record(conn, &order.id, Some(order.status), to, operator, reason)?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ChangeParams {
+    status: OrderStatus,
+    reason: String,
+}
+
+/// 取消、退款或退货，成交和完成需要使用对应的接口
+pub async fn change_status(
+    user: AuthUser,
+    Path(id): Path<String>,
+    Json(value): Json<JsonValue>,
+) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let params: ChangeParams = serde_json::from_value(value)?;
+    log!("{user} 请求把订单{id}的状态改为{}", params.status);
+    if !matches!(
+        params.status,
+        OrderStatus::Cancelled | OrderStatus::Refunded | OrderStatus::Returned
+    ) {
+        return Err(Response::invalid_value(format!(
+            "不能通过该接口把订单改为`{}`",
+            params.status
+        )));
+    }
+    if params.reason.trim().is_empty() {
+        return Err(Response::invalid_value("必须填写原因"));
+    }
+    let order = query_order_by_id(&mut conn, &id)?;
+    if order.salesman.id != user.id {
+        log!("{user} 修改订单{id}的状态失败，只能修改自己的订单");
+        return Err(Response::permission_denied());
+    }
+    commit_or_rollback!(__change_status, &mut conn, &order, &params, &user.id)?;
+    ORDER_CACHE.clear();
+    ORDER_CACHE_WITH_ID.clear();
+    PRODUCT_CACHE.clear();
+    log!("{user} 成功把订单{id}的状态改为{}", params.status);
+    Ok(Response::empty())
+}
+
+fn __change_status(
+    conn: &mut PooledConn,
+    order: &Order,
+    params: &ChangeParams,
+    operator: &str,
+) -> Result<(), Response> {
+    order.status.check(params.status)?;
+    let shipped = Ship::lock(conn, &order.id, order.status)?;
+    if params.status == OrderStatus::Returned && shipped.shipped != 1 {
+        return Err(Response::dissatisfy("订单还没有发货，不能退货"));
+    }
+    stock::release(conn, &order.id)?;
+    if params.status != OrderStatus::Refunded {
+        Ship::default().apply_stock(
+            conn,
+            &order.id,
+            Some(&shipped),
+            &order.product,
+            operator,
+        )?;
+    }
+    transition(conn, order, params.status, operator, &params.reason)
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct History {
+    from_status: Option<OrderStatus>,
+    to_status: OrderStatus,
+    operator: String,
+    operator_name: Option<String>,
+    create_time: String,
+    reason: Option<String>,
+}
+
+/// 订单的状态变化记录，业务员本人或可以查看他人订单的用户可以查看
+pub async fn query_status_history(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let order = query_order_by_id(&mut conn, &id)?;
+    if order.salesman.id != user.id {
+        user.can(OtherPerm::QueryOrder, Scope::Any).await?;
+    }
+    let history: Vec<History> = conn.exec(
+        "SELECT h.from_status, h.to_status, h.operator, u.name AS operator_name, h.create_time, h.reason
+        FROM order_status_history h LEFT JOIN user u ON u.id = h.operator
+        WHERE h.order_id = ?
+        ORDER BY h.id",
+        (&id,),
+    )?;
+    log!("{user} 查询了订单{id}的{}条状态记录", history.len());
+    Ok(Response::ok(json!(history)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OrderStatus::{self, *};
+
+    #[test]
+    fn codes_round_trip() {
+        for status in [Intent, Transaction, Complete, Cancelled, Refunded, Returned] {
+            assert_eq!(OrderStatus::try_from(status.code()), Ok(status));
+        }
+        assert!(OrderStatus::try_from(6).is_err());
+        assert!(OrderStatus::try_from(-1).is_err());
+    }
+
+    #[test]
+    fn transitions() {
+        assert!(Intent.check(Transaction).is_ok());
+        assert!(Transaction.check(Returned).is_ok());
+        assert!(Complete.check(Refunded).is_ok());
+        assert!(Intent.check(Complete).is_err());
+        assert!(Intent.check(Refunded).is_err());
+        assert!(Complete.check(Cancelled).is_err());
+        assert!(Complete.check(Transaction).is_err());
+        for terminal in [Cancelled, Refunded, Returned] {
+            assert!(terminal.is_terminal());
+            assert_eq!(
+                terminal.check(Intent).unwrap_err().to_string(),
+                format!("订单不能从`{terminal}`变为`意向`")
+            );
+        }
+    }
+}
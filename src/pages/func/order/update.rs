@@ -1,20 +1,24 @@
-use axum::{extract::Path, http::HeaderMap, Json};
+use axum::{extract::Path, Json};
 use mysql::{params, prelude::Queryable, PooledConn};
 use serde::Deserialize;
 use serde_json::{json, Value};
 
 use crate::{
-    bearer, commit_or_rollback,
+    commit_or_rollback,
     database::get_db,
-    libs::{cache::{ORDER_CACHE, ORDER_CACHE_WITH_ID}, TimeFormat, TIME},
+    libs::{cache::{ORDER_CACHE, ORDER_CACHE_WITH_ID, PRODUCT_CACHE}, TimeFormat, TIME},
     log,
-    pages::{account::get_user, User},
-    parse_jwt_macro, Response, ResponseResult,
+    pages::User,
+    perm::auth::AuthUser,
+    Response, ResponseResult,
 };
 
 use super::{
     customer::Customer, data::Order, invoice::Invoice, payment::Instalment, product::Product,
-    query_order_by_id, ship::Ship, verify_instalment,
+    query_order_by_id,
+    ship::{refresh_reservation, Ship},
+    status::{self, OrderStatus},
+    verify_instalment,
 };
 
 #[derive(Deserialize)]
@@ -27,17 +31,14 @@ struct TranOrder {
     customer: Customer,
 }
 
-pub async fn order_transaction(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+pub async fn order_transaction(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let mut param: TranOrder = serde_json::from_value(value)?;
     commit_or_rollback!(__order_transaction, &mut conn, &mut param, &user)?;
     log!("{user} 成功设置订单{} 为成交订单", param.id);
     ORDER_CACHE.clear();
     ORDER_CACHE_WITH_ID.clear();
+    PRODUCT_CACHE.clear();
     Ok(Response::ok(json!("订单已设为成交订单")))
 }
 
@@ -57,76 +58,78 @@ fn __order_transaction(
         );
         return Err(Response::permission_denied());
     }
+    order.status.check(OrderStatus::Transaction)?;
     verify_instalment(&param.product, &param.instalment)?;
-    if order.status == 0 {
-        if param.ship.shipped == 1 && param.ship.storehouse.is_none() {
-            return Err(Response::dissatisfy("ship的storehouse必须设置"));
-        }
-        if param.ship.shipped == 1 && param.ship.date.is_none() {
-            param.ship.date = Some(time.format(TimeFormat::YYYYMMDD_HHMMSS))
-        }
-        if param.invoice.required == 1 {
-            param.invoice.insert_or_update(
-                &param.id,
-                conn,
-                order.salesman.name(),
-                &order.customer.name,
-            )?;
-        }
-        Instalment::insert(conn, &param.id, &param.instalment, false)?;
-        Product::insert(&param.product, &param.id, conn, true)?;
-
-        conn.exec_drop(
-            "update order_data set transaction_date=:td, 
-                        shipped=:sd, shipped_date=:sdd, 
-                        status=1, shipped_storehouse=:ssh, 
-                        invoice_required=:ir,
-                        customer=:customer,
-                        purchase_unit=:pu,
-                        address=:address
-                        where id = :id
-                        limit 1",
-            params! {
-                "sd" => param.ship.shipped,
-                "sdd" => &param.ship.date,
-                "td" => time.format(TimeFormat::YYYYMMDD_HHMMSS),
-                "ssh" => &param.ship.storehouse,
-                "ir" => &param.invoice.required,
-                "id" => &param.id,
-                "customer" => &param.customer.id,
-                "address" => &param.customer.address,
-                "pu" => &param.customer.purchase_unit
-            },
+    if param.ship.shipped == 1 && param.ship.storehouse.is_none() {
+        return Err(Response::dissatisfy("ship的storehouse必须设置"));
+    }
+    if param.ship.shipped == 1 && param.ship.date.is_none() {
+        param.ship.date = Some(time.format(TimeFormat::YYYYMMDD_HHMMSS))
+    }
+    if param.invoice.required == 1 {
+        param.invoice.insert_or_update(
+            &param.id,
+            conn,
+            order.salesman.name(),
+            &order.customer.name,
         )?;
-        Ok(())
+    }
+    Instalment::insert(conn, &param.id, &param.instalment, false)?;
+    Product::insert(&param.product, &param.id, conn, true)?;
+    if param.ship.shipped == 1 {
+        param
+            .ship
+            .apply_stock(conn, &param.id, Some(&order.ship), &param.product, &user.id)?;
     } else {
-        Err(Response::dissatisfy("仅支持意向订单"))
+        refresh_reservation(conn, &param.id, &param.product)?;
     }
+
+    conn.exec_drop(
+        "update order_data set transaction_date=:td, 
+                    shipped=:sd, shipped_date=:sdd, 
+                    shipped_storehouse=:ssh, 
+                    invoice_required=:ir,
+                    customer=:customer,
+                    purchase_unit=:pu,
+                    address=:address
+                    where id = :id
+                    limit 1",
+        params! {
+            "sd" => param.ship.shipped,
+            "sdd" => &param.ship.date,
+            "td" => time.format(TimeFormat::YYYYMMDD_HHMMSS),
+            "ssh" => &param.ship.storehouse,
+            "ir" => &param.invoice.required,
+            "id" => &param.id,
+            "customer" => &param.customer.id,
+            "address" => &param.customer.address,
+            "pu" => &param.customer.purchase_unit
+        },
+    )?;
+    status::transition(conn, &order, OrderStatus::Transaction, &user.id, "成交")
 }
 
-pub async fn complete_order(header: HeaderMap, Path(id): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+pub async fn complete_order(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let order = query_order_by_id(&mut conn, &id)?;
     log!("{:#?}", order);
-    if order.status != 1 {
-        return Err(Response::dissatisfy("非成交订单不可完成"));
-    }
-    if order.salesman.id != uid {
+    if order.salesman.id != user.id {
         log!("{user} 试图完成 {} 的订单，被系统拒绝", order.salesman.name);
         return Err(Response::permission_denied());
     }
+    order.status.check(OrderStatus::Complete)?;
     let flag = order.instalment.iter().any(|inv| inv.finish == 0);
     log!("{flag}");
     if flag {
         return Err(Response::dissatisfy("存在未完成的回款，无法完成订单"));
     }
-    conn.exec_drop(
-        "update order_data set status = 2 where id = ? limit 1",
-        (&id,),
+    commit_or_rollback!(
+        status::transition,
+        &mut conn,
+        &order,
+        OrderStatus::Complete,
+        &user.id,
+        "完成订单"
     )?;
     log!("{user}已成功将订单{}的状态设为完成", id);
     ORDER_CACHE.clear();
@@ -154,17 +157,14 @@ struct UpdateOrderParam1 {
     ship: Ship,
 }
 
-pub async fn update_order(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+pub async fn update_order(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     log!("{user} 请求更新订单");
     commit_or_rollback!(async __update_order, &mut conn, value, &user)?;
     log!("{user} 成功更新订单");
     ORDER_CACHE.clear();
     ORDER_CACHE_WITH_ID.clear();
+    PRODUCT_CACHE.clear();
     Ok(Response::ok(json!("更新订单成功")))
 }
 
@@ -182,19 +182,26 @@ async fn __update_order(conn: &mut PooledConn, value: Value, user: &User) -> Res
         );
         return Err(Response::permission_denied());
     }
-    if order.status == 0 {
-        let mut param: UpdateOrderParam0 = serde_json::from_value(value)?;
-        update_status0(conn, &mut param)
-    } else if order.status == 1 {
-        let mut param: UpdateOrderParam1 = serde_json::from_value(value)?;
-        update_status1(conn, user, &mut param, &order)
-    } else {
-        log!(
-            "系统拒绝{}修改订单{}的状态，因为该订单处于已完成状态",
-            user,
-            id
-        );
-        Err(Response::dissatisfy("该订单处于已完成状态, 不允许被修改"))
+    match order.status {
+        OrderStatus::Intent => {
+            let mut param: UpdateOrderParam0 = serde_json::from_value(value)?;
+            update_status0(conn, &mut param)
+        }
+        OrderStatus::Transaction => {
+            let mut param: UpdateOrderParam1 = serde_json::from_value(value)?;
+            update_status1(conn, user, &mut param, &order)
+        }
+        other => {
+            log!(
+                "系统拒绝{}修改订单{}，因为该订单处于{}状态",
+                user,
+                id,
+                other
+            );
+            Err(Response::dissatisfy(format!(
+                "该订单处于{other}状态, 不允许被修改"
+            )))
+        }
     }
 }
 
@@ -218,6 +225,7 @@ fn update_status0(conn: &mut PooledConn, param: &mut UpdateOrderParam0) -> Resul
         },
     )?;
     Product::insert(&param.product, &param.id, conn, true)?;
+    refresh_reservation(conn, &param.id, &param.product)?;
 
     Ok(())
 }
@@ -248,6 +256,10 @@ fn update_status1(
         }
         Instalment::insert(conn, &order.id, &param.instalment, true)?;
     }
+    let shipped = Ship::lock(conn, &order.id, order.status)?;
+    param
+        .ship
+        .apply_stock(conn, &order.id, Some(&shipped), &order.product, &user.id)?;
     if param.invoice.required == 1 {
         param
             .invoice
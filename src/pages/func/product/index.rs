@@ -1,28 +1,30 @@
 use crate::{
-    bearer, commit_or_rollback,
-    database::get_db,
+    commit_or_rollback,
+    database::{
+        get_db,
+        query::{Cond, Query},
+    },
     libs::{
         cache::PRODUCT_CACHE,
-        dser::{deser_f32, serialize_f32_to_string},
-        gen_file_link, gen_id, parse_multipart, FilePart, TimeFormat, TIME,
+        gen_file_link, gen_id,
+        money::Money,
+        parse_multipart, FilePart, TimeFormat, TIME,
     },
     log,
     pages::{
-        account::get_user,
         func::{
             __insert_custom_fields, __update_custom_fields, customer::index::CustomCustomerData,
-            get_custom_fields,
+            get_custom_fields, store::stock,
         },
         DROP_DOWN_BOX,
     },
-    parse_jwt_macro,
-    perm::action::StorehouseGroup,
+    perm::auth::{AuthUser, Scope, StorehousePerm},
     response::BodyFile,
-    verify_perms, Response, ResponseResult,
+    Response, ResponseResult,
 };
 use axum::{
     extract::{Multipart, Path},
-    http::{HeaderMap, StatusCode},
+    http::StatusCode,
     routing::{delete, get, post},
     Json, Router,
 };
@@ -108,13 +110,9 @@ struct ProductParams {
     model: String,
     /// 单位
     unit: String,
-    #[serde(deserialize_with = "deser_f32")]
-    #[serde(serialize_with = "serialize_f32_to_string")]
-    purchase_price: f32,
+    purchase_price: Money,
     product_type: String,
-    #[serde(deserialize_with = "deser_f32")]
-    #[serde(serialize_with = "serialize_f32_to_string")]
-    price: f32,
+    price: Money,
     /// 条形码
     barcode: String,
     explanation: String,
@@ -123,19 +121,11 @@ struct ProductParams {
     inventory: WrapperInventory,
 }
 
-async fn add_product(header: HeaderMap, part: Multipart) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&id, &mut conn).await?;
-    if !verify_perms!(
-        &user.role,
-        StorehouseGroup::NAME,
-        StorehouseGroup::ADD_PRODUCT
-    ) {
+async fn add_product(user: AuthUser, part: Multipart) -> ResponseResult {
+    let mut conn = get_db().await?;
+    if let Err(e) = user.can(StorehousePerm::AddProduct, Scope::Any).await {
         log!("系统拒绝 {user} 添加产品的请求，原因是没有添加产品的权限");
-        return Err(Response::permission_denied());
+        return Err(e.into());
     }
 
     let part = parse_multipart(part).await?;
@@ -143,31 +133,23 @@ async fn add_product(header: HeaderMap, part: Multipart) -> ResponseResult {
     let name = data.name.clone();
     log!("{user} 请求添加产品 {} -- 带封面", name);
     let file = op::some!(part.files.first(); ret Err(Response::dissatisfy("缺少封面")));
-    commit_or_rollback!(async __insert, &mut conn, data, Some(file), &user.role)?;
+    commit_or_rollback!(async __insert, &mut conn, data, Some(file), &user)?;
     PRODUCT_CACHE.clear();
     log!("{user} 成功添加产品 {} -- 带封面", name);
     Ok(Response::empty())
 }
 
-async fn add_product_json(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&id, &mut conn).await?;
-    if !verify_perms!(
-        &user.role,
-        StorehouseGroup::NAME,
-        StorehouseGroup::ADD_PRODUCT
-    ) {
+async fn add_product_json(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    if let Err(e) = user.can(StorehousePerm::AddProduct, Scope::Any).await {
         log!("系统拒绝 {user} 添加产品的请求，原因是没有添加产品的权限");
-        return Err(Response::permission_denied());
+        return Err(e.into());
     }
 
     let data: ProductParams = serde_json::from_value(value)?;
     let name = data.name.clone();
     log!("{user} 请求添加产品 {} -- 默认封面", name);
-    commit_or_rollback!(async __insert, &mut conn, data, None, &user.role)?;
+    commit_or_rollback!(async __insert, &mut conn, data, None, &user)?;
     log!("{user} 成功添加产品 {} -- 默认封面", name);
     PRODUCT_CACHE.clear();
     Ok(Response::empty())
@@ -177,24 +159,23 @@ async fn __insert(
     conn: &mut PooledConn,
     mut data: ProductParams,
     part: Option<&FilePart>,
-    role: &str,
+    user: &AuthUser,
 ) -> Result<(), Response> {
     let time = TIME::now()?;
     data.id = gen_id(&time, &data.name);
     let pinyin = rust_pinyin::get_pinyin(&data.name);
-    let n: Option<i32> = conn.query_first(format!(
-        "select num from product_num where name='{}'",
-        pinyin
-    ))?;
+    let n: Option<i32> =
+        conn.exec_first("select num from product_num where name = ?", (&pinyin,))?;
 
     let n = n.unwrap_or(0) + 1;
     if data.num.is_empty() {
         data.num = format!("NO.{}{:0>7}", pinyin, n)
     }
-    conn.query_drop(format!(
-        "INSERT INTO product_num (name, num) VALUES ('{pinyin}', {n})
-    ON DUPLICATE KEY UPDATE num = {n}"
-    ))?;
+    conn.exec_drop(
+        "INSERT INTO product_num (name, num) VALUES (:name, :num)
+    ON DUPLICATE KEY UPDATE num = :num",
+        params! { "name" => &pinyin, "num" => n },
+    )?;
     data.create_time = time.format(TimeFormat::YYYYMMDD_HHMMSS);
     let link = if let Some(part) = part {
         gen_file_link(&time, part.filename())
@@ -226,7 +207,7 @@ async fn __insert(
 
         },
     )?;
-    first_update_store(conn, &data.id, &data.inventory.inner, role).await?;
+    first_update_store(conn, &data.id, &data.inventory.inner, user).await?;
     __insert_custom_fields(conn, &data.custom_fields.inner, 1, &data.id)?;
     if let Some(part) = part {
         std::fs::write(format!("resources/product/cover/{link}"), &part.bytes)?;
@@ -238,90 +219,59 @@ async fn first_update_store(
     conn: &mut PooledConn,
     id: &str,
     store: &[Inventory],
-    role: &str,
+    user: &AuthUser,
 ) -> Result<(), Response> {
-    if !store.is_empty()
-        && !verify_perms!(
-            role,
-            StorehouseGroup::NAME,
-            StorehouseGroup::ADJUSTING_PRODUCT_INVENTORY
-        )
-    {
-        return Err(Response::permission_denied());
+    if !store.is_empty() {
+        user.can(StorehousePerm::AdjustingProductInventory, Scope::Any)
+            .await?;
     }
     unsafe {
         let map = DROP_DOWN_BOX.get("storehouse");
-        for s in store {
-            if map.contains(&s.storehouse.as_str()) {
-                conn.query_drop(format!(
-                    "insert into product_store (product, storehouse, amount) 
-                        values ('{id}', '{}', {})",
-                    s.storehouse, s.amount
-                ))?;
-            }
+        for s in store
+            .iter()
+            .filter(|s| map.contains(&s.storehouse.as_str()))
+        {
+            stock::set_amount(conn, id, &s.storehouse, s.amount, &user.id)?;
         }
-
         Ok(())
     }
 }
 
-async fn update_store(
+/// 库存的修改记为手动调整
+async fn __update_store(
     conn: &mut PooledConn,
     id: &str,
     store: &[Inventory],
-    role: &str,
+    user: &AuthUser,
 ) -> Result<(), Response> {
-    if !verify_perms!(
-        role,
-        StorehouseGroup::NAME,
-        StorehouseGroup::ADJUSTING_PRODUCT_INVENTORY
-    ) {
-        return Err(Response::permission_denied());
-    }
+    user.can(StorehousePerm::AdjustingProductInventory, Scope::Any)
+        .await?;
     for s in store {
-        conn.query_drop(format!(
-            "insert into product_store (product, storehouse, amount) 
-                values ('{}', '{}', {}) 
-                on duplicate key update amount = {}",
-            id, s.storehouse, s.amount, s.amount
-        ))?;
+        stock::set_amount(conn, id, &s.storehouse, s.amount, &user.id)?;
     }
-
     Ok(())
 }
 
 async fn update_product_store(
-    header: HeaderMap,
+    user: AuthUser,
     Path(id): Path<String>,
     Json(value): Json<Value>,
 ) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+    let mut conn = get_db().await?;
     log!("{user} 请求更新产品 {} 的库存", id);
     let inventory: Vec<Inventory> = serde_json::from_value(value)?;
-    update_store(&mut conn, &id, &inventory, &user.role).await?;
+    commit_or_rollback!(async __update_store, &mut conn, &id, &inventory, &user)?;
 
     log!("{user} 成功更新产品 {} 的库存", id);
     PRODUCT_CACHE.clear();
     Ok(Response::empty())
 }
 
-async fn update_product(header: HeaderMap, part: Multipart) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&id, &mut conn).await?;
-    if !verify_perms!(
-        &user.role,
-        StorehouseGroup::NAME,
-        StorehouseGroup::UPDATE_PRODUCT
-    ) {
+async fn update_product(user: AuthUser, part: Multipart) -> ResponseResult {
+    let mut conn = get_db().await?;
+    if let Err(e) = user.can(StorehousePerm::UpdateProduct, Scope::Any).await {
         log!("{user} 因权限不足而被系统拒绝更新产品信息 -- 带封面");
-        return Err(Response::permission_denied());
+        return Err(e.into());
     }
     let part = parse_multipart(part).await?;
     let data: ProductParams = serde_json::from_str(&part.json)?;
@@ -334,20 +284,11 @@ async fn update_product(header: HeaderMap, part: Multipart) -> ResponseResult {
     Ok(Response::empty())
 }
 
-async fn update_product_json(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&id, &mut conn).await?;
-    if !verify_perms!(
-        &user.role,
-        StorehouseGroup::NAME,
-        StorehouseGroup::UPDATE_PRODUCT,
-        None
-    ) {
+async fn update_product_json(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    if let Err(e) = user.can(StorehousePerm::UpdateProduct, Scope::Any).await {
         log!("{user} 因权限不足而被系统拒绝更新产品信息 -- 无封面");
-        return Err(Response::permission_denied());
+        return Err(e.into());
     }
     let data: ProductParams = serde_json::from_value(value)?;
     log!("{user} 请求更新产品 {} 信息 -- 无封面", data.id);
@@ -362,10 +303,8 @@ fn __update(
     data: ProductParams,
     part: Option<&FilePart>,
 ) -> Result<(), Response> {
-    let cover: Option<String> = conn.query_first(format!(
-        "SELECT cover FROM product WHERE id = '{}' LIMIT 1",
-        data.id
-    ))?;
+    let cover: Option<String> =
+        conn.exec_first("SELECT cover FROM product WHERE id = ? LIMIT 1", (&data.id,))?;
     let cover = op::some!(cover; ret Err(Response::not_exist("code: 180909")));
     let time = TIME::now()?;
 
@@ -376,8 +315,7 @@ fn __update(
         cover.clone()
     };
     conn.exec_drop(
-        format!(
-            "UPDATE product 
+        "UPDATE product 
                 SET num=:num, 
                 name=:name, 
                 specification=:specification,
@@ -389,10 +327,9 @@ fn __update(
                 barcode=:barcode, 
                 explanation=:explanation,
                 purchase_price=:purchase_price
-                WHERE id = '{}' LIMIT 1",
-            data.id
-        ),
+                WHERE id = :id LIMIT 1",
         params! {
+            "id" => &data.id,
             "num" => data.num, "name" => data.name,
             "specification" => data.specification, "cover" => &link,
             "model" => data.model, "unit" => data.unit,
@@ -421,8 +358,7 @@ struct QueryParams {
 }
 
 async fn query_product(Json(value): Json<Value>) -> ResponseResult {
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
+    let mut conn = get_db().await?;
     let param_str = value.to_string();
     if let Some(data) = PRODUCT_CACHE.get(&param_str) {
         log!("查询产品信息，缓存命中");
@@ -430,48 +366,54 @@ async fn query_product(Json(value): Json<Value>) -> ResponseResult {
     }
     log!("查询产品信息，缓存未命中, 查询中....");
     let data: QueryParams = serde_json::from_value(value)?;
-    let ty = op::ternary!(data.ty.is_empty() => "IS NOT NULL".into(); format!("= '{}'", data.ty));
-    let stock = match data.stock {
-        1 => "> 0",
-        2 => "= 0",
-        _ => "is not null",
+    let ty = op::ternary!(data.ty.is_empty() => Cond::not_null("pr.product_type"); Cond::eq("pr.product_type", data.ty.as_str()));
+    let stock = |column: &str| match data.stock {
+        1 => Cond::new(format!("{column} > 0"), Vec::new()),
+        2 => Cond::new(format!("{column} = 0"), Vec::new()),
+        _ => Cond::not_null(column),
     };
-
-    let store = if data.storehouse.is_empty() {
-        "is not null".to_owned()
-    } else {
-        format!("= '{}'", &data.storehouse)
+    let store = |column: &str| {
+        if data.storehouse.is_empty() {
+            Cond::not_null(column)
+        } else {
+            Cond::eq(column, data.storehouse.as_str())
+        }
     };
+    let query = Query::new(
+        "select pr.*, 1 as custom_fields, 1 as inventory from product pr 
+            where ",
+    )
+    .cond(&ty);
     let query = if data.stock == 0 && data.storehouse.is_empty() {
-        format!(
-            "select pr.*, 1 as custom_fields, 1 as inventory from product pr 
-            where pr.product_type {ty} order by pr.create_time"
-        )
+        query
     } else if data.storehouse.eq("null") {
-        format!(
-            "select pr.*, 1 as custom_fields, 1 as inventory from product pr 
-            where pr.product_type {ty} and 
-            not exists (select 1 from product_store ps where ps.product = pr.id) order by pr.create_time"
+        query.push(
+            " and not exists (select 1 from product_store ps where ps.product = pr.id)",
         )
     } else {
-        format!(
-            "select pr.*, 1 as custom_fields, 1 as inventory from product pr 
-            where pr.product_type {ty} and 
-            exists (select 1 from product_store ps 
-                where ps.product = pr.id and ps.storehouse {store} and ps.amount {stock}) order by pr.create_time"
-        )
+        query
+            .push(
+                " and exists (select 1 from product_store ps 
+                where ps.product = pr.id and ",
+            )
+            .cond(&store("ps.storehouse").and(stock("ps.amount")))
+            .push(")")
     };
 
-    let tmp: Vec<ProductParams> = conn.query(query)?;
+    let tmp: Vec<ProductParams> = query.push(" order by pr.create_time").fetch(&mut conn)?;
+    let filter = stock("amount").and(store("storehouse"));
     let mut products = Vec::new();
     for mut product in tmp {
-        product.inventory.inner = conn.query(format!(
+        product.inventory.inner = Query::new(
             "select storehouse, amount 
                 from product_store 
-                where product = '{}' and amount {stock} 
-                and storehouse {store} order by storehouse",
-            product.id
-        ))?;
+                where product = ",
+        )
+        .bind(product.id.as_str())
+        .push(" and ")
+        .cond(&filter)
+        .push(" order by storehouse")
+        .fetch(&mut conn)?;
         products.push(product);
     }
 
@@ -486,18 +428,18 @@ async fn query_by(Path(id): Path<String>) -> ResponseResult {
         log!("产品--缓存命中");
         return Ok(Response::ok(data));
     }
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let mut data: Option<ProductParams> = conn.query_first(format!(
-        "SELECT *, 1 as custom_fields, 1 as inventory FROM product WHERE id = '{id}' ORDER BY create_time"
-    ))?;
+    let mut conn = get_db().await?;
+    let mut data: Option<ProductParams> = conn.exec_first(
+        "SELECT *, 1 as custom_fields, 1 as inventory FROM product WHERE id = ? ORDER BY create_time",
+        (&id,),
+    )?;
     if let Some(d) = &mut data {
-        d.inventory.inner = conn.query(format!(
+        d.inventory.inner = conn.exec(
             "select storehouse, amount 
                 from product_store 
-                where product = '{}' order by storehouse",
-            d.id
-        ))?;
+                where product = ? order by storehouse",
+            (&d.id,),
+        )?;
         d.custom_fields = get_custom_fields(&mut conn, &d.id, 1)?;
     }
     let value = json!(data);
@@ -505,53 +447,41 @@ async fn query_by(Path(id): Path<String>) -> ResponseResult {
     Ok(Response::ok(value))
 }
 async fn delete_storehouse(
-    header: HeaderMap,
+    user: AuthUser,
     Path(id): Path<String>,
     Json(value): Json<Vec<String>>,
 ) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let user = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&user, &mut conn).await?;
-    if !verify_perms!(
-        &user.role,
-        StorehouseGroup::NAME,
-        StorehouseGroup::ADJUSTING_PRODUCT_INVENTORY
-    ) {
-        return Err(Response::permission_denied());
-    }
-    conn.exec_batch(
-        "delete from product_store where product = ? and storehouse = ?",
-        value.iter().map(|v| (&id, v)),
-    )?;
+    let mut conn = get_db().await?;
+    user.can(StorehousePerm::AdjustingProductInventory, Scope::Any).await?;
+    commit_or_rollback!(__delete_storehouse, &mut conn, &id, &value, &user.id)?;
 
     PRODUCT_CACHE.clear();
     Ok(Response::empty())
 }
-async fn delete_product(header: HeaderMap, Path(id): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let user = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&user, &mut conn).await?;
-    if !verify_perms!(
-        &user.role,
-        StorehouseGroup::NAME,
-        StorehouseGroup::DELETE_PRODUCT
-    ) {
-        return Err(Response::permission_denied());
+fn __delete_storehouse(
+    conn: &mut PooledConn,
+    id: &str,
+    storehouse: &[String],
+    operator: &str,
+) -> Result<(), Response> {
+    for s in storehouse {
+        stock::remove_store(conn, id, s, operator)?;
     }
+    Ok(())
+}
+async fn delete_product(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    user.can(StorehousePerm::DeleteProduct, Scope::Any).await?;
     commit_or_rollback!(__delete_product, &mut conn, &id)?;
     PRODUCT_CACHE.clear();
     Ok(Response::empty())
 }
 fn __delete_product(conn: &mut PooledConn, id: &str) -> Result<(), Response> {
     let cover: Option<String> =
-        conn.query_first(format!("select cover from product where id = '{id}'"))?;
-    conn.query_drop(format!("DELETE FROM custom_field_data WHERE id = '{id}'"))?;
-    conn.query_drop(format!("DELETE FROM product WHERE id = '{id}' LIMIT 1"))?;
-    conn.query_drop(format!("DELETE FROM product_store WHERE product = '{id}'"))?;
+        conn.exec_first("select cover from product where id = ?", (id,))?;
+    conn.exec_drop("DELETE FROM custom_field_data WHERE id = ?", (id,))?;
+    conn.exec_drop("DELETE FROM product WHERE id = ? LIMIT 1", (id,))?;
+    conn.exec_drop("DELETE FROM product_store WHERE product = ?", (id,))?;
 
     if let Some(cover) = cover {
         if !cover.eq(DEFAULT.0) {
@@ -0,0 +1,349 @@
+//! 采购单
+//!
+//! 采购单向某个供应商(`supper`)采购产品，产品单价取创建或修改时产品的采购价。
+//! 草稿可以修改和删除，下单后不能再修改；到货时登记到货单并计入库房，
+//! 根据到货数量变为部分到货或全部到货。应付款为已到货金额减去已付款
+mod payable;
+mod receipt;
+
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use mysql::{params, prelude::Queryable, PooledConn};
+use mysql_common::prelude::FromRow;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    commit_or_rollback,
+    database::{
+        get_db,
+        query::{Cond, Query},
+    },
+    libs::{
+        dser::{deserialize_inventory, deserialize_storehouse},
+        gen_id,
+        money::Money,
+        TimeFormat, TIME,
+    },
+    log,
+    perm::auth::{AuthUser, PurchasePerm, Scope},
+    Response, ResponseResult,
+};
+
+pub fn purchase_router() -> Router {
+    Router::new()
+        .route("/purchase/create", post(create_purchase))
+        .route("/purchase/update", post(update_purchase))
+        .route("/purchase/query", post(query_purchase))
+        .route("/purchase/order/:id", post(place_purchase))
+        .route("/purchase/receive/:id", post(receipt::receive_goods))
+        .route("/purchase/payment/:id", post(payable::add_payment))
+        .route("/purchase/payable", get(payable::query_payables))
+        .route(
+            "/purchase/payable/:supper",
+            get(payable::query_supper_payable),
+        )
+        .route("/purchase/:id", get(query_by).delete(delete_purchase))
+}
+
+/// 草稿
+const DRAFT: i32 = 0;
+/// 已下单
+const ORDERED: i32 = 1;
+/// 部分到货
+const PARTIALLY_RECEIVED: i32 = 2;
+/// 全部到货
+const RECEIVED: i32 = 3;
+
+/// 到货到`storehouse`且还没有全部到货的采购单数量
+pub fn count_open_purchases(conn: &mut PooledConn, storehouse: &str) -> mysql::Result<usize> {
+    let count: Option<usize> = conn.exec_first(
+        "SELECT COUNT(*) FROM purchase_order WHERE storehouse = ? AND status IN (?, ?)",
+        (storehouse, ORDERED, PARTIALLY_RECEIVED),
+    )?;
+    Ok(count.unwrap_or(0))
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct Purchase {
+    id: String,
+    supper: String,
+    supper_company: Option<String>,
+    storehouse: String,
+    status: i32,
+    creator: String,
+    create_time: String,
+    order_time: Option<String>,
+    comment: Option<String>,
+}
+
+static QUERY_PURCHASE: &str = "SELECT po.id, po.supper, s.company AS supper_company, po.storehouse,
+        po.status, po.creator, po.create_time, po.order_time, po.comment
+    FROM purchase_order po LEFT JOIN supper s ON s.id = po.supper";
+
+#[derive(Debug, Serialize, FromRow)]
+struct PurchaseProduct {
+    product: String,
+    name: Option<String>,
+    price: Money,
+    amount: i32,
+    received: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct LineParams {
+    product: String,
+    #[serde(deserialize_with = "deserialize_inventory")]
+    amount: i32,
+}
+
+#[derive(Deserialize)]
+struct PurchaseParams {
+    #[serde(default)]
+    id: String,
+    supper: String,
+    #[serde(deserialize_with = "deserialize_storehouse")]
+    storehouse: String,
+    product: Vec<LineParams>,
+    #[serde(default)]
+    comment: String,
+}
+
+impl PurchaseParams {
+    fn verify(&self, conn: &mut PooledConn) -> Result<(), Response> {
+        let supper: Option<String> = conn.exec_first(
+            "SELECT id FROM supper WHERE id = ? LIMIT 1",
+            (&self.supper,),
+        )?;
+        if supper.is_none() {
+            return Err(Response::not_exist("供应商不存在"));
+        }
+        if self.product.is_empty() {
+            return Err(Response::invalid_value("采购单至少需要一个产品"));
+        }
+        if self.product.iter().any(|p| p.amount <= 0) {
+            return Err(Response::invalid_value("采购数量必须大于0"));
+        }
+        let mut ids: Vec<&str> = self.product.iter().map(|p| p.product.as_str()).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        if ids.len() != self.product.len() {
+            return Err(Response::invalid_value("同一个产品只能出现一次"));
+        }
+        Ok(())
+    }
+}
+
+/// 按产品当前的采购价写入采购单的产品
+fn insert_products(
+    conn: &mut PooledConn,
+    id: &str,
+    products: &[LineParams],
+) -> Result<(), Response> {
+    conn.exec_drop(
+        "DELETE FROM purchase_order_product WHERE order_id = ?",
+        (id,),
+    )?;
+    for line in products {
+        let price: Option<Money> = conn.exec_first(
+            "SELECT purchase_price FROM product WHERE id = ? LIMIT 1",
+            (&line.product,),
+        )?;
+        let price =
+            op::some!(price; ret Err(Response::not_exist(format!("产品`{}`不存在", line.product))));
+        conn.exec_drop(
+            "INSERT INTO purchase_order_product (order_id, product, price, amount, received)
+            VALUES (?, ?, ?, ?, 0)",
+            (id, &line.product, price, line.amount),
+        )?;
+    }
+    Ok(())
+}
+
+/// 查询并锁定采购单
+fn query_purchase_by_id(conn: &mut PooledConn, id: &str) -> Result<Purchase, Response> {
+    let purchase: Option<Purchase> = conn.exec_first(
+        format!("{QUERY_PURCHASE} WHERE po.id = ? LIMIT 1 FOR UPDATE"),
+        (id,),
+    )?;
+    Ok(op::some!(purchase; ret Err(Response::not_exist("采购单不存在"))))
+}
+
+fn query_products(conn: &mut PooledConn, id: &str) -> mysql::Result<Vec<PurchaseProduct>> {
+    conn.exec(
+        "SELECT pp.product, p.name, pp.price, pp.amount, pp.received
+        FROM purchase_order_product pp LEFT JOIN product p ON p.id = pp.product
+        WHERE pp.order_id = ? ORDER BY p.name",
+        (id,),
+    )
+}
+
+async fn create_purchase(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    user.can(PurchasePerm::ManageOrder, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    let mut params: PurchaseParams = serde_json::from_value(value)?;
+    log!("{user} 请求创建供应商`{}`的采购单", params.supper);
+    commit_or_rollback!(__create_purchase, &mut conn, &mut params, &user)?;
+    log!("{user} 成功创建采购单{}", params.id);
+    Ok(Response::ok(json!(params.id)))
+}
+
+fn __create_purchase(
+    conn: &mut PooledConn,
+    params: &mut PurchaseParams,
+    user: &AuthUser,
+) -> Result<(), Response> {
+    params.verify(conn)?;
+    let time = TIME::now()?;
+    params.id = gen_id(&time, "purchase");
+    conn.exec_drop(
+        "INSERT INTO purchase_order (id, supper, storehouse, status, creator, create_time, comment)
+        VALUES (:id, :supper, :storehouse, :status, :creator, :time, :comment)",
+        params! {
+            "id" => &params.id,
+            "supper" => &params.supper,
+            "storehouse" => &params.storehouse,
+            "status" => DRAFT,
+            "creator" => &user.id,
+            "time" => time.format(TimeFormat::YYYYMMDD_HHMMSS),
+            "comment" => &params.comment
+        },
+    )?;
+    insert_products(conn, &params.id, &params.product)
+}
+
+/// 只能修改草稿，产品单价按当前的采购价重新计算
+async fn update_purchase(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    user.can(PurchasePerm::ManageOrder, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    let params: PurchaseParams = serde_json::from_value(value)?;
+    log!("{user} 请求修改采购单{}", params.id);
+    commit_or_rollback!(__update_purchase, &mut conn, &params)?;
+    log!("{user} 成功修改采购单{}", params.id);
+    Ok(Response::empty())
+}
+
+fn __update_purchase(conn: &mut PooledConn, params: &PurchaseParams) -> Result<(), Response> {
+    let purchase = query_purchase_by_id(conn, &params.id)?;
+    if purchase.status != DRAFT {
+        return Err(Response::dissatisfy("只能修改草稿状态的采购单"));
+    }
+    params.verify(conn)?;
+    conn.exec_drop(
+        "UPDATE purchase_order SET supper = ?, storehouse = ?, comment = ? WHERE id = ? LIMIT 1",
+        (
+            &params.supper,
+            &params.storehouse,
+            &params.comment,
+            &params.id,
+        ),
+    )?;
+    insert_products(conn, &params.id, &params.product)
+}
+
+/// 只能删除草稿
+async fn delete_purchase(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    user.can(PurchasePerm::ManageOrder, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    log!("{user} 请求删除采购单{id}");
+    commit_or_rollback!(__delete_purchase, &mut conn, &id)?;
+    log!("{user} 成功删除采购单{id}");
+    Ok(Response::empty())
+}
+
+fn __delete_purchase(conn: &mut PooledConn, id: &str) -> Result<(), Response> {
+    let purchase = query_purchase_by_id(conn, id)?;
+    if purchase.status != DRAFT {
+        return Err(Response::dissatisfy("只能删除草稿状态的采购单"));
+    }
+    conn.exec_drop(
+        "DELETE FROM purchase_order_product WHERE order_id = ?",
+        (id,),
+    )?;
+    conn.exec_drop("DELETE FROM purchase_order WHERE id = ? LIMIT 1", (id,))?;
+    Ok(())
+}
+
+/// 下单，之后不能再修改
+async fn place_purchase(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    user.can(PurchasePerm::ManageOrder, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    log!("{user} 请求下达采购单{id}");
+    commit_or_rollback!(__place_purchase, &mut conn, &id)?;
+    log!("{user} 成功下达采购单{id}");
+    Ok(Response::empty())
+}
+
+fn __place_purchase(conn: &mut PooledConn, id: &str) -> Result<(), Response> {
+    let purchase = query_purchase_by_id(conn, id)?;
+    if purchase.status != DRAFT {
+        return Err(Response::dissatisfy("采购单已经下单"));
+    }
+    conn.exec_drop(
+        "UPDATE purchase_order SET status = ?, order_time = ? WHERE id = ? LIMIT 1",
+        (
+            ORDERED,
+            TIME::now()?.format(TimeFormat::YYYYMMDD_HHMMSS),
+            id,
+        ),
+    )?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct QueryParams {
+    /// 小于0时查询所有状态
+    #[serde(default = "all_status")]
+    status: i32,
+    #[serde(default)]
+    supper: String,
+    #[serde(default)]
+    limit: u32,
+}
+
+fn all_status() -> i32 {
+    -1
+}
+
+async fn query_purchase(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    user.can(PurchasePerm::Query, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    let params: QueryParams = serde_json::from_value(value)?;
+    let status =
+        op::ternary!(params.status < 0 => Cond::any(); Cond::eq("po.status", params.status));
+    let supper = op::ternary!(params.supper.is_empty() => Cond::any(); Cond::eq("po.supper", params.supper.as_str()));
+    let limit = op::ternary!(params.limit == 0 => 50; params.limit);
+    let list: Vec<Purchase> = Query::new(format!("{QUERY_PURCHASE} WHERE "))
+        .cond(&status.and(supper))
+        .push(" ORDER BY po.create_time DESC LIMIT ")
+        .bind(limit)
+        .fetch(&mut conn)?;
+    log!("{user} 查询到{}个采购单", list.len());
+    Ok(Response::ok(json!(list)))
+}
+
+/// 采购单详情，包括产品、到货和付款记录
+async fn query_by(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    user.can(PurchasePerm::Query, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    let purchase: Option<Purchase> =
+        conn.exec_first(format!("{QUERY_PURCHASE} WHERE po.id = ? LIMIT 1"), (&id,))?;
+    let purchase = op::some!(purchase; ret Err(Response::not_exist("采购单不存在")));
+    let products = query_products(&mut conn, &id)?;
+    let total: Money = products
+        .iter()
+        .map(|p| p.price.times(p.amount as usize))
+        .sum();
+    let receipts = receipt::query_receipts(&mut conn, &id)?;
+    let payments = payable::query_payments(&mut conn, &id)?;
+    Ok(Response::ok(json!({
+        "purchase": purchase,
+        "product": products,
+        "total": total,
+        "receipt": receipts,
+        "payment": payments
+    })))
+}
@@ -0,0 +1,199 @@
+use axum::{extract::Path, Json};
+use mysql::{params, prelude::Queryable, PooledConn};
+use mysql_common::prelude::FromRow;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{query_purchase_by_id, DRAFT};
+use crate::{
+    commit_or_rollback,
+    database::get_db,
+    libs::{gen_id, money::Money, TimeFormat, TIME},
+    log,
+    perm::auth::{AuthUser, PurchasePerm, Scope},
+    Response, ResponseResult,
+};
+
+/// 采购单已到货的金额
+const RECEIVED_AMOUNT: &str = "SELECT COALESCE(SUM(pp.price * pp.received), 0)
+    FROM purchase_order_product pp WHERE pp.order_id = po.id";
+/// 采购单已付款的金额
+const PAID_AMOUNT: &str =
+    "SELECT COALESCE(SUM(pay.amount), 0) FROM purchase_payment pay WHERE pay.order_id = po.id";
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct PaymentData {
+    id: String,
+    amount: Money,
+    operator: String,
+    create_time: String,
+    comment: Option<String>,
+}
+
+pub fn query_payments(conn: &mut PooledConn, order_id: &str) -> mysql::Result<Vec<PaymentData>> {
+    conn.exec(
+        "SELECT id, amount, operator, create_time, comment FROM purchase_payment
+        WHERE order_id = ? ORDER BY create_time",
+        (order_id,),
+    )
+}
+
+#[derive(Deserialize)]
+struct PaymentParams {
+    amount: Money,
+    #[serde(default)]
+    comment: String,
+}
+
+/// 登记向供应商的付款，累计付款不能超过采购单总额
+pub async fn add_payment(
+    user: AuthUser,
+    Path(id): Path<String>,
+    Json(value): Json<Value>,
+) -> ResponseResult {
+    user.can(PurchasePerm::Payment, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    let params: PaymentParams = serde_json::from_value(value)?;
+    log!("{user} 请求登记采购单{id}的付款{}", params.amount);
+    let payment = commit_or_rollback!(__add_payment, &mut conn, &id, &params, &user.id)?;
+    log!("{user} 成功登记采购单{id}的付款{}", params.amount);
+    Ok(Response::ok(json!(payment)))
+}
+
+/// 检查付款金额，累计付款不能超过采购单总额
+fn check_payment(total: Money, paid: Money, amount: Money) -> Result<(), Response> {
+    if amount == Money::ZERO {
+        return Err(Response::invalid_value("付款金额必须大于0"));
+    }
+    if paid + amount > total {
+        return Err(Response::invalid_value(format!(
+            "累计付款不能超过采购单总额{total}，已付款{paid}"
+        )));
+    }
+    Ok(())
+}
+
+fn __add_payment(
+    conn: &mut PooledConn,
+    id: &str,
+    params: &PaymentParams,
+    operator: &str,
+) -> Result<String, Response> {
+    let purchase = query_purchase_by_id(conn, id)?;
+    if purchase.status == DRAFT {
+        return Err(Response::dissatisfy("草稿状态的采购单不能付款"));
+    }
+    let total: Option<Money> = conn.exec_first(
+        "SELECT COALESCE(SUM(price * amount), 0) FROM purchase_order_product WHERE order_id = ?",
+        (id,),
+    )?;
+    let paid: Option<Money> = conn.exec_first(
+        "SELECT COALESCE(SUM(amount), 0) FROM purchase_payment WHERE order_id = ?",
+        (id,),
+    )?;
+    check_payment(total.unwrap_or_default(), paid.unwrap_or_default(), params.amount)?;
+    let time = TIME::now()?;
+    let payment = gen_id(&time, "payment");
+    conn.exec_drop(
+        "INSERT INTO purchase_payment (id, order_id, amount, operator, create_time, comment)
+        VALUES (:id, :order_id, :amount, :operator, :time, :comment)",
+        params! {
+            "id" => &payment,
+            "order_id" => id,
+            "amount" => params.amount,
+            "operator" => operator,
+            "time" => time.format(TimeFormat::YYYYMMDD_HHMMSS),
+            "comment" => &params.comment
+        },
+    )?;
+    Ok(payment)
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct SupperPayable {
+    supper: String,
+    company: Option<String>,
+    /// 已下单的总额
+    ordered: Money,
+    /// 已到货的金额
+    received: Money,
+    paid: Money,
+    /// 应付款，已到货的金额减去已付款
+    payable: Money,
+}
+
+/// 每个供应商的应付款，不包括草稿
+pub async fn query_payables(user: AuthUser) -> ResponseResult {
+    user.can(PurchasePerm::Query, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    let list: Vec<SupperPayable> = conn.exec(
+        format!(
+            "SELECT t.supper, s.company, t.ordered, t.received, t.paid, t.received - t.paid AS payable
+            FROM (
+                SELECT po.supper,
+                    SUM((SELECT COALESCE(SUM(pp.price * pp.amount), 0)
+                        FROM purchase_order_product pp WHERE pp.order_id = po.id)) AS ordered,
+                    SUM(({RECEIVED_AMOUNT})) AS received,
+                    SUM(({PAID_AMOUNT})) AS paid
+                FROM purchase_order po
+                WHERE po.status <> ?
+                GROUP BY po.supper
+            ) t LEFT JOIN supper s ON s.id = t.supper
+            ORDER BY payable DESC"
+        ),
+        (DRAFT,),
+    )?;
+    log!("{user} 查询了{}个供应商的应付款", list.len());
+    Ok(Response::ok(json!(list)))
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct OrderPayable {
+    id: String,
+    status: i32,
+    order_time: Option<String>,
+    received: Money,
+    paid: Money,
+    payable: Money,
+}
+
+/// 某个供应商每个采购单的应付款
+pub async fn query_supper_payable(user: AuthUser, Path(supper): Path<String>) -> ResponseResult {
+    user.can(PurchasePerm::Query, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    let list: Vec<OrderPayable> = conn.exec(
+        format!(
+            "SELECT t.id, t.status, t.order_time, t.received, t.paid, t.received - t.paid AS payable
+            FROM (
+                SELECT po.id, po.status, po.order_time,
+                    ({RECEIVED_AMOUNT}) AS received,
+                    ({PAID_AMOUNT}) AS paid
+                FROM purchase_order po
+                WHERE po.supper = ? AND po.status <> ?
+            ) t
+            ORDER BY t.order_time DESC"
+        ),
+        (&supper, DRAFT),
+    )?;
+    let payable: Money = list.iter().map(|o| o.payable).sum();
+    Ok(Response::ok(json!({
+        "supper": supper,
+        "payable": payable,
+        "order": list
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payment_cap() {
+        let total = Money::from_fen(10000);
+        assert!(check_payment(total, Money::ZERO, Money::from_fen(10000)).is_ok());
+        assert!(check_payment(total, Money::from_fen(6000), Money::from_fen(4000)).is_ok());
+        assert!(check_payment(total, Money::from_fen(6000), Money::from_fen(4001)).is_err());
+        assert!(check_payment(total, Money::ZERO, Money::ZERO).is_err());
+        assert!(check_payment(Money::ZERO, Money::ZERO, Money::from_fen(1)).is_err());
+    }
+}
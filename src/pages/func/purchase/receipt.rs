@@ -0,0 +1,195 @@
+use axum::{extract::Path, Json};
+use mysql::{params, prelude::Queryable, PooledConn};
+use mysql_common::prelude::FromRow;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{
+    query_products, query_purchase_by_id, LineParams, PurchaseProduct, ORDERED,
+    PARTIALLY_RECEIVED, RECEIVED,
+};
+use crate::{
+    commit_or_rollback,
+    database::get_db,
+    libs::{cache::PRODUCT_CACHE, dser::op_deserialize_storehouse, gen_id, TimeFormat, TIME},
+    log,
+    pages::func::store::stock::{self, Movement, MovementKind},
+    perm::auth::{AuthUser, PurchasePerm, Scope},
+    Response, ResponseResult,
+};
+
+#[derive(Deserialize)]
+struct ReceiveParams {
+    /// 为空时计入采购单的默认库房
+    #[serde(default, deserialize_with = "op_deserialize_storehouse")]
+    storehouse: Option<String>,
+    product: Vec<LineParams>,
+    #[serde(default)]
+    comment: String,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct ReceiptData {
+    id: String,
+    product: String,
+    name: Option<String>,
+    amount: i32,
+    storehouse: String,
+    operator: String,
+    create_time: String,
+    comment: Option<String>,
+}
+
+pub fn query_receipts(conn: &mut PooledConn, order_id: &str) -> mysql::Result<Vec<ReceiptData>> {
+    conn.exec(
+        "SELECT r.id, rp.product, p.name, rp.amount, r.storehouse, r.operator, r.create_time, r.comment
+        FROM purchase_receipt r
+        JOIN purchase_receipt_product rp ON rp.receipt_id = r.id
+        LEFT JOIN product p ON p.id = rp.product
+        WHERE r.order_id = ?
+        ORDER BY r.create_time, p.name",
+        (order_id,),
+    )
+}
+
+/// 把到货的产品计入`products`的已到货数量，返回到货后采购单的状态。
+/// 每个产品只能出现一次，到货数量不能超过未到货的数量，`lines`中数量为0的行已被去掉
+fn apply_receipt(products: &mut [PurchaseProduct], lines: &[&LineParams]) -> Result<i32, Response> {
+    if lines.is_empty() {
+        return Err(Response::invalid_value("到货单至少需要一个产品"));
+    }
+    let mut ids: Vec<&str> = lines.iter().map(|p| p.product.as_str()).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    if ids.len() != lines.len() {
+        return Err(Response::invalid_value("同一个产品只能出现一次"));
+    }
+    for line in lines {
+        let product = products.iter_mut().find(|p| p.product == line.product);
+        let product = op::some!(product; ret Err(Response::not_exist(format!("采购单中没有产品`{}`", line.product))));
+        let remain = product.amount - product.received;
+        if line.amount < 0 || line.amount > remain {
+            return Err(Response::invalid_value(format!(
+                "产品`{}`的到货数量必须在1到{remain}之间",
+                product.name.as_deref().unwrap_or(&product.product)
+            )));
+        }
+        product.received += line.amount;
+    }
+    let finished = products.iter().all(|p| p.received >= p.amount);
+    Ok(op::ternary!(finished => RECEIVED; PARTIALLY_RECEIVED))
+}
+
+/// 登记到货，产品计入库房并记入库存流水，到货数量不能超过未到货的数量
+pub async fn receive_goods(
+    user: AuthUser,
+    Path(id): Path<String>,
+    Json(value): Json<Value>,
+) -> ResponseResult {
+    user.can(PurchasePerm::ReceiveGoods, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    let params: ReceiveParams = serde_json::from_value(value)?;
+    log!("{user} 请求登记采购单{id}的到货");
+    let receipt = commit_or_rollback!(__receive_goods, &mut conn, &id, &params, &user.id)?;
+    PRODUCT_CACHE.clear();
+    log!("{user} 成功登记采购单{id}的到货，到货单为{receipt}");
+    Ok(Response::ok(json!(receipt)))
+}
+
+fn __receive_goods(
+    conn: &mut PooledConn,
+    id: &str,
+    params: &ReceiveParams,
+    operator: &str,
+) -> Result<String, Response> {
+    let purchase = query_purchase_by_id(conn, id)?;
+    if !matches!(purchase.status, ORDERED | PARTIALLY_RECEIVED) {
+        return Err(Response::dissatisfy(
+            "只有已下单且未全部到货的采购单可以登记到货",
+        ));
+    }
+    let lines: Vec<&LineParams> = params.product.iter().filter(|p| p.amount != 0).collect();
+    let mut products = query_products(conn, id)?;
+    let status = apply_receipt(&mut products, &lines)?;
+    let storehouse = params.storehouse.as_deref().unwrap_or(&purchase.storehouse);
+    let time = TIME::now()?;
+    let receipt = gen_id(&time, "receipt");
+    conn.exec_drop(
+        "INSERT INTO purchase_receipt (id, order_id, storehouse, operator, create_time, comment)
+        VALUES (:id, :order_id, :storehouse, :operator, :time, :comment)",
+        params! {
+            "id" => &receipt,
+            "order_id" => id,
+            "storehouse" => storehouse,
+            "operator" => operator,
+            "time" => time.format(TimeFormat::YYYYMMDD_HHMMSS),
+            "comment" => &params.comment
+        },
+    )?;
+    let movement = Movement::new(MovementKind::Receipt, operator, Some(&receipt));
+    for line in &lines {
+        conn.exec_drop(
+            "INSERT INTO purchase_receipt_product (receipt_id, product, amount) VALUES (?, ?, ?)",
+            (&receipt, &line.product, line.amount),
+        )?;
+        conn.exec_drop(
+            "UPDATE purchase_order_product SET received = received + ?
+            WHERE order_id = ? AND product = ? LIMIT 1",
+            (line.amount, id, &line.product),
+        )?;
+        stock::move_stock(conn, &line.product, storehouse, line.amount, &movement)?;
+    }
+    conn.exec_drop(
+        "UPDATE purchase_order SET status = ? WHERE id = ? LIMIT 1",
+        (status, id),
+    )?;
+    Ok(receipt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::money::Money;
+
+    fn products() -> Vec<PurchaseProduct> {
+        [("p1", 10, 4), ("p2", 5, 0)]
+            .into_iter()
+            .map(|(product, amount, received)| PurchaseProduct {
+                product: product.to_owned(),
+                name: None,
+                price: Money::from_fen(100),
+                amount,
+                received,
+            })
+            .collect()
+    }
+
+    fn line(product: &str, amount: i32) -> LineParams {
+        LineParams {
+            product: product.to_owned(),
+            amount,
+        }
+    }
+
+    #[test]
+    fn partial_and_full_receipt() {
+        let mut list = products();
+        let (a, b) = (line("p1", 6), line("p2", 2));
+        assert_eq!(apply_receipt(&mut list, &[&a, &b]).unwrap(), PARTIALLY_RECEIVED);
+        assert_eq!((list[0].received, list[1].received), (10, 2));
+        // 只能再到货未到货的数量
+        assert!(apply_receipt(&mut list, &[&line("p2", 4)]).is_err());
+        assert!(apply_receipt(&mut list, &[&line("p1", 1)]).is_err());
+        assert_eq!(apply_receipt(&mut list, &[&line("p2", 3)]).unwrap(), RECEIVED);
+    }
+
+    #[test]
+    fn invalid_receipt() {
+        let mut list = products();
+        assert!(apply_receipt(&mut list, &[]).is_err());
+        assert!(apply_receipt(&mut list, &[&line("p3", 1)]).is_err());
+        assert!(apply_receipt(&mut list, &[&line("p1", -1)]).is_err());
+        let (a, b) = (line("p1", 1), line("p1", 2));
+        assert!(apply_receipt(&mut list, &[&a, &b]).is_err());
+    }
+}
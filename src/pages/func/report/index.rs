@@ -1,19 +1,20 @@
 use crate::{
-    bearer, commit_or_rollback,
-    database::get_db,
+    commit_or_rollback,
+    database::{
+        get_db,
+        query::{Cond, Query},
+    },
     libs::{
         dser::{deser_empty_to_none, deserialize_time_scope},
         gen_id, TimeFormat, TIME,
     },
     log,
-    pages::account::{get_user, User},
-    parse_jwt_macro,
-    perm::roles::role_to_name,
+    pages::account::User,
+    perm::{auth::AuthUser, roles::role_to_name},
     Response, ResponseResult,
 };
 use axum::{
     extract::Path,
-    http::HeaderMap,
     routing::{delete, post},
     Json, Router,
 };
@@ -40,13 +41,9 @@ struct InsertReportParams {
     contents: String,
 }
 
-async fn add_report(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
+async fn add_report(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let data: InsertReportParams = serde_json::from_value(value)?;
-    let user = get_user(&uid, &mut conn).await?;
     let role = role_to_name(&user.role);
     log!("{}-{} 发起添加报告请求", role, user.name);
     commit_or_rollback!(__insert_report, &mut conn, (&data, &user))?;
@@ -80,25 +77,20 @@ fn __insert_report(
                 "send_time" => send_time
         },
     )?;
-    for cc in &params.cc {
-        conn.query_drop(format!(
-            "INSERT IGNORE INTO report_cc (cc, report) VALUES ('{}', '{}')",
-            cc, id
-        ))?;
-    }
+    conn.exec_batch(
+        "INSERT IGNORE INTO report_cc (cc, report) VALUES (?, ?)",
+        params.cc.iter().map(|cc| (cc, &id)),
+    )?;
     Ok(())
 }
 
-async fn delete_report(header: HeaderMap, Path(id): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header);
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+async fn delete_report(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
     log!("{}-{}请求删除报告 {}", user.department, user.name, id);
-    let key: Option<i32> = conn.query_first(format!(
-        "select 1 from report where id = '{id}' and applicant='{uid}'"
-    ))?;
+    let key: Option<i32> = conn.exec_first(
+        "select 1 from report where id = ? and applicant = ?",
+        (&id, &user.id),
+    )?;
     if key.is_none() {
         log!(
             "{}-{}删除报告 {} 失败，该报告不存在或申请人不是{}",
@@ -114,8 +106,8 @@ async fn delete_report(header: HeaderMap, Path(id): Path<String>) -> ResponseRes
     Ok(Response::empty())
 }
 fn __delete_report(conn: &mut PooledConn, id: &str) -> Result<(), Response> {
-    conn.query_drop(format!("delete from report where id = '{id}' LIMIT 1"))?;
-    conn.query_drop(format!("delete from report_cc where report = '{id}'"))?;
+    conn.exec_drop("delete from report where id = ? LIMIT 1", (id,))?;
+    conn.exec_drop("delete from report_cc where report = ?", (id,))?;
     Ok(())
 }
 
@@ -126,26 +118,20 @@ struct ReadParams {
     opinion: String,
 }
 
-async fn read_report(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+async fn read_report(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let data: ReadParams = serde_json::from_value(value)?;
     log!("{}-{} 请求批阅报告 {}", user.department, user.name, data.id);
-    let report: Report = conn
-        .query_first(format!(
+    let report: Report = op::some!(conn.exec_first(
             "select *, 1 as ac_name, 1 as applicant_name, 1 as reviewer_name 
         from report 
-        where id ='{}'",
-            data.id
-        ))?
-        .unwrap();
+        where id = ?",
+            (&data.id,),
+        )?; ret Err(Response::not_exist("报告不存在")));
     if report.send_time.is_none() || report.processing_time.is_some() {
         return Err(Response::dissatisfy("未发送或已审批"));
     }
-    if report.reviewer != uid {
+    if report.reviewer != user.id {
         log!(
             "{}-{} 批阅报告 {} 失败，因为 {} 不是该报告的批阅人",
             user.department,
@@ -157,16 +143,14 @@ async fn read_report(header: HeaderMap, Json(value): Json<Value>) -> ResponseRes
     }
     let status = op::ternary!(data.ok => 0, 1);
     let process_time = TIME::now()?.format(TimeFormat::YYYYMMDD_HHMMSS);
-    let update = format!(
-        "update report set status={status}, 
-        processing_time='{process_time}', opinion='{}' 
-        WHERE id = '{}' AND reviewer='{uid}' 
+    conn.exec_drop(
+        "update report set status = ?, 
+        processing_time = ?, opinion = ? 
+        WHERE id = ? AND reviewer = ? 
         AND send_time IS NOT NULL 
         and processing_time is NULL LIMIT 1",
-        data.opinion, data.id
-    );
-    // println!("{update}");
-    conn.query_drop(update)?;
+        (status, &process_time, &data.opinion, &data.id, &user.id),
+    )?;
     log!("{}-{} 成功批阅报告 {}, 报告状态 {}", user.department, user.name, data.id, status);
     Ok(Response::empty())
 }
@@ -179,18 +163,14 @@ struct UpdateParams {
     ac: String,
     contents: String,
 }
-async fn update_report(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+async fn update_report(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let data: UpdateParams = serde_json::from_value(value)?;
     log!("{} 发起修改报告请求, 报告id为{}", user, data.id);
-    let key: Option<Option<String>> = conn.query_first(format!(
-        "select processing_time from report where id = '{}' and applicant='{uid}'",
-        data.id
-    ))?;
+    let key: Option<Option<String>> = conn.exec_first(
+        "select processing_time from report where id = ? and applicant = ?",
+        (&data.id, &user.id),
+    )?;
     if let Some(r) = key {
         if r.is_some() {
             return Err(Response::dissatisfy("已批阅的报告无法修改"));
@@ -209,18 +189,22 @@ async fn update_report(header: HeaderMap, Json(value): Json<Value>) -> ResponseR
 }
 
 fn __update_report(conn: &mut PooledConn, param: &UpdateParams) -> Result<(), Response> {
-    conn.query_drop(format!(
-        "update report set ty={}, reviewer='{}', ac='{}', contents='{}' 
-        where id ='{}' limit 1",
-        param.ty, param.reviewer, param.ac, param.contents, param.id
-    ))?;
-    conn.query_drop(format!("delete from report_cc where report='{}'", param.id))?;
-    for cc in &param.cc {
-        conn.query_drop(format!(
-            "insert ignore into report_cc (cc, report) values ('{}', '{}')",
-            cc, param.id
-        ))?;
-    }
+    conn.exec_drop(
+        "update report set ty = ?, reviewer = ?, ac = ?, contents = ? 
+        where id = ? limit 1",
+        (
+            param.ty,
+            &param.reviewer,
+            &param.ac,
+            &param.contents,
+            &param.id,
+        ),
+    )?;
+    conn.exec_drop("delete from report_cc where report = ?", (&param.id,))?;
+    conn.exec_batch(
+        "insert ignore into report_cc (cc, report) values (?, ?)",
+        param.cc.iter().map(|cc| (cc, &param.id)),
+    )?;
     Ok(())
 }
 
@@ -257,12 +241,8 @@ struct Report {
     status: i32,
 }
 
-async fn query_report(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+async fn query_report(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     log!("{}-{} 发起查询报告请求", user.department, user.name);
     let data: QueryParams = serde_json::from_value(value)?;
     let reports = __query(&mut conn, &data, &user)?;
@@ -277,63 +257,58 @@ async fn query_report(header: HeaderMap, Json(value): Json<Value>) -> ResponseRe
 }
 
 fn __query_statement(
-    send_time: &str,
-    processing_time: &str,
-    status: &str,
+    send_time: &Cond,
+    processing_time: &Cond,
+    status: &Cond,
     param: &QueryParams,
-) -> Result<String, Response> {
-    let cc = if param.cc.is_empty() {
-        String::new()
-    } else {
-        format!(
-            "and exists (select 1 from report_cc rc where rc.report = r.id and rc.cc = '{}')",
-            param.cc
-        )
-    };
-
+) -> Result<Query, Response> {
     let applicant = if param.applicant.is_empty() {
-        "is not null".to_owned()
+        Cond::not_null("r.applicant")
     } else {
-        format!("= '{}'", param.applicant)
+        Cond::eq("r.applicant", param.applicant.as_str())
     };
     let reviewer = if param.reviewer.is_empty() {
-        "is not null".to_owned()
+        Cond::not_null("r.reviewer")
     } else {
-        format!("= '{}'", param.reviewer)
+        Cond::eq("r.reviewer", param.reviewer.as_str())
     };
 
     let ty = match param.ty {
-        0..=2 => format!("= {}", param.ty),
-        3 => "is not null".to_owned(),
+        0..=2 => Cond::eq("r.ty", param.ty),
+        3 => Cond::not_null("r.ty"),
         _ => return Err(Response::invalid_value("ty值非法")),
     };
-    let ac_filter = if param.ac.is_empty() {
-        "left join customer c on c.id=r.ac".to_owned()
-    } else {
-        format!("join customer c on c.id=r.ac and c.id = '{}'", param.ac)
-    };
-    let query = format!(
+    let query = Query::new(
         "select r.*, a.name as applicant_name, 
         rev.name as reviewer_name, 
         c.name as ac_name
         from report r
         join user a on r.applicant=a.id 
-        join user rev on rev.id=r.reviewer
-        {ac_filter}
-        where (r.ty {ty}) 
-            and ({send_time})
-            and ({processing_time})
-            and (r.status {status}) 
-            and (r.reviewer {reviewer}) 
-            and (r.applicant {applicant})
-            {cc}
-        order by r.send_time desc
-        limit {}
-        ",
-        param.limit
+        join user rev on rev.id=r.reviewer ",
     );
-    // println!("{}", query);
-    Ok(query)
+    let query = if param.ac.is_empty() {
+        query.push("left join customer c on c.id=r.ac")
+    } else {
+        query
+            .push("join customer c on c.id=r.ac and c.id = ")
+            .bind(param.ac.as_str())
+    };
+    let mut query = query.push(" where ").cond(
+        &ty.and(send_time.clone())
+            .and(processing_time.clone())
+            .and(status.clone())
+            .and(reviewer)
+            .and(applicant),
+    );
+    if !param.cc.is_empty() {
+        query = query
+            .push(" and exists (select 1 from report_cc rc where rc.report = r.id and rc.cc = ")
+            .bind(param.cc.as_str())
+            .push(")");
+    }
+    Ok(query
+        .push(" order by r.send_time desc limit ")
+        .bind(param.limit))
 }
 
 fn __query(
@@ -352,30 +327,25 @@ fn __query(
     let st = &params.send_time;
     let pt = &params.processing_time;
     let pt = if pt.0.eq("0000-00-00") && pt.1.eq("9999-99-99") {
-        "r.processing_time is null or r.processing_time is not null".to_string()
+        Cond::any()
     } else {
-        format!(
-            "r.processing_time >= '{}' and r.processing_time <= '{}'",
-            pt.0, pt.1
-        )
+        Cond::ge("r.processing_time", pt.0.as_str()).and(Cond::le("r.processing_time", pt.1.as_str()))
     };
     let status = if params.status >= 3 {
-        "is not null".to_string()
+        Cond::not_null("r.status")
     } else {
-        format!("= {}", params.status)
+        Cond::eq("r.status", params.status)
     };
-    let st = format!("r.send_time >= '{}' and r.send_time <= '{}'", st.0, st.1);
+    let st = Cond::ge("r.send_time", st.0.as_str()).and(Cond::le("r.send_time", st.1.as_str()));
     let query = __query_statement(&st, &pt, &status, params)?;
-    let reports: Vec<Report> = conn.query(query)?;
+    let reports: Vec<Report> = query.fetch(conn)?;
     let mut data = Vec::new();
     for row in reports {
-        let cc = conn.query_map(
-            format!(
-                "select rc.cc, u.name from report_cc rc
+        let cc = conn.exec_map(
+            "select rc.cc, u.name from report_cc rc
                     join user u on u.id=rc.cc
-                    where rc.report='{}'",
-                row.id
-            ),
+                    where rc.report = ?",
+            (&row.id,),
             |(cc, name): (String, String)| {
                 json!({
                     "name": name,
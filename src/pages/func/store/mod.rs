@@ -1,4 +1,6 @@
+pub mod stock;
 mod storehouse;
+mod transfer;
 use axum::Router;
 use mysql_common::prelude::FromRow;
 use serde::{Deserialize, Serialize};
@@ -19,4 +21,6 @@ impl PartialEq for Storehouse {
 }
 pub fn store_router() -> Router {
     storehouse::storehouse_router()
+        .merge(stock::stock_router())
+        .merge(transfer::transfer_router())
 }
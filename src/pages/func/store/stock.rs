@@ -0,0 +1,546 @@
+//! 库存流水、订单出库和库存预留
+//!
+//! 所有对`product_store.amount`的修改都通过[`move_stock`]进行，同时在`stock_movement`中追加一条流水，
+//! 记录变动数量、变动后的库存、操作人和关联单据，`product_store`可以随时从流水重建。
+//!
+//! 订单发货时从发货仓库扣减库存，取消发货或删除订单时退回。
+//! 意向订单可以在某个仓库预留库存，预留的数量不能再被其他订单发货或预留，
+//! 订单发货时释放自己的预留。库存不足时是否允许继续由`config.json`中的
+//! `inventory.allow_negative`决定。这里修改库存的函数都应该在事务中调用
+use std::collections::{HashMap, HashSet};
+
+use axum::{routing::post, Json, Router};
+use mysql::{params, prelude::Queryable, PooledConn};
+use mysql_common::prelude::FromRow;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    commit_or_rollback,
+    database::{
+        get_db,
+        query::{Cond, Query},
+    },
+    libs::{cache::PRODUCT_CACHE, TimeFormat, TIME},
+    log,
+    perm::auth::{AuthUser, Scope, StorehousePerm},
+    Response, ResponseResult, CONFIG,
+};
+
+pub fn stock_router() -> Router {
+    Router::new()
+        .route("/store/query/movement", post(query_movement))
+        .route("/store/rebuild/stock", post(rebuild_stock))
+}
+
+/// 库存变动的类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovementKind {
+    /// 期初
+    Opening,
+    /// 手动调整
+    Adjust,
+    /// 订单发货
+    Ship,
+    /// 取消发货退回
+    Return,
+    /// 调拨出库
+    TransferOut,
+    /// 调拨入库
+    TransferIn,
+    /// 采购入库
+    Receipt,
+}
+
+impl MovementKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Opening => "opening",
+            Self::Adjust => "adjust",
+            Self::Ship => "ship",
+            Self::Return => "return",
+            Self::TransferOut => "transfer_out",
+            Self::TransferIn => "transfer_in",
+            Self::Receipt => "receipt",
+        }
+    }
+}
+
+/// 库存变动的来源
+#[derive(Debug, Clone, Copy)]
+pub struct Movement<'a> {
+    pub kind: MovementKind,
+    pub operator: &'a str,
+    /// 关联的单据，例如订单id
+    pub reference: Option<&'a str>,
+}
+
+impl<'a> Movement<'a> {
+    pub fn new(kind: MovementKind, operator: &'a str, reference: Option<&'a str>) -> Self {
+        Self {
+            kind,
+            operator,
+            reference,
+        }
+    }
+}
+
+/// 修改库存并追加流水，返回变动后的库存
+pub fn move_stock(
+    conn: &mut PooledConn,
+    product: &str,
+    storehouse: &str,
+    delta: i32,
+    movement: &Movement,
+) -> mysql::Result<i32> {
+    conn.exec_drop(
+        "INSERT INTO product_store (product, storehouse, amount) VALUES (:product, :storehouse, :delta)
+        ON DUPLICATE KEY UPDATE amount = amount + :delta",
+        params! {
+            "product" => product,
+            "storehouse" => storehouse,
+            "delta" => delta
+        },
+    )?;
+    let balance: i32 = conn
+        .exec_first(
+            "SELECT amount FROM product_store WHERE product = ? AND storehouse = ? LIMIT 1",
+            (product, storehouse),
+        )?
+        .unwrap_or(delta);
+    if delta != 0 {
+        let time = TIME::now().unwrap_or_default();
+        conn.exec_drop(
+            "INSERT INTO stock_movement
+            (product, storehouse, kind, delta, balance, operator, reference, create_time)
+            VALUES (:product, :storehouse, :kind, :delta, :balance, :operator, :reference, :time)",
+            params! {
+                "product" => product,
+                "storehouse" => storehouse,
+                "kind" => movement.kind.as_str(),
+                "delta" => delta,
+                "balance" => balance,
+                "operator" => movement.operator,
+                "reference" => movement.reference,
+                "time" => time.format(TimeFormat::YYYYMMDD_HHMMSS)
+            },
+        )?;
+    }
+    Ok(balance)
+}
+
+/// 把库存设为`amount`，差额记为手动调整
+pub fn set_amount(
+    conn: &mut PooledConn,
+    product: &str,
+    storehouse: &str,
+    amount: i32,
+    operator: &str,
+) -> mysql::Result<()> {
+    let current: Option<i32> = conn.exec_first(
+        "SELECT amount FROM product_store WHERE product = ? AND storehouse = ? LIMIT 1 FOR UPDATE",
+        (product, storehouse),
+    )?;
+    let movement = Movement::new(MovementKind::Adjust, operator, None);
+    move_stock(
+        conn,
+        product,
+        storehouse,
+        amount - current.unwrap_or(0),
+        &movement,
+    )?;
+    Ok(())
+}
+
+/// 把库存调整为0后删除库存记录，用于产品移出库房或删除库房
+pub fn remove_store(
+    conn: &mut PooledConn,
+    product: &str,
+    storehouse: &str,
+    operator: &str,
+) -> mysql::Result<()> {
+    set_amount(conn, product, storehouse, 0, operator)?;
+    conn.exec_drop(
+        "DELETE FROM product_store WHERE product = ? AND storehouse = ? LIMIT 1",
+        (product, storehouse),
+    )
+}
+
+/// 一行出库或预留的产品
+#[derive(Debug, Clone, Copy)]
+pub struct StockItem<'a> {
+    pub product: &'a str,
+    /// 产品名称，用于错误信息
+    pub name: &'a str,
+    pub amount: i32,
+}
+
+/// 可用库存，会锁定库存行直到事务结束。`order_id`自己的预留不计入
+fn available(
+    conn: &mut PooledConn,
+    product: &str,
+    storehouse: &str,
+    order_id: &str,
+) -> mysql::Result<i32> {
+    let amount: Option<i32> = conn.exec_first(
+        "SELECT amount FROM product_store WHERE product = ? AND storehouse = ? LIMIT 1 FOR UPDATE",
+        (product, storehouse),
+    )?;
+    let reserved: Option<i32> = conn.exec_first(
+        "SELECT CAST(COALESCE(SUM(amount), 0) AS SIGNED) FROM product_reservation
+        WHERE product = ? AND storehouse = ? AND order_id <> ?",
+        (product, storehouse, order_id),
+    )?;
+    Ok(amount.unwrap_or(0) - reserved.unwrap_or(0))
+}
+
+/// 按产品合并数量，同一产品出现在多行时需要一起检查库存
+fn merge_items<'a>(items: &[StockItem<'a>]) -> Vec<StockItem<'a>> {
+    let mut merged: Vec<StockItem> = Vec::new();
+    for item in items {
+        match merged.iter_mut().find(|m| m.product == item.product) {
+            Some(m) => m.amount += item.amount,
+            None => merged.push(*item),
+        }
+    }
+    merged
+}
+
+/// 检查`storehouse`中是否有足够的可用库存，`reference`自己的预留不计入
+pub fn check_available(
+    conn: &mut PooledConn,
+    reference: &str,
+    storehouse: &str,
+    items: &[StockItem],
+) -> Result<(), Response> {
+    if CONFIG.inventory().allow_negative() {
+        return Ok(());
+    }
+    for item in &merge_items(items) {
+        let available = available(conn, item.product, storehouse, reference)?;
+        if available < item.amount {
+            return Err(Response::dissatisfy(format!(
+                "产品`{}`在库房`{storehouse}`的库存不足，可用{available}，需要{}",
+                item.name, item.amount
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// 订单发货，释放订单的预留并从`storehouse`扣减库存
+pub fn ship_out(
+    conn: &mut PooledConn,
+    order_id: &str,
+    storehouse: &str,
+    items: &[StockItem],
+    operator: &str,
+) -> Result<(), Response> {
+    release(conn, order_id)?;
+    check_available(conn, order_id, storehouse, items)?;
+    let movement = Movement::new(MovementKind::Ship, operator, Some(order_id));
+    for item in items {
+        move_stock(conn, item.product, storehouse, -item.amount, &movement)?;
+    }
+    Ok(())
+}
+
+/// 取消发货，把库存退回到发货的库房
+pub fn ship_back(
+    conn: &mut PooledConn,
+    order_id: &str,
+    storehouse: &str,
+    items: &[StockItem],
+    operator: &str,
+) -> Result<(), Response> {
+    let movement = Movement::new(MovementKind::Return, operator, Some(order_id));
+    for item in items {
+        move_stock(conn, item.product, storehouse, item.amount, &movement)?;
+    }
+    Ok(())
+}
+
+/// 为订单预留库存，替换订单之前的预留
+pub fn reserve(
+    conn: &mut PooledConn,
+    order_id: &str,
+    storehouse: &str,
+    items: &[StockItem],
+) -> Result<(), Response> {
+    release(conn, order_id)?;
+    check_available(conn, order_id, storehouse, items)?;
+    let time = TIME::now()?.format(TimeFormat::YYYYMMDD_HHMMSS);
+    conn.exec_batch(
+        "INSERT INTO product_reservation (order_id, product, storehouse, amount, create_time)
+        VALUES (:order_id, :product, :storehouse, :amount, :time)
+        ON DUPLICATE KEY UPDATE amount = amount + :amount",
+        items.iter().map(|item| {
+            params! {
+                "order_id" => order_id,
+                "product" => item.product,
+                "storehouse" => storehouse,
+                "amount" => item.amount,
+                "time" => &time
+            }
+        }),
+    )?;
+    Ok(())
+}
+
+/// 释放订单的所有预留
+pub fn release(conn: &mut PooledConn, order_id: &str) -> mysql::Result<()> {
+    conn.exec_drop(
+        "DELETE FROM product_reservation WHERE order_id = ?",
+        (order_id,),
+    )
+}
+
+/// 订单预留库存的库房，没有预留时为None
+pub fn reserved_storehouse(conn: &mut PooledConn, order_id: &str) -> mysql::Result<Option<String>> {
+    conn.exec_first(
+        "SELECT storehouse FROM product_reservation WHERE order_id = ? LIMIT 1",
+        (order_id,),
+    )
+}
+
+#[derive(Deserialize)]
+struct MovementParams {
+    #[serde(default)]
+    product: String,
+    #[serde(default)]
+    storehouse: String,
+    #[serde(default)]
+    kind: String,
+    #[serde(default)]
+    reference: String,
+    #[serde(default)]
+    start_time: String,
+    #[serde(default)]
+    end_time: String,
+    #[serde(default)]
+    limit: u32,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct MovementData {
+    id: u64,
+    product: String,
+    product_name: Option<String>,
+    storehouse: String,
+    kind: String,
+    delta: i32,
+    balance: i32,
+    operator: String,
+    operator_name: Option<String>,
+    reference: Option<String>,
+    create_time: String,
+}
+
+/// 按产品、库房、类型、单据或时间查询库存流水，最新的在前，产品和库房至少指定一个
+async fn query_movement(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    user.can(StorehousePerm::AdjustingProductInventory, Scope::Any)
+        .await?;
+    let mut conn = get_db().await?;
+    let params: MovementParams = serde_json::from_value(value)?;
+    if params.product.is_empty() && params.storehouse.is_empty() {
+        return Err(Response::invalid_value(
+            "product和storehouse至少需要设置一个",
+        ));
+    }
+    let text = |column: &str, value: &str| op::ternary!(value.is_empty() => Cond::any(); Cond::eq(column, value));
+    let cond = text("m.product", &params.product)
+        .and(text("m.storehouse", &params.storehouse))
+        .and(text("m.kind", &params.kind))
+        .and(text("m.reference", &params.reference))
+        .and(op::ternary!(params.start_time.is_empty() => Cond::any(); Cond::ge("m.create_time", params.start_time.as_str())))
+        .and(op::ternary!(params.end_time.is_empty() => Cond::any(); Cond::le("m.create_time", params.end_time.as_str())));
+    let limit = op::ternary!(params.limit == 0 => 200; params.limit);
+    let list: Vec<MovementData> = Query::new(
+        "SELECT m.id, m.product, p.name AS product_name, m.storehouse, m.kind, m.delta, m.balance,
+            m.operator, u.name AS operator_name, m.reference, m.create_time
+        FROM stock_movement m
+        LEFT JOIN product p ON p.id = m.product
+        LEFT JOIN user u ON u.id = m.operator
+        WHERE ",
+    )
+    .cond(&cond)
+    .push(" ORDER BY m.id DESC LIMIT ")
+    .bind(limit)
+    .fetch(&mut conn)?;
+    log!("{user} 查询到{}条库存流水", list.len());
+    Ok(Response::ok(json!(list)))
+}
+
+#[derive(Deserialize)]
+struct RebuildParams {
+    /// 为true时只返回差异，不修改库存
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// 库存和流水不一致的记录
+#[derive(Debug, Serialize)]
+struct Discrepancy {
+    product: String,
+    storehouse: String,
+    /// 当前的库存，没有库存记录时为None
+    amount: Option<i32>,
+    /// 流水合计
+    ledger: i32,
+}
+
+/// 每个产品在每个库房的流水合计
+#[derive(Debug, FromRow)]
+struct LedgerSum {
+    product: String,
+    storehouse: String,
+    ledger: i32,
+    /// 产品是否还存在，已删除的产品不重建库存
+    active: bool,
+}
+
+/// 比较库存和流水合计。没有库存记录的按0计算，有库存但没有任何流水的视为流水合计为0
+fn compare(stock: &[(String, String, i32)], ledger: &[LedgerSum]) -> Vec<Discrepancy> {
+    let amounts: HashMap<(&str, &str), i32> = stock
+        .iter()
+        .map(|(p, s, a)| ((p.as_str(), s.as_str()), *a))
+        .collect();
+    let moved: HashSet<(&str, &str)> = ledger
+        .iter()
+        .map(|l| (l.product.as_str(), l.storehouse.as_str()))
+        .collect();
+    let mut list: Vec<Discrepancy> = ledger
+        .iter()
+        .filter(|l| l.active)
+        .filter_map(|l| {
+            let amount = amounts.get(&(l.product.as_str(), l.storehouse.as_str())).copied();
+            (amount.unwrap_or(0) != l.ledger).then(|| Discrepancy {
+                product: l.product.clone(),
+                storehouse: l.storehouse.clone(),
+                amount,
+                ledger: l.ledger,
+            })
+        })
+        .chain(
+            stock
+                .iter()
+                .filter(|(p, s, a)| *a != 0 && !moved.contains(&(p.as_str(), s.as_str())))
+                .map(|(p, s, a)| Discrepancy {
+                    product: p.clone(),
+                    storehouse: s.clone(),
+                    amount: Some(*a),
+                    ledger: 0,
+                }),
+        )
+        .collect();
+    list.sort_by(|a, b| (&a.product, &a.storehouse).cmp(&(&b.product, &b.storehouse)));
+    list
+}
+
+fn discrepancies(conn: &mut PooledConn) -> mysql::Result<Vec<Discrepancy>> {
+    let stock: Vec<(String, String, i32)> =
+        conn.query("SELECT product, storehouse, amount FROM product_store")?;
+    let ledger: Vec<LedgerSum> = conn.query(
+        "SELECT m.product, m.storehouse, CAST(SUM(m.delta) AS SIGNED) AS ledger,
+            EXISTS (SELECT 1 FROM product p WHERE p.id = m.product) AS active
+        FROM stock_movement m
+        GROUP BY m.product, m.storehouse",
+    )?;
+    Ok(compare(&stock, &ledger))
+}
+
+fn __rebuild_stock(conn: &mut PooledConn, dry_run: bool) -> Result<Vec<Discrepancy>, Response> {
+    let list = discrepancies(conn)?;
+    if dry_run {
+        return Ok(list);
+    }
+    conn.exec_batch(
+        "INSERT INTO product_store (product, storehouse, amount) VALUES (:product, :storehouse, :amount)
+        ON DUPLICATE KEY UPDATE amount = :amount",
+        list.iter().map(|d| {
+            params! {
+                "product" => &d.product,
+                "storehouse" => &d.storehouse,
+                "amount" => d.ledger
+            }
+        }),
+    )?;
+    Ok(list)
+}
+
+/// 用流水重建`product_store`，返回重建前不一致的记录
+async fn rebuild_stock(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    user.can(StorehousePerm::AdjustingProductInventory, Scope::Any)
+        .await?;
+    let params: RebuildParams = serde_json::from_value(value)?;
+    log!("{user} 请求从流水重建库存，dry_run: {}", params.dry_run);
+    let list = commit_or_rollback!(__rebuild_stock, &mut conn, params.dry_run)?;
+    if !params.dry_run {
+        PRODUCT_CACHE.clear();
+    }
+    log!("{user} 完成库存检查，共{}条记录与流水不一致", list.len());
+    Ok(Response::ok(json!(list)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stock(p: &str, s: &str, amount: i32) -> (String, String, i32) {
+        (p.to_owned(), s.to_owned(), amount)
+    }
+
+    fn ledger(p: &str, s: &str, ledger: i32, active: bool) -> LedgerSum {
+        LedgerSum {
+            product: p.to_owned(),
+            storehouse: s.to_owned(),
+            ledger,
+            active,
+        }
+    }
+
+    #[test]
+    fn compare_with_ledger() {
+        let list = compare(
+            &[
+                stock("p1", "A", 5),
+                stock("p1", "B", 3),
+                stock("p2", "A", 4),
+                stock("p3", "A", 0),
+            ],
+            &[
+                ledger("p1", "A", 5, true),
+                ledger("p1", "B", 2, true),
+                ledger("p3", "B", 6, true),
+                ledger("p4", "A", 0, true),
+                ledger("p5", "A", 9, false),
+            ],
+        );
+        let list: Vec<_> = list
+            .iter()
+            .map(|d| (d.product.as_str(), d.storehouse.as_str(), d.amount, d.ledger))
+            .collect();
+        // p1/A一致，p3/A和p4/A都为0，已删除的p5不重建
+        assert_eq!(
+            list,
+            [
+                ("p1", "B", Some(3), 2),
+                ("p2", "A", Some(4), 0),
+                ("p3", "B", None, 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_same_product() {
+        let item = |product, amount| StockItem {
+            product,
+            name: product,
+            amount,
+        };
+        let merged = merge_items(&[item("p1", 3), item("p2", 1), item("p1", 4)]);
+        let merged: Vec<_> = merged.iter().map(|i| (i.product, i.amount)).collect();
+        assert_eq!(merged, [("p1", 7), ("p2", 1)]);
+    }
+}
@@ -1,14 +1,12 @@
 use crate::{
-    bearer,
     database::get_db,
     libs::{cache::STORE_HOUSE_CACHE, gen_id, TIME},
     log,
-    pages::account::get_user,
-    parse_jwt_macro, verify_perms, Response, ResponseResult,
+    perm::auth::{AuthUser, Scope, StorehousePerm},
+    Response, ResponseResult,
 };
 use axum::{
     extract::Path,
-    http::HeaderMap,
     routing::{delete, get, post},
     Json, Router,
 };
@@ -48,15 +46,9 @@ async fn query_storehouse(Json(mut param): Json<QueryParam>) -> ResponseResult {
     Ok(Response::ok(json!(param)))
 }
 
-async fn create_storehouse(header: HeaderMap, Json(mut value): Json<Storehouse>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
-    if !verify_perms!(&user.role, "storehouse", "add_storehouse") {
-        return Err(Response::permission_denied());
-    }
+async fn create_storehouse(user: AuthUser, Json(mut value): Json<Storehouse>) -> ResponseResult {
+    user.can(StorehousePerm::AddStorehouse, Scope::Any).await?;
+    let mut conn = get_db().await?;
     let time = TIME::now()?;
     value.id = gen_id(&time, "storehouse");
     value.create_time = time.format(crate::libs::TimeFormat::YYYYMMDD_HHMMSS);
@@ -75,15 +67,9 @@ async fn create_storehouse(header: HeaderMap, Json(mut value): Json<Storehouse>)
     Ok(Response::ok(json!("创建仓库成功")))
 }
 
-async fn update_storehouse(header: HeaderMap, Json(mut value): Json<Storehouse>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
-    if !verify_perms!(&user.role, "storehouse", "update_storehouse") {
-        return Err(Response::permission_denied());
-    }
+async fn update_storehouse(user: AuthUser, Json(mut value): Json<Storehouse>) -> ResponseResult {
+    user.can(StorehousePerm::UpdateStorehouse, Scope::Any).await?;
+    let mut conn = get_db().await?;
     if let Some(store) = STORE_HOUSE_CACHE.get(&value.id) {
         value.create_time = store.create_time.clone();
         conn.exec_drop(
@@ -100,15 +86,9 @@ async fn update_storehouse(header: HeaderMap, Json(mut value): Json<Storehouse>)
     }
 }
 
-async fn delete_storehouse(header: HeaderMap, Path(id): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
-    if !verify_perms!(&user.role, "storehouse", "delete_storehouse") {
-        return Err(Response::permission_denied());
-    }
+async fn delete_storehouse(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    user.can(StorehousePerm::DeleteStorehouse, Scope::Any).await?;
+    let mut conn = get_db().await?;
     if STORE_HOUSE_CACHE.contains_key(&id) {
         conn.exec_drop("delete from storehouse where id = ? LIMIT 1", (&id,))?;
         let stmt = format!("{user}成功删除库房{id}");
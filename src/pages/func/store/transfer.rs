@@ -0,0 +1,435 @@
+//! 库房之间的调拨单
+//!
+//! 调拨单创建后为草稿，可以修改和删除；发出时从调出库房扣减库存，变为在途；
+//! 接收时计入调入库房，变为已接收。扣减和计入都记入库存流水，关联单据为调拨单id
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Json, Router,
+};
+use mysql::{params, prelude::Queryable, PooledConn};
+use mysql_common::prelude::FromRow;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::stock::{self, Movement, MovementKind, StockItem};
+use crate::{
+    commit_or_rollback,
+    database::{
+        get_db,
+        query::{Cond, Query},
+    },
+    libs::{
+        cache::PRODUCT_CACHE,
+        dser::{deserialize_inventory, deserialize_storehouse},
+        gen_id, TimeFormat, TIME,
+    },
+    log,
+    perm::auth::{AuthUser, Scope, StorehousePerm},
+    Response, ResponseResult,
+};
+
+pub fn transfer_router() -> Router {
+    Router::new()
+        .route("/store/transfer/create", post(create_transfer))
+        .route("/store/transfer/update", post(update_transfer))
+        .route("/store/transfer/query", post(query_transfer))
+        .route("/store/transfer/send/:id", post(send_transfer))
+        .route("/store/transfer/receive/:id", post(receive_transfer))
+        .route("/store/transfer/:id", get(query_by).delete(delete_transfer))
+}
+
+/// 草稿
+const DRAFT: i32 = 0;
+/// 在途，已从调出库房扣减
+const IN_TRANSIT: i32 = 1;
+/// 已接收，已计入调入库房
+const RECEIVED: i32 = 2;
+
+/// 调拨单的操作
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    Update,
+    Delete,
+    Send,
+    Receive,
+}
+
+/// 检查调拨单在`status`状态下能否进行`step`，草稿可以修改、删除和发出，在途的可以接收
+fn check_status(status: i32, step: Step) -> Result<(), Response> {
+    let message = match (step, status) {
+        (Step::Update | Step::Delete | Step::Send, DRAFT) | (Step::Receive, IN_TRANSIT) => {
+            return Ok(())
+        }
+        (Step::Update, _) => "只能修改草稿状态的调拨单",
+        (Step::Delete, _) => "只能删除草稿状态的调拨单",
+        (Step::Send, _) => "调拨单已经发出",
+        (Step::Receive, DRAFT) => "调拨单还没有发出",
+        (Step::Receive, _) => "调拨单已经接收",
+    };
+    Err(Response::dissatisfy(message))
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+struct TransferProduct {
+    product: String,
+    #[serde(skip_deserializing)]
+    name: Option<String>,
+    #[serde(deserialize_with = "deserialize_inventory")]
+    amount: i32,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+struct Transfer {
+    id: String,
+    source: String,
+    destination: String,
+    status: i32,
+    creator: String,
+    create_time: String,
+    sender: Option<String>,
+    send_time: Option<String>,
+    receiver: Option<String>,
+    receive_time: Option<String>,
+    comment: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TransferParams {
+    #[serde(default)]
+    id: String,
+    #[serde(deserialize_with = "deserialize_storehouse")]
+    source: String,
+    #[serde(deserialize_with = "deserialize_storehouse")]
+    destination: String,
+    product: Vec<TransferProduct>,
+    #[serde(default)]
+    comment: String,
+}
+
+impl TransferParams {
+    /// 不需要查询数据库的检查
+    fn check(&self) -> Result<(), Response> {
+        if self.source == self.destination {
+            return Err(Response::invalid_value("调出库房和调入库房不能相同"));
+        }
+        if self.product.is_empty() {
+            return Err(Response::invalid_value("调拨单至少需要一个产品"));
+        }
+        if self.product.iter().any(|p| p.amount <= 0) {
+            return Err(Response::invalid_value("调拨数量必须大于0"));
+        }
+        let ids: Vec<&str> = self.product.iter().map(|p| p.product.as_str()).collect();
+        let mut unique = ids.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        if unique.len() != ids.len() {
+            return Err(Response::invalid_value("同一个产品只能出现一次"));
+        }
+        Ok(())
+    }
+
+    fn verify(&self, conn: &mut PooledConn) -> Result<(), Response> {
+        self.check()?;
+        let ids: Vec<&str> = self.product.iter().map(|p| p.product.as_str()).collect();
+        let len = ids.len();
+        let count: Option<usize> = Query::new("SELECT COUNT(*) FROM product WHERE ")
+            .cond(&Cond::in_list("id", ids))
+            .first(conn)?;
+        if count != Some(len) {
+            return Err(Response::not_exist("调拨单中存在不存在的产品"));
+        }
+        Ok(())
+    }
+}
+
+fn insert_products(
+    conn: &mut PooledConn,
+    id: &str,
+    products: &[TransferProduct],
+) -> mysql::Result<()> {
+    conn.exec_drop(
+        "DELETE FROM stock_transfer_product WHERE transfer_id = ?",
+        (id,),
+    )?;
+    conn.exec_batch(
+        "INSERT INTO stock_transfer_product (transfer_id, product, amount) VALUES (?, ?, ?)",
+        products.iter().map(|p| (id, &p.product, p.amount)),
+    )
+}
+
+fn query_transfer_by_id(conn: &mut PooledConn, id: &str) -> Result<Transfer, Response> {
+    let transfer: Option<Transfer> = conn.exec_first(
+        "SELECT * FROM stock_transfer WHERE id = ? LIMIT 1 FOR UPDATE",
+        (id,),
+    )?;
+    Ok(op::some!(transfer; ret Err(Response::not_exist("调拨单不存在"))))
+}
+
+fn query_products(conn: &mut PooledConn, id: &str) -> mysql::Result<Vec<TransferProduct>> {
+    conn.exec(
+        "SELECT tp.product, p.name, tp.amount FROM stock_transfer_product tp
+        LEFT JOIN product p ON p.id = tp.product
+        WHERE tp.transfer_id = ? ORDER BY p.name",
+        (id,),
+    )
+}
+
+fn stock_items(products: &[TransferProduct]) -> Vec<StockItem<'_>> {
+    products
+        .iter()
+        .map(|p| StockItem {
+            product: &p.product,
+            name: p.name.as_deref().unwrap_or(&p.product),
+            amount: p.amount,
+        })
+        .collect()
+}
+
+async fn create_transfer(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    user.can(StorehousePerm::TransferStock, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    let mut params: TransferParams = serde_json::from_value(value)?;
+    log!(
+        "{user} 请求创建从{}到{}的调拨单",
+        params.source,
+        params.destination
+    );
+    commit_or_rollback!(__create_transfer, &mut conn, &mut params, &user)?;
+    log!("{user} 成功创建调拨单{}", params.id);
+    Ok(Response::ok(json!(params.id)))
+}
+
+fn __create_transfer(
+    conn: &mut PooledConn,
+    params: &mut TransferParams,
+    user: &AuthUser,
+) -> Result<(), Response> {
+    params.verify(conn)?;
+    let time = TIME::now()?;
+    params.id = gen_id(&time, "transfer");
+    conn.exec_drop(
+        "INSERT INTO stock_transfer (id, source, destination, status, creator, create_time, comment)
+        VALUES (:id, :source, :destination, :status, :creator, :time, :comment)",
+        params! {
+            "id" => &params.id,
+            "source" => &params.source,
+            "destination" => &params.destination,
+            "status" => DRAFT,
+            "creator" => &user.id,
+            "time" => time.format(TimeFormat::YYYYMMDD_HHMMSS),
+            "comment" => &params.comment
+        },
+    )?;
+    insert_products(conn, &params.id, &params.product)?;
+    Ok(())
+}
+
+/// 只能修改草稿
+async fn update_transfer(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    user.can(StorehousePerm::TransferStock, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    let params: TransferParams = serde_json::from_value(value)?;
+    log!("{user} 请求修改调拨单{}", params.id);
+    commit_or_rollback!(__update_transfer, &mut conn, &params)?;
+    log!("{user} 成功修改调拨单{}", params.id);
+    Ok(Response::empty())
+}
+
+fn __update_transfer(conn: &mut PooledConn, params: &TransferParams) -> Result<(), Response> {
+    let transfer = query_transfer_by_id(conn, &params.id)?;
+    check_status(transfer.status, Step::Update)?;
+    params.verify(conn)?;
+    conn.exec_drop(
+        "UPDATE stock_transfer SET source = ?, destination = ?, comment = ? WHERE id = ? LIMIT 1",
+        (
+            &params.source,
+            &params.destination,
+            &params.comment,
+            &params.id,
+        ),
+    )?;
+    insert_products(conn, &params.id, &params.product)?;
+    Ok(())
+}
+
+/// 只能删除草稿
+async fn delete_transfer(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    user.can(StorehousePerm::TransferStock, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    log!("{user} 请求删除调拨单{id}");
+    commit_or_rollback!(__delete_transfer, &mut conn, &id)?;
+    log!("{user} 成功删除调拨单{id}");
+    Ok(Response::empty())
+}
+
+fn __delete_transfer(conn: &mut PooledConn, id: &str) -> Result<(), Response> {
+    let transfer = query_transfer_by_id(conn, id)?;
+    check_status(transfer.status, Step::Delete)?;
+    conn.exec_drop(
+        "DELETE FROM stock_transfer_product WHERE transfer_id = ?",
+        (id,),
+    )?;
+    conn.exec_drop("DELETE FROM stock_transfer WHERE id = ? LIMIT 1", (id,))?;
+    Ok(())
+}
+
+/// 发出调拨单，从调出库房扣减库存
+async fn send_transfer(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    user.can(StorehousePerm::TransferStock, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    log!("{user} 请求发出调拨单{id}");
+    commit_or_rollback!(__send_transfer, &mut conn, &id, &user.id)?;
+    PRODUCT_CACHE.clear();
+    log!("{user} 成功发出调拨单{id}");
+    Ok(Response::empty())
+}
+
+fn __send_transfer(conn: &mut PooledConn, id: &str, operator: &str) -> Result<(), Response> {
+    let transfer = query_transfer_by_id(conn, id)?;
+    check_status(transfer.status, Step::Send)?;
+    let products = query_products(conn, id)?;
+    let items = stock_items(&products);
+    stock::check_available(conn, id, &transfer.source, &items)?;
+    let movement = Movement::new(MovementKind::TransferOut, operator, Some(id));
+    for item in &items {
+        stock::move_stock(
+            conn,
+            item.product,
+            &transfer.source,
+            -item.amount,
+            &movement,
+        )?;
+    }
+    conn.exec_drop(
+        "UPDATE stock_transfer SET status = ?, sender = ?, send_time = ? WHERE id = ? LIMIT 1",
+        (
+            IN_TRANSIT,
+            operator,
+            TIME::now()?.format(TimeFormat::YYYYMMDD_HHMMSS),
+            id,
+        ),
+    )?;
+    Ok(())
+}
+
+/// 接收调拨单，计入调入库房
+async fn receive_transfer(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    user.can(StorehousePerm::TransferStock, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    log!("{user} 请求接收调拨单{id}");
+    commit_or_rollback!(__receive_transfer, &mut conn, &id, &user.id)?;
+    PRODUCT_CACHE.clear();
+    log!("{user} 成功接收调拨单{id}");
+    Ok(Response::empty())
+}
+
+fn __receive_transfer(conn: &mut PooledConn, id: &str, operator: &str) -> Result<(), Response> {
+    let transfer = query_transfer_by_id(conn, id)?;
+    check_status(transfer.status, Step::Receive)?;
+    let products = query_products(conn, id)?;
+    let movement = Movement::new(MovementKind::TransferIn, operator, Some(id));
+    for p in &products {
+        stock::move_stock(conn, &p.product, &transfer.destination, p.amount, &movement)?;
+    }
+    conn.exec_drop(
+        "UPDATE stock_transfer SET status = ?, receiver = ?, receive_time = ? WHERE id = ? LIMIT 1",
+        (
+            RECEIVED,
+            operator,
+            TIME::now()?.format(TimeFormat::YYYYMMDD_HHMMSS),
+            id,
+        ),
+    )?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct QueryParams {
+    /// 小于0时查询所有状态
+    #[serde(default = "all_status")]
+    status: i32,
+    /// 调出或调入的库房
+    #[serde(default)]
+    storehouse: String,
+    #[serde(default)]
+    limit: u32,
+}
+
+fn all_status() -> i32 {
+    -1
+}
+
+async fn query_transfer(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    user.can(StorehousePerm::TransferStock, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    let params: QueryParams = serde_json::from_value(value)?;
+    let status = op::ternary!(params.status < 0 => Cond::any(); Cond::eq("status", params.status));
+    let storehouse = if params.storehouse.is_empty() {
+        Cond::any()
+    } else {
+        Cond::eq("source", params.storehouse.as_str())
+            .or(Cond::eq("destination", params.storehouse.as_str()))
+    };
+    let limit = op::ternary!(params.limit == 0 => 50; params.limit);
+    let list: Vec<Transfer> = Query::new("SELECT * FROM stock_transfer WHERE ")
+        .cond(&status.and(storehouse))
+        .push(" ORDER BY create_time DESC LIMIT ")
+        .bind(limit)
+        .fetch(&mut conn)?;
+    log!("{user} 查询到{}个调拨单", list.len());
+    Ok(Response::ok(json!(list)))
+}
+
+async fn query_by(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    user.can(StorehousePerm::TransferStock, Scope::Any).await?;
+    let mut conn = get_db().await?;
+    let transfer: Option<Transfer> =
+        conn.exec_first("SELECT * FROM stock_transfer WHERE id = ? LIMIT 1", (&id,))?;
+    let transfer = op::some!(transfer; ret Err(Response::not_exist("调拨单不存在")));
+    let products = query_products(&mut conn, &id)?;
+    Ok(Response::ok(json!({
+        "transfer": transfer,
+        "product": products
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_guards() {
+        for step in [Step::Update, Step::Delete, Step::Send] {
+            assert!(check_status(DRAFT, step).is_ok());
+            assert!(check_status(IN_TRANSIT, step).is_err());
+            assert!(check_status(RECEIVED, step).is_err());
+        }
+        assert!(check_status(DRAFT, Step::Receive).is_err());
+        assert!(check_status(IN_TRANSIT, Step::Receive).is_ok());
+        assert!(check_status(RECEIVED, Step::Receive).is_err());
+    }
+
+    #[test]
+    fn params_check() {
+        let params = |source: &str, product: Vec<(&str, i32)>| TransferParams {
+            id: String::new(),
+            source: source.to_owned(),
+            destination: "B".to_owned(),
+            product: product
+                .into_iter()
+                .map(|(product, amount)| TransferProduct {
+                    product: product.to_owned(),
+                    name: None,
+                    amount,
+                })
+                .collect(),
+            comment: String::new(),
+        };
+        assert!(params("A", vec![("p1", 1), ("p2", 3)]).check().is_ok());
+        assert!(params("B", vec![("p1", 1)]).check().is_err());
+        assert!(params("A", vec![]).check().is_err());
+        assert!(params("A", vec![("p1", 0)]).check().is_err());
+        assert!(params("A", vec![("p1", 1), ("p1", 2)]).check().is_err());
+    }
+}
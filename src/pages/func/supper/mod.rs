@@ -1,10 +1,10 @@
-use axum::{extract::Path, http::HeaderMap, routing::{delete, post}, Json, Router};
+use axum::{extract::Path, routing::{delete, post}, Json, Router};
 use mysql::{params, prelude::{FromValue, Queryable}, PooledConn};
 use mysql_common::prelude::FromRow;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use crate::{bearer, commit_or_rollback, database::get_db, libs::{gen_id, TIME}, log, mysql_stmt, pages::{account::get_user, func::supper}, parse_jwt_macro, Response, ResponseResult};
+use crate::{commit_or_rollback, database::get_db, libs::{gen_id, TIME}, log, mysql_stmt, perm::auth::AuthUser, Response, ResponseResult};
 
 
 pub fn router() -> Router {
@@ -67,12 +67,8 @@ pub struct Supper {
     remark: String
 }
 
-async fn create_supper(header: HeaderMap, Json(param): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn);
-    let user = get_user(&uid, &mut conn).await?;
+async fn create_supper(user: AuthUser, Json(param): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     log!("{user} 添加供应商 {}", param);
     let mut param: Supper = serde_json::from_value(param)?;
     commit_or_rollback!(async __create, &mut conn, &mut param)?;
@@ -114,12 +110,8 @@ async fn __create(conn: &mut PooledConn, supper: &mut Supper) -> Result<(), Resp
     Ok(())
 }
 
-async fn update_supper(header: HeaderMap, Json(param): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn);
-    let user = get_user(&uid, &mut conn).await?;
+async fn update_supper(user: AuthUser, Json(param): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     log!("{user}正在修改供应商数据， 数据为：{:#?}", param);
     let mut supper: Supper = serde_json::from_value(param)?;
     commit_or_rollback!(async __update_supper, &mut conn, &mut supper)?;
@@ -140,11 +132,8 @@ async fn __update_supper(conn: &mut PooledConn, supper: &mut Supper) -> Result<(
     })?;
     Ok(())
 }
-async fn delete_supper(header: HeaderMap, Path(id): Path<String>) -> ResponseResult {
-    let bearer = bearer!(&header); 
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let _uid = parse_jwt_macro!(&bearer, &mut conn);
+async fn delete_supper(_user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
     conn.exec_drop("delete supper where id = ? limit 1", (&id, ))?;
     Ok(Response::ok(json!("删除成功")))
 }
@@ -163,9 +152,8 @@ struct QueryResponse {
     records: Vec<Supper>
 }
 
-async fn query_supper(header: HeaderMap, Json(param): Json<Value>) -> ResponseResult {
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
+async fn query_supper(_user: AuthUser, Json(param): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
     let param: QueryParam = serde_json::from_value(param)?;
     let buf: Vec<Supper> = conn.query(
         "select *, 1 as custom from supper order by create_time")?;
@@ -2,10 +2,14 @@ mod user;
 use axum::Router;
 
 mod account;
-pub use account::User;
+pub use account::{
+    get_user, purge_login_failures, DepartmentTree, User, __insert_department,
+    __merge_department, __rename_department, ROOT_DEPARTMENT,
+};
 mod form;
 pub mod func;
 mod message;
+mod portal;
 mod setting;
 pub use setting::{
     option::*, CustomFields, Field, STATIC_CUSTOM_BOX_OPTIONS, STATIC_CUSTOM_FIELDS,
@@ -17,4 +21,5 @@ pub fn pages_router() -> Router {
         .merge(message::message_router())
         .merge(func::func_router())
         .merge(user::user_router())
+        .merge(portal::portal_router())
 }
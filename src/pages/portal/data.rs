@@ -0,0 +1,143 @@
+use axum::{extract::Path, Json};
+use mysql::prelude::Queryable;
+use mysql_common::prelude::FromRow;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::AuthCustomer;
+use crate::{
+    database::get_db,
+    libs::{dser::deser_yyyy_mm_dd_hh_mm_ss, gen_id, money::Money, TimeFormat, TIME},
+    log,
+    pages::func::{query_customer_order, query_customer_orders},
+    Response, ResponseResult,
+};
+
+pub async fn query_info(customer: AuthCustomer) -> ResponseResult {
+    Ok(Response::ok(json!(customer)))
+}
+
+/// 客户的所有订单，包括产品、分期和发票
+pub async fn query_orders(customer: AuthCustomer) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let orders = query_customer_orders(&mut conn, &customer.id)?;
+    log!("{customer} 查询到{}个订单", orders.len());
+    Ok(Response::ok(json!(orders)))
+}
+
+pub async fn query_order(customer: AuthCustomer, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let order = query_customer_order(&mut conn, &customer.id, &id)?;
+    Ok(Response::ok(json!(order)))
+}
+
+#[derive(Serialize, FromRow)]
+struct InvoiceData {
+    order_id: String,
+    order_number: String,
+    number: String,
+    title: String,
+    deadline: Option<String>,
+    description: Option<String>,
+}
+
+pub async fn query_invoices(customer: AuthCustomer) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let list: Vec<InvoiceData> = conn.exec(
+        "SELECT i.order_id, o.number as order_number, i.number, i.title, i.deadline, i.description
+        FROM invoice i JOIN order_data o ON o.id = i.order_id
+        WHERE o.customer = ? AND o.invoice_required = 1
+        ORDER BY o.create_time DESC",
+        (&customer.id,),
+    )?;
+    Ok(Response::ok(json!(list)))
+}
+
+#[derive(Serialize, FromRow)]
+struct InstalmentData {
+    order_id: String,
+    order_number: String,
+    inv_index: i32,
+    interest: Money,
+    original_amount: Money,
+    date: String,
+    finish: i32,
+}
+
+/// 所有订单的分期，按还款日期排序
+pub async fn query_instalments(customer: AuthCustomer) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let list: Vec<InstalmentData> = conn.exec(
+        "SELECT i.order_id, o.number as order_number, i.inv_index, i.interest,
+            i.original_amount, i.date, i.finish
+        FROM order_instalment i JOIN order_data o ON o.id = i.order_id
+        WHERE o.customer = ?
+        ORDER BY i.date, o.number, i.inv_index",
+        (&customer.id,),
+    )?;
+    Ok(Response::ok(json!(list)))
+}
+
+#[derive(Serialize, FromRow)]
+struct AppointmentData {
+    id: String,
+    salesman: Option<String>,
+    salesman_name: Option<String>,
+    appointment: String,
+    finish_time: Option<String>,
+    theme: Option<String>,
+    content: Option<String>,
+}
+
+pub async fn query_appointments(customer: AuthCustomer) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let list: Vec<AppointmentData> = conn.exec(
+        "SELECT app.id, app.salesman, s.name as salesman_name, app.appointment,
+            app.finish_time, app.theme, app.content
+        FROM appointment app LEFT JOIN user s ON s.id = app.salesman
+        WHERE app.customer = ?
+        ORDER BY app.appointment DESC",
+        (&customer.id,),
+    )?;
+    Ok(Response::ok(json!(list)))
+}
+
+#[derive(Deserialize)]
+struct BookParams {
+    #[serde(deserialize_with = "deser_yyyy_mm_dd_hh_mm_ss")]
+    appointment: String,
+    theme: String,
+    content: String,
+}
+
+/// 客户预约拜访，由客户的业务员负责，申请人为客户自己
+pub async fn book_appointment(customer: AuthCustomer, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let params: BookParams = serde_json::from_value(value)?;
+    let time = TIME::now()?;
+    if params.appointment <= time.format(TimeFormat::YYYYMMDD_HHMMSS) {
+        return Err(Response::invalid_value("预约时间必须晚于当前时间"));
+    }
+    let salesman: Option<Option<String>> = conn.exec_first(
+        "SELECT salesman FROM extra_customer_data WHERE id = ? LIMIT 1",
+        (&customer.id,),
+    )?;
+    let salesman = op::some!(salesman.flatten(); ret Err(Response::dissatisfy("暂时没有负责的业务员，无法预约")));
+    let id = gen_id(&time, &rand::random::<i32>().to_string());
+    conn.exec_drop(
+        "INSERT INTO appointment
+        (id, customer, applicant, salesman, appointment, finish_time, theme, content)
+        VALUES (?, ?, ?, ?, ?, NULL, ?, ?)",
+        (
+            &id,
+            &customer.id,
+            &customer.id,
+            &salesman,
+            &params.appointment,
+            &params.theme,
+            &params.content,
+        ),
+    )?;
+    log!("{customer} 预约了拜访 {}", params.appointment);
+    Ok(Response::ok(json!(id)))
+}
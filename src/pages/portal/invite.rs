@@ -0,0 +1,175 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path},
+    http::HeaderMap,
+    Json,
+};
+use mysql::{params, prelude::Queryable, PooledConn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    commit_or_rollback,
+    database::get_db,
+    libs::{
+        headers::ClientInfo,
+        password::{check_password_policy, hash_password, verify_password},
+        TimeFormat, TIME,
+    },
+    log,
+    pages::{
+        account::guard::{self, AccountType},
+        func::customer::index::check_user_customer,
+    },
+    perm::auth::{AuthUser, CustomerPerm, Scope},
+    token::revoke_sessions,
+    Response, ResponseResult,
+};
+
+/// 邀请码有效期，单位秒
+const INVITE_LIFETIME: i64 = 7 * 24 * 60 * 60;
+
+#[derive(Deserialize)]
+struct InviteParams {
+    smartphone: String,
+}
+
+/// 只有客户的业务员可以管理客户的登录账号
+async fn ver_invite_perm(
+    conn: &mut PooledConn,
+    user: &AuthUser,
+    customer: &str,
+) -> Result<(), Response> {
+    check_user_customer(&user.id, customer, conn)?;
+    user.can(CustomerPerm::UpdateCustomerData, Scope::Any)
+        .await?;
+    Ok(())
+}
+
+fn customer_by_phone(conn: &mut PooledConn, smartphone: &str) -> Result<String, Response> {
+    Ok(op::some!(
+        conn.exec_first("SELECT id FROM customer WHERE smartphone = ? LIMIT 1", (smartphone,))?;
+        ret Err(Response::not_exist("该手机号没有对应的客户"))
+    ))
+}
+
+/// 邀请客户开通账号，返回邀请码，由业务员发送给客户。
+/// 已开通的账号不能重复邀请，未激活的邀请会被新的邀请码替换
+pub async fn invite_customer(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let params: InviteParams = serde_json::from_value(value)?;
+    let id = customer_by_phone(&mut conn, &params.smartphone)?;
+    log!("{user} 请求邀请客户`{id}`开通账号");
+    if let Err(e) = ver_invite_perm(&mut conn, &user, &id).await {
+        log!("{user} 邀请客户`{id}`失败，原因权限不足");
+        return Err(e);
+    }
+    let activated: Option<Option<String>> = conn.exec_first(
+        "SELECT password_hash FROM customer_login WHERE id = ? LIMIT 1",
+        (&id,),
+    )?;
+    if activated.flatten().is_some() {
+        return Err(Response::already_exist("该客户已经开通账号"));
+    }
+    let code = format!("{:08}", rand::random::<u32>() % 100_000_000);
+    let time = TIME::now()?;
+    let expire = (time.naos() / 1_000_000_000) as i64 + INVITE_LIFETIME;
+    conn.exec_drop(
+        "INSERT INTO customer_login (id, password_hash, invite_code, invite_expire, inviter, create_time)
+        VALUES (:id, NULL, :code, :expire, :inviter, :time)
+        ON DUPLICATE KEY UPDATE invite_code = :code, invite_expire = :expire, inviter = :inviter",
+        params! {
+            "id" => &id,
+            "code" => hash_password(&code)?,
+            "expire" => expire,
+            "inviter" => &user.id,
+            "time" => time.format(TimeFormat::YYYYMMDD_HHMMSS)
+        },
+    )?;
+    log!("{user} 成功邀请客户`{id}`开通账号");
+    Ok(Response::ok(json!({
+        "code": code,
+        "expire": expire
+    })))
+}
+
+/// 关闭客户的账号，注销客户所有的登录
+pub async fn revoke_customer(user: AuthUser, Path(id): Path<String>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    log!("{user} 请求关闭客户`{id}`的账号");
+    if let Err(e) = ver_invite_perm(&mut conn, &user, &id).await {
+        log!("{user} 关闭客户`{id}`的账号失败，原因权限不足");
+        return Err(e);
+    }
+    commit_or_rollback!(__revoke_customer, &mut conn, &id)?;
+    log!("{user} 成功关闭客户`{id}`的账号");
+    Ok(Response::empty())
+}
+
+fn __revoke_customer(conn: &mut PooledConn, id: &str) -> Result<(), Response> {
+    conn.exec_drop("DELETE FROM customer_login WHERE id = ? LIMIT 1", (id,))?;
+    revoke_sessions(conn, false, id)?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ActivateParams {
+    smartphone: String,
+    code: String,
+    password: String,
+}
+
+/// 客户使用邀请码设置密码，失败次数与登录共用限制
+pub async fn activate_customer(
+    headers: HeaderMap,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    Json(value): Json<Value>,
+) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let client = ClientInfo::new(&headers, addr.map(|a| a.0));
+    let params: ActivateParams = serde_json::from_value(value)?;
+    guard::begin_attempt(&mut conn)?;
+    let result = __activate_customer(&mut conn, &params, &client);
+    guard::finish_attempt(&mut conn, &result)?;
+    result
+}
+
+fn __activate_customer(
+    conn: &mut PooledConn,
+    params: &ActivateParams,
+    client: &ClientInfo,
+) -> ResponseResult {
+    let ty = AccountType::Customer;
+    let now = guard::now_secs()?;
+    if let Some(retry_after) = guard::check_login(conn, ty, &params.smartphone, &client.ip, now)? {
+        return Err(Response::too_many_attempts(retry_after));
+    }
+    let invite: Option<(String, Option<String>, Option<i64>)> = conn.exec_first(
+        "SELECT cl.id, cl.invite_code, cl.invite_expire FROM customer_login cl
+        JOIN customer c ON c.id = cl.id
+        WHERE c.smartphone = ? AND cl.password_hash IS NULL LIMIT 1",
+        (&params.smartphone,),
+    )?;
+    let (id, hash) = match invite {
+        Some((id, Some(hash), Some(expire))) if expire > now => (id, hash),
+        _ => {
+            guard::record_failure(conn, ty, &params.smartphone, &client.ip, now)?;
+            return Err(Response::not_exist("邀请不存在或已过期"));
+        }
+    };
+    if !verify_password(&params.code, &hash) {
+        guard::record_failure(conn, ty, &params.smartphone, &client.ip, now)?;
+        log!("客户`{id}`激活账号失败，邀请码错误");
+        return Err(Response::invalid_value("邀请码错误"));
+    }
+    check_password_policy(&params.password, &params.smartphone)?;
+    conn.exec_drop(
+        "UPDATE customer_login SET password_hash = ?, invite_code = NULL, invite_expire = NULL
+        WHERE id = ? LIMIT 1",
+        (hash_password(&params.password)?, &id),
+    )?;
+    guard::record_success(conn, ty, &params.smartphone)?;
+    log!("客户`{id}`成功激活账号");
+    Ok(Response::empty())
+}
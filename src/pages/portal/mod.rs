@@ -0,0 +1,75 @@
+//! 客户端
+//!
+//! 员工通过手机号邀请客户，客户使用邀请码设置密码后登录(`/customer/login`)。
+//! 客户的token中`sub`为false，只能访问[`AuthCustomer`]保护的接口，
+//! 员工的接口要求`sub`为true，两者互不相通
+mod data;
+mod invite;
+
+use std::fmt;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::request::Parts,
+    routing::{delete, get, post},
+    Router,
+};
+use mysql::prelude::Queryable;
+use serde::Serialize;
+
+use crate::{database::get_db, libs::headers::Bearer, parse_jwt_macro, Response};
+
+pub fn portal_router() -> Router {
+    Router::new()
+        .route("/customer/portal/invite", post(invite::invite_customer))
+        .route("/customer/portal/:id", delete(invite::revoke_customer))
+        .route("/customer/portal/activate", post(invite::activate_customer))
+        .route("/portal/info", get(data::query_info))
+        .route("/portal/orders", get(data::query_orders))
+        .route("/portal/order/:id", get(data::query_order))
+        .route("/portal/invoices", get(data::query_invoices))
+        .route("/portal/instalments", get(data::query_instalments))
+        .route(
+            "/portal/appointments",
+            get(data::query_appointments).post(data::book_appointment),
+        )
+}
+
+/// 已登录的客户，从请求头的token中解析，只接受客户的token
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthCustomer {
+    pub id: String,
+    pub name: String,
+    pub company: String,
+    pub smartphone: String,
+}
+
+impl fmt::Display for AuthCustomer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "客户{}({})", self.name, self.id)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthCustomer {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let bearer = Bearer::try_from(&parts.headers).map_err(Response::token_error)?;
+        let mut conn = get_db().await?;
+        let id = parse_jwt_macro!(&bearer, &mut conn => false);
+        let row: Option<(String, String, String)> = conn.exec_first(
+            "SELECT name, company, smartphone FROM customer WHERE id = ? LIMIT 1",
+            (&id,),
+        )?;
+        let (name, company, smartphone) =
+            op::some!(row; ret Err(Response::token_error("Invalid Token")));
+        Ok(Self {
+            id,
+            name,
+            company,
+            smartphone,
+        })
+    }
+}
@@ -1,8 +1,8 @@
-use axum::{http::HeaderMap, Json, Router};
+use axum::{Json, Router};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
-use crate::{bearer, database::get_db, pages::account::get_user, parse_jwt_macro, Response, ResponseResult};
+use crate::{perm::auth::AuthUser, Response, ResponseResult};
 
 pub fn custom_router() -> Router {
     Router::new()
@@ -22,11 +22,6 @@ pub struct CustomField {
 }
 
 
-async fn add_custom_field(header: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let uid = parse_jwt_macro!(&bearer, &mut conn => true);
-    let user = get_user(&uid, &mut conn).await?;
+async fn add_custom_field(_user: AuthUser, Json(_value): Json<Value>) -> ResponseResult {
     Ok(Response::ok(json!("")))
 }
@@ -1,13 +1,13 @@
 use std::collections::HashMap;
 
-use axum::{extract::Path, http::HeaderMap, Json};
+use axum::{extract::Path, Json};
 use mysql::{prelude::Queryable, PooledConn};
 use mysql_common::prelude::FromRow;
 use serde_json::{json, Value};
 
 use crate::{
-    bearer, commit_or_rollback, database::{get_db, DB}, libs::time::{TimeFormat, TIME}, pages::account::get_user, 
-    parse_jwt_macro, perm::action::OtherGroup, verify_perms, Response, ResponseResult
+    commit_or_rollback, database::get_db, libs::time::{TimeFormat, TIME},
+    perm::auth::{AuthUser, OtherPerm, Scope}, Response, ResponseResult
 };
 
 #[derive(serde::Deserialize, Debug)]
@@ -22,15 +22,9 @@ pub struct CustomInfos {
     new_value: String,
 }
 
-async fn verify_perm<'err>(headers: HeaderMap, conn: &mut DB<'err>) -> Result<String, Response> {
-    let bearer = bearer!(&headers);
-    let id = parse_jwt_macro!(&bearer, conn => true);
-    let user = get_user(&id, conn).await?;
-    if verify_perms!(&user.role, OtherGroup::NAME, OtherGroup::CUSTOM_FIELD) {
-        Ok(id)
-    } else {
-        Err(Response::permission_denied())
-    }
+async fn verify_perm(user: &AuthUser) -> Result<String, Response> {
+    user.can(OtherPerm::CustomField, Scope::Any).await?;
+    Ok(user.id.clone())
 }
 
 #[derive(FromRow, serde::Serialize, Clone, Debug)]
@@ -158,10 +152,9 @@ impl CustomFields {
     }
 }
 
-pub async fn insert_custom_field(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = verify_perm(headers, &mut conn).await?;
+pub async fn insert_custom_field(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let id = verify_perm(&user).await?;
     let data: CustomInfos = serde_json::from_value(value)?;
     if data.ty > 1 {
         return Err(Response::invalid_value("ty 大于 1"));
@@ -183,28 +176,20 @@ fn _insert_field(conn: &mut PooledConn, param: &CustomInfos) -> Result<(), Respo
             return Ok(());
         }
     }
-    conn.query_drop(format!(
-        "INSERT INTO custom_fields (ty, display, value, create_time) VALUES ({}, '{}', '{}', '{}')",
-        param.ty, param.display, param.value, create_time
-    ))?;
+    conn.exec_drop(
+        "INSERT INTO custom_fields (ty, display, value, create_time) VALUES (?, ?, ?, ?)",
+        (param.ty, &param.display, &param.value, &create_time),
+    )?;
     let id: Vec<String> = if param.ty == 0 {
         conn.query_map("SELECT id FROM customer", |s| s)?
     } else {
         conn.query_map("SELECT id FROM product", |s| s)?
     };
-    if !id.is_empty() {
-        let mut values: String = id.iter().fold(String::new(), |output, id| {
-            format!(
-                "{output} ({}, {},'{}' ,'{}', ''),",
-                param.ty, param.display, param.value, id
-            )
-        });
-        values.pop();
-        conn.query_drop(format!(
-            "INSERT INTO custom_field_data (fields, ty, display, id, value) VALUES {}",
-            values
-        ))?;
-    }
+    conn.exec_batch(
+        "INSERT INTO custom_field_data (fields, ty, display, id, value) VALUES (?, ?, ?, ?, '')",
+        id.iter()
+            .map(|id| (param.ty, &param.display, &param.value, id)),
+    )?;
     unsafe {
         STATIC_CUSTOM_FIELDS.push(
             param.ty,
@@ -216,10 +201,9 @@ fn _insert_field(conn: &mut PooledConn, param: &CustomInfos) -> Result<(), Respo
     Ok(())
 }
 
-pub async fn insert_box_option(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = verify_perm(headers, &mut conn).await?;
+pub async fn insert_box_option(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let id = verify_perm(&user).await?;
     let data: CustomInfos = serde_json::from_value(value)?;
     if data.ty > 1 {
         return Err(Response::invalid_value("ty 大于 1"));
@@ -236,19 +220,18 @@ pub async fn insert_box_option(headers: HeaderMap, Json(value): Json<Value>) ->
             return Ok(Response::empty());
         }
 
-        conn.query_drop(format!(
-        "INSERT INTO custom_field_option (ty, display, value, create_time) VALUES ({}, '{}', '{}', '{create_time}')",
-        data.ty, data.display, data.value
-    ))?;
+        conn.exec_drop(
+            "INSERT INTO custom_field_option (ty, display, value, create_time) VALUES (?, ?, ?, ?)",
+            (data.ty, &data.display, &data.value, &create_time),
+        )?;
         STATIC_CUSTOM_BOX_OPTIONS.push(data.ty, data.display, data.value, create_time);
     }
     Ok(Response::empty())
 }
 
-pub async fn update_custom_field(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = verify_perm(headers, &mut conn).await?;
+pub async fn update_custom_field(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let id = verify_perm(&user).await?;
     let data: CustomInfos = serde_json::from_value(value)?;
     if !matches!(data.display.as_str(), "0" | "1" | "2") {
         return Err(Response::invalid_value(format!(
@@ -267,19 +250,19 @@ pub async fn update_custom_field(headers: HeaderMap, Json(value): Json<Value>) -
 
 fn _update_custom_field(conn: &mut PooledConn, param: &CustomInfos) -> Result<(), Response> {
     // 更新字段
-    conn.query_drop(format!(
-        "UPDATE custom_fields SET value = '{}' WHERE value = '{}' AND ty = {} AND display = '{}'",
-        param.new_value, param.old_value, param.ty, param.display
-    ))?;
-    conn.query_drop(format!(
-        "UPDATE custom_field_data SET display = '{}' WHERE display = '{}' AND fields = {} AND ty = {}",
-        param.new_value, param.old_value, param.ty, param.display 
-    ))?;
+    conn.exec_drop(
+        "UPDATE custom_fields SET value = ? WHERE value = ? AND ty = ? AND display = ?",
+        (&param.new_value, &param.old_value, param.ty, &param.display),
+    )?;
+    conn.exec_drop(
+        "UPDATE custom_field_data SET display = ? WHERE display = ? AND fields = ? AND ty = ?",
+        (&param.new_value, &param.old_value, param.ty, &param.display),
+    )?;
     if param.display.eq("2") {
-        conn.query_drop(format!(
-            "UPDATE custom_field_option SET display = '{}' WHERE display = '{}' AND ty = {}",
-            param.new_value, param.old_value, param.ty
-        ))?;
+        conn.exec_drop(
+            "UPDATE custom_field_option SET display = ? WHERE display = ? AND ty = ?",
+            (&param.new_value, &param.old_value, param.ty),
+        )?;
         unsafe {
             STATIC_CUSTOM_BOX_OPTIONS.update_display(
                 param.ty,
@@ -300,10 +283,9 @@ fn _update_custom_field(conn: &mut PooledConn, param: &CustomInfos) -> Result<()
     Ok(())
 }
 
-pub async fn update_box_option(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = verify_perm(headers, &mut conn).await?;
+pub async fn update_box_option(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let id = verify_perm(&user).await?;
 
     let data: CustomInfos = serde_json::from_value(value)?;
     if data.new_value.is_empty() {
@@ -311,20 +293,19 @@ pub async fn update_box_option(headers: HeaderMap, Json(value): Json<Value>) ->
     }
 
     // let table = CUSTOM_BOX_FIELDS[data.ty];
-    conn.query_drop(format!(
-        "UPDATE custom_field_option SET value = '{}' WHERE value = '{}' AND display = '{}' AND ty = {}",
-        data.new_value, data.old_value, data.display, data.ty
-    ))?;
+    conn.exec_drop(
+        "UPDATE custom_field_option SET value = ? WHERE value = ? AND display = ? AND ty = ?",
+        (&data.new_value, &data.old_value, &data.display, data.ty),
+    )?;
     unsafe {
         STATIC_CUSTOM_BOX_OPTIONS.update(data.ty, &data.display, &data.old_value, data.new_value);
     }
     Ok(Response::empty())
 }
 
-pub async fn delete_custom_field(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-        let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = verify_perm(headers, &mut conn).await?;
+pub async fn delete_custom_field(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let id = verify_perm(&user).await?;
     let data: CustomInfos = serde_json::from_value(value)?;
     if data.ty > 1 {
         return Err(Response::invalid_value("ty 大于 1"));
@@ -339,21 +320,21 @@ fn _delete_custom_field(conn: &mut PooledConn, param: &CustomInfos) -> Result<()
         return Err(Response::invalid_value("display非法"));
     }
     // 删除字段
-    conn.query_drop(format!(
-        "DELETE FROM custom_fields WHERE value = '{}' AND ty = {} AND display = '{}'",
-        param.value, param.ty, param.display
-    ))?;
+    conn.exec_drop(
+        "DELETE FROM custom_fields WHERE value = ? AND ty = ? AND display = ?",
+        (&param.value, param.ty, &param.display),
+    )?;
     // 删除客户或产品对应的字段值
-    conn.query_drop(format!(
-        "DELETE FROM custom_field_data WHERE display = '{}' AND ty = {} AND fields={}",
-        param.value, param.display, param.ty
-    ))?;
+    conn.exec_drop(
+        "DELETE FROM custom_field_data WHERE display = ? AND ty = ? AND fields = ?",
+        (&param.value, &param.display, param.ty),
+    )?;
     // 删除下拉字段选项对应的字段
     if param.display.eq("2") {
-        conn.query_drop(format!(
-            "DELETE FROM custom_field_option WHERE display = '{}' AND ty = {}",
-            param.display, param.ty
-        ))?;
+        conn.exec_drop(
+            "DELETE FROM custom_field_option WHERE display = ? AND ty = ?",
+            (&param.display, param.ty),
+        )?;
         unsafe {
             STATIC_CUSTOM_BOX_OPTIONS.remove_display(param.ty, &param.value);
         }
@@ -364,16 +345,15 @@ fn _delete_custom_field(conn: &mut PooledConn, param: &CustomInfos) -> Result<()
     Ok(())
 }
 
-pub async fn delete_box_option(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let id = verify_perm(headers, &mut conn).await?;
+pub async fn delete_box_option(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let mut conn = get_db().await?;
+    let id = verify_perm(&user).await?;
     let data: CustomInfos = serde_json::from_value(value)?;
     // let table = CUSTOM_BOX_FIELDS[data.ty];
-    conn.query_drop(format!(
-        "DELETE FROM custom_field_option WHERE value = '{}' AND display = '{}' AND ty = {}",
-        data.value, data.display, data.ty
-    ))?;
+    conn.exec_drop(
+        "DELETE FROM custom_field_option WHERE value = ? AND display = ? AND ty = ?",
+        (&data.value, &data.display, data.ty),
+    )?;
     unsafe {
         STATIC_CUSTOM_BOX_OPTIONS.remove(data.ty, &data.display, &data.value);
     }
@@ -414,8 +394,7 @@ pub async fn get_custom_info() -> ResponseResult {
 }
 
 pub async fn query_custom_fields(Path((ty, id)): Path<(u8, String)>) -> ResponseResult {
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
+    let mut conn = get_db().await?;
     let data = crate::pages::func::get_custom_fields(&mut conn, &id, ty)?;
     Ok(Response::ok(json!(data)))
 }
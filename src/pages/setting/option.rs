@@ -1,14 +1,18 @@
 use std::collections::HashMap;
 
-use axum::{extract::Path, http::HeaderMap, Json};
+use axum::{extract::Path, Json};
 use mysql::{prelude::Queryable, PooledConn};
 use serde_json::{json, Value};
 
 use crate::{
-    bearer, commit_or_rollback,
-    database::DBC,
+    commit_or_rollback,
+    database::{get_db, query::Query},
     libs::time::{TimeFormat, TIME},
-    parse_jwt_macro,
+    pages::{
+        __insert_department, __merge_department, __rename_department,
+        func::{count_open_purchases, store::stock},
+        ROOT_DEPARTMENT,
+    },
     response::Response,
     ResponseResult,
 };
@@ -22,6 +26,11 @@ pub fn check_drop_down_box(key: &str, value: &str) -> Option<bool> {
     }
 }
 
+/// 其他模块修改了`drop_down_box`表后重新加载下拉框
+pub fn reload_drop_down_box(conn: &mut PooledConn) -> mysql::Result<()> {
+    unsafe { DROP_DOWN_BOX.init(conn) }
+}
+
 pub const DROP_DOWN_BOX_ALL: [&str; 17] = [
     "customer_type",
     "customer_status",
@@ -63,20 +72,13 @@ struct OptionValue {
     delete_value: String,
     next_value: String,
 }
-use crate::verify_perms;
-
-use crate::perm::action::OtherGroup;
+use crate::perm::auth::{AuthUser, OtherPerm, Scope};
 macro_rules! parse_option {
-    ($headers:expr, $value:expr, $begin:expr) => {
+    ($user:expr, $value:expr, $begin:expr) => {
         {
-            let bearer = bearer!(&$headers);
-            let mut conn = DBC.lock().await;
-            let id = parse_jwt_macro!(&bearer, &mut conn => true);
-
-            let role: String = op::some!(conn.query_first(format!("SELECT role FROM user WHERE id = '{id}'"))?; ret Err(Response::not_exist("用户不存在")));
-            if !verify_perms!(&role, OtherGroup::NAME, OtherGroup::DROP_DOWN_BOX) {
-                return Err(Response::permission_denied())
-            }
+            $user.can(OtherPerm::DropDownBox, Scope::Any).await?;
+            let id = $user.id.clone();
+            let mut conn = get_db().await?;
 
             if $begin {
                 conn.query_drop("BEGIN")?;
@@ -87,8 +89,8 @@ macro_rules! parse_option {
     };
 }
 
-pub async fn insert_options(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let (id, mut conn, info) = parse_option!(headers, value, false);
+pub async fn insert_options(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let (id, mut conn, info) = parse_option!(user, value, false);
     let time = TIME::now()?;
     if info.info.value.is_empty() {
         return Err(Response::invalid_value("value不能为空字符串"));
@@ -112,17 +114,19 @@ pub async fn insert_options(headers: HeaderMap, Json(value): Json<Value>) -> Res
 
         value = level.to_string();
     }
-    conn.query_drop(format!(
-        "INSERT IGNORE INTO drop_down_box (name, value, create_time) VALUES ('{}', '{}', '{}')",
-        name,
-        value,
-        time.format(TimeFormat::YYYYMMDD_HHMMSS)
-    ))?;
+    conn.exec_drop(
+        "INSERT IGNORE INTO drop_down_box (name, value, create_time) VALUES (?, ?, ?)",
+        (name, &value, time.format(TimeFormat::YYYYMMDD_HHMMSS)),
+    )?;
+    if name.eq("department") {
+        __insert_department(&mut conn, &value, Some(ROOT_DEPARTMENT), None, &time)?;
+    }
     unsafe {
         if let Some(k) = level_key {
-            conn.query_drop(format!(
-                "DELETE FROM drop_down_box WHERE name='customer_level' AND value='{k}' LIMIT 1"
-            ))?;
+            conn.exec_drop(
+                "DELETE FROM drop_down_box WHERE name='customer_level' AND value = ? LIMIT 1",
+                (k,),
+            )?;
         }
         DROP_DOWN_BOX.init(&mut conn)?;
     }
@@ -262,8 +266,8 @@ fn split_level(level: &str) -> Level {
     }
 }
 
-pub async fn update_option_value(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let (id, mut conn, info) = parse_option!(headers, value, true);
+pub async fn update_option_value(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let (id, mut conn, info) = parse_option!(user, value, true);
     // conn.query_drop(Database::SET_FOREIGN_KEY_0)?;
     commit_or_rollback!(_update, &mut conn, &info)?;
 
@@ -286,16 +290,10 @@ fn _update(conn: &mut PooledConn, param: &ReceiveOptionInfo) -> Result<(), Respo
             if param.info.old_value.eq("总经办") || param.info.new_value.eq("总经办") {
                 return Err(Response::invalid_value("总经办这个部门不允许被修改"));
             }
-            conn.query_drop(format!(
-                "UPDATE user SET department = '{}' WHERE department = '{}'",
-                param.info.new_value, param.info.old_value
-            ))?;
+            __rename_department(conn, &param.info.old_value, &param.info.new_value)?;
         }
         "customer_level" => {
-            let old_level = Level::from(param.info.old_value.as_str());
-            let mut new_level = split_level(&param.info.new_value);
-            new_level.level = old_level.level;
-            new_value = new_level.to_string();
+            new_value = rename_level(&param.info.old_value, &param.info.new_value);
         }
         "storehouse" => {
             update_storehouse(conn, &param.info.old_value, &param.info.new_value)?;
@@ -303,10 +301,7 @@ fn _update(conn: &mut PooledConn, param: &ReceiveOptionInfo) -> Result<(), Respo
         _ => {}
     }
 
-    conn.query_drop(format!(
-        "UPDATE drop_down_box SET value = '{}' WHERE value = '{}' AND name = '{}' LIMIT 1",
-        new_value, param.info.old_value, name
-    ))?;
+    update_option_query(name, &param.info.old_value, &new_value).execute(conn)?;
     unsafe {
         DROP_DOWN_BOX.init(conn)?;
         println!("{:#?}", DROP_DOWN_BOX);
@@ -314,21 +309,63 @@ fn _update(conn: &mut PooledConn, param: &ReceiveOptionInfo) -> Result<(), Respo
     Ok(())
 }
 
+/// 客户等级改名时保留原来的等级字母
+fn rename_level(old: &str, new: &str) -> String {
+    let mut new_level = split_level(new);
+    new_level.level = Level::from(old).level;
+    new_level.to_string()
+}
+
+fn update_option_query(name: &str, old: &str, new: &str) -> Query {
+    Query::new("UPDATE drop_down_box SET value = ")
+        .bind(new)
+        .push(" WHERE value = ")
+        .bind(old)
+        .push(" AND name = ")
+        .bind(name)
+        .push(" LIMIT 1")
+}
+
+/// 库房改名，库存流水、预留、调拨单和采购单一起修改，保证可以从流水重建库存
 fn update_storehouse(conn: &mut PooledConn, old: &str, new: &str) -> mysql::Result<()> {
     conn.exec_drop(
         "update product_store set storehouse = ? where storehouse = ?",
         (new, old),
     )?;
+    conn.exec_drop(
+        "update stock_movement set storehouse = ? where storehouse = ?",
+        (new, old),
+    )?;
+    conn.exec_drop(
+        "update product_reservation set storehouse = ? where storehouse = ?",
+        (new, old),
+    )?;
+    conn.exec_drop(
+        "update stock_transfer set source = ? where source = ?",
+        (new, old),
+    )?;
+    conn.exec_drop(
+        "update stock_transfer set destination = ? where destination = ?",
+        (new, old),
+    )?;
     conn.exec_drop(
         "update order_data set shipped_storehouse = ? where shipped_storehouse = ?",
         (new, old),
     )?;
+    conn.exec_drop(
+        "update purchase_order set storehouse = ? where storehouse = ?",
+        (new, old),
+    )?;
+    conn.exec_drop(
+        "update purchase_receipt set storehouse = ? where storehouse = ?",
+        (new, old),
+    )?;
 
     Ok(())
 }
 
-pub async fn delete_option_value(headers: HeaderMap, Json(value): Json<Value>) -> ResponseResult {
-    let (id, mut conn, info) = parse_option!(headers, value, true);
+pub async fn delete_option_value(user: AuthUser, Json(value): Json<Value>) -> ResponseResult {
+    let (id, mut conn, info) = parse_option!(user, value, true);
     let name = *get_drop_down_box!(info.ty);
     if name.eq("department") {
         if info.info.delete_value.eq("总经办") {
@@ -340,7 +377,7 @@ pub async fn delete_option_value(headers: HeaderMap, Json(value): Json<Value>) -
             }
         }
     }
-    commit_or_rollback!(_delete, &mut conn, (&info, name))?;
+    commit_or_rollback!(_delete, &mut conn, (&info, name, &id))?;
     unsafe {
         DROP_DOWN_BOX.init(&mut conn)?;
         println!("{:#?}", DROP_DOWN_BOX);
@@ -349,27 +386,38 @@ pub async fn delete_option_value(headers: HeaderMap, Json(value): Json<Value>) -
 }
 fn _delete(
     conn: &mut PooledConn,
-    (param, name): (&ReceiveOptionInfo, &str),
+    (param, name, operator): (&ReceiveOptionInfo, &str, &str),
 ) -> Result<(), Response> {
     match name {
         "department" => {
-            conn.query_drop(format!(
-                "UPDATE user SET department = '{}' WHERE department = '{}'",
-                param.info.next_value, param.info.delete_value
-            ))?;
+            __merge_department(conn, &param.info.delete_value, &param.info.next_value)?;
         }
         "storehouse" => {
+            let purchases = count_open_purchases(conn, &param.info.delete_value)?;
+            if purchases > 0 {
+                return Err(Response::dissatisfy(format!(
+                    "还有{purchases}个采购单未全部到货到该库房，不能删除"
+                )));
+            }
+            // 库存清零记入流水，预留一起释放
+            let products: Vec<String> = conn.exec(
+                "select product from product_store where storehouse = ?",
+                (&param.info.delete_value,),
+            )?;
+            for product in products {
+                stock::remove_store(conn, &product, &param.info.delete_value, operator)?;
+            }
             conn.exec_drop(
-                "delete from product_store where storehouse = ?",
+                "delete from product_reservation where storehouse = ?",
                 (&param.info.delete_value,),
             )?;
         }
         _ => {}
     }
-    conn.query_drop(format!(
-        "DELETE FROM drop_down_box WHERE name = '{}' AND value = '{}' LIMIT 1",
-        name, param.info.delete_value
-    ))?;
+    conn.exec_drop(
+        "DELETE FROM drop_down_box WHERE name = ? AND value = ? LIMIT 1",
+        (name, &param.info.delete_value),
+    )?;
 
     Ok(())
 }
@@ -399,3 +447,25 @@ pub async fn query_specific_info(Path(ty): Path<usize>) -> ResponseResult {
     }
     )))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{rename_level, update_option_query};
+    use crate::database::query::{assert_bound, PAYLOADS};
+
+    #[test]
+    fn update_binds_input() {
+        for payload in PAYLOADS {
+            let query = update_option_query("customer_type", payload, payload);
+            assert_eq!(
+                query.sql(),
+                "UPDATE drop_down_box SET value = ? WHERE value = ? AND name = ? LIMIT 1"
+            );
+            assert_bound(&query, payload);
+
+            let level = rename_level(&format!("B-{payload}"), &format!("A-{payload}"));
+            assert_eq!(level, format!("B-{}", payload.trim_end()));
+            assert_bound(&update_option_query("customer_level", payload, &level), &level);
+        }
+    }
+}
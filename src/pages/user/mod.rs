@@ -1,11 +1,17 @@
 use std::collections::HashMap;
 
-use axum::{extract::Path, http::HeaderMap, routing::post, Json, Router};
+use axum::{extract::Path, routing::post, Json, Router};
 use mysql::prelude::Queryable;
 use serde_json::json;
 
 use crate::{
-    bearer, database::get_db, libs::dser::deserialize_roles, parse_jwt_macro, Response, ResponseResult,
+    database::{
+        get_db,
+        query::{Cond, Query},
+    },
+    libs::dser::deserialize_roles,
+    perm::auth::AuthUser,
+    Response, ResponseResult,
 };
 
 use super::account::User;
@@ -17,10 +23,9 @@ pub fn user_router() -> Router {
 }
 
 async fn get_user_name(Path(id): Path<String>) -> ResponseResult {
-     let db = get_db().await?;
-    let mut conn = db.lock().await;
+    let mut conn = get_db().await?;
     let name: Option<String> =
-        conn.query_first(format!("SELECT name FROM user WHERE id = '{id}' LIMIT 1"))?;
+        conn.exec_first("SELECT name FROM user WHERE id = ? LIMIT 1", (&id,))?;
     Ok(Response::ok(json!(name)))
 }
 
@@ -31,24 +36,26 @@ struct LimitParams {
     roles: Vec<String>,
 }
 async fn query_limit_user(
-    header: HeaderMap,
+    _user: AuthUser,
     Json(value): Json<serde_json::Value>,
 ) -> ResponseResult {
-    let bearer = bearer!(&header);
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let _uid = parse_jwt_macro!(&bearer, &mut conn => true);
+    let mut conn = get_db().await?;
     let data: LimitParams = serde_json::from_value(value)?;
     let filter = if data.customer.is_empty() {
-        String::new()
+        Cond::any()
     } else {
-        format!("and exists (select 1 from extra_customer_data ex where ex.id='{}' and ex.salesman=u.id)", data.customer)
+        Cond::new(
+            "exists (select 1 from extra_customer_data ex where ex.id = ? and ex.salesman=u.id)",
+            vec![data.customer.into()],
+        )
     };
     // TODO 后面需要考虑共享情况
-    let users: Vec<User> = conn.query(format!(
+    let users: Vec<User> = Query::new(
         "select * from user u
-        where NOT EXISTS (SELECT 1 FROM leaver l WHERE l.id=u.id) {filter}"
-    ))?;
+        where NOT EXISTS (SELECT 1 FROM leaver l WHERE l.id=u.id) AND ",
+    )
+    .cond(&filter)
+    .fetch(&mut conn)?;
     let mut map: HashMap<String, Vec<User>> = HashMap::new();
     for u in users {
         if data.roles.is_empty() || data.roles.contains(&u.role) {
@@ -73,7 +73,7 @@ impl CustomerGroup {
     pub const ADD_APPOINT: &str = "add_appoint";
 }
 #[forbid(unused)]
-pub static STOREHOUSE: [&str; 8] = [
+pub static STOREHOUSE: [&str; 9] = [
     StorehouseGroup::ACTIVATION,
     StorehouseGroup::ADD_PRODUCT,
     StorehouseGroup::UPDATE_PRODUCT,
@@ -82,6 +82,7 @@ pub static STOREHOUSE: [&str; 8] = [
     StorehouseGroup::ADD_STOREHOUSE,
     StorehouseGroup::DELETE_STOREHOUSE,
     StorehouseGroup::UPDATE_STOREHOUSE,
+    StorehouseGroup::TRANSFER_STOCK,
 ];
 
 pub struct StorehouseGroup;
@@ -96,17 +97,31 @@ impl StorehouseGroup {
     pub const ADD_STOREHOUSE: &str = "add_storehouse";
     pub const DELETE_STOREHOUSE: &str = "delete_storehouse";
     pub const UPDATE_STOREHOUSE: &str = "update_storehouse";
+    /// 创建和处理库房之间的调拨单
+    pub const TRANSFER_STOCK: &str = "transfer_stock";
     // TODO:
 }
 
 #[forbid(unused)]
-pub static PURCHASE: [&str; 2] = [PurchaseGroup::ACTIVATION, PurchaseGroup::QUERY];
+pub static PURCHASE: [&str; 5] = [
+    PurchaseGroup::ACTIVATION,
+    PurchaseGroup::QUERY,
+    PurchaseGroup::MANAGE_ORDER,
+    PurchaseGroup::RECEIVE_GOODS,
+    PurchaseGroup::PAYMENT,
+];
 pub struct PurchaseGroup;
 
 impl PurchaseGroup {
     pub const NAME: &str = "purchase";
     pub const ACTIVATION: &str = "activation";
     pub const QUERY: &str = "query";
+    /// 创建、修改和下达采购单
+    pub const MANAGE_ORDER: &str = "manage_order";
+    /// 采购入库
+    pub const RECEIVE_GOODS: &str = "receive_goods";
+    /// 登记向供应商的付款
+    pub const PAYMENT: &str = "payment";
 }
 
 #[forbid(unused)]
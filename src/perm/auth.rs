@@ -0,0 +1,271 @@
+//! 请求的登录员工和权限检查
+//!
+//! 处理函数的参数中加入[`AuthUser`]即可完成token解析和员工查询，
+//! 权限用[`User::can`]检查，操作只能使用下面生成的枚举，不再手写权限组和操作的字符串
+use std::{fmt, ops::Deref, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+};
+use serde::Serialize;
+use serde_json::json;
+
+use super::{action::*, ROLES_GROUP_MAP};
+use crate::{
+    database::get_db,
+    libs::headers::Bearer,
+    pages::{get_user, User},
+    parse_jwt_macro,
+    token::parse_jwt,
+    Response,
+};
+
+/// 权限组中的一个操作
+pub trait Action: Copy + fmt::Debug {
+    /// 所属的权限组
+    const GROUP: &'static str;
+    fn name(self) -> &'static str;
+}
+
+macro_rules! actions {
+    ($($(#[$meta:meta])* $name:ident($group:ident) { $($variant:ident => $value:ident),+ $(,)? })+) => {
+        $(
+            $(#[$meta])*
+            #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+            pub enum $name {
+                $($variant),+
+            }
+            impl Action for $name {
+                const GROUP: &'static str = $group::NAME;
+                fn name(self) -> &'static str {
+                    match self {
+                        $(Self::$variant => $group::$value),+
+                    }
+                }
+            }
+        )+
+    };
+}
+
+actions! {
+    /// 职务管理权限组
+    RolePerm(RoleGroup) {
+        Create => CREATE,
+        Update => UPDATE,
+        Delete => DELETE,
+        ChangeRole => CHANGE_ROLE,
+    }
+    /// 账号权限组
+    AccountPerm(AccountGroup) {
+        Create => CREATE,
+        Delete => DELETE,
+    }
+    /// 客户管理权限组
+    CustomerPerm(CustomerGroup) {
+        Activation => ACTIVATION,
+        Query => QUERY,
+        EnterCustomerData => ENTER_CUSTOMER_DATA,
+        UpdateCustomerData => UPDATE_CUSTOMER_DATA,
+        DeleteCustomerData => DELETE_CUSTOMER_DATA,
+        QueryPubSea => QUERY_PUB_SEA,
+        TransferCustomer => TRANSFER_CUSTOMER,
+        ExportData => EXPORT_DATA,
+        ReleaseCustomer => RELEASE_CUSTOMER,
+        AddAppoint => ADD_APPOINT,
+    }
+    /// 库房权限组
+    StorehousePerm(StorehouseGroup) {
+        Activation => ACTIVATION,
+        AddProduct => ADD_PRODUCT,
+        UpdateProduct => UPDATE_PRODUCT,
+        DeleteProduct => DELETE_PRODUCT,
+        AdjustingProductInventory => ADJUSTING_PRODUCT_INVENTORY,
+        AddStorehouse => ADD_STOREHOUSE,
+        DeleteStorehouse => DELETE_STOREHOUSE,
+        UpdateStorehouse => UPDATE_STOREHOUSE,
+        TransferStock => TRANSFER_STOCK,
+    }
+    /// 采购权限组
+    PurchasePerm(PurchaseGroup) {
+        Activation => ACTIVATION,
+        Query => QUERY,
+        ManageOrder => MANAGE_ORDER,
+        ReceiveGoods => RECEIVE_GOODS,
+        Payment => PAYMENT,
+    }
+    /// 财务权限组
+    FinancePerm(FinanceGroup) {
+        Activation => ACTIVATION,
+        Query => QUERY,
+    }
+    /// 其他权限组
+    OtherPerm(OtherGroup) {
+        QuerySignIn => QUERY_SIGN_IN,
+        CustomField => CUSTOM_FIELD,
+        DropDownBox => DROP_DOWN_BOX,
+        SeaRule => SEA_RULE,
+        CompanyStaffData => COMPANY_STAFF_DATA,
+        QueryOrder => QUERY_ORDER,
+    }
+}
+
+/// 要求的数据范围
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope<'a> {
+    /// 只要求勾选了该操作
+    Any,
+    Department,
+    AllDepartment,
+    All,
+    /// 可以操作的职务，值为职务名称
+    Role(&'a str),
+}
+
+impl Scope<'_> {
+    fn value(&self) -> Option<&str> {
+        match self {
+            Self::Any => None,
+            Self::Department => Some("department"),
+            Self::AllDepartment => Some("all_department"),
+            Self::All => Some("all"),
+            Self::Role(role) => Some(role),
+        }
+    }
+}
+
+/// 权限不足的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeniedReason {
+    /// 该职务没有任何权限
+    NoPermission,
+    /// 没有勾选该操作
+    Action,
+    /// 勾选了该操作，但没有要求的数据范围
+    Scope,
+}
+
+/// 权限检查失败的详细信息
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Denied {
+    pub reason: DeniedReason,
+    pub group: &'static str,
+    pub action: &'static str,
+    pub scope: Option<String>,
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.reason {
+            DeniedReason::NoPermission => write!(f, "该职务没有任何权限"),
+            DeniedReason::Action => write!(f, "没有`{}.{}`的权限", self.group, self.action),
+            DeniedReason::Scope => write!(
+                f,
+                "`{}.{}`的数据范围不包括`{}`",
+                self.group,
+                self.action,
+                self.scope.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+impl From<Denied> for Response {
+    fn from(value: Denied) -> Self {
+        Response::new(
+            StatusCode::OK,
+            4,
+            json!({
+                "message": format!("权限不足，{value}"),
+                "reason": value.reason,
+                "group": value.group,
+                "action": value.action,
+                "scope": value.scope,
+            }),
+        )
+    }
+}
+
+/// 检查职务是否有操作`action`在数据范围`scope`内的权限，root拥有所有权限
+pub async fn check<A: Action>(role: &str, action: A, scope: Scope<'_>) -> Result<(), Denied> {
+    if role.eq("root") {
+        return Ok(());
+    }
+    let denied = |reason| Denied {
+        reason,
+        group: A::GROUP,
+        action: action.name(),
+        scope: scope.value().map(str::to_owned),
+    };
+    let role_perm_maps = ROLES_GROUP_MAP.lock().await;
+    let perms = op::some!(role_perm_maps.get(role); ret Err(denied(DeniedReason::NoPermission)));
+    let data = op::some!(
+        perms.get(A::GROUP).and_then(|p| p.get(action.name()));
+        ret Err(denied(DeniedReason::Action))
+    );
+    match scope.value() {
+        Some(s) if !data.iter().any(|d| d == s) => Err(denied(DeniedReason::Scope)),
+        _ => Ok(()),
+    }
+}
+
+/// 已登录的员工，从请求头的token中解析
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    user: Arc<User>,
+    sid: String,
+}
+
+impl AuthUser {
+    pub fn user(&self) -> &Arc<User> {
+        &self.user
+    }
+    /// 发起请求的会话
+    pub fn sid(&self) -> &str {
+        &self.sid
+    }
+}
+
+impl Deref for AuthUser {
+    type Target = User;
+    fn deref(&self) -> &Self::Target {
+        &self.user
+    }
+}
+
+impl fmt::Display for AuthUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.user.fmt(f)
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let bearer = Bearer::try_from(&parts.headers).map_err(Response::token_error)?;
+        let mut conn = get_db().await?;
+        let id = parse_jwt_macro!(&bearer, &mut conn => true);
+        let sid = parse_jwt(&bearer).map(|jwt| jwt.sid).unwrap_or_default();
+        let user = get_user(&id, &mut conn).await?;
+        Ok(Self { user, sid })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_names() {
+        assert_eq!(CustomerPerm::GROUP, "customer");
+        assert_eq!(CustomerPerm::ExportData.name(), "export_data");
+        assert_eq!(RolePerm::ChangeRole.name(), "change_role");
+        assert_eq!(StorehousePerm::AddStorehouse.name(), "add_storehouse");
+        assert_eq!(Scope::Role("salesman").value(), Some("salesman"));
+        assert_eq!(Scope::Any.value(), None);
+    }
+}
@@ -1,16 +1,15 @@
+pub mod auth;
 pub mod inner;
 pub mod roles;
+pub mod store;
 use std::collections::HashMap;
 
 use crate::{
-    bearer,
-    database::get_db,
-    parse_jwt_macro,
-    perm::{action::groups, roles::ROLE_TABLES},
+    libs::perm::RolePermission,
+    perm::{action::groups, auth::AuthUser, roles::role_tables},
     Response, ResponseResult,
 };
-use axum::{http::HeaderMap, routing::post, Router};
-use mysql::{prelude::Queryable, PooledConn};
+use axum::{routing::post, Router};
 use serde_json::json;
 use tokio::sync::Mutex;
 
@@ -25,34 +24,8 @@ lazy_static::lazy_static! {
     pub static ref PERMISSION_GROUPS: HashMap<&'static str, Vec<&'static str>> = {
         groups()
     };
-    pub static ref ROLES_GROUP_MAP: Mutex<HashMap<String, PermissionGroupMap>> = {
-        let map = if let Ok(bytes) = std::fs::read("data/perm") {
-            serde_json::from_slice(&bytes).expect("权限文件结构遭到破坏，请联系开发人员进行修复")
-        } else {
-            let mut map = HashMap::new();
-            map.insert("salesman".to_owned(), role_salesman());
-            map.insert("admin".to_owned(), unsafe { role_adm() });
-            map.insert("manager".to_owned(), unsafe { role_manager() });
-
-            std::fs::write("data/perm", json!(map.clone()).to_string().as_bytes()).expect("写入权限文件失败");
-            map
-        };
-        // let mut dash_map = DashMap::new();
-        // for (key, value) in map {
-        //     dash_map.insert(key, value);
-        // }
-        // dash_map
-        Mutex::new(map)
-    };
-}
-pub async fn update_role_map(role: &str, perms: PermissionGroupMap) -> Result<(), Response> {
-    use std::fs::write;
-    let mut map = ROLES_GROUP_MAP.lock().await;
-    if let Some(v) = map.get_mut(role) {
-        *v = perms;
-        write("data/perm", json!(map.clone()).to_string().as_bytes())?;
-    }
-    Ok(())
+    /// 所有角色的权限树，启动时由`store::init_role_map`从数据库加载
+    pub static ref ROLES_GROUP_MAP: Mutex<HashMap<String, RolePermission>> = Mutex::new(HashMap::new());
 }
 fn role_salesman() -> PermissionGroupMap {
     use action::*;
@@ -173,11 +146,11 @@ unsafe fn role_adm() -> PermissionGroupMap {
             [
                 (
                     AccountGroup::CREATE,
-                    vec![ROLE_TABLES.get_name_uncheck("salesman")],
+                    vec![role_tables().get_name_uncheck("salesman")],
                 ),
                 (
                     AccountGroup::DELETE,
-                    vec![ROLE_TABLES.get_name_uncheck("salesman")],
+                    vec![role_tables().get_name_uncheck("salesman")],
                 ),
             ]
             .into_iter()
@@ -215,132 +188,11 @@ pub fn perm_router() -> Router {
     Router::new().route("/get/perm", post(get_perm))
 }
 
-pub async fn verify_permissions(
-    role: &str,
-    perm: &str,
-    action: &str,
-    data: Option<&[&str]>,
-) -> bool {
-    if role.eq("root") {
-        return true;
-    }
-    let role_perm_maps = ROLES_GROUP_MAP.lock().await;
-    let role_perms = op::some!(role_perm_maps.get(role); ret false);
-
-    op::some!(role_perms.get(perm); ret false)
-        .get(action)
-        .map_or(false, |v| {
-            data.map_or(true, |d| d.iter().all(|k| v.contains(&k.to_string())))
-        })
-}
-#[macro_export]
-macro_rules! verify_perms {
-    ($role:expr, $perm:expr, $(($action:expr, $data:expr)), +) => {
-        if !$role.eq("root") {
-            let role_perm_maps = $crate::perm::ROLES_GROUP_MAP.lock().await;
-            $(
-                match op::catch!(role_perm_maps.get($role)?.get($perm)?.get($action)) {
-                    Some(v) => {
-                            if let Some::<&[&str]>(d) = $data {
-                                d.iter().all(|k| v.contains(&k.to_string()))
-                            } else {
-                                true
-                            }
-                    }
-                    _ => {if let Some::<&[&str]>(_) = $data { } false}
-                }
-            ,)+
-        } else {
-            ($({
-                    if let Some<&[&str]>(_) = $data {} true
-            },)+)
-        }
-    };
-
-    ($role:expr, $perm:expr, $(($action:expr, $($data:expr), +)), +) => {
-        if !$role.eq("root") {
-            let role_perm_maps = $crate::perm::ROLES_GROUP_MAP.lock().await;
-            $(
-                match op::catch!(role_perm_maps.get($role)?.get($perm)?.get($action)) {
-                    Some(v) => {
-                        vec![$(
-                            if let Some::<&[&str]>(d) = $data {
-                                d.iter().all(|k| v.contains(&k.to_string()))
-                            } else {
-                                true
-                            },
-                        )+]
-                    }
-                    _ => vec![$({if let Some::<&[&str]>(_) = $data { } false}, )+]
-                }
-
-            ,)+
-        } else {
-            ($({
-                    let _ = $action;
-                    vec![$({if let Some<&[&str]>(_) = $data {} true},)+]
-            },)+)
-        }
-    };
-
-
-
-    ($role:expr, $perm:expr, $action:expr) => {
-        $crate::verify_perms!($role, $perm, $action, None)
-    };
-
-    // 普通验证
-    ($role:expr, $perm:expr, $action:expr, $data:expr) => {
-        $role.eq("root") || {
-            let role_perm_maps = $crate::perm::ROLES_GROUP_MAP.lock().await;
-            op::catch!{ role_perm_maps.get($role)?.get($perm)?.get($action)}
-            .map_or(false, |v| {
-                $data.map_or(true, |d: &[&str]| d.iter().all(|k| v.contains(&k.to_string())))
-            })
-
-        }
-    };
-
-
-
-    // 同时验证多个
-    ($role:expr, $perm:expr, $action:expr, $($data:expr), +) => {
-        if !$role.eq("root") {
-            let role_perm_maps = $crate::perm::ROLES_GROUP_MAP.lock().await;
-                match op::catch!(role_perm_maps.get($role)?.get($perm)?.get($action)) {
-                    Some(v) => {
-                        ($(
-                            if let Some::<&[&str]>(d) = $data {
-                                d.iter().all(|k| v.contains(&k.to_string()))
-                            } else {
-                                true
-                            },
-                        )+)
-                    }
-                    _ => ($({if let Some::<&[&str]>(_) = $data { } false}, )+)
-                }
-        } else {
-            ($( { if let Some::<&[&str]>(_) = $data{} true }, )+)
-        }
-    };
-
-}
-
-async fn get_perm(headers: HeaderMap) -> ResponseResult {
-    let db = get_db().await?;
-    let mut conn = db.lock().await;
-    let bearer = bearer!(&headers);
-    let id = parse_jwt_macro!(&bearer, &mut conn => true);
-    let role = get_role(&id, &mut conn)?;
+async fn get_perm(user: AuthUser) -> ResponseResult {
     let perm_map = ROLES_GROUP_MAP.lock().await;
-    if let Some(perms) = perm_map.get(&role) {
-        Ok(Response::ok(json!(perms[&role])))
+    if let Some(perms) = perm_map.get(&user.role) {
+        Ok(Response::ok(json!(perms.to_group_map())))
     } else {
         Ok(Response::ok(json!(PermissionGroupMap::new())))
     }
 }
-#[inline(always)]
-pub fn get_role(id: &str, conn: &mut PooledConn) -> Result<String, Response> {
-    let role = op::some!(conn.query_first(format!("SELECT role FROM user WHERE id = '{id}'"))?; ret Err(Response::not_exist("用户不存在")));
-    Ok(role)
-}
@@ -1,37 +1,43 @@
+use std::sync::{PoisonError, RwLock, RwLockReadGuard};
+
 use mysql::{prelude::Queryable, PooledConn};
 
-pub static mut ROLE_TABLES: RoleTable = RoleTable::empty();
+lazy_static::lazy_static! {
+    /// 角色id和名称，启动时初始化，角色修改后由`reload_roles`重新加载
+    static ref ROLE_TABLES: RwLock<RoleTable> = RwLock::new(RoleTable::empty());
+}
 #[derive(Debug)]
 pub struct RoleTable {
     table: Vec<(String, String)>,
 }
 
+/// 读取角色表，其他线程更新角色表时panic不影响读取
+pub fn role_tables() -> RwLockReadGuard<'static, RoleTable> {
+    ROLE_TABLES.read().unwrap_or_else(PoisonError::into_inner)
+}
+/// 从数据库重新加载角色表
+pub fn reload_roles(conn: &mut PooledConn) -> mysql::Result<()> {
+    let table = RoleTable::query(conn)?;
+    ROLE_TABLES
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .table = table;
+    Ok(())
+}
+
 pub fn role_to_name(role: &str) -> String {
-    unsafe {
-        ROLE_TABLES.get_name(role).map_or(String::new(), |s|s.to_owned())
-    }
+    role_tables().get_name(role).map_or(String::new(), |s|s.to_owned())
 }
 pub fn name_to_role(name: &str) -> String {
-    unsafe {
-        ROLE_TABLES.get_id(name).map_or(String::new(), |s|s.to_owned())
-    }
+    role_tables().get_id(name).map_or(String::new(), |s|s.to_owned())
 }
 
 impl RoleTable {
     pub const fn empty() -> RoleTable {
         RoleTable { table: Vec::new() }
     }
-    pub fn init(&mut self, conn: &mut PooledConn) {
-        let map: Vec<(String, String)> = conn
-            .query_map("SELECT id, name FROM roles", |(id, name)| (id, name))
-            .expect("初始化角色表时查询失败");
-        for (id, name) in map {
-            self.table.push((id, name))
-        }
-    }
-    pub fn update(&mut self, conn: &mut PooledConn) -> mysql::Result<()> {
-        self.table = conn.query_map("SELECT id, name FROM roles", |(id, name)| (name, id))?;
-        Ok(())
+    fn query(conn: &mut PooledConn) -> mysql::Result<Vec<(String, String)>> {
+        conn.query_map("SELECT id, name FROM roles", |(id, name)| (id, name))
     }
     pub fn get_name(&self, id: &str) -> Option<&str> {
         for (id_k, name) in &self.table {
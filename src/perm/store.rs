@@ -0,0 +1,194 @@
+//! 角色权限的存储
+//!
+//! 权限保存在`role_perm`表中，每次修改都写入`role_perm_log`。
+//! 每台服务器在内存中保存一份权限，定时检查`role_perm_log`的最大id，
+//! 其他服务器修改过权限时重新加载。数据库中保存扁平结构，内存中为权限树
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use mysql::{params, prelude::Queryable, PooledConn};
+use serde_json::json;
+
+use super::{role_adm, role_manager, role_salesman, PermissionGroupMap, ROLES_GROUP_MAP};
+use crate::{
+    libs::{perm::RolePermission, TimeFormat, TIME},
+    log, Response,
+};
+
+/// 本服务器加载权限时`role_perm_log`的最大id
+static PERM_VERSION: AtomicU64 = AtomicU64::new(0);
+
+fn perm_version(conn: &mut PooledConn) -> mysql::Result<u64> {
+    let id: Option<Option<u64>> = conn.query_first("SELECT MAX(id) FROM role_perm_log")?;
+    Ok(id.flatten().unwrap_or(0))
+}
+
+fn load_role_map(conn: &mut PooledConn) -> Result<HashMap<String, RolePermission>, Response> {
+    let rows: Vec<(String, String, String, String)> =
+        conn.query("SELECT role, perm_group, action, scopes FROM role_perm")?;
+    let mut map: HashMap<String, PermissionGroupMap> = HashMap::new();
+    for (role, group, action, scopes) in rows {
+        map.entry(role)
+            .or_default()
+            .entry(group)
+            .or_default()
+            .insert(action, serde_json::from_str(&scopes)?);
+    }
+    Ok(to_trees(map))
+}
+
+fn to_trees(map: HashMap<String, PermissionGroupMap>) -> HashMap<String, RolePermission> {
+    map.into_iter()
+        .map(|(role, perms)| (role, RolePermission::from_group_map(&perms)))
+        .collect()
+}
+
+/// 覆盖角色的权限，`perms`为`None`表示删除该角色的权限
+fn save_role_perms(
+    conn: &mut PooledConn,
+    role: &str,
+    perms: Option<&PermissionGroupMap>,
+) -> Result<(), Response> {
+    conn.exec_drop("DELETE FROM role_perm WHERE role = ?", (role,))?;
+    let rows: Vec<(&String, &String, String)> = perms
+        .into_iter()
+        .flatten()
+        .flat_map(|(group, actions)| {
+            actions
+                .iter()
+                .map(move |(action, scopes)| (group, action, json!(scopes).to_string()))
+        })
+        .collect();
+    conn.exec_batch(
+        "INSERT INTO role_perm (role, perm_group, action, scopes)
+        VALUES (:role, :perm_group, :action, :scopes)",
+        rows.iter().map(|(group, action, scopes)| {
+            params! {
+                "role" => role,
+                "perm_group" => group,
+                "action" => action,
+                "scopes" => scopes
+            }
+        }),
+    )?;
+    Ok(())
+}
+
+fn log_role_perms(
+    conn: &mut PooledConn,
+    role: &str,
+    operator: &str,
+    before: Option<&PermissionGroupMap>,
+    after: Option<&PermissionGroupMap>,
+    time: &TIME,
+) -> Result<(), Response> {
+    conn.exec_drop(
+        "INSERT INTO role_perm_log (role, operator, before_perms, after_perms, time)
+        VALUES (:role, :operator, :before_perms, :after_perms, :time)",
+        params! {
+            "role" => role,
+            "operator" => operator,
+            "before_perms" => before.map(|p| json!(p).to_string()),
+            "after_perms" => after.map(|p| json!(p).to_string()),
+            "time" => time.format(TimeFormat::YYYYMMDD_HHMMSS)
+        },
+    )?;
+    Ok(())
+}
+
+/// 执行`write`后保存有变化的角色并记录修改人
+fn __save_changes<W>(
+    conn: &mut PooledConn,
+    write: W,
+    operator: &str,
+    before: &HashMap<String, RolePermission>,
+    after: &HashMap<String, RolePermission>,
+) -> Result<(), Response>
+where
+    W: FnOnce(&mut PooledConn) -> Result<(), Response>,
+{
+    write(conn)?;
+    let time = TIME::now()?;
+    let mut roles: Vec<&String> = before.keys().chain(after.keys()).collect();
+    roles.sort();
+    roles.dedup();
+    for role in roles {
+        let old = before.get(role).map(RolePermission::to_group_map);
+        let new = after.get(role).map(RolePermission::to_group_map);
+        if old != new {
+            save_role_perms(conn, role, new.as_ref())?;
+            log_role_perms(conn, role, operator, old.as_ref(), new.as_ref(), &time)?;
+        }
+    }
+    Ok(())
+}
+
+/// 修改角色权限，所有变化在同一个事务中写入数据库，失败时内存中的权限不变
+pub async fn modify_role_map<F>(conn: &mut PooledConn, operator: &str, f: F) -> Result<(), Response>
+where
+    F: FnOnce(&mut HashMap<String, RolePermission>),
+{
+    modify_role_map_with(conn, operator, |_| Ok(()), f).await
+}
+
+/// 与[`modify_role_map`]相同，`write`在同一个事务中先执行，用于同时修改`roles`表
+pub async fn modify_role_map_with<W, F>(
+    conn: &mut PooledConn,
+    operator: &str,
+    write: W,
+    f: F,
+) -> Result<(), Response>
+where
+    W: FnOnce(&mut PooledConn) -> Result<(), Response>,
+    F: FnOnce(&mut HashMap<String, RolePermission>),
+{
+    let mut map = ROLES_GROUP_MAP.lock().await;
+    let mut after = map.clone();
+    f(&mut after);
+    crate::commit_or_rollback!(__save_changes, conn, write, operator, &map, &after)?;
+    PERM_VERSION.store(perm_version(conn)?, Ordering::Release);
+    *map = after;
+    Ok(())
+}
+
+/// 启动时加载权限，数据库中没有权限时从`data/perm`导入，文件也不存在时使用默认权限
+pub async fn init_role_map(conn: &mut PooledConn) -> Result<(), Response> {
+    let version = perm_version(conn)?;
+    let map = load_role_map(conn)?;
+    if !map.is_empty() || version > 0 {
+        PERM_VERSION.store(version, Ordering::Release);
+        *ROLES_GROUP_MAP.lock().await = map;
+        return Ok(());
+    }
+    let map: HashMap<String, PermissionGroupMap> = match std::fs::read("data/perm") {
+        Ok(bytes) => {
+            log!("从data/perm导入角色权限");
+            serde_json::from_slice(&bytes)?
+        }
+        Err(_) => unsafe {
+            [
+                ("salesman".to_owned(), role_salesman()),
+                ("admin".to_owned(), role_adm()),
+                ("manager".to_owned(), role_manager()),
+            ]
+            .into_iter()
+            .collect()
+        },
+    };
+    modify_role_map(conn, "system", |m| *m = to_trees(map)).await
+}
+
+/// 其他服务器修改过权限时重新加载
+pub async fn sync_role_map(conn: &mut PooledConn) -> Result<(), Response> {
+    let mut current = ROLES_GROUP_MAP.lock().await;
+    let version = perm_version(conn)?;
+    if version == PERM_VERSION.load(Ordering::Acquire) {
+        return Ok(());
+    }
+    *current = load_role_map(conn)?;
+    PERM_VERSION.store(version, Ordering::Release);
+    log!("角色权限已被其他服务器修改，重新加载");
+    Ok(())
+}
@@ -103,6 +103,14 @@ impl Response {
     pub fn unknown_err(e: impl Display) -> Self {
         Self::new(StatusCode::OK, 9, json!(e.to_string()))
     }
+    /// 登录失败次数过多，`retry_after`秒后才能再次尝试
+    pub fn too_many_attempts(retry_after: u64) -> Self {
+        Self::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            10,
+            json!({"message": "登录失败次数过多，请稍后再试", "retry_after": retry_after}),
+        )
+    }
     pub fn code(&self) -> StatusCode {
         self.code
     }
@@ -184,6 +192,14 @@ impl BodyFile {
     pub fn new(body: Vec<u8>) -> Self {
         Self { body, ..Default::default() }
     }
+    /// 作为附件下载，`filename`只能包含ASCII字符
+    pub fn attachment(body: Vec<u8>, filename: String, mime: &'static str) -> Self {
+        Self {
+            body,
+            filename,
+            mime,
+        }
+    }
     pub fn new_with_base64_url(
         parent: impl AsRef<Path>,
         url: &str,
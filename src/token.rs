@@ -1,15 +1,22 @@
 use std::collections::BTreeMap;
 
-use chrono::{prelude::TimeZone, Days};
+use chrono::{prelude::TimeZone, Duration};
 use hmac::{Hmac, Mac};
-use jwt::{Header, SignWithKey, Token, VerifyWithKey};
-use mysql::prelude::Queryable;
+use jwt::{
+    AlgorithmType, Header, SignWithKey, SigningAlgorithm, Token, VerifyWithKey, VerifyingAlgorithm,
+};
+use mysql::{params, prelude::Queryable, PooledConn};
 use serde_json::json;
-use sha2::Sha512;
+use sha2::{Digest, Sha256, Sha384, Sha512};
 
 use crate::{
-    database::DB,
-    libs::{headers::Bearer, time::TIME},
+    libs::{
+        cache::TOKEN_CACHE,
+        headers::{Bearer, ClientInfo},
+        time::TIME,
+        TimeFormat,
+    },
+    JwtConfig, JwtKey, CONFIG,
 };
 /// 从请求头中获取token
 #[macro_export]
@@ -49,7 +56,139 @@ impl TokenVerification {
         *self == TokenVerification::Error
     }
 }
-static SECRET_KEY: &str = "_SECRET_KEY_RUST_SERVER";
+/// 签名和验证token使用的密钥
+pub enum SigningKey {
+    Hs256(Hmac<Sha256>),
+    Hs384(Hmac<Sha384>),
+    Hs512(Hmac<Sha512>),
+}
+impl SigningKey {
+    pub fn new(algorithm: &str, secret: &str) -> Result<Self, String> {
+        if secret.len() < 32 {
+            return Err("JWT密钥长度不能少于32个字符".to_owned());
+        }
+        let secret = secret.as_bytes();
+        let invalid = |e: hmac::digest::InvalidLength| e.to_string();
+        Ok(match algorithm.to_uppercase().as_str() {
+            "HS256" => Self::Hs256(Hmac::new_from_slice(secret).map_err(invalid)?),
+            "HS384" => Self::Hs384(Hmac::new_from_slice(secret).map_err(invalid)?),
+            "HS512" => Self::Hs512(Hmac::new_from_slice(secret).map_err(invalid)?),
+            _ => return Err(format!("不支持的JWT算法`{algorithm}`")),
+        })
+    }
+}
+impl SigningAlgorithm for SigningKey {
+    fn algorithm_type(&self) -> AlgorithmType {
+        match self {
+            Self::Hs256(_) => AlgorithmType::Hs256,
+            Self::Hs384(_) => AlgorithmType::Hs384,
+            Self::Hs512(_) => AlgorithmType::Hs512,
+        }
+    }
+    fn sign(&self, header: &str, claims: &str) -> Result<String, jwt::Error> {
+        match self {
+            Self::Hs256(k) => k.sign(header, claims),
+            Self::Hs384(k) => k.sign(header, claims),
+            Self::Hs512(k) => k.sign(header, claims),
+        }
+    }
+}
+impl VerifyingAlgorithm for SigningKey {
+    fn algorithm_type(&self) -> AlgorithmType {
+        SigningAlgorithm::algorithm_type(self)
+    }
+    fn verify_bytes(
+        &self,
+        header: &str,
+        claims: &str,
+        signature: &[u8],
+    ) -> Result<bool, jwt::Error> {
+        match self {
+            Self::Hs256(k) => k.verify_bytes(header, claims, signature),
+            Self::Hs384(k) => k.verify_bytes(header, claims, signature),
+            Self::Hs512(k) => k.verify_bytes(header, claims, signature),
+        }
+    }
+}
+
+/// 签发用的`kid`和所有可用于验证的密钥
+pub struct KeyStore {
+    kid: String,
+    keys: BTreeMap<String, SigningKey>,
+    access_lifetime: i64,
+    refresh_lifetime: i64,
+}
+impl KeyStore {
+    /// `env`中的密钥会覆盖设置文件中相同`kid`的密钥
+    pub fn new(config: &JwtConfig, env: Option<JwtKey>) -> Result<Self, String> {
+        let kid = env
+            .as_ref()
+            .map_or_else(|| config.kid().to_owned(), |k| k.kid.clone());
+        let mut keys = BTreeMap::new();
+        for k in config.keys().iter().chain(env.as_ref()) {
+            let key = SigningKey::new(&k.algorithm, &k.secret)
+                .map_err(|e| format!("密钥`{}`错误：{e}", k.kid))?;
+            keys.insert(k.kid.clone(), key);
+        }
+        if !keys.contains_key(&kid) {
+            return Err(format!(
+                "没有找到签发token使用的密钥`{kid}`，请在config/config.json的jwt.keys中或环境变量CRM_JWT_SECRET中设置"
+            ));
+        }
+        Ok(Self {
+            kid,
+            keys,
+            access_lifetime: config.access_lifetime() as i64,
+            refresh_lifetime: config.refresh_lifetime() as i64,
+        })
+    }
+    fn from_env(config: &JwtConfig) -> Result<Self, String> {
+        let env = std::env::var("CRM_JWT_SECRET").ok().map(|secret| JwtKey {
+            kid: std::env::var("CRM_JWT_KID").unwrap_or_else(|_| config.kid().to_owned()),
+            secret,
+            algorithm: std::env::var("CRM_JWT_ALGORITHM").unwrap_or_else(|_| "HS512".to_owned()),
+        });
+        Self::new(config, env)
+    }
+    fn signing_key(&self) -> &SigningKey {
+        &self.keys[&self.kid]
+    }
+    /// 没有`kid`的token使用签发密钥验证
+    pub fn verify(&self, token: &str) -> Option<BTreeMap<String, serde_json::Value>> {
+        let unverified: Token<Header, BTreeMap<String, serde_json::Value>, _> =
+            Token::parse_unverified(token).ok()?;
+        let key = match unverified.header().key_id.as_deref() {
+            Some(kid) => self.keys.get(kid)?,
+            None => self.signing_key(),
+        };
+        let token = unverified.verify_with_key(key).ok()?;
+        Some(token.claims().clone())
+    }
+    pub fn sign(&self, claims: &BTreeMap<&str, serde_json::Value>) -> String {
+        let key = self.signing_key();
+        let header = Header {
+            algorithm: SigningAlgorithm::algorithm_type(key),
+            key_id: Some(self.kid.clone()),
+            ..Default::default()
+        };
+        Token::new(header, claims)
+            .sign_with_key(key)
+            .unwrap()
+            .as_str()
+            .into()
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref KEYS: KeyStore = match KeyStore::from_env(CONFIG.jwt()) {
+        Ok(keys) => keys,
+        Err(e) => panic!("{e}"),
+    };
+}
+/// 启动时检查密钥设置，设置错误时拒绝启动
+pub fn init_keys() {
+    lazy_static::initialize(&KEYS);
+}
 #[derive(Debug, Default)]
 pub struct JWToken {
     pub id: String,
@@ -61,33 +200,36 @@ pub struct JWToken {
     pub iat: i64,
     /// 过期时间
     pub exp: i64,
+    /// 登录会话id
+    pub sid: String,
+    /// 只能用于修改默认密码
+    pub password_only: bool,
 }
 impl JWToken {
-    pub fn verify(&self, conn: &mut DB<'_>) -> mysql::Result<TokenVerification> {
+    pub fn verify(&self, conn: &mut PooledConn) -> mysql::Result<TokenVerification> {
         // 检查用户是否存在
         let is_exist = if self.sub {
-            conn.query_first::<String, String>(format!(
-                "SELECT u.id FROM user u WHERE u.id = '{}' AND NOT EXISTS( 
+            conn.exec_first::<String, _, _>(
+                "SELECT u.id FROM user u WHERE u.id = ? AND NOT EXISTS( 
                     SELECT 1 FROM leaver b WHERE b.id=u.id)",
-                self.id
-            ))?
+                (&self.id,),
+            )?
         } else {
-            // conn.query_first(format!(
-            //     "SELECT id FROM customer_login WHERE id = '{}'",
-            //     self.id
-            // ))?
-            // todo
-            None
+            // 客户设置密码后才能登录
+            conn.exec_first::<String, _, _>(
+                "SELECT id FROM customer_login WHERE id = ? AND password_hash IS NOT NULL",
+                (&self.id,),
+            )?
         };
         if is_exist.is_none() {
             return Ok(TokenVerification::Error);
         }
         // 检查token的签名时间是否在允许范围内
         let ty = if self.sub { 0 } else { 1 };
-        let tbn: Option<i64> = conn.query_first(format!(
-            "SELECT tbn FROM token WHERE id = '{}' AND ty = {}",
-            self.id, ty
-        ))?;
+        let tbn: Option<i64> = conn.exec_first(
+            "SELECT tbn FROM token WHERE id = ? AND ty = ?",
+            (&self.id, ty),
+        )?;
         if let Some(tbn) = tbn {
             let tbn = chrono::Local.timestamp_nanos(tbn);
             let iat = chrono::Local.timestamp_nanos(self.iat);
@@ -95,78 +237,76 @@ impl JWToken {
                 return Ok(TokenVerification::Error);
             }
         }
+        // 检查会话是否已被注销
+        let session: Option<i32> = conn.exec_first(
+            "SELECT 1 FROM session WHERE id = ? AND ty = ? AND user = ?",
+            (&self.sid, ty, &self.id),
+        )?;
+        if session.is_none() {
+            return Ok(TokenVerification::Error);
+        }
         // 检查是否过期
         let exp = chrono::Local.timestamp_nanos(self.exp);
         if TIME::now().is_ok_and(|t| t.naos() as i64 >= exp.timestamp_nanos_opt().unwrap_or(0)) {
             return Ok(TokenVerification::Expired);
         }
+        touch_session(conn, &self.sid)?;
         Ok(TokenVerification::Ok)
     }
-    pub fn is_refresh(&self) -> bool {
-        let now = TIME::now().unwrap();
-        let now = chrono::Local.timestamp_nanos(now.naos() as i64);
-        let exp = chrono::Local.timestamp_nanos(self.exp);
-        //过期前一天
-        let sub_one_day = exp.checked_sub_days(Days::new(1)).unwrap();
-        // 过期后一天
-        let add_one_day = exp.checked_add_days(Days::new(1)).unwrap();
-        now >= sub_one_day && now <= add_one_day
-    }
 }
 
 #[macro_export]
 macro_rules! parse_jwt_macro {
     // 解析token，从中获取id信息
     ($bearer:expr, $conn:expr) => {{
-        if let Some(id) = $crate::libs::cache::TOKEN_CACHE.get($bearer.token()) {
+        if let Some(id) = $crate::libs::cache::TOKEN_CACHE
+            .get($bearer.token())
+            .filter(|_| !$crate::token::token_expired($bearer.token()))
+        {
             id.to_owned()
         } else {
             match $crate::token::parse_jwt($bearer) {
-                Some(jwt) => {
-                    if jwt.verify($conn)?.is_ok() {
-                        $crate::libs::cache::TOKEN_CACHE
-                            .insert($bearer.token().to_owned(), jwt.id.clone());
-                        jwt.id
-                    } else {
-                        return Err($crate::Response::token_error("Invalid Token"));
-                    }
+                Some(jwt) if !jwt.password_only && jwt.verify($conn)?.is_ok() => {
+                    $crate::libs::cache::TOKEN_CACHE
+                        .insert($bearer.token().to_owned(), jwt.id.clone());
+                    jwt.id
                 }
                 _ => return Err($crate::Response::token_error("Invalid Token")),
             }
         }
     }};
     ($bearer:expr, $conn:expr => $sub:expr) => {{
-        if let Some(id) = $crate::libs::cache::TOKEN_CACHE.get($bearer.token()) {
+        if let Some(id) = $crate::libs::cache::TOKEN_CACHE
+            .get($bearer.token())
+            .filter(|_| $crate::token::token_sub($bearer.token()) == Some($sub))
+            .filter(|_| !$crate::token::token_expired($bearer.token()))
+        {
             id.to_owned()
         } else {
             match $crate::token::parse_jwt($bearer) {
-                Some(jwt) => {
-                    if jwt.sub == $sub && jwt.verify($conn)?.is_ok() {
-                        $crate::libs::cache::TOKEN_CACHE
-                            .insert($bearer.token().to_owned(), jwt.id.clone());
-                        jwt.id
-                    } else {
-                        return Err($crate::Response::token_error("Invalid Token"));
-                    }
+                Some(jwt) if jwt.sub == $sub && !jwt.password_only && jwt.verify($conn)?.is_ok() => {
+                    $crate::libs::cache::TOKEN_CACHE
+                        .insert($bearer.token().to_owned(), jwt.id.clone());
+                    jwt.id
                 }
                 _ => return Err($crate::Response::token_error("Invalid Token")),
             }
         }
     }};
     ($bearer:expr => $sub:expr) => {{
-        if let Some(id) = $crate::libs::cache::TOKEN_CACHE.get($bearer.token()) {
+        if let Some(id) = $crate::libs::cache::TOKEN_CACHE
+            .get($bearer.token())
+            .filter(|_| $crate::token::token_sub($bearer.token()) == Some($sub))
+            .filter(|_| !$crate::token::token_expired($bearer.token()))
+        {
             id.to_owned()
         } else {
-            let mut conn = $crate::database::DBC.lock().await;
+            let mut conn = $crate::database::get_db().await?;
             match $crate::token::parse_jwt($bearer) {
-                Some(jwt) => {
-                    if jwt.sub == $sub && jwt.verify(&mut conn)?.is_ok() {
-                        $crate::libs::cache::TOKEN_CACHE
-                            .insert($bearer.token.to_owned(), jwt.id.clone());
-                        jwt.id
-                    } else {
-                        return Err($crate::Response::token_error("Invalid Token"));
-                    }
+                Some(jwt) if jwt.sub == $sub && !jwt.password_only && jwt.verify(&mut conn)?.is_ok() => {
+                    $crate::libs::cache::TOKEN_CACHE
+                        .insert($bearer.token.to_owned(), jwt.id.clone());
+                    jwt.id
                 }
                 _ => return Err($crate::Response::token_error("Invalid Token")),
             }
@@ -175,43 +315,277 @@ macro_rules! parse_jwt_macro {
 }
 
 pub fn parse_jwt(bearer: &Bearer) -> Option<JWToken> {
-    let key: Hmac<Sha512> = Hmac::new_from_slice(SECRET_KEY.as_bytes()).unwrap();
-    let token: Token<Header, BTreeMap<String, serde_json::Value>, _> =
-        VerifyWithKey::verify_with_key(bearer.token(), &key).ok()?;
-    let claims = token.claims();
+    let claims = KEYS.verify(bearer.token())?;
     Some(JWToken {
         id: claims.get("id")?.as_str()?.to_owned(),
-        sub: claims.get("sub")?.as_bool()? ,
+        sub: claims.get("sub")?.as_bool()?,
         iss: claims.get("iss")?.as_str()?.to_owned(),
         iat: claims.get("iat")?.as_i64()?,
         exp: claims.get("exp")?.as_i64()?,
+        sid: claims.get("sid")?.as_str()?.to_owned(),
+        password_only: claims.get("pwd").and_then(|v| v.as_bool()).unwrap_or(false),
     })
 }
 
-pub fn generate_jwt(sub: bool, id: &str) -> String {
-    let key: Hmac<Sha512> = Hmac::new_from_slice(SECRET_KEY.as_bytes()).unwrap();
-    let header = Header {
-        algorithm: jwt::AlgorithmType::Hs512,
-        ..Default::default()
-    };
+pub fn generate_jwt(sub: bool, id: &str, sid: &str) -> String {
+    sign_jwt(sub, id, sid, false)
+}
+
+/// 仍在使用默认密码的员工登录后只能修改密码
+pub fn generate_password_jwt(id: &str, sid: &str) -> String {
+    sign_jwt(true, id, sid, true)
+}
+
+fn sign_jwt(sub: bool, id: &str, sid: &str, password_only: bool) -> String {
     let mut claims = BTreeMap::new();
+    if password_only {
+        claims.insert("pwd", json!(true));
+    }
     // 签发者
     claims.insert("iss", json!("CRM-SHA-1"));
     claims.insert("id", id.into());
+    // 登录会话
+    claims.insert("sid", sid.into());
     // 用户
     claims.insert("sub", sub.into());
     let time = TIME::now().expect("Time go ahead");
     // 设置token签发时间
     claims.insert("iat", (time.naos() as i64).into());
     let local = chrono::Local.timestamp_nanos(time.naos() as i64);
-    let next_week = local
-        .checked_add_days(Days::new(7))
-        .expect("token error-week");
+    let exp = local + Duration::seconds(KEYS.access_lifetime);
     // 设置token过期时间
-    claims.insert("exp", next_week.timestamp_nanos_opt().unwrap().into());
-    Token::new(header, claims)
-        .sign_with_key(&key)
-        .unwrap()
-        .as_str()
-        .into()
+    claims.insert("exp", exp.timestamp_nanos_opt().unwrap().into());
+    KEYS.sign(&claims)
+}
+
+/// refresh token在`token`表中的类型
+const REFRESH_TOKEN_TY: i32 = 2;
+
+pub fn random_hex(bytes: usize) -> String {
+    (0..bytes)
+        .map(|_| format!("{:02x}", rand::random::<u8>()))
+        .collect()
+}
+
+/// 数据库中只保存refresh token的sha256
+pub fn hash_refresh_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// 签发新的refresh token，`family`为登录会话id
+pub fn issue_refresh_token(conn: &mut PooledConn, id: &str, family: &str) -> mysql::Result<String> {
+    let token = random_hex(32);
+    let now = TIME::now().map_or(0, |t| t.naos() as i64);
+    let expire = now + KEYS.refresh_lifetime * 1_000_000_000;
+    conn.exec_drop(
+        "INSERT INTO token (ty, id, tbn, user, family, expire, used)
+        VALUES (?, ?, NULL, ?, ?, ?, 0)",
+        (
+            REFRESH_TOKEN_TY,
+            hash_refresh_token(&token),
+            id,
+            family,
+            expire,
+        ),
+    )?;
+    Ok(token)
+}
+
+pub enum RefreshResult {
+    /// 换取成功，返回用户id、会话id和新的refresh token
+    Rotated {
+        id: String,
+        sid: String,
+        token: String,
+    },
+    /// 已经使用过的token被再次使用，整个token族和对应的会话已被撤销
+    Reused { id: String },
+    Expired,
+    Invalid,
+}
+
+/// 使用refresh token换取新的refresh token，旧token标记为已使用，需要在事务中调用
+pub fn rotate_refresh_token(conn: &mut PooledConn, token: &str) -> mysql::Result<RefreshResult> {
+    let row: Option<(String, String, i64, i32)> = conn.exec_first(
+        "SELECT user, family, expire, used FROM token WHERE ty = ? AND id = ? FOR UPDATE",
+        (REFRESH_TOKEN_TY, hash_refresh_token(token)),
+    )?;
+    let Some((id, family, expire, used)) = row else {
+        return Ok(RefreshResult::Invalid);
+    };
+    if used != 0 {
+        delete_session(conn, &family)?;
+        return Ok(RefreshResult::Reused { id });
+    }
+    if TIME::now().map_or(true, |t| t.naos() as i64 >= expire) {
+        return Ok(RefreshResult::Expired);
+    }
+    conn.exec_drop(
+        "UPDATE token SET used = 1 WHERE ty = ? AND id = ? LIMIT 1",
+        (REFRESH_TOKEN_TY, hash_refresh_token(token)),
+    )?;
+    let token = issue_refresh_token(conn, &id, &family)?;
+    touch_session(conn, &family)?;
+    Ok(RefreshResult::Rotated {
+        id,
+        sid: family,
+        token,
+    })
+}
+
+/// 创建登录会话，返回会话id，该次登录的refresh token使用会话id作为family
+pub fn create_session(
+    conn: &mut PooledConn,
+    sub: bool,
+    id: &str,
+    device: &str,
+    client: &ClientInfo,
+) -> mysql::Result<String> {
+    let sid = random_hex(16);
+    let now = TIME::now()
+        .map(|t| t.format(TimeFormat::YYYYMMDD_HHMMSS))
+        .unwrap_or_default();
+    conn.exec_drop(
+        "INSERT INTO session (id, ty, user, device, ip, user_agent, create_time, last_seen)
+        VALUES (:id, :ty, :user, :device, :ip, :user_agent, :time, :time)",
+        params! {
+            "id" => &sid,
+            "ty" => if sub { 0 } else { 1 },
+            "user" => id,
+            "device" => device.chars().take(100).collect::<String>(),
+            "ip" => &client.ip,
+            "user_agent" => &client.user_agent,
+            "time" => now
+        },
+    )?;
+    Ok(sid)
+}
+
+fn touch_session(conn: &mut PooledConn, sid: &str) -> mysql::Result<()> {
+    let now = TIME::now()
+        .map(|t| t.format(TimeFormat::YYYYMMDD_HHMMSS))
+        .unwrap_or_default();
+    conn.exec_drop(
+        "UPDATE session SET last_seen = ? WHERE id = ? LIMIT 1",
+        (now, sid),
+    )
+}
+
+/// 从token中读取`sub`，不验证签名，只用于已经验证并缓存过的token
+pub fn token_sub(token: &str) -> Option<bool> {
+    let token: Token<Header, BTreeMap<String, serde_json::Value>, _> =
+        Token::parse_unverified(token).ok()?;
+    token.claims().get("sub")?.as_bool()
+}
+
+/// 缓存的token是否已过期，不验证签名，只用于已经验证并缓存过的token。
+/// 缓存每隔一段时间才清空，命中缓存时也要检查`exp`
+pub fn token_expired(token: &str) -> bool {
+    let exp = Token::<Header, BTreeMap<String, serde_json::Value>, _>::parse_unverified(token)
+        .ok()
+        .and_then(|t| t.claims().get("exp")?.as_i64());
+    match (exp, TIME::now()) {
+        (Some(exp), Ok(now)) => now.naos() as i64 >= exp,
+        _ => true,
+    }
+}
+
+/// 从token中读取会话id，不验证签名
+fn token_sid(token: &str) -> Option<String> {
+    let token: Token<Header, BTreeMap<String, serde_json::Value>, _> =
+        Token::parse_unverified(token).ok()?;
+    Some(token.claims().get("sid")?.as_str()?.to_owned())
+}
+
+/// 注销会话，同时删除该会话的refresh token和已缓存的access token
+pub fn delete_session(conn: &mut PooledConn, sid: &str) -> mysql::Result<()> {
+    conn.exec_drop(
+        "DELETE FROM token WHERE ty = ? AND family = ?",
+        (REFRESH_TOKEN_TY, sid),
+    )?;
+    conn.exec_drop("DELETE FROM session WHERE id = ? LIMIT 1", (sid,))?;
+    TOKEN_CACHE.retain(|token, _| token_sid(token).as_deref() != Some(sid));
+    Ok(())
+}
+
+/// 注销用户所有的会话
+pub fn revoke_sessions(conn: &mut PooledConn, sub: bool, id: &str) -> mysql::Result<()> {
+    let ty = if sub { 0 } else { 1 };
+    conn.exec_drop(
+        "DELETE t FROM token t JOIN session s ON s.id = t.family
+        WHERE t.ty = ? AND s.ty = ? AND s.user = ?",
+        (REFRESH_TOKEN_TY, ty, id),
+    )?;
+    conn.exec_drop("DELETE FROM session WHERE ty = ? AND user = ?", (ty, id))?;
+    TOKEN_CACHE.retain(|_, user| user != id);
+    Ok(())
+}
+
+/// 删除已过期的refresh token，返回删除的数量
+pub fn purge_refresh_tokens(conn: &mut PooledConn) -> mysql::Result<u64> {
+    let now = TIME::now().map_or(0, |t| t.naos() as i64);
+    conn.exec_drop(
+        "DELETE FROM token WHERE ty = ? AND expire < ?",
+        (REFRESH_TOKEN_TY, now),
+    )?;
+    let count = conn.affected_rows();
+    // 没有可用refresh token的会话已经无法继续使用
+    conn.exec_drop(
+        "DELETE FROM session s WHERE NOT EXISTS
+        (SELECT 1 FROM token t WHERE t.ty = ? AND t.family = s.id AND t.used = 0)",
+        (REFRESH_TOKEN_TY,),
+    )?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(kid: &str, kids: &[&str]) -> KeyStore {
+        let keys: Vec<_> = kids
+            .iter()
+            .map(|k| json!({"kid": k, "secret": format!("{k}-secret-0123456789abcdef0123456789")}))
+            .collect();
+        let config: JwtConfig = serde_json::from_value(json!({"kid": kid, "keys": keys})).unwrap();
+        KeyStore::new(&config, None).unwrap()
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let claims = BTreeMap::from([("id", json!("u1"))]);
+        let old = store("k1", &["k1"]).sign(&claims);
+        // 轮换后旧token仍然有效，新token使用新密钥
+        let rotated = store("k2", &["k1", "k2"]);
+        assert_eq!(rotated.verify(&old).unwrap()["id"], json!("u1"));
+        let new = rotated.sign(&claims);
+        assert!(store("k1", &["k1"]).verify(&new).is_none());
+        // 移除旧密钥后旧token失效
+        assert!(store("k2", &["k2"]).verify(&old).is_none());
+    }
+
+    #[test]
+    fn test_missing_or_weak_key() {
+        let config: JwtConfig = serde_json::from_value(json!({"kid": "k1"})).unwrap();
+        assert!(KeyStore::new(&config, None).is_err());
+        let weak = JwtKey {
+            kid: "k1".to_owned(),
+            secret: "short".to_owned(),
+            algorithm: "HS256".to_owned(),
+        };
+        assert!(KeyStore::new(&config, Some(weak)).is_err());
+    }
+
+    #[test]
+    fn test_token_expired() {
+        let now = TIME::now().unwrap().naos() as i64;
+        let keys = store("k1", &["k1"]);
+        let token = |exp: i64| keys.sign(&BTreeMap::from([("exp", json!(exp))]));
+        assert!(token_expired(&token(now - 1)));
+        assert!(!token_expired(&token(now + 60_000_000_000)));
+        assert!(token_expired(&keys.sign(&BTreeMap::from([("id", json!("u1"))]))));
+    }
 }